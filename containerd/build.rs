@@ -6,13 +6,14 @@ fn main() -> Result<()> {
     // TODO: Remove once figurout a way to configure google.rpc proto
     prost_build::Config::new().compile_protos(
         &[
+            "proto/plugin/fieldpath.proto",
             "proto/plugin/rpc/status.proto",
         ],
         &["./proto/"],
     )?;
 
     // build API types
-    prost_build::Config::new().compile_protos(
+    config().compile_protos(
         &[
             "proto/api/types/descriptor.proto",
             "proto/api/types/metrics.proto",
@@ -25,7 +26,7 @@ fn main() -> Result<()> {
     )?;
 
     // build events APIs
-    prost_build::Config::new().compile_protos(
+    config().compile_protos(
         &[
             "proto/api/events/container.proto",
             "proto/api/events/content.proto",
@@ -38,7 +39,7 @@ fn main() -> Result<()> {
     )?;
 
     // build runtime APIs
-    prost_build::Config::new().compile_protos(
+    config().compile_protos(
         &[
             "proto/api/runtime/sandbox/v1/sandbox.proto",
             "proto/api/runtime/task/v2/shim.proto",
@@ -47,7 +48,11 @@ fn main() -> Result<()> {
     )?;
 
 
-    prost_build::Config::new().compile_protos(
+    config().compile_protos(&["proto/api/services/ttrpc/events/v1/events.proto"], &["./proto/"])?;
+
+    // build gRPC services
+    tonic_build::configure().compile_with_config(
+        config(),
         &[
             "proto/api/services/containers/v1/containers.proto",
             "proto/api/services/content/v1/content.proto",
@@ -60,7 +65,6 @@ fn main() -> Result<()> {
             "proto/api/services/sandbox/v1/sandbox.proto",
             "proto/api/services/snapshots/v1/snapshots.proto",
             "proto/api/services/tasks/v1/tasks.proto",
-            "proto/api/services/ttrpc/events/v1/events.proto",
             "proto/api/services/version/v1/version.proto",
         ],
         &["./proto/"],
@@ -68,3 +72,31 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// config returns the prost configuration shared by the API builds.
+///
+/// The imported files are generated again by every build, so the attributes of
+/// the API types have to be set on each of them. Descriptor and Platform are
+/// also used to read and write OCI image layouts, so they get the JSON encoding
+/// of the OCI image spec.
+fn config() -> prost_build::Config {
+    let mut config = prost_build::Config::new();
+    config
+        .type_attribute(
+            ".containerd.api.types.Descriptor",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(rename_all = \"camelCase\")]",
+        )
+        .field_attribute(
+            ".containerd.api.types.Descriptor.annotations",
+            "#[serde(default, skip_serializing_if = \"::std::collections::HashMap::is_empty\")]",
+        )
+        .type_attribute(
+            ".containerd.api.types.Platform",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .field_attribute(
+            ".containerd.api.types.Platform.variant",
+            "#[serde(default, skip_serializing_if = \"::std::string::String::is_empty\")]",
+        );
+    config
+}
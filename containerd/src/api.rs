@@ -77,6 +77,13 @@ pub mod services {
         pub mod events {
             pub mod v1 {
             include!(concat!(env!("OUT_DIR"), "/containerd.api.services.events.ttrpc.v1.rs"));
+
+                crate::ttrpc::service! {
+                    /// Events forwards the events of a shim to containerd.
+                    service Events = "containerd.services.events.ttrpc.v1.Events", EventsClient, register_events_service {
+                        fn forward(ForwardRequest) -> () = "Forward";
+                    }
+                }
             }
         }
     }
@@ -90,6 +97,31 @@ pub mod services {
 pub mod task {
     pub mod v2 {
         include!(concat!(env!("OUT_DIR"), "/containerd.api.task.v2.rs"));
+
+        // the service keeps the upstream package name on the wire, standard
+        // containerd-shim-*-v2 binaries only answer to that name
+        crate::ttrpc::service! {
+            /// Task is the service a v2 shim serves to manage its tasks.
+            service Task = "containerd.task.v2.Task", TaskClient, register_task_service {
+                fn state(StateRequest) -> StateResponse = "State";
+                fn create(CreateTaskRequest) -> CreateTaskResponse = "Create";
+                fn start(StartRequest) -> StartResponse = "Start";
+                fn delete(DeleteRequest) -> DeleteResponse = "Delete";
+                fn pids(PidsRequest) -> PidsResponse = "Pids";
+                fn pause(PauseRequest) -> () = "Pause";
+                fn resume(ResumeRequest) -> () = "Resume";
+                fn checkpoint(CheckpointTaskRequest) -> () = "Checkpoint";
+                fn kill(KillRequest) -> () = "Kill";
+                fn exec(ExecProcessRequest) -> () = "Exec";
+                fn resize_pty(ResizePtyRequest) -> () = "ResizePty";
+                fn close_io(CloseIoRequest) -> () = "CloseIO";
+                fn update(UpdateTaskRequest) -> () = "Update";
+                fn wait(WaitRequest) -> WaitResponse = "Wait";
+                fn stats(StatsRequest) -> StatsResponse = "Stats";
+                fn connect(ConnectRequest) -> ConnectResponse = "Connect";
+                fn shutdown(ShutdownRequest) -> () = "Shutdown";
+            }
+        }
     }
 }
 
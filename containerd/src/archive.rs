@@ -0,0 +1,152 @@
+//! Archive applies the tar streams of layers onto directories.
+
+pub mod compression;
+
+use crate::errdefs::Error;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// WHITEOUT_PREFIX prefixes the name of a file to remove the file of the
+/// same name from the layers below.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// WHITEOUT_META_PREFIX prefixes the whiteout files with a special meaning,
+/// which are not for removing an actual file.
+pub const WHITEOUT_META_PREFIX: &str = ".wh..wh.";
+
+/// WHITEOUT_OPAQUE_DIR hides the content of the directory it is in from the
+/// layers below.
+pub const WHITEOUT_OPAQUE_DIR: &str = ".wh..wh..opq";
+
+/// apply applies the tar stream of a layer onto the directory at root, the
+/// changes of the layer are kept while the whiteout files remove the files
+/// they hide instead of being extracted. The stream is read up to the end of
+/// the archive.
+///
+/// Entries whose path leaves root are rejected.
+pub fn apply(root: &Path, r: &mut dyn Read) -> Result<(), Error> {
+    let as_root = nix::unistd::geteuid().is_root();
+    let resolved_root = fs::canonicalize(root)?;
+    let mut archive = tar::Archive::new(r);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_overwrite(true);
+    archive.set_preserve_ownerships(as_root);
+    archive.set_unpack_xattrs(as_root);
+
+    // the paths written by the layer, which an opaque directory keeps
+    let mut unpacked = HashSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = clean_path(&entry.path()?)?;
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            // the root of the layer
+            None => continue,
+        };
+        let parent = root.join(path.parent().unwrap_or_else(|| Path::new("")));
+        check_inside(&resolved_root, &parent)?;
+
+        if name == WHITEOUT_OPAQUE_DIR {
+            remove_all_but(&parent, &unpacked)?;
+            continue;
+        }
+        if name.starts_with(WHITEOUT_META_PREFIX) {
+            log::debug!("skipping whiteout meta file {:?}", path);
+            continue;
+        }
+        if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            remove_all(&parent.join(hidden))?;
+            continue;
+        }
+
+        let target = root.join(&path);
+        replace(&target, entry.header().entry_type().is_dir())?;
+        entry
+            .unpack_in(root)
+            .map_err(|e| Error::Unknown(format!("failed to extract {:?}: {}", path, e)))?;
+        unpacked.insert(target);
+    }
+
+    Ok(())
+}
+
+/// clean_path returns the path of an entry relative to the root it is
+/// applied on, a path leaving the root is InvalidArgument.
+fn clean_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut cleaned = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => cleaned.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(Error::InvalidArgument(format!(
+                    "path {:?} of a layer entry leaves the root",
+                    path
+                )))
+            }
+        }
+    }
+    Ok(cleaned)
+}
+
+/// check_inside returns an error unless the directory of an entry resolves
+/// to a path below the resolved root, so that nothing is removed or written
+/// through a symlink leaving it. A missing directory is created below root.
+fn check_inside(resolved_root: &Path, dir: &Path) -> Result<(), Error> {
+    match fs::canonicalize(dir) {
+        Ok(resolved) if resolved.starts_with(resolved_root) => Ok(()),
+        Ok(resolved) => Err(Error::InvalidArgument(format!(
+            "{} resolves to {}, outside of the root",
+            dir.display(),
+            resolved.display()
+        ))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// replace removes the file at target unless both it and the entry replacing
+/// it are directories, so that nothing is written through a symlink of a
+/// lower layer.
+fn replace(target: &Path, dir: bool) -> Result<(), Error> {
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() && dir => Ok(()),
+        Ok(_) => remove_all(target),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// remove_all removes the file or directory tree at path, if any.
+fn remove_all(path: &Path) -> Result<(), Error> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(Error::Unknown(format!("failed to remove {}: {}", path.display(), e)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// remove_all_but empties the directory, except for the paths to keep.
+fn remove_all_but(dir: &Path, keep: &HashSet<PathBuf>) -> Result<(), Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if !keep.contains(&path) {
+            remove_all(&path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use crate::errdefs::Error;
+use std::io::{BufRead, BufReader, Read};
+
+/// GZIP_MAGIC starts a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// ZSTD_MAGIC starts a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression is the compression of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Uncompressed,
+    Gzip,
+    Zstd,
+}
+
+/// detect_compression detects the compression of a stream from its first
+/// bytes.
+pub fn detect_compression(p: &[u8]) -> Compression {
+    if p.starts_with(&GZIP_MAGIC) {
+        Compression::Gzip
+    } else if p.starts_with(&ZSTD_MAGIC) {
+        Compression::Zstd
+    } else {
+        Compression::Uncompressed
+    }
+}
+
+/// decompress_stream returns a reader of the decompressed stream, whose
+/// compression is detected. Uncompressed streams are read as is.
+pub fn decompress_stream<'a>(r: impl Read + 'a) -> Result<Box<dyn Read + 'a>, Error> {
+    let mut r = BufReader::new(r);
+    match detect_compression(r.fill_buf()?) {
+        Compression::Uncompressed => Ok(Box::new(r)),
+        Compression::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(r))),
+        Compression::Zstd => Err(Error::NotImplemented(
+            "zstd compressed streams are not supported".to_string(),
+        )),
+    }
+}
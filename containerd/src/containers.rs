@@ -1,37 +1,43 @@
+use super::context::Context;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use super::identifiers;
+use super::labels;
 use std::collections::HashMap;
-use time::Time;
+use time::OffsetDateTime;
 
 /// Container represents the set of data pinned by a container. Unless otherwise
 /// noted, the resources here are considered in use by the container.
 ///
 /// The resources specified in this object are used to create tasks from the container.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Container {
     /// id uniquely identifies the container in a namespace.
     ///
     /// This property is required and cannot be changed after creation.
-    id: String,
+    pub id: String,
 
     /// labels provide metadata extension for a container.
     ///
     /// These are optional and fully mutable.
-    labels: HashMap<String, String>,
+    pub labels: HashMap<String, String>,
 
     /// image specifies the image reference used for a container.
     ///
     /// This property is optional and mutable.
-    image: String,
+    pub image: String,
 
     /// runtime specifies which runtime should be used when launching container
     /// tasks.
     ///
     /// This property is required and immutable.
-    runtime: RuntimeInfo,
+    pub runtime: RuntimeInfo,
 
     /// Spec should carry the runtime specification used to implement the
 	/// container.
 	///
 	/// This field is required but mutable.
-    spec: prost_types::Any,
+    pub spec: prost_types::Any,
 
     /// snapshot_key specifies the snapshot key to use for the container's root
     /// filesystem. When starting a task from this container, a caller should
@@ -39,57 +45,187 @@ pub struct Container {
     /// task create request.
     ///
     /// This field is not required but mutable.
-    snapshot_key: String,
+    pub snapshot_key: String,
 
     /// snapshotter specifies the snapshotter name used for rootfs
 	///
 	/// This field is not required but immutable.
-    snapshotter: String,
+    pub snapshotter: String,
 
     /// created_at is the time at which the container was created.
-    created_at: Time,
+    pub created_at: OffsetDateTime,
 
     /// updated_at is the time at which the container was updated.
-    updated_at: Time,
+    pub updated_at: OffsetDateTime,
 
     /// extensions stores client-specified metadata
-    extensions: HashMap<String, prost_types::Any>,
+    pub extensions: HashMap<String, prost_types::Any>,
 
     /// sandbox_id is an identifier of sandbox this container belongs to.
     ///
     /// This property is optional, but can't be changed after creation.
-    sandbox_id: String
+    pub sandbox_id: String
+}
+
+impl Container {
+    pub fn new(id: &str, runtime: &str) -> Container {
+        Container {
+            id: id.to_string(),
+            labels: HashMap::new(),
+            image: String::new(),
+            runtime: RuntimeInfo {
+                name: runtime.to_string(),
+                options: Default::default(),
+            },
+            spec: Default::default(),
+            snapshot_key: String::new(),
+            snapshotter: String::new(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+            extensions: HashMap::new(),
+            sandbox_id: String::new(),
+        }
+    }
 }
 
 /// RuntimeInfo holds runtime specific information
-struct RuntimeInfo {
-    name: String,
-    options: prost_types::Any
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RuntimeInfo {
+    pub name: String,
+    pub options: prost_types::Any
 }
 
 /// Store interacts with the underlying container storage
-trait Store {
+pub trait Store: Send + Sync {
     /// Get a container using the id.
     ///
     /// Container object is returned on success. If the id is not known to the
     /// store, an error will be returned.
-    fn get(&self, id: &str) -> Result<Container, String>;
+    fn get(&self, ctx: &Context, id: &str) -> Result<Container, Error>;
 
     /// list returns containers that match one or more of the provided filters.
-    fn list(&self,filters: &[&str]) -> Result<Vec<Container>, String>;
+    fn list(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Container>, Error>;
 
     /// create a container in the store from the provided container.
-    fn create(&self,container: Container) -> Result<Vec<Container>, String>;
+    ///
+    /// The id must not be in use in the namespace, created_at and updated_at
+    /// are set by the store.
+    fn create(&self, ctx: &Context, container: Container) -> Result<Container, Error>;
 
     /// update the container with the provided container object. ID must be set.
     ///
     /// If one or more fieldpaths are provided, only the field corresponding to
     /// the fieldpaths will be mutated.
-    fn update(&self, container: Container, fieldpaths: &[&str]) -> Result<Vec<Container>, String>;
+    fn update(&self, ctx: &Context, container: Container, fieldpaths: &[&str]) -> Result<Container, Error>;
 
     /// delete a container using the id.
     ///
     /// nil will be returned on success. If the container is not known to the
     /// store, ErrNotFound will be returned.
-    fn delete(&self, id: &str) -> Result<(), String>;
-}
\ No newline at end of file
+    fn delete(&self, ctx: &Context, id: &str) -> Result<(), Error>;
+}
+
+/// validate checks the fields of a container required by every store.
+pub fn validate(container: &Container) -> Result<(), Error> {
+    identifiers::validate(&container.id)?;
+    labels::validate_all(&container.labels)?;
+    if container.runtime.name.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "container {:?}: must specify a runtime name",
+            container.id
+        )));
+    }
+    if container.extensions.keys().any(|k| k.is_empty()) {
+        return Err(Error::InvalidArgument(format!(
+            "container {:?}: extension keys must not be zero-length",
+            container.id
+        )));
+    }
+    Ok(())
+}
+
+/// update_fields applies the fields of the requested container selected by
+/// the fieldpaths to the current container.
+///
+/// The mutable fields are `labels`, `labels.<key>`, `image`, `spec`,
+/// `snapshot_key`, `extensions` and `extensions.<key>`, a key missing from the
+/// requested container is removed. `runtime`, `snapshotter` and `sandbox_id`
+/// are immutable. Without fieldpaths every mutable field is replaced, and the
+/// immutable fields of the requested container must match the current ones.
+pub fn update_fields(current: &mut Container, requested: Container, fieldpaths: &[&str]) -> Result<(), Error> {
+    let immutable = |field: &str| {
+        Error::InvalidArgument(format!("container {:?}: {} field is immutable", current.id, field))
+    };
+
+    if fieldpaths.is_empty() {
+        if current.runtime != requested.runtime {
+            return Err(immutable("runtime"));
+        }
+        if current.snapshotter != requested.snapshotter {
+            return Err(immutable("snapshotter"));
+        }
+        if current.sandbox_id != requested.sandbox_id {
+            return Err(immutable("sandbox_id"));
+        }
+
+        current.labels = requested.labels;
+        current.image = requested.image;
+        current.spec = requested.spec;
+        current.snapshot_key = requested.snapshot_key;
+        current.extensions = requested.extensions;
+        return Ok(());
+    }
+
+    for path in fieldpaths {
+        if let Some(key) = path.strip_prefix("labels.").filter(|k| !k.is_empty()) {
+            match requested.labels.get(key) {
+                Some(value) => current.labels.insert(key.to_string(), value.clone()),
+                None => current.labels.remove(key),
+            };
+            continue;
+        }
+
+        if let Some(key) = path.strip_prefix("extensions.").filter(|k| !k.is_empty()) {
+            match requested.extensions.get(key) {
+                Some(value) => current.extensions.insert(key.to_string(), value.clone()),
+                None => current.extensions.remove(key),
+            };
+            continue;
+        }
+
+        match *path {
+            "labels" => current.labels = requested.labels.clone(),
+            "image" => current.image = requested.image.clone(),
+            "spec" => current.spec = requested.spec.clone(),
+            "snapshot_key" => current.snapshot_key = requested.snapshot_key.clone(),
+            "extensions" => current.extensions = requested.extensions.clone(),
+            "runtime" | "snapshotter" | "sandbox_id" => return Err(immutable(path)),
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "cannot update {:?} field on container {:?}",
+                    path, current.id
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adaptor for containers exposes the `id`, `image`, `runtime.name`,
+/// `snapshotter`, `snapshot_key`, `sandbox_id` and `labels.<key>` field paths
+/// to filters.
+impl Adaptor for Container {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        let value = match fieldpath.first()?.as_str() {
+            "id" => &self.id,
+            "image" => &self.image,
+            "runtime" if fieldpath.get(1).map(String::as_str) == Some("name") => &self.runtime.name,
+            "snapshotter" => &self.snapshotter,
+            "snapshot_key" => &self.snapshot_key,
+            "sandbox_id" => &self.sandbox_id,
+            "labels" => return filters::check_map(&fieldpath[1..], &self.labels),
+            _ => return None,
+        };
+        Some(value.clone()).filter(|v| !v.is_empty())
+    }
+}
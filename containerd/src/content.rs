@@ -0,0 +1,207 @@
+pub mod local;
+
+use super::api::types::Descriptor;
+use super::context::Context;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+use time::OffsetDateTime;
+
+/// Info holds content specific information
+#[derive(Debug, Clone, PartialEq)]
+pub struct Info {
+    pub digest: String,
+    pub size: i64,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+    pub labels: HashMap<String, String>,
+}
+
+/// Status of a content operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub reference: String,
+    pub offset: i64,
+    pub total: i64,
+    pub expected: String,
+    pub started_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// Reader gives random access to a committed blob.
+pub trait Reader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Reader for T {}
+
+/// Writer handles writing of content into a content store
+pub trait Writer: Write + Send {
+    /// digest may return empty digest or panics until committed.
+    fn digest(&self) -> String;
+
+    /// commit commits the blob (but no roll-back is guaranteed on an error).
+    /// size and expected can be zero-value when unknown.
+    /// Commit always closes the writer, even on error.
+    fn commit(&mut self, size: i64, expected: &str, labels: HashMap<String, String>) -> Result<(), Error>;
+
+    /// status returns the current state of write
+    fn status(&self) -> Result<Status, Error>;
+
+    /// truncate updates the size of the target blob
+    fn truncate(&mut self, size: i64) -> Result<(), Error>;
+}
+
+/// WriterOpts selects the ingest a writer appends to and the content expected
+/// once it is committed.
+#[derive(Debug, Clone, Default)]
+pub struct WriterOpts {
+    // reference identifies the ingest, writers with the same reference resume
+    // the previous ingest
+    pub reference: String,
+    // desc is the expected content, the digest and size may be left empty
+    pub desc: Descriptor,
+}
+
+/// Store combines the methods of content-oriented interfaces into a set that
+/// are commonly provided by complete implementations.
+pub trait Store: Send + Sync {
+    /// info will return metadata about content available in the content store.
+    ///
+    /// If the content is not present, NotFound will be returned.
+    fn info(&self, ctx: &Context, digest: &str) -> Result<Info, Error>;
+
+    /// update updates mutable information related to content.
+    /// If one or more fieldpaths are provided, only those
+    /// fields will be updated.
+    /// Mutable fields:
+    ///  labels.*
+    fn update(&self, ctx: &Context, info: Info, fieldpaths: &[&str]) -> Result<Info, Error>;
+
+    /// walk will call f for each item in the content store which
+    /// match the provided filters. If no filters are given all
+    /// items will be walked.
+    fn walk(&self, ctx: &Context, filters: &[&str], f: &mut dyn FnMut(Info) -> Result<(), Error>)
+        -> Result<(), Error>;
+
+    /// delete removes the content from the store.
+    fn delete(&self, ctx: &Context, digest: &str) -> Result<(), Error>;
+
+    /// reader returns a reader for the committed blob.
+    fn reader(&self, ctx: &Context, digest: &str) -> Result<Box<dyn Reader>, Error>;
+
+    /// status returns the status of the provided ref.
+    fn status(&self, ctx: &Context, reference: &str) -> Result<Status, Error>;
+
+    /// list_statuses returns the status of any active ingestions whose ref match the
+    /// provided regular expression. If empty, all active ingestions will be
+    /// returned.
+    fn list_statuses(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Status>, Error>;
+
+    /// abort completely cancels the ingest operation targeted by ref.
+    fn abort(&self, ctx: &Context, reference: &str) -> Result<(), Error>;
+
+    /// writer initiates a writing operation (aka ingestion). A single ingestion
+    /// is uniquely identified by its ref, provided using `opts.reference`.
+    ///
+    /// AlreadyExists is returned when the expected content is already present
+    /// in the namespace.
+    fn writer(&self, ctx: &Context, opts: WriterOpts) -> Result<Box<dyn Writer>, Error>;
+}
+
+/// read_blob retrieves the entire contents of the blob from the store.
+pub fn read_blob(store: &dyn Store, ctx: &Context, desc: &Descriptor) -> Result<Vec<u8>, Error> {
+    let mut reader = store.reader(ctx, &desc.digest)?;
+
+    // the size may come from untrusted content, only its first MiB is
+    // preallocated
+    let mut p = Vec::with_capacity(desc.size.clamp(0, 1 << 20) as usize);
+    reader.read_to_end(&mut p)?;
+
+    Ok(p)
+}
+
+/// write_blob writes data with the expected digest into the content store. If
+/// expected already exists, the method returns immediately and the reader will
+/// not be consumed.
+///
+/// This is useful when the digest and size are known beforehand.
+pub fn write_blob(
+    store: &dyn Store,
+    ctx: &Context,
+    reference: &str,
+    r: &mut dyn Read,
+    desc: &Descriptor,
+    labels: HashMap<String, String>,
+) -> Result<(), Error> {
+    let mut w = match store.writer(
+        ctx,
+        WriterOpts {
+            reference: reference.to_string(),
+            desc: desc.clone(),
+        },
+    ) {
+        Ok(w) => w,
+        Err(e) if e.is_already_exists() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    copy(w.as_mut(), r, desc.size, &desc.digest, labels)
+}
+
+/// copy copies data with the expected digest from the reader into the
+/// provided content store writer. This copy commits the writer.
+///
+/// The writer is resumed from its current offset, the data before it is
+/// skipped in the reader.
+pub fn copy(
+    w: &mut dyn Writer,
+    r: &mut dyn Read,
+    size: i64,
+    expected: &str,
+    labels: HashMap<String, String>,
+) -> Result<(), Error> {
+    let offset = w.status()?.offset;
+    if offset > 0 {
+        let skipped = std::io::copy(&mut r.take(offset as u64), &mut std::io::sink())?;
+        if skipped as i64 != offset {
+            return Err(Error::FailedPrecondition(format!(
+                "unexpected end of data while skipping to offset {}",
+                offset
+            )));
+        }
+    }
+
+    std::io::copy(r, w)?;
+
+    match w.commit(size, expected, labels) {
+        Err(e) if !e.is_already_exists() => Err(Error::FailedPrecondition(format!(
+            "failed commit on ref {:?}: {}",
+            w.status().map(|s| s.reference).unwrap_or_default(),
+            e
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Adaptor for content info exposes the `digest`, `size` and
+/// `labels.<key>` field paths to filters.
+impl Adaptor for Info {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        match fieldpath.first()?.as_str() {
+            "digest" => Some(self.digest.clone()),
+            "size" => Some(self.size.to_string()),
+            "labels" => filters::check_map(&fieldpath[1..], &self.labels),
+            _ => None,
+        }
+    }
+}
+
+/// Adaptor for ingest statuses exposes the `ref` field path to filters.
+impl Adaptor for Status {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        match fieldpath.first()?.as_str() {
+            "ref" => Some(self.reference.clone()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,595 @@
+use super::{Info, Reader, Status, WriterOpts};
+use crate::context::Context;
+use crate::digest::{self, Digester};
+use crate::errdefs::Error;
+use crate::gc::{MutationCallback, MutationCallbacks};
+use crate::labels;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+/// Store is a content store backed by a directory on the local filesystem.
+///
+/// Blobs are stored once under `blobs/<algorithm>/<encoded>` and shared by all
+/// namespaces. Each namespace keeps its own record (size, labels and
+/// timestamps) of the blobs it references under
+/// `namespaces/<namespace>/<algorithm>/<encoded>`, and a blob is only removed
+/// once no namespace references it anymore. Active ingests are kept under
+/// `ingest/<key>` until they are committed or aborted.
+pub struct Store {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    root: PathBuf,
+    // ingest keys that have an active writer
+    locks: Mutex<HashSet<String>>,
+    // serializes the changes to blobs and namespace records
+    meta: Mutex<()>,
+    mutation_callbacks: MutationCallbacks,
+}
+
+/// Record is the per namespace metadata kept for a blob.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    size: i64,
+    created_at: i64,
+    updated_at: i64,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+/// IngestStatus is persisted next to the ingest data to allow resuming.
+#[derive(Serialize, Deserialize)]
+struct IngestStatus {
+    reference: String,
+    namespace: String,
+    total: i64,
+    expected: String,
+    started_at: i64,
+}
+
+impl Store {
+    /// new returns a local content store rooted at root.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Store, Error> {
+        let root = root.as_ref().to_path_buf();
+        for dir in ["blobs", "ingest", "namespaces"] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+
+        Ok(Store {
+            inner: Arc::new(Inner {
+                root,
+                locks: Mutex::new(HashSet::new()),
+                meta: Mutex::new(()),
+                mutation_callbacks: MutationCallbacks::default(),
+            }),
+        })
+    }
+
+    /// register_mutation_callback registers a callback called after every
+    /// change made to the blobs or their records, deletions being dirty.
+    pub fn register_mutation_callback(&self, f: MutationCallback) {
+        self.inner.mutation_callbacks.register(f);
+    }
+}
+
+impl Inner {
+    fn blob_path(&self, dgst: &str) -> Result<PathBuf, Error> {
+        let (algorithm, encoded) = digest::split(dgst)?;
+        Ok(self.root.join("blobs").join(algorithm).join(encoded))
+    }
+
+    fn record_path(&self, namespace: &str, dgst: &str) -> Result<PathBuf, Error> {
+        let (algorithm, encoded) = digest::split(dgst)?;
+        Ok(self.root.join("namespaces").join(namespace).join(algorithm).join(encoded))
+    }
+
+    fn ingest_path(&self, namespace: &str, reference: &str) -> PathBuf {
+        self.root.join("ingest").join(ingest_key(namespace, reference))
+    }
+
+    fn read_record(&self, namespace: &str, dgst: &str) -> Result<Record, Error> {
+        let p = match fs::read(self.record_path(namespace, dgst)?) {
+            Ok(p) => p,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(format!("content digest {}", dgst)))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        serde_json::from_slice(&p)
+            .map_err(|e| Error::Unknown(format!("failed to decode record of content {}: {}", dgst, e)))
+    }
+
+    fn write_record(&self, namespace: &str, dgst: &str, record: &Record) -> Result<(), Error> {
+        let path = self.record_path(namespace, dgst)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let p = serde_json::to_vec(record)
+            .map_err(|e| Error::Unknown(format!("failed to encode record of content {}: {}", dgst, e)))?;
+        write_atomic(&path, &p)
+    }
+
+    /// referenced reports whether any namespace still holds a record of the blob.
+    fn referenced(&self, dgst: &str) -> Result<bool, Error> {
+        for entry in fs::read_dir(self.root.join("namespaces"))? {
+            let namespace = entry?.file_name();
+            if self.record_path(&namespace.to_string_lossy(), dgst)?.exists() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn info(&self, dgst: &str, record: Record) -> Info {
+        Info {
+            digest: dgst.to_string(),
+            size: record.size,
+            created_at: from_nanos(record.created_at),
+            updated_at: from_nanos(record.updated_at),
+            labels: record.labels,
+        }
+    }
+
+    fn read_status(&self, path: &Path) -> Result<(IngestStatus, Status), Error> {
+        let p = fs::read(path.join("status"))?;
+        let ingest: IngestStatus = serde_json::from_slice(&p)
+            .map_err(|e| Error::Unknown(format!("failed to decode ingest status: {}", e)))?;
+
+        let data = fs::metadata(path.join("data"))?;
+        let status = Status {
+            reference: ingest.reference.clone(),
+            offset: data.len() as i64,
+            total: ingest.total,
+            expected: ingest.expected.clone(),
+            started_at: from_nanos(ingest.started_at),
+            updated_at: data.modified().map(OffsetDateTime::from).unwrap_or_else(|_| OffsetDateTime::now_utc()),
+        };
+
+        Ok((ingest, status))
+    }
+}
+
+impl super::Store for Store {
+    fn info(&self, ctx: &Context, dgst: &str) -> Result<Info, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let record = self.inner.read_record(namespace, dgst)?;
+        Ok(self.inner.info(dgst, record))
+    }
+
+    fn update(&self, ctx: &Context, info: Info, fieldpaths: &[&str]) -> Result<Info, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let _meta = self.inner.meta.lock().unwrap();
+        let mut record = self.inner.read_record(namespace, &info.digest)?;
+
+        if fieldpaths.is_empty() {
+            record.labels = info.labels;
+        } else {
+            for path in fieldpaths {
+                if let Some(key) = path.strip_prefix("labels.") {
+                    match info.labels.get(key) {
+                        Some(value) => record.labels.insert(key.to_string(), value.clone()),
+                        None => record.labels.remove(key),
+                    };
+                    continue;
+                }
+
+                match *path {
+                    "labels" => record.labels = info.labels.clone(),
+                    _ => {
+                        return Err(Error::InvalidArgument(format!(
+                            "cannot update {:?} field on content info {:?}",
+                            path, info.digest
+                        )))
+                    }
+                }
+            }
+        }
+
+        labels::validate_all(&record.labels)?;
+        record.updated_at = now_nanos();
+        self.inner.write_record(namespace, &info.digest, &record)?;
+        self.inner.mutation_callbacks.notify(false);
+
+        Ok(self.inner.info(&info.digest, record))
+    }
+
+    fn walk(
+        &self,
+        ctx: &Context,
+        filters: &[&str],
+        f: &mut dyn FnMut(Info) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let filter = crate::filters::parse_all(filters)?;
+
+        let root = self.inner.root.join("namespaces").join(namespace);
+        let algorithms = match fs::read_dir(&root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        for algorithm in algorithms {
+            let algorithm = algorithm?;
+            for entry in fs::read_dir(algorithm.path())? {
+                let dgst = format!(
+                    "{}:{}",
+                    algorithm.file_name().to_string_lossy(),
+                    entry?.file_name().to_string_lossy()
+                );
+                if digest::validate(&dgst).is_err() {
+                    // skip partially written records
+                    continue;
+                }
+
+                let info = match self.inner.read_record(namespace, &dgst) {
+                    Ok(record) => self.inner.info(&dgst, record),
+                    // removed while walking
+                    Err(e) if e.is_not_found() => continue,
+                    Err(e) => return Err(e),
+                };
+                if filter.matches(&info) {
+                    f(info)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, ctx: &Context, dgst: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let _meta = self.inner.meta.lock().unwrap();
+        match fs::remove_file(self.inner.record_path(namespace, dgst)?) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(format!("content digest {}", dgst)))
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        if !self.inner.referenced(dgst)? {
+            match fs::remove_file(self.inner.blob_path(dgst)?) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.inner.mutation_callbacks.notify(true);
+
+        Ok(())
+    }
+
+    fn reader(&self, ctx: &Context, dgst: &str) -> Result<Box<dyn Reader>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        self.inner.read_record(namespace, dgst)?;
+        match File::open(self.inner.blob_path(dgst)?) {
+            Ok(f) => Ok(Box::new(f)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::NotFound(format!("content digest {}", dgst)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn status(&self, ctx: &Context, reference: &str) -> Result<Status, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        match self.inner.read_status(&self.inner.ingest_path(namespace, reference)) {
+            Ok((_, status)) => Ok(status),
+            Err(e) if e.is_not_found() => Err(Error::NotFound(format!("ref {}", reference))),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list_statuses(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Status>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let filter = crate::filters::parse_all(filters)?;
+
+        let mut statuses = Vec::new();
+        for entry in fs::read_dir(self.inner.root.join("ingest"))? {
+            let (ingest, status) = match self.inner.read_status(&entry?.path()) {
+                Ok(s) => s,
+                // ingest is being created or removed
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => return Err(e),
+            };
+
+            if ingest.namespace == namespace && filter.matches(&status) {
+                statuses.push(status);
+            }
+        }
+
+        Ok(statuses)
+    }
+
+    fn abort(&self, ctx: &Context, reference: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+
+        match fs::remove_dir_all(self.inner.ingest_path(namespace, reference)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::NotFound(format!("ingest ref {:?}", reference)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn writer(&self, ctx: &Context, opts: WriterOpts) -> Result<Box<dyn super::Writer>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        if opts.reference.is_empty() {
+            return Err(Error::InvalidArgument("ref must not be empty".to_string()));
+        }
+
+        let expected = opts.desc.digest.clone();
+        if !expected.is_empty() {
+            let _meta = self.inner.meta.lock().unwrap();
+            if self.inner.record_path(namespace, &expected)?.exists() {
+                return Err(Error::AlreadyExists(format!("content {}", expected)));
+            }
+
+            // the blob is shared with another namespace, only the record is missing
+            if let Ok(m) = fs::metadata(self.inner.blob_path(&expected)?) {
+                if opts.desc.size == 0 || opts.desc.size == m.len() as i64 {
+                    let now = now_nanos();
+                    self.inner.write_record(
+                        namespace,
+                        &expected,
+                        &Record {
+                            size: m.len() as i64,
+                            created_at: now,
+                            updated_at: now,
+                            labels: HashMap::new(),
+                        },
+                    )?;
+                    return Err(Error::AlreadyExists(format!("content {}", expected)));
+                }
+            }
+        }
+
+        let key = ingest_key(namespace, &opts.reference);
+        if !self.inner.locks.lock().unwrap().insert(key.clone()) {
+            return Err(Error::Unavailable(format!("ref {} locked", opts.reference)));
+        }
+
+        // the lock is released when the writer is dropped
+        let mut w = LocalWriter {
+            inner: self.inner.clone(),
+            key,
+            path: self.inner.ingest_path(namespace, &opts.reference),
+            namespace: namespace.to_string(),
+            reference: opts.reference,
+            file: None,
+            digester: Digester::new(),
+            offset: 0,
+            total: opts.desc.size,
+            expected,
+            started_at: OffsetDateTime::now_utc(),
+        };
+        w.open()?;
+
+        Ok(Box::new(w))
+    }
+}
+
+/// LocalWriter appends to the data file of an ingest.
+struct LocalWriter {
+    inner: Arc<Inner>,
+    key: String,
+    path: PathBuf,
+    namespace: String,
+    reference: String,
+    file: Option<File>,
+    digester: Digester,
+    offset: i64,
+    total: i64,
+    expected: String,
+    started_at: OffsetDateTime,
+}
+
+impl LocalWriter {
+    /// open creates the ingest or resumes it, rehashing the data written so far.
+    fn open(&mut self) -> Result<(), Error> {
+        let data = self.path.join("data");
+
+        if self.path.exists() {
+            let (ingest, status) = self.inner.read_status(&self.path)?;
+            if self.total > 0 && ingest.total > 0 && self.total != ingest.total {
+                return Err(Error::FailedPrecondition(format!(
+                    "provided total differs from status: {} != {}",
+                    self.total, ingest.total
+                )));
+            }
+            if self.total == 0 {
+                self.total = ingest.total;
+            }
+            if self.expected.is_empty() {
+                self.expected = ingest.expected;
+            }
+            self.started_at = status.started_at;
+
+            let mut f = File::open(&data)?;
+            let mut buf = vec![0u8; 32 * 1024];
+            loop {
+                let n = f.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                self.digester.update(&buf[..n]);
+                self.offset += n as i64;
+            }
+        } else {
+            fs::create_dir_all(&self.path)?;
+            let status = IngestStatus {
+                reference: self.reference.clone(),
+                namespace: self.namespace.clone(),
+                total: self.total,
+                expected: self.expected.clone(),
+                started_at: to_nanos(self.started_at),
+            };
+            let p = serde_json::to_vec(&status)
+                .map_err(|e| Error::Unknown(format!("failed to encode ingest status: {}", e)))?;
+            write_atomic(&self.path.join("status"), &p)?;
+        }
+
+        self.file = Some(OpenOptions::new().create(true).append(true).open(&data)?);
+        Ok(())
+    }
+
+    fn file(&mut self) -> std::io::Result<&mut File> {
+        match self.file.as_mut() {
+            Some(f) => Ok(f),
+            None => Err(std::io::Error::other("writer is closed")),
+        }
+    }
+}
+
+impl Write for LocalWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.file()?.write(buf)?;
+        self.digester.update(&buf[..n]);
+        self.offset += n as i64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file()?.flush()
+    }
+}
+
+impl super::Writer for LocalWriter {
+    fn digest(&self) -> String {
+        self.digester.digest()
+    }
+
+    fn commit(&mut self, size: i64, expected: &str, labels: HashMap<String, String>) -> Result<(), Error> {
+        let f = match self.file.take() {
+            Some(f) => f,
+            None => return Err(Error::FailedPrecondition("cannot commit on closed writer".to_string())),
+        };
+        f.sync_all()?;
+        drop(f);
+
+        labels::validate_all(&labels)?;
+
+        if size > 0 && size != self.offset {
+            return Err(Error::FailedPrecondition(format!(
+                "unexpected commit size {}, expected {}",
+                self.offset, size
+            )));
+        }
+        if self.total > 0 && self.total != self.offset {
+            return Err(Error::FailedPrecondition(format!(
+                "unexpected commit size {}, expected {}",
+                self.offset, self.total
+            )));
+        }
+
+        let dgst = self.digester.digest();
+        for want in [expected, self.expected.as_str()] {
+            if !want.is_empty() && want != dgst {
+                return Err(Error::FailedPrecondition(format!(
+                    "unexpected commit digest {}, expected {}",
+                    dgst, want
+                )));
+            }
+        }
+
+        let _meta = self.inner.meta.lock().unwrap();
+
+        let target = self.inner.blob_path(&dgst)?;
+        if !target.exists() {
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::rename(self.path.join("data"), &target)?;
+            fs::set_permissions(&target, fs::Permissions::from_mode(0o444))?;
+        }
+        fs::remove_dir_all(&self.path)?;
+
+        if self.inner.record_path(&self.namespace, &dgst)?.exists() {
+            return Err(Error::AlreadyExists(format!("content {}", dgst)));
+        }
+
+        let now = now_nanos();
+        self.inner.write_record(
+            &self.namespace,
+            &dgst,
+            &Record {
+                size: self.offset,
+                created_at: now,
+                updated_at: now,
+                labels,
+            },
+        )?;
+        self.inner.mutation_callbacks.notify(false);
+        Ok(())
+    }
+
+    fn status(&self) -> Result<Status, Error> {
+        Ok(Status {
+            reference: self.reference.clone(),
+            offset: self.offset,
+            total: self.total,
+            expected: self.expected.clone(),
+            started_at: self.started_at,
+            updated_at: OffsetDateTime::now_utc(),
+        })
+    }
+
+    fn truncate(&mut self, size: i64) -> Result<(), Error> {
+        if size != 0 {
+            return Err(Error::InvalidArgument("truncate: unsupported size".to_string()));
+        }
+
+        self.file()?.set_len(0)?;
+        self.digester = Digester::new();
+        self.offset = 0;
+        Ok(())
+    }
+}
+
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        self.inner.locks.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// ingest_key maps a namespaced reference to a directory name.
+fn ingest_key(namespace: &str, reference: &str) -> String {
+    let dgst = digest::from_bytes(format!("{}/{}", namespace, reference).as_bytes());
+    // the digest is always in the canonical form
+    dgst.split_once(':').unwrap().1.to_string()
+}
+
+/// write_atomic writes the file next to path and renames it in place.
+fn write_atomic(path: &Path, p: &[u8]) -> Result<(), Error> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, p)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn now_nanos() -> i64 {
+    to_nanos(OffsetDateTime::now_utc())
+}
+
+fn to_nanos(t: OffsetDateTime) -> i64 {
+    t.unix_timestamp_nanos() as i64
+}
+
+fn from_nanos(nanos: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(nanos as i128).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
@@ -0,0 +1,204 @@
+use super::errdefs::Error;
+use super::identifiers;
+use super::leases;
+use super::namespaces;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// GRPC_TIMEOUT_HEADER is the gRPC metadata key carrying the time left
+/// before the deadline of a request.
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Metadata is the gRPC metadata of a request, indexed by lower case key.
+pub type Metadata = HashMap<String, Vec<String>>;
+
+/// Context carries the scope of a request through the stores and services:
+/// the namespace it operates in, the lease protecting the resources it
+/// creates and the deadline it has to complete by.
+///
+/// A context is cheap to clone, the `with_*` methods return a copy with the
+/// value replaced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    namespace: Option<String>,
+    lease: Option<String>,
+    deadline: Option<Instant>,
+}
+
+impl Context {
+    /// new returns an empty context, without namespace, lease or deadline.
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// with_namespace sets the namespace on the context.
+    pub fn with_namespace(&self, namespace: &str) -> Context {
+        Context {
+            namespace: Some(namespace.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// with_lease sets the lease on the context, the resources created with
+    /// the context are added to the lease.
+    pub fn with_lease(&self, lease: &str) -> Context {
+        Context {
+            lease: Some(lease.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// with_deadline sets the deadline on the context. A deadline later than
+    /// the current one is ignored.
+    pub fn with_deadline(&self, deadline: Instant) -> Context {
+        Context {
+            deadline: Some(self.deadline.map_or(deadline, |d| d.min(deadline))),
+            ..self.clone()
+        }
+    }
+
+    /// with_timeout sets the deadline on the context to the timeout from now.
+    pub fn with_timeout(&self, timeout: Duration) -> Context {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// namespace returns the namespace of the context, if any.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref().filter(|ns| !ns.is_empty())
+    }
+
+    /// namespace_required returns the namespace of the context, or an error
+    /// if it has none or it is not valid.
+    pub fn namespace_required(&self) -> Result<&str, Error> {
+        let namespace = self
+            .namespace()
+            .ok_or_else(|| Error::FailedPrecondition("namespace is required".to_string()))?;
+        identifiers::validate(namespace)
+            .map_err(|e| Error::FailedPrecondition(format!("namespace validation: {}", e)))?;
+        Ok(namespace)
+    }
+
+    /// lease returns the lease of the context, if any.
+    pub fn lease(&self) -> Option<&str> {
+        self.lease.as_deref().filter(|l| !l.is_empty())
+    }
+
+    /// deadline returns the deadline of the context, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// timeout returns the time left before the deadline of the context, if
+    /// it has one.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.deadline.map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
+    /// check_deadline returns DeadlineExceeded once the deadline of the
+    /// context passed.
+    pub fn check_deadline(&self) -> Result<(), Error> {
+        match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => {
+                Err(Error::DeadlineExceeded("context deadline exceeded".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// from_grpc_metadata returns the context of an incoming request. The
+    /// namespace falls back to default_namespace when the request does not
+    /// carry one, an empty default leaves the namespace unset.
+    pub fn from_grpc_metadata(md: &Metadata, default_namespace: &str) -> Result<Context, Error> {
+        let first = |key: &str| md.get(key).and_then(|values| values.first()).filter(|v| !v.is_empty());
+
+        let mut ctx = Context::new();
+        match first(namespaces::GRPC_HEADER) {
+            Some(namespace) => ctx.namespace = Some(namespace.clone()),
+            None if !default_namespace.is_empty() => ctx.namespace = Some(default_namespace.to_string()),
+            None => {}
+        }
+        ctx.lease = first(leases::GRPC_HEADER).cloned();
+        if let Some(timeout) = first(GRPC_TIMEOUT_HEADER) {
+            ctx.deadline = Some(Instant::now() + parse_timeout(timeout)?);
+        }
+        Ok(ctx)
+    }
+
+    /// to_grpc_metadata sets the namespace, lease and deadline of the context
+    /// on the metadata of an outgoing request.
+    pub fn to_grpc_metadata(&self, md: &mut Metadata) {
+        if let Some(namespace) = self.namespace() {
+            md.insert(namespaces::GRPC_HEADER.to_string(), vec![namespace.to_string()]);
+        }
+        if let Some(lease) = self.lease() {
+            md.insert(leases::GRPC_HEADER.to_string(), vec![lease.to_string()]);
+        }
+        if let Some(timeout) = self.timeout() {
+            md.insert(GRPC_TIMEOUT_HEADER.to_string(), vec![format_timeout(timeout)]);
+        }
+    }
+
+    /// from_ttrpc_metadata returns the context of an incoming ttrpc request
+    /// from the namespace of its metadata. The deadline of a ttrpc request is
+    /// carried by the request itself rather than its metadata.
+    pub fn from_ttrpc_metadata(md: &Metadata) -> Context {
+        let mut ctx = Context::new();
+        ctx.namespace = md
+            .get(namespaces::TTRPC_HEADER)
+            .and_then(|values| values.first())
+            .filter(|v| !v.is_empty())
+            .cloned();
+        ctx
+    }
+
+    /// to_ttrpc_metadata sets the namespace of the context on the metadata of
+    /// an outgoing ttrpc request.
+    pub fn to_ttrpc_metadata(&self, md: &mut Metadata) {
+        if let Some(namespace) = self.namespace() {
+            md.insert(namespaces::TTRPC_HEADER.to_string(), vec![namespace.to_string()]);
+        }
+    }
+}
+
+/// parse_timeout parses a gRPC timeout: at most 8 digits followed by the
+/// unit, one of `H`, `M`, `S`, `m`, `u` or `n`.
+fn parse_timeout(timeout: &str) -> Result<Duration, Error> {
+    let invalid = || Error::InvalidArgument(format!("invalid grpc timeout {:?}", timeout));
+    if timeout.len() < 2 || timeout.len() > 9 {
+        return Err(invalid());
+    }
+    let (value, unit) = timeout.split_at(timeout.len() - 1);
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    match unit {
+        "H" => Ok(Duration::from_secs(value * 3600)),
+        "M" => Ok(Duration::from_secs(value * 60)),
+        "S" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_millis(value)),
+        "u" => Ok(Duration::from_micros(value)),
+        "n" => Ok(Duration::from_nanos(value)),
+        _ => Err(invalid()),
+    }
+}
+
+/// format_timeout formats the timeout in the finest unit that fits 8 digits,
+/// rounding up.
+fn format_timeout(timeout: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+    let nanos = timeout.as_nanos();
+    for (unit, per) in [
+        ("n", 1),
+        ("u", 1_000),
+        ("m", 1_000_000),
+        ("S", 1_000_000_000),
+        ("M", 60_000_000_000),
+    ] {
+        let value = nanos.div_ceil(per);
+        if value <= MAX {
+            return format!("{}{}", value, unit);
+        }
+    }
+    format!("{}H", nanos.div_ceil(3_600_000_000_000).min(MAX))
+}
@@ -0,0 +1,21 @@
+//! Dialer connects clients to the gRPC API of a daemon on its Unix socket.
+
+use crate::errdefs::Error;
+use std::path::{Path, PathBuf};
+use tokio::net::UnixStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+
+/// connect returns a channel to the server listening on the socket at
+/// address. The clients of all services can share the channel, for example
+/// `VersionClient::new(channel.clone())`.
+pub async fn connect<P: AsRef<Path>>(address: P) -> Result<Channel, Error> {
+    let address: PathBuf = address.as_ref().to_path_buf();
+    // the uri is required by the endpoint but never resolved, every
+    // connection goes to the socket
+    let endpoint = Endpoint::from_static("http://[::]:0");
+    let path = address.clone();
+    endpoint
+        .connect_with_connector(tower::service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+        .await
+        .map_err(|e| Error::Unavailable(format!("connect to {}: {}", address.display(), e)))
+}
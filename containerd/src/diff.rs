@@ -0,0 +1,17 @@
+pub mod apply;
+
+use super::api::types::Descriptor;
+use super::context::Context;
+use super::errdefs::Error;
+use super::mount::Mount;
+
+/// Applier applies the content of a layer to mounts.
+pub trait Applier: Send + Sync {
+    /// apply applies the content described by desc to the provided mounts.
+    /// The content is read from the content store of the applier, compressed
+    /// layers are decompressed on the fly.
+    ///
+    /// The returned descriptor describes the uncompressed diff that was
+    /// applied, its digest is the diff ID of the layer.
+    fn apply(&self, ctx: &Context, desc: &Descriptor, mounts: &[Mount]) -> Result<Descriptor, Error>;
+}
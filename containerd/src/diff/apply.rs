@@ -0,0 +1,103 @@
+use super::Applier;
+use crate::api::types::Descriptor;
+use crate::archive::{self, compression};
+use crate::content;
+use crate::context::Context;
+use crate::digest::Digester;
+use crate::errdefs::Error;
+use crate::images::oci;
+use crate::mount::{self, Mount};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// FileSystemApplier applies the layers of a content store by extracting
+/// them onto the filesystem of the mounts.
+pub struct FileSystemApplier {
+    content: Arc<dyn content::Store>,
+}
+
+impl FileSystemApplier {
+    pub fn new(content: Arc<dyn content::Store>) -> FileSystemApplier {
+        FileSystemApplier { content }
+    }
+}
+
+impl Applier for FileSystemApplier {
+    fn apply(&self, ctx: &Context, desc: &Descriptor, mounts: &[Mount]) -> Result<Descriptor, Error> {
+        let r = self.content.reader(ctx, &desc.digest)?;
+        let mut r = Digesting {
+            r: compression::decompress_stream(r)?,
+            digester: Digester::new(),
+            size: 0,
+        };
+
+        apply(mounts, &mut r)?;
+        // the diff ID covers the padding after the end of the archive
+        io::copy(&mut r, &mut io::sink())?;
+
+        Ok(Descriptor {
+            media_type: oci::MEDIA_TYPE_IMAGE_LAYER.to_string(),
+            digest: r.digester.digest(),
+            size: r.size as i64,
+            ..Default::default()
+        })
+    }
+}
+
+/// apply extracts the layer onto the mounts. A bind mount is extracted into
+/// its source directly, other mounts are mounted on a temporary directory.
+fn apply(mounts: &[Mount], r: &mut dyn Read) -> Result<(), Error> {
+    match mounts {
+        [m] if m.fs_type() == "bind" => archive::apply(m.source(), r),
+        _ => with_temp_mount(mounts, |root| archive::apply(root, r)),
+    }
+}
+
+/// with_temp_mount mounts the mounts on a temporary directory while f runs
+/// on it.
+fn with_temp_mount<T>(mounts: &[Mount], f: impl FnOnce(&Path) -> Result<T, Error>) -> Result<T, Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "containerd-mount-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir(&root)?;
+
+    let result = Mount::all(mounts, &root.to_string_lossy())
+        .map_err(|e| Error::Unknown(format!("failed to mount {}: {}", root.display(), e)))
+        .and_then(|()| f(&root));
+    cleanup(&root);
+    result
+}
+
+/// cleanup unmounts and removes the temporary mount point, a mount point
+/// which is still in use is left behind.
+fn cleanup(root: &Path) {
+    if let Err(e) = mount::unmount_recursive(root, 0) {
+        log::warn!("failed to unmount {}: {}", root.display(), e);
+        return;
+    }
+    if let Err(e) = fs::remove_dir(root) {
+        log::warn!("failed to remove temporary mount {}: {}", root.display(), e);
+    }
+}
+
+/// Digesting digests and counts the bytes read through it.
+struct Digesting<R> {
+    r: R,
+    digester: Digester,
+    size: u64,
+}
+
+impl<R: Read> Read for Digesting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(buf)?;
+        self.digester.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,78 @@
+use super::errdefs::Error;
+use sha2::{Digest, Sha256};
+
+/// SHA256 is the canonical algorithm used for content addressing.
+pub const SHA256: &str = "sha256";
+
+/// validate checks that the digest is of the form `<algorithm>:<hex>` and that
+/// the encoded part has the right length for the algorithm.
+pub fn validate(digest: &str) -> Result<(), Error> {
+    let (algorithm, encoded) = match digest.split_once(':') {
+        Some(parts) => parts,
+        None => return Err(Error::InvalidArgument(format!("invalid digest {:?}: invalid format", digest))),
+    };
+
+    let size = match algorithm {
+        "sha256" => 64,
+        "sha384" => 96,
+        "sha512" => 128,
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "invalid digest {:?}: unsupported digest algorithm",
+                digest
+            )))
+        }
+    };
+
+    if encoded.len() != size || !encoded.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err(Error::InvalidArgument(format!("invalid digest {:?}: invalid checksum digest format", digest)));
+    }
+
+    Ok(())
+}
+
+/// split returns the algorithm and the encoded part of a digest.
+pub fn split(digest: &str) -> Result<(&str, &str), Error> {
+    validate(digest)?;
+    // validate ensured the separator is present
+    Ok(digest.split_once(':').unwrap())
+}
+
+/// from_bytes digests the input with the canonical algorithm.
+pub fn from_bytes(p: &[u8]) -> String {
+    let mut digester = Digester::new();
+    digester.update(p);
+    digester.digest()
+}
+
+/// Digester calculates the canonical digest of the data written to it.
+#[derive(Clone, Default)]
+pub struct Digester {
+    hash: Sha256,
+}
+
+impl Digester {
+    pub fn new() -> Digester {
+        Digester { hash: Sha256::new() }
+    }
+
+    pub fn update(&mut self, p: &[u8]) {
+        self.hash.update(p);
+    }
+
+    /// digest returns the digest of the data written so far.
+    pub fn digest(&self) -> String {
+        format!("{}:{}", SHA256, hex::encode(self.hash.clone().finalize()))
+    }
+}
+
+impl std::io::Write for Digester {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+/// Error is the common error type returned by the containerd stores and services.
+///
+/// Each variant carries the context the error happened in (for example
+/// `image "docker.io/library/alpine:latest"`), so that callers can match on the
+/// kind of failure while still getting a useful message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // Unknown is used when the error does not fit any other kind
+    Unknown(String),
+    // InvalidArgument when the request or the object is malformed
+    InvalidArgument(String),
+    // NotFound when the requested object does not exist
+    NotFound(String),
+    // AlreadyExists when an object with the same identifier is already stored
+    AlreadyExists(String),
+    // FailedPrecondition when the system is not in a state required for the operation
+    FailedPrecondition(String),
+    // Unavailable when a resource is temporarily not reachable
+    Unavailable(String),
+    // NotImplemented when the operation is not supported
+    NotImplemented(String),
+    // DeadlineExceeded when the deadline of the request passed before it completed
+    DeadlineExceeded(String),
+}
+
+impl Error {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound(_))
+    }
+
+    pub fn is_already_exists(&self) -> bool {
+        matches!(self, Error::AlreadyExists(_))
+    }
+
+    pub fn is_invalid_argument(&self) -> bool {
+        matches!(self, Error::InvalidArgument(_))
+    }
+
+    pub fn is_failed_precondition(&self) -> bool {
+        matches!(self, Error::FailedPrecondition(_))
+    }
+
+    pub fn is_deadline_exceeded(&self) -> bool {
+        matches!(self, Error::DeadlineExceeded(_))
+    }
+
+    /// kind returns the textual description of the error kind.
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::Unknown(_) => "unknown",
+            Error::InvalidArgument(_) => "invalid argument",
+            Error::NotFound(_) => "not found",
+            Error::AlreadyExists(_) => "already exists",
+            Error::FailedPrecondition(_) => "failed precondition",
+            Error::Unavailable(_) => "unavailable",
+            Error::NotImplemented(_) => "not implemented",
+            Error::DeadlineExceeded(_) => "deadline exceeded",
+        }
+    }
+
+    fn context(&self) -> &str {
+        match self {
+            Error::Unknown(c)
+            | Error::InvalidArgument(c)
+            | Error::NotFound(c)
+            | Error::AlreadyExists(c)
+            | Error::FailedPrecondition(c)
+            | Error::Unavailable(c)
+            | Error::NotImplemented(c)
+            | Error::DeadlineExceeded(c) => c,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.context().is_empty() {
+            write!(f, "{}", self.kind())
+        } else {
+            write!(f, "{}: {}", self.context(), self.kind())
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for String {
+    fn from(e: Error) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Error::NotFound(e.to_string()),
+            std::io::ErrorKind::AlreadyExists => Error::AlreadyExists(e.to_string()),
+            std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => {
+                Error::InvalidArgument(e.to_string())
+            }
+            std::io::ErrorKind::TimedOut => Error::DeadlineExceeded(e.to_string()),
+            _ => Error::Unknown(e.to_string()),
+        }
+    }
+}
+
+/// The gRPC status of an error carries its kind in the code and its context in
+/// the message, so that the error is the same on both ends of a call.
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        let code = match e {
+            Error::Unknown(_) => tonic::Code::Unknown,
+            Error::InvalidArgument(_) => tonic::Code::InvalidArgument,
+            Error::NotFound(_) => tonic::Code::NotFound,
+            Error::AlreadyExists(_) => tonic::Code::AlreadyExists,
+            Error::FailedPrecondition(_) => tonic::Code::FailedPrecondition,
+            Error::Unavailable(_) => tonic::Code::Unavailable,
+            Error::NotImplemented(_) => tonic::Code::Unimplemented,
+            Error::DeadlineExceeded(_) => tonic::Code::DeadlineExceeded,
+        };
+        tonic::Status::new(code, e.context())
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        let context = status.message().to_string();
+        match status.code() {
+            tonic::Code::InvalidArgument => Error::InvalidArgument(context),
+            tonic::Code::NotFound => Error::NotFound(context),
+            tonic::Code::AlreadyExists => Error::AlreadyExists(context),
+            tonic::Code::FailedPrecondition => Error::FailedPrecondition(context),
+            tonic::Code::Unavailable => Error::Unavailable(context),
+            tonic::Code::Unimplemented => Error::NotImplemented(context),
+            tonic::Code::DeadlineExceeded => Error::DeadlineExceeded(context),
+            _ => Error::Unknown(context),
+        }
+    }
+}
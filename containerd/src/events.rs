@@ -0,0 +1,311 @@
+pub mod exchange;
+pub mod journal;
+
+use super::api::events as api;
+use super::api::services::events::v1 as services;
+use super::api::services::ttrpc::events::v1 as ttrpc;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use super::identifiers;
+use super::protobuf;
+use time::OffsetDateTime;
+
+/// Event is a protobuf message that can be published on a topic.
+///
+/// The type url is used to pack the event into a `prost_types::Any` so that
+/// consumers can find out which message they received, the topic is the one
+/// containerd publishes the event on.
+pub trait Event: prost::Message + Default + Adaptor {
+    const TYPE_URL: &'static str;
+    const TOPIC: &'static str;
+}
+
+/// Envelope provides the packaging for an event: the time it was published,
+/// the namespace and topic it was published under and the event itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    /// sequence is the number of the event in the journal of the exchange,
+    /// 0 if the event was not journaled.
+    pub sequence: u64,
+    pub timestamp: OffsetDateTime,
+    pub namespace: String,
+    pub topic: String,
+    pub event: prost_types::Any,
+}
+
+impl Envelope {
+    pub fn new(namespace: &str, topic: &str, event: prost_types::Any) -> Envelope {
+        Envelope {
+            sequence: 0,
+            timestamp: OffsetDateTime::now_utc(),
+            namespace: namespace.to_string(),
+            topic: topic.to_string(),
+            event,
+        }
+    }
+
+    /// decode unpacks the event of the envelope, which must be of type E.
+    pub fn decode<E: Event>(&self) -> Result<E, Error> {
+        decode(&self.event)
+    }
+
+    /// typed unpacks the event of the envelope according to its type url.
+    pub fn typed(&self) -> Result<TypedEvent, Error> {
+        TypedEvent::decode(&self.event)
+    }
+}
+
+impl From<Envelope> for services::Envelope {
+    fn from(envelope: Envelope) -> Self {
+        services::Envelope {
+            timestamp: Some(protobuf::to_timestamp(envelope.timestamp)),
+            namespace: envelope.namespace,
+            topic: envelope.topic,
+            event: Some(envelope.event),
+        }
+    }
+}
+
+impl From<services::Envelope> for Envelope {
+    fn from(envelope: services::Envelope) -> Self {
+        Envelope {
+            sequence: 0,
+            timestamp: protobuf::from_timestamp(envelope.timestamp.as_ref()),
+            namespace: envelope.namespace,
+            topic: envelope.topic,
+            event: envelope.event.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<ttrpc::Envelope> for Envelope {
+    fn from(envelope: ttrpc::Envelope) -> Self {
+        Envelope {
+            sequence: 0,
+            timestamp: protobuf::from_timestamp(envelope.timestamp.as_ref()),
+            namespace: envelope.namespace,
+            topic: envelope.topic,
+            event: envelope.event.unwrap_or_default(),
+        }
+    }
+}
+
+/// Adaptor for envelopes exposes the `namespace`, `topic` and `event.<field>`
+/// field paths to filters, the fields of the event being those of the known
+/// event types.
+impl Adaptor for Envelope {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        let value = match fieldpath.first()?.as_str() {
+            "namespace" => self.namespace.clone(),
+            "topic" => self.topic.clone(),
+            "event" => return self.typed().ok()?.field(&fieldpath[1..]),
+            _ => return None,
+        };
+        Some(value).filter(|v| !v.is_empty())
+    }
+}
+
+/// validate_topic checks that the topic is a path of identifiers, starting
+/// with a slash.
+pub fn validate_topic(topic: &str) -> Result<(), Error> {
+    let components = match topic.strip_prefix('/') {
+        Some(components) if !components.is_empty() => components,
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "topic {:?} must start with a slash and name at least one component",
+                topic
+            )))
+        }
+    };
+    for component in components.split('/') {
+        identifiers::validate(component).map_err(|e| Error::InvalidArgument(format!("topic {:?}: {}", topic, e)))?;
+    }
+    Ok(())
+}
+
+/// validate_envelope checks the namespace, topic and event of the envelope.
+pub fn validate_envelope(envelope: &Envelope) -> Result<(), Error> {
+    identifiers::validate(&envelope.namespace)
+        .map_err(|e| Error::InvalidArgument(format!("event namespace validation: {}", e)))?;
+    validate_topic(&envelope.topic)?;
+    if envelope.event.type_url.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "event of topic {:?} must have a type url",
+            envelope.topic
+        )));
+    }
+    Ok(())
+}
+
+/// Publisher posts the event to the event system under the given topic.
+pub trait Publisher: Send + Sync {
+    fn publish(&self, namespace: &str, topic: &str, event: prost_types::Any) -> Result<(), Error>;
+}
+
+/// NoopPublisher drops all the events it receives.
+pub struct NoopPublisher {}
+
+impl Publisher for NoopPublisher {
+    fn publish(&self, _namespace: &str, _topic: &str, _event: prost_types::Any) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// marshal packs the event into an Any using its type url.
+pub fn marshal<E: Event>(event: &E) -> prost_types::Any {
+    prost_types::Any {
+        type_url: E::TYPE_URL.to_string(),
+        value: event.encode_to_vec(),
+    }
+}
+
+/// decode unpacks the event from the Any, which must hold an event of type E.
+pub fn decode<E: Event>(event: &prost_types::Any) -> Result<E, Error> {
+    if event.type_url != E::TYPE_URL {
+        return Err(Error::InvalidArgument(format!(
+            "event has type {:?}, not {:?}",
+            event.type_url,
+            E::TYPE_URL
+        )));
+    }
+    E::decode(event.value.as_slice())
+        .map_err(|e| Error::InvalidArgument(format!("failed to decode event {}: {}", E::TYPE_URL, e)))
+}
+
+/// Registration links the type url of an event to the topic it is published
+/// on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registration {
+    pub type_url: &'static str,
+    pub topic: &'static str,
+}
+
+/// lookup_type_url returns the registration of the event type.
+pub fn lookup_type_url(type_url: &str) -> Option<&'static Registration> {
+    REGISTRY.iter().find(|r| r.type_url == type_url)
+}
+
+/// lookup_topic returns the registration of the event published on the
+/// topic.
+pub fn lookup_topic(topic: &str) -> Option<&'static Registration> {
+    REGISTRY.iter().find(|r| r.topic == topic)
+}
+
+macro_rules! impl_event {
+    (@type_url $name:ident) => {
+        concat!("containerd.api.events.", stringify!($name))
+    };
+    (@type_url $name:ident $message:literal) => {
+        concat!("containerd.api.events.", $message)
+    };
+    ($($name:ident $(as $message:literal)? => $topic:literal { $($field:ident),* $(; $map:ident)? }),* $(,)?) => {
+        $(
+            impl Event for api::$name {
+                const TYPE_URL: &'static str = impl_event!(@type_url $name $($message)?);
+                const TOPIC: &'static str = $topic;
+            }
+
+            impl Adaptor for api::$name {
+                fn field(&self, fieldpath: &[String]) -> Option<String> {
+                    let value = match fieldpath.first()?.as_str() {
+                        $(stringify!($field) => self.$field.to_string(),)*
+                        $(stringify!($map) => return filters::check_map(&fieldpath[1..], &self.$map),)?
+                        _ => return None,
+                    };
+                    Some(value).filter(|v| !v.is_empty())
+                }
+            }
+        )*
+
+        /// REGISTRY lists the known event types and their topics.
+        pub const REGISTRY: &[Registration] = &[
+            $(Registration {
+                type_url: <api::$name as Event>::TYPE_URL,
+                topic: <api::$name as Event>::TOPIC,
+            },)*
+        ];
+
+        /// TypedEvent is an event decoded into the message of its type.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum TypedEvent {
+            $($name(api::$name),)*
+        }
+
+        impl TypedEvent {
+            /// decode unpacks the event from the Any according to its type
+            /// url. NotFound is returned for unknown event types.
+            pub fn decode(event: &prost_types::Any) -> Result<TypedEvent, Error> {
+                $(
+                    if event.type_url == <api::$name as Event>::TYPE_URL {
+                        return decode::<api::$name>(event).map(TypedEvent::$name);
+                    }
+                )*
+                Err(Error::NotFound(format!("event type {:?}", event.type_url)))
+            }
+
+            /// type_url returns the type url of the event.
+            pub fn type_url(&self) -> &'static str {
+                match self {
+                    $(TypedEvent::$name(_) => <api::$name as Event>::TYPE_URL,)*
+                }
+            }
+
+            /// topic returns the topic the event is published on.
+            pub fn topic(&self) -> &'static str {
+                match self {
+                    $(TypedEvent::$name(_) => <api::$name as Event>::TOPIC,)*
+                }
+            }
+
+            /// marshal packs the event into an Any using its type url.
+            pub fn marshal(&self) -> prost_types::Any {
+                match self {
+                    $(TypedEvent::$name(e) => marshal(e),)*
+                }
+            }
+        }
+
+        $(
+            impl From<api::$name> for TypedEvent {
+                fn from(event: api::$name) -> Self {
+                    TypedEvent::$name(event)
+                }
+            }
+        )*
+
+        impl Adaptor for TypedEvent {
+            fn field(&self, fieldpath: &[String]) -> Option<String> {
+                match self {
+                    $(TypedEvent::$name(e) => e.field(fieldpath),)*
+                }
+            }
+        }
+    };
+}
+
+impl_event!(
+    ContainerCreate => "/containers/create" { id, image },
+    ContainerUpdate => "/containers/update" { id, image, snapshot_key; labels },
+    ContainerDelete => "/containers/delete" { id },
+    ContentDelete => "/content/delete" { digest },
+    ImageCreate => "/images/create" { name; labels },
+    ImageUpdate => "/images/update" { name; labels },
+    ImageDelete => "/images/delete" { name },
+    NamespaceCreate => "/namespaces/create" { name; labels },
+    NamespaceUpdate => "/namespaces/update" { name; labels },
+    NamespaceDelete => "/namespaces/delete" { name },
+    SnapshotPrepare => "/snapshot/prepare" { key, parent, snapshotter },
+    SnapshotCommit => "/snapshot/commit" { key, name, snapshotter },
+    SnapshotRemove => "/snapshot/remove" { key, snapshotter },
+    TaskCreate => "/tasks/create" { container_id, bundle, checkpoint, pid },
+    TaskStart => "/tasks/start" { container_id, pid },
+    TaskDelete => "/tasks/delete" { container_id, id, pid, exit_status },
+    TaskExit => "/tasks/exit" { container_id, id, pid, exit_status },
+    // prost renames the TaskOOM message, its type url keeps the protobuf name
+    TaskOom as "TaskOOM" => "/tasks/oom" { container_id },
+    TaskExecAdded => "/tasks/exec-added" { container_id, exec_id },
+    TaskExecStarted => "/tasks/exec-started" { container_id, exec_id, pid },
+    TaskPaused => "/tasks/paused" { container_id },
+    TaskResumed => "/tasks/resumed" { container_id },
+    TaskCheckpointed => "/tasks/checkpointed" { container_id, checkpoint },
+);
@@ -0,0 +1,374 @@
+use super::journal::{Journal, Position};
+use super::{marshal, validate_envelope, Envelope, Event, Publisher};
+use crate::errdefs::Error;
+use crate::filters::{self, Filter};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// SlowConsumerPolicy decides what happens to the events published while the
+/// buffer of a subscriber is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Drop the events the subscriber has no room for, they are counted by
+    /// `Subscription::dropped`.
+    Drop,
+    /// Disconnect the subscriber, it receives the events buffered so far and
+    /// then the error it was disconnected with.
+    Disconnect,
+}
+
+/// SubscribeOpts configures a subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscribeOpts {
+    /// buffer is the number of events kept for the subscriber until it
+    /// receives them.
+    pub buffer: usize,
+    pub policy: SlowConsumerPolicy,
+    /// from replays the journaled events from the position before the live
+    /// ones, it requires the exchange to have a journal.
+    pub from: Option<Position>,
+}
+
+impl Default for SubscribeOpts {
+    fn default() -> Self {
+        SubscribeOpts {
+            buffer: 128,
+            policy: SlowConsumerPolicy::Drop,
+            from: None,
+        }
+    }
+}
+
+/// Exchange broadcasts the events published to it to the subscribers whose
+/// filters match them.
+///
+/// Publishing never blocks on subscribers: every subscriber has a bounded
+/// buffer and its policy decides what happens once the buffer is full.
+///
+/// With a journal, every event is appended to it before it is broadcast so
+/// that subscribers can resume from where they left off.
+#[derive(Default)]
+pub struct Exchange {
+    subscribers: Mutex<Vec<Subscriber>>,
+    next_id: AtomicU64,
+    journal: Option<Arc<Journal>>,
+}
+
+struct Subscriber {
+    id: u64,
+    filter: Filter,
+    tx: SyncSender<Envelope>,
+    opts: SubscribeOpts,
+    state: Arc<State>,
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.state.disconnected.store(true, Ordering::Release);
+        self.state.notify.notify_one();
+    }
+}
+
+/// State is shared by a subscriber and its subscription.
+#[derive(Default)]
+struct State {
+    dropped: AtomicU64,
+    // set once the subscription is dropped
+    unsubscribed: AtomicBool,
+    // the reason the exchange disconnected the subscriber
+    closed: Mutex<Option<Error>>,
+    // set once the exchange dropped the subscriber
+    disconnected: AtomicBool,
+    // notified when an event is sent to the subscription or the subscriber
+    // is dropped
+    notify: Notify,
+}
+
+impl Exchange {
+    pub fn new() -> Exchange {
+        Exchange::default()
+    }
+
+    /// with_journal returns the exchange journaling its events.
+    pub fn with_journal(mut self, journal: Journal) -> Exchange {
+        self.journal = Some(Arc::new(journal));
+        self
+    }
+
+    /// journal returns the journal of the exchange, if it has one.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_deref()
+    }
+
+    /// publish_event packs the event and publishes it under the topic.
+    pub fn publish_event<E: Event>(&self, namespace: &str, topic: &str, event: &E) -> Result<(), Error> {
+        self.publish(namespace, topic, marshal(event))
+    }
+
+    /// forward broadcasts an envelope that was already built, for example by
+    /// a shim, as is.
+    pub fn forward(&self, mut envelope: Envelope) -> Result<(), Error> {
+        validate_envelope(&envelope)?;
+
+        // journaling under the lock keeps the journal and the live events in
+        // the same order
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(journal) = &self.journal {
+            journal.append(&mut envelope)?;
+        }
+        broadcast(&mut subscribers, envelope);
+        Ok(())
+    }
+
+    /// subscribe returns a subscription to the events matching one or more
+    /// of the filters, every event if there are none. Filters apply to the
+    /// `namespace`, `topic` and `event.<field>` field paths of the envelopes.
+    ///
+    /// The journaled events matching the filters from `opts.from` on are
+    /// received first, followed by the live events without gap. They are
+    /// read from the journal as they are received, `opts.buffer` at a time,
+    /// while the live events published meanwhile are buffered as usual.
+    pub fn subscribe(&self, filters: &[&str], opts: SubscribeOpts) -> Result<Subscription, Error> {
+        if opts.buffer == 0 {
+            return Err(Error::InvalidArgument(
+                "subscription buffer must hold at least one event".to_string(),
+            ));
+        }
+        let filter = filters::parse_all(filters)?;
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let replay = match (opts.from, &self.journal) {
+            (None, _) => None,
+            // events are journaled under the lock, the ones from the next
+            // sequence number on are live
+            (Some(from), Some(journal)) => Some(Replay {
+                journal: journal.clone(),
+                filter: filter.clone(),
+                from,
+                end: journal.next(),
+                page: opts.buffer,
+                pending: VecDeque::new(),
+                done: false,
+            }),
+            (Some(_), None) => {
+                return Err(Error::FailedPrecondition(
+                    "cannot replay events of an exchange without journal".to_string(),
+                ))
+            }
+        };
+
+        let (tx, rx) = mpsc::sync_channel(opts.buffer);
+        let state = Arc::new(State::default());
+        subscribers.push(Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            filter,
+            tx,
+            opts,
+            state: state.clone(),
+        });
+
+        Ok(Subscription {
+            rx: Mutex::new(rx),
+            replay: Mutex::new(replay),
+            state,
+        })
+    }
+
+    /// subscribers returns the number of active subscribers.
+    pub fn subscribers(&self) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| !s.state.unsubscribed.load(Ordering::Relaxed));
+        subscribers.len()
+    }
+}
+
+fn broadcast(subscribers: &mut Vec<Subscriber>, envelope: Envelope) {
+    subscribers.retain(|s| {
+        if s.state.unsubscribed.load(Ordering::Relaxed) {
+            return false;
+        }
+        if !s.filter.matches(&envelope) {
+            return true;
+        }
+
+        match s.tx.try_send(envelope.clone()) {
+            Ok(()) => {
+                s.state.notify.notify_one();
+                true
+            }
+            // the subscription was dropped
+            Err(TrySendError::Disconnected(_)) => false,
+            Err(TrySendError::Full(_)) => match s.opts.policy {
+                SlowConsumerPolicy::Drop => {
+                    let dropped = s.state.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    log::warn!(
+                        "subscriber {} is too slow, dropped event {} ({} dropped so far)",
+                        s.id,
+                        envelope.topic,
+                        dropped
+                    );
+                    true
+                }
+                SlowConsumerPolicy::Disconnect => {
+                    log::warn!(
+                        "subscriber {} is too slow, disconnecting it at event {}",
+                        s.id,
+                        envelope.topic
+                    );
+                    *s.state.closed.lock().unwrap() = Some(Error::Unavailable(format!(
+                        "subscriber disconnected: buffer of {} events full at event {}",
+                        s.opts.buffer, envelope.topic
+                    )));
+                    false
+                }
+            },
+        }
+    });
+}
+
+impl Publisher for Exchange {
+    fn publish(&self, namespace: &str, topic: &str, event: prost_types::Any) -> Result<(), Error> {
+        self.forward(Envelope::new(namespace, topic, event))
+    }
+}
+
+/// Subscription receives the events of a subscriber, dropping it
+/// unsubscribes.
+pub struct Subscription {
+    // locked so that recv_async can be awaited from any tokio task, a
+    // subscription has a single reader
+    rx: Mutex<Receiver<Envelope>>,
+    // journaled events, received before the live ones
+    replay: Mutex<Option<Replay>>,
+    state: Arc<State>,
+}
+
+impl Subscription {
+    /// recv waits for the next event. An error is returned once the
+    /// subscriber was disconnected and its buffered events were received.
+    pub fn recv(&self) -> Result<Envelope, Error> {
+        if let Some(envelope) = self.replayed()? {
+            return Ok(envelope);
+        }
+        self.rx.lock().unwrap().recv().map_err(|_| self.closed())
+    }
+
+    /// recv_timeout waits for the next event up to the timeout, None is
+    /// returned if there was none.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Envelope>, Error> {
+        if let Some(envelope) = self.replayed()? {
+            return Ok(Some(envelope));
+        }
+        let result = self.rx.lock().unwrap().recv_timeout(timeout);
+        match result {
+            Ok(envelope) => Ok(Some(envelope)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(self.closed()),
+        }
+    }
+
+    /// try_recv returns the next event if one is buffered.
+    pub fn try_recv(&self) -> Result<Option<Envelope>, Error> {
+        if let Some(envelope) = self.replayed()? {
+            return Ok(Some(envelope));
+        }
+        let result = self.rx.lock().unwrap().try_recv();
+        match result {
+            Ok(envelope) => Ok(Some(envelope)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(self.closed()),
+        }
+    }
+
+    /// recv_async waits for the next event without blocking the thread. An
+    /// error is returned once the subscriber was disconnected and its
+    /// buffered events were received.
+    pub async fn recv_async(&self) -> Result<Envelope, Error> {
+        loop {
+            if let Some(envelope) = self.try_recv()? {
+                return Ok(envelope);
+            }
+            if self.state.disconnected.load(Ordering::Acquire) {
+                // the events sent before the disconnection are buffered
+                return self.try_recv()?.ok_or_else(|| self.closed());
+            }
+            self.state.notify.notified().await;
+        }
+    }
+
+    /// dropped returns the number of events dropped because the buffer of
+    /// the subscriber was full.
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+
+    fn replayed(&self) -> Result<Option<Envelope>, Error> {
+        let mut replay = self.replay.lock().unwrap();
+        let envelope = match replay.as_mut() {
+            Some(replay) => replay.next()?,
+            None => return Ok(None),
+        };
+        if envelope.is_none() {
+            *replay = None;
+        }
+        Ok(envelope)
+    }
+
+    fn closed(&self) -> Error {
+        match &*self.state.closed.lock().unwrap() {
+            Some(e) => e.clone(),
+            None => Error::Unavailable("event exchange closed".to_string()),
+        }
+    }
+}
+
+/// Replay reads the journaled events of a subscription a page at a time, up
+/// to its first live event.
+struct Replay {
+    journal: Arc<Journal>,
+    filter: Filter,
+    from: Position,
+    // sequence number of the first live event
+    end: u64,
+    page: usize,
+    pending: VecDeque<Envelope>,
+    done: bool,
+}
+
+impl Replay {
+    /// next returns the next journaled event matching the filter, None once
+    /// they were all returned.
+    fn next(&mut self) -> Result<Option<Envelope>, Error> {
+        loop {
+            if let Some(envelope) = self.pending.pop_front() {
+                return Ok(Some(envelope));
+            }
+            if self.done {
+                return Ok(None);
+            }
+
+            let page = self.journal.read_page(self.from, self.page)?;
+            self.done = page.len() < self.page;
+            for envelope in page {
+                if envelope.sequence >= self.end {
+                    self.done = true;
+                    break;
+                }
+                self.from = Position::Sequence(envelope.sequence + 1);
+                if self.filter.matches(&envelope) {
+                    self.pending.push_back(envelope);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.state.unsubscribed.store(true, Ordering::Relaxed);
+    }
+}
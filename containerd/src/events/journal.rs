@@ -0,0 +1,483 @@
+use super::Envelope;
+use crate::errdefs::Error;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// MAGIC starts every segment file.
+const MAGIC: &[u8; 8] = b"ctrdevts";
+
+/// FORMAT_VERSION is the version of the segment encoding.
+const FORMAT_VERSION: u32 = 1;
+
+const HEADER_SIZE: u64 = MAGIC.len() as u64 + 4;
+
+const SEGMENT_EXTENSION: &str = "log";
+
+/// JournalOpts configures the rotation and retention of a journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalOpts {
+    /// segment_size is the size after which the active segment is closed
+    /// and a new one started.
+    pub segment_size: u64,
+    /// max_age removes the segments whose newest event is older.
+    pub max_age: Option<Duration>,
+    /// max_size removes the oldest segments while the journal is larger.
+    pub max_size: Option<u64>,
+}
+
+impl Default for JournalOpts {
+    fn default() -> Self {
+        JournalOpts {
+            segment_size: 16 << 20,
+            max_age: None,
+            max_size: None,
+        }
+    }
+}
+
+/// Position locates the first event to read from a journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    /// Sequence starts at the event with the sequence number.
+    Sequence(u64),
+    /// Timestamp starts at the first event published at or after the time.
+    Timestamp(OffsetDateTime),
+}
+
+/// Journal is an append-only log of envelopes on disk.
+///
+/// Every envelope is assigned the next sequence number, starting at 1, and is
+/// synced to disk before append returns. The journal is split in segments
+/// named after the sequence number of their first event; once the active
+/// segment grows past the segment size a new one is started, and whole
+/// segments are removed by the retention policy, never the active one.
+///
+/// A torn record at the end of the active segment, left by a crash in the
+/// middle of an append, is truncated when the journal is opened.
+pub struct Journal {
+    dir: PathBuf,
+    opts: JournalOpts,
+    inner: Mutex<Inner>,
+    // holds the lock on the directory for as long as the journal is open
+    _lock: File,
+}
+
+struct Inner {
+    // segments in order, the last one being the active segment
+    segments: Vec<Segment>,
+    active: File,
+    next: u64,
+    // set when a failed append could not be rolled back, the active segment
+    // may end with a torn record which further appends must not follow
+    poisoned: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    path: PathBuf,
+    first: u64,
+    size: u64,
+    // publication time of the newest event of the segment
+    newest: Option<OffsetDateTime>,
+}
+
+impl Journal {
+    /// open opens the journal in dir, creating it if it does not exist.
+    ///
+    /// The journal can only be opened once at a time, Unavailable is
+    /// returned while another Journal holds it.
+    pub fn open<P: AsRef<Path>>(dir: P, opts: JournalOpts) -> Result<Journal, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join("LOCK"))?;
+        // SAFETY: the descriptor is owned by lock and stays open with it.
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(Error::Unavailable(format!("event journal {} is in use", dir.display())));
+        }
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let first = match path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+                Some(first) => first,
+                None => continue,
+            };
+            segments.push(Segment {
+                path,
+                first,
+                size: 0,
+                newest: None,
+            });
+        }
+        segments.sort_by_key(|s| s.first);
+
+        let mut next = 1;
+        let count = segments.len();
+        for (i, segment) in segments.iter_mut().enumerate() {
+            let p = fs::read(&segment.path)?;
+            let scan = scan(&p);
+            if let Some(e) = &scan.err {
+                if i + 1 < count {
+                    return Err(corrupt(&segment.path, e));
+                }
+                // only the active segment can be torn by a crash
+                log::warn!(
+                    "event journal segment {} is torn after {} bytes, truncating it: {}",
+                    segment.path.display(),
+                    scan.valid,
+                    e
+                );
+                let f = OpenOptions::new().write(true).open(&segment.path)?;
+                f.set_len(scan.valid as u64)?;
+                f.sync_all()?;
+            }
+            segment.size = scan.valid as u64;
+            segment.newest = scan.records.last().map(|e| e.timestamp);
+            next = scan
+                .records
+                .last()
+                .map(|e| e.sequence + 1)
+                .unwrap_or(segment.first)
+                .max(next);
+        }
+
+        let active = match segments.last() {
+            Some(segment) if segment.size >= HEADER_SIZE => OpenOptions::new().append(true).open(&segment.path)?,
+            _ => {
+                // an empty journal, or a segment whose header was never written
+                segments.pop();
+                let (segment, f) = create_segment(&dir, next)?;
+                segments.push(segment);
+                f
+            }
+        };
+
+        Ok(Journal {
+            dir,
+            opts,
+            inner: Mutex::new(Inner {
+                segments,
+                active,
+                next,
+                poisoned: None,
+            }),
+            _lock: lock,
+        })
+    }
+
+    /// dir returns the directory of the journal.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// append adds the envelope to the journal and sets its sequence number.
+    ///
+    /// A record which failed to be written is truncated from the active
+    /// segment. If that fails too, the journal refuses further appends until
+    /// it is opened again, which truncates the torn record.
+    pub fn append(&self, envelope: &mut Envelope) -> Result<u64, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(e) = &inner.poisoned {
+            return Err(Error::Unavailable(format!(
+                "event journal {} failed to roll back an append: {}",
+                self.dir.display(),
+                e
+            )));
+        }
+
+        let size = inner.segments.last().unwrap().size;
+        if size > HEADER_SIZE && size >= self.opts.segment_size {
+            let (segment, f) = create_segment(&self.dir, inner.next)?;
+            inner.segments.push(segment);
+            inner.active = f;
+        }
+
+        let seq = inner.next;
+        let record = encode_record(seq, envelope);
+        let size = inner.segments.last().unwrap().size;
+        if let Err(e) = write_record(&mut inner.active, &record) {
+            if let Err(truncate) = inner.active.set_len(size).and_then(|()| inner.active.sync_data()) {
+                log::error!(
+                    "failed to truncate event journal segment {} to {} bytes: {}",
+                    inner.segments.last().unwrap().path.display(),
+                    size,
+                    truncate
+                );
+                inner.poisoned = Some(truncate.to_string());
+            }
+            return Err(e.into());
+        }
+        inner.next += 1;
+        let segment = inner.segments.last_mut().unwrap();
+        segment.size += record.len() as u64;
+        segment.newest = Some(envelope.timestamp);
+        envelope.sequence = seq;
+
+        self.retain(&mut inner)?;
+        Ok(seq)
+    }
+
+    /// next returns the sequence number the next event will get.
+    pub fn next(&self) -> u64 {
+        self.inner.lock().unwrap().next
+    }
+
+    /// first returns the sequence number of the oldest event kept.
+    pub fn first(&self) -> u64 {
+        self.inner.lock().unwrap().segments[0].first
+    }
+
+    /// prune applies the retention policy, which is otherwise applied on
+    /// every append.
+    pub fn prune(&self) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        self.retain(&mut inner)
+    }
+
+    /// read returns the events from the position on, in order. Reading from
+    /// before the oldest event kept starts at the oldest event, the gap
+    /// shows in the sequence numbers.
+    pub fn read(&self, from: Position) -> Result<Vec<Envelope>, Error> {
+        self.read_page(from, usize::MAX)
+    }
+
+    /// read_page returns at most limit events from the position on, in
+    /// order, like read. Only the segments holding them are read.
+    pub fn read_page(&self, from: Position, limit: usize) -> Result<Vec<Envelope>, Error> {
+        let inner = self.inner.lock().unwrap();
+
+        let mut envelopes = Vec::new();
+        let mut started = false;
+        for (i, segment) in inner.segments.iter().enumerate() {
+            if envelopes.len() >= limit {
+                break;
+            }
+            if let Position::Sequence(seq) = from {
+                // the next segment starts at or before the sequence number
+                if inner.segments.get(i + 1).is_some_and(|next| next.first <= seq) {
+                    continue;
+                }
+            }
+
+            let p = fs::read(&segment.path)?;
+            let scan = scan(&p[..segment.size as usize]);
+            if let Some(e) = scan.err {
+                return Err(corrupt(&segment.path, &e));
+            }
+            for e in scan.records {
+                started = started
+                    || match from {
+                        Position::Sequence(seq) => e.sequence >= seq,
+                        Position::Timestamp(t) => e.timestamp >= t,
+                    };
+                if started {
+                    envelopes.push(e);
+                }
+            }
+        }
+
+        envelopes.truncate(limit);
+        Ok(envelopes)
+    }
+
+    /// size returns the size of the segments on disk.
+    pub fn size(&self) -> u64 {
+        self.inner.lock().unwrap().segments.iter().map(|s| s.size).sum()
+    }
+
+    /// retain removes the oldest segments as long as they are past the
+    /// retention policy.
+    fn retain(&self, inner: &mut Inner) -> Result<(), Error> {
+        let expired = self.opts.max_age.map(|age| OffsetDateTime::now_utc() - age);
+        while inner.segments.len() > 1 {
+            let oldest = &inner.segments[0];
+            let too_old = match (expired, oldest.newest) {
+                (Some(expired), Some(newest)) => newest < expired,
+                _ => false,
+            };
+            let too_large = self
+                .opts
+                .max_size
+                .is_some_and(|max| inner.segments.iter().map(|s| s.size).sum::<u64>() > max);
+            if !too_old && !too_large {
+                break;
+            }
+
+            let oldest = inner.segments.remove(0);
+            log::debug!(
+                "removing event journal segment {} of events {}..{}",
+                oldest.path.display(),
+                oldest.first,
+                inner.segments[0].first
+            );
+            fs::remove_file(&oldest.path)?;
+        }
+        Ok(())
+    }
+}
+
+fn corrupt(path: &Path, msg: &str) -> Error {
+    Error::Unknown(format!("event journal segment {}: {}", path.display(), msg))
+}
+
+/// create_segment creates the segment starting at the sequence number.
+fn create_segment(dir: &Path, first: u64) -> Result<(Segment, File), Error> {
+    let path = dir.join(format!("{:020}.{}", first, SEGMENT_EXTENSION));
+    let mut f = OpenOptions::new().create(true).truncate(true).write(true).open(&path)?;
+    f.write_all(MAGIC)?;
+    f.write_all(&FORMAT_VERSION.to_be_bytes())?;
+    f.sync_all()?;
+    File::open(dir)?.sync_all()?;
+
+    let f = OpenOptions::new().append(true).open(&path)?;
+    let segment = Segment {
+        path,
+        first,
+        size: HEADER_SIZE,
+        newest: None,
+    };
+    Ok((segment, f))
+}
+
+/// write_record appends the record to the active segment and syncs it.
+fn write_record(f: &mut File, record: &[u8]) -> std::io::Result<()> {
+    f.write_all(record)?;
+    f.sync_data()
+}
+
+/// encode_record returns the record of the event: the length of the body,
+/// the body and the sha256 checksum of the body.
+fn encode_record(seq: u64, envelope: &Envelope) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&seq.to_be_bytes());
+    body.extend_from_slice(&envelope.timestamp.unix_timestamp_nanos().to_be_bytes());
+    for field in [
+        envelope.namespace.as_bytes(),
+        envelope.topic.as_bytes(),
+        envelope.event.type_url.as_bytes(),
+        &envelope.event.value,
+    ] {
+        body.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        body.extend_from_slice(field);
+    }
+
+    let mut record = (body.len() as u32).to_be_bytes().to_vec();
+    record.extend_from_slice(&body);
+    record.extend_from_slice(&Sha256::digest(&body));
+    record
+}
+
+/// Scan holds the records of a segment up to the first invalid one.
+struct Scan {
+    records: Vec<Envelope>,
+    // length of the valid records, including the header
+    valid: usize,
+    err: Option<String>,
+}
+
+fn scan(p: &[u8]) -> Scan {
+    let mut scan = Scan {
+        records: Vec::new(),
+        valid: 0,
+        err: None,
+    };
+    if p.len() < HEADER_SIZE as usize || &p[..MAGIC.len()] != MAGIC {
+        scan.err = Some("not an event journal segment".to_string());
+        return scan;
+    }
+    let version = u32::from_be_bytes(p[MAGIC.len()..HEADER_SIZE as usize].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        scan.err = Some(format!("unsupported format version {}", version));
+        return scan;
+    }
+
+    scan.valid = HEADER_SIZE as usize;
+    while scan.valid < p.len() {
+        match decode_record(&p[scan.valid..]) {
+            Ok((n, envelope)) => {
+                scan.records.push(envelope);
+                scan.valid += n;
+            }
+            Err(e) => {
+                scan.err = Some(e);
+                break;
+            }
+        }
+    }
+    scan
+}
+
+/// decode_record decodes the record at the start of p and returns its length
+/// with the event.
+fn decode_record(p: &[u8]) -> Result<(usize, Envelope), String> {
+    let mut d = Decoder { p };
+    let n = d.u32()? as usize;
+    let body = d.take(n)?;
+    let sum = d.take(32)?;
+    if Sha256::digest(body).as_slice() != sum {
+        return Err("checksum mismatch".to_string());
+    }
+
+    let mut d = Decoder { p: body };
+    let seq = u64::from_be_bytes(d.take(8)?.try_into().unwrap());
+    let nanos = i128::from_be_bytes(d.take(16)?.try_into().unwrap());
+    let timestamp = OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|e| e.to_string())?;
+    let namespace = d.string()?;
+    let topic = d.string()?;
+    let type_url = d.string()?;
+    let value = d.bytes()?.to_vec();
+    if !d.p.is_empty() {
+        return Err("trailing data in record".to_string());
+    }
+
+    let envelope = Envelope {
+        sequence: seq,
+        timestamp,
+        namespace,
+        topic,
+        event: prost_types::Any { type_url, value },
+    };
+    Ok((4 + n + 32, envelope))
+}
+
+struct Decoder<'a> {
+    p: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.p.len() < n {
+            return Err("unexpected end of data".to_string());
+        }
+        let (head, tail) = self.p.split_at(n);
+        self.p = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let n = self.u32()? as usize;
+        self.take(n)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,334 @@
+//! Filters match objects by the values of their fields, with the filter
+//! syntax of containerd.
+//!
+//! A filter is a comma separated list of selectors, which must all match:
+//!
+//! ```text
+//! filter    := selector ( ',' selector )*
+//! selector  := fieldpath [ operator value ]
+//! fieldpath := field ( '.' field )*
+//! field     := quoted | [A-Za-z] [A-Za-z0-9_]*
+//! operator  := '==' | '!=' | '~='
+//! value     := quoted | [^\s,]+
+//! quoted    := '"' ... '"' | "'" ... "'" | '`' ... '`'
+//! ```
+//!
+//! A selector without operator matches when the field is present, `==` and
+//! `!=` compare the value of the field and `~=` matches it against a regular
+//! expression. Double and single quoted strings take the escapes of Go
+//! strings, backquoted strings are raw.
+//!
+//! Some examples:
+//!
+//! ```text
+//! labels."com.example.owner"==ops
+//! image~=^docker.io/library/,labels.tier!=gold
+//! snapshot_key
+//! ```
+
+use super::errdefs::Error;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Adaptor specifies the mapping of fieldpaths to a type. For the given field
+/// path, the value and whether it is present should be returned. The mapping of
+/// the fieldpath to a field is deferred to the adaptor implementation, but
+/// should generally follow protobuf field path/mask semantics.
+pub trait Adaptor {
+    fn field(&self, fieldpath: &[String]) -> Option<String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Present,
+    Equal,
+    NotEqual,
+    Matches,
+}
+
+#[derive(Debug, Clone)]
+struct Selector {
+    fieldpath: Vec<String>,
+    operator: Operator,
+    value: String,
+    // compiled value of the Matches operator
+    re: Option<Regex>,
+}
+
+impl Selector {
+    fn matches(&self, adaptor: &dyn Adaptor) -> bool {
+        let value = adaptor.field(&self.fieldpath);
+        match self.operator {
+            Operator::Present => value.is_some(),
+            Operator::Equal => value.as_deref() == Some(self.value.as_str()),
+            Operator::NotEqual => value.unwrap_or_default() != self.value,
+            Operator::Matches => match &self.re {
+                Some(re) => re.is_match(&value.unwrap_or_default()),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Filter matches specific resources based the provided filters.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    // selectors, which must all match for one of the filters
+    any: Vec<Vec<Selector>>,
+}
+
+impl Filter {
+    pub fn matches(&self, adaptor: &dyn Adaptor) -> bool {
+        if self.any.is_empty() {
+            return true;
+        }
+
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|selector| selector.matches(adaptor)))
+    }
+}
+
+/// parse parses a single filter.
+pub fn parse(s: &str) -> Result<Filter, Error> {
+    let all = Parser::new(s)
+        .filter()
+        .map_err(|e| Error::InvalidArgument(format!("filters: {}", e)))?;
+    Ok(Filter { any: vec![all] })
+}
+
+/// parse_all parses each filter in ss and returns a filter that will return
+/// true if any filter matches the expression.
+///
+/// If no filters are provided, the filter will match anything.
+pub fn parse_all(ss: &[&str]) -> Result<Filter, Error> {
+    let mut any = Vec::with_capacity(ss.len());
+    for s in ss {
+        any.extend(parse(s)?.any);
+    }
+
+    Ok(Filter { any })
+}
+
+/// check_map looks up the remaining fieldpath as a key in m. Keys containing
+/// dots are addressed as separate fields.
+pub fn check_map(fieldpath: &[String], m: &HashMap<String, String>) -> Option<String> {
+    if fieldpath.is_empty() {
+        return None;
+    }
+
+    m.get(&fieldpath.join(".")).cloned()
+}
+
+/// ParseError locates the character of the input a filter failed to parse
+/// at.
+#[derive(Debug)]
+struct ParseError {
+    input: String,
+    pos: usize,
+    msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.input[self.pos..].chars().next() {
+            Some(c) => {
+                let after = self.pos + c.len_utf8();
+                write!(
+                    f,
+                    "parse error: [{} >|{}|< {}]: {}",
+                    &self.input[..self.pos],
+                    c,
+                    &self.input[after..],
+                    self.msg
+                )
+            }
+            None => write!(f, "parse error: [{}]: {}", self.input, self.msg),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    // byte offset of the next character
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Parser<'a> {
+        Parser { input, pos: 0 }
+    }
+
+    fn error(&self, pos: usize, msg: String) -> ParseError {
+        ParseError {
+            input: self.input.to_string(),
+            pos,
+            msg,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn filter(&mut self) -> Result<Vec<Selector>, ParseError> {
+        let mut all = vec![self.selector()?];
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Ok(all),
+                Some(',') => {
+                    self.bump();
+                    all.push(self.selector()?);
+                }
+                Some(c) => return Err(self.error(self.pos, format!("expected a selector separator, got {:?}", c))),
+            }
+        }
+    }
+
+    fn selector(&mut self) -> Result<Selector, ParseError> {
+        let fieldpath = self.fieldpath()?;
+
+        self.skip_whitespace();
+        if matches!(self.peek(), None | Some(',')) {
+            return Ok(Selector {
+                fieldpath,
+                operator: Operator::Present,
+                value: String::new(),
+                re: None,
+            });
+        }
+
+        let operator = self.operator()?;
+        self.skip_whitespace();
+        let start = self.pos;
+        let value = self.value()?;
+        let re = match operator {
+            Operator::Matches => {
+                Some(Regex::new(&value).map_err(|e| self.error(start, format!("invalid regular expression: {}", e)))?)
+            }
+            _ => None,
+        };
+
+        Ok(Selector {
+            fieldpath,
+            operator,
+            value,
+            re,
+        })
+    }
+
+    fn fieldpath(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut fieldpath = vec![self.field()?];
+        while self.peek() == Some('.') {
+            self.bump();
+            fieldpath.push(self.field()?);
+        }
+        Ok(fieldpath)
+    }
+
+    fn field(&mut self) -> Result<String, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if is_quote(c) => self.quoted(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.bump();
+                }
+                Ok(self.input[start..self.pos].to_string())
+            }
+            Some(c) => Err(self.error(self.pos, format!("expected a field or a quoted string, got {:?}", c))),
+            None => Err(self.error(self.pos, "expected a field or a quoted string".to_string())),
+        }
+    }
+
+    fn operator(&mut self) -> Result<Operator, ParseError> {
+        let operator = match self.input[self.pos..].get(..2) {
+            Some("==") => Operator::Equal,
+            Some("!=") => Operator::NotEqual,
+            Some("~=") => Operator::Matches,
+            _ => return Err(self.error(self.pos, "expected an operator (\"==\", \"!=\" or \"~=\")".to_string())),
+        };
+        self.pos += 2;
+        Ok(operator)
+    }
+
+    fn value(&mut self) -> Result<String, ParseError> {
+        if self.peek().is_some_and(is_quote) {
+            return self.quoted();
+        }
+
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c != ',' && !c.is_whitespace()) {
+            self.bump();
+        }
+        if start == self.pos {
+            return Err(self.error(start, "expected a value or a quoted string".to_string()));
+        }
+        Ok(self.input[start..self.pos].to_string())
+    }
+
+    /// quoted returns the unquoted content of the quoted string at the
+    /// current position.
+    fn quoted(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        let quote = self.bump().unwrap();
+        let mut s = String::new();
+        loop {
+            let pos = self.pos;
+            match self.bump() {
+                None => return Err(self.error(start, "quoted string not terminated".to_string())),
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') if quote != '`' => s.push(self.escape(pos)?),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// escape returns the character of the escape sequence following the
+    /// backslash at pos.
+    fn escape(&mut self, pos: usize) -> Result<char, ParseError> {
+        let invalid = |p: &Parser| p.error(pos, "invalid escape sequence".to_string());
+        let c = match self.bump().ok_or_else(|| invalid(self))? {
+            'a' => '\u{07}',
+            'b' => '\u{08}',
+            'f' => '\u{0c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'v' => '\u{0b}',
+            c @ ('\\' | '"' | '\'' | '/') => c,
+            c @ ('x' | 'u' | 'U') => {
+                let n = match c {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let digits = self.input[self.pos..].get(..n).ok_or_else(|| invalid(self))?;
+                let code = u32::from_str_radix(digits, 16).map_err(|_| invalid(self))?;
+                self.pos += n;
+                char::from_u32(code).ok_or_else(|| invalid(self))?
+            }
+            _ => return Err(invalid(self)),
+        };
+        Ok(c)
+    }
+}
+
+fn is_quote(c: char) -> bool {
+    matches!(c, '"' | '\'' | '`')
+}
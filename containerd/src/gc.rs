@@ -0,0 +1,168 @@
+pub mod scheduler;
+
+use super::errdefs::Error;
+use super::labels;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// ResourceType is the type of a resource tracked by the garbage collector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ResourceType {
+    Content,
+    Snapshot,
+}
+
+/// Node is a resource of a namespace in the reference graph.
+///
+/// Content is keyed by digest, snapshots by `<snapshotter>/<key>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Node {
+    pub kind: ResourceType,
+    pub namespace: String,
+    pub key: String,
+}
+
+impl Node {
+    pub fn content(namespace: &str, digest: &str) -> Node {
+        Node {
+            kind: ResourceType::Content,
+            namespace: namespace.to_string(),
+            key: digest.to_string(),
+        }
+    }
+
+    pub fn snapshot(namespace: &str, snapshotter: &str, key: &str) -> Node {
+        Node {
+            kind: ResourceType::Snapshot,
+            namespace: namespace.to_string(),
+            key: format!("{}/{}", snapshotter, key),
+        }
+    }
+
+    /// snapshot_key splits the key of a snapshot node into the snapshotter
+    /// and the snapshot key.
+    pub fn snapshot_key(&self) -> Option<(&str, &str)> {
+        match self.kind {
+            ResourceType::Snapshot => self.key.split_once('/'),
+            ResourceType::Content => None,
+        }
+    }
+}
+
+/// Stats are the statistics of a garbage collection run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// mark is the time spent finding the referenced resources.
+    pub mark: Duration,
+    /// sweep is the time spent removing the unreferenced resources.
+    pub sweep: Duration,
+    pub content_deleted: usize,
+    pub snapshots_deleted: usize,
+    /// leases_deleted is the number of expired leases removed.
+    pub leases_deleted: usize,
+}
+
+impl Stats {
+    /// elapsed returns the duration of the whole run.
+    pub fn elapsed(&self) -> Duration {
+        self.mark + self.sweep
+    }
+}
+
+/// Collector removes the resources that are no longer referenced.
+pub trait Collector: Send + Sync {
+    /// garbage_collect runs a full collection.
+    fn garbage_collect(&self) -> Result<Stats, Error>;
+}
+
+/// MutationCallback is called after a store changed, dirty being set when the
+/// change removed references so that resources may need to be collected.
+pub type MutationCallback = Arc<dyn Fn(bool) + Send + Sync>;
+
+/// MutationCallbacks are the callbacks registered on a store.
+#[derive(Default)]
+pub struct MutationCallbacks {
+    callbacks: RwLock<Vec<MutationCallback>>,
+}
+
+impl MutationCallbacks {
+    /// register adds a callback called after every mutation.
+    pub fn register(&self, f: MutationCallback) {
+        self.callbacks.write().unwrap().push(f);
+    }
+
+    /// notify calls the callbacks for a mutation.
+    pub fn notify(&self, dirty: bool) {
+        for f in self.callbacks.read().unwrap().iter() {
+            f(dirty);
+        }
+    }
+}
+
+/// tricolor implements basic, single-thread tri-color GC. Given the roots, the
+/// complete set and a refs function, this function returns a set of all
+/// reachable objects.
+///
+/// Correct usage requires that the caller not allow the arguments to change
+/// until the result is used to delete objects in the system.
+///
+/// It will allocate memory proportional to the size of the reachable set.
+///
+/// We can probably use this to inform a design for incremental GC by injecting
+/// callbacks to the set modification algorithms.
+pub fn tricolor<F>(roots: Vec<Node>, mut refs: F) -> Result<HashSet<Node>, Error>
+where
+    F: FnMut(&Node) -> Result<Vec<Node>, Error>,
+{
+    let mut grays = roots;
+    let mut seen: HashSet<Node> = grays.iter().cloned().collect();
+    // the black set is the reachable set once every gray node is scanned
+    let mut reachable = HashSet::new();
+
+    while let Some(node) = grays.pop() {
+        for r in refs(&node)? {
+            if seen.insert(r.clone()) {
+                grays.push(r);
+            }
+        }
+        reachable.insert(node);
+    }
+
+    Ok(reachable)
+}
+
+/// references returns the nodes referenced by the `containerd.io/gc.ref.*`
+/// labels of an object of the namespace.
+///
+/// Content is referenced with `containerd.io/gc.ref.content` optionally
+/// followed by a unique suffix, snapshots with
+/// `containerd.io/gc.ref.snapshot.<snapshotter>` optionally followed by a
+/// slash and a unique suffix.
+pub fn references(namespace: &str, labels: &HashMap<String, String>) -> Vec<Node> {
+    let mut refs = Vec::new();
+    for (key, value) in labels {
+        if value.is_empty() {
+            continue;
+        }
+        if let Some(suffix) = key.strip_prefix(labels::GC_REF_CONTENT_PREFIX) {
+            if suffix.is_empty() || suffix.starts_with(['.', '/']) {
+                refs.push(Node::content(namespace, value));
+            }
+        } else if let Some(suffix) = key.strip_prefix(labels::GC_REF_SNAPSHOT_PREFIX) {
+            let snapshotter = match suffix.strip_prefix('.') {
+                Some(s) => s.split('/').next().unwrap_or_default(),
+                None => continue,
+            };
+            if !snapshotter.is_empty() {
+                refs.push(Node::snapshot(namespace, snapshotter, value));
+            }
+        }
+    }
+    refs
+}
+
+/// is_root reports whether the labels mark the object as a root.
+pub fn is_root(labels: &HashMap<String, String>) -> bool {
+    labels.contains_key(labels::GC_ROOT)
+}
@@ -0,0 +1,212 @@
+use super::{Collector, MutationCallback, Stats};
+use crate::errdefs::Error;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Config configures the garbage collection policies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// pause_threshold is the maximum fraction of time garbage collection
+    /// may take, collections triggered by mutations are delayed until the
+    /// last collection took at most this fraction of the time since it
+    /// started. For example 0.02 allows collections to take 2% of the time.
+    ///
+    /// Zero disables the limit, the maximum is 0.5.
+    pub pause_threshold: f64,
+
+    /// deletion_threshold is the number of deletions after which a
+    /// collection is scheduled, regardless of the pause threshold. Zero
+    /// disables deletion triggers.
+    pub deletion_threshold: usize,
+
+    /// mutation_threshold is the number of mutations after which a
+    /// collection is scheduled, within the pause threshold. Zero disables
+    /// mutation triggers.
+    pub mutation_threshold: usize,
+
+    /// schedule_delay is the delay between a collection being triggered and
+    /// it running, to batch the changes of a burst of mutations.
+    pub schedule_delay: Duration,
+
+    /// startup_delay is the delay before the first collection once the
+    /// scheduler started. Zero skips the startup collection.
+    pub startup_delay: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            pause_threshold: 0.02,
+            deletion_threshold: 0,
+            mutation_threshold: 100,
+            schedule_delay: Duration::ZERO,
+            startup_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+enum Event {
+    Mutation { dirty: bool },
+    Schedule(Sender<Result<Stats, Error>>),
+    Stop,
+}
+
+/// Scheduler runs the collections of a collector on a background thread,
+/// when requested or as the mutations of the store accumulate.
+pub struct Scheduler {
+    tx: Sender<Event>,
+    last: Arc<Mutex<Option<Stats>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn new(collector: Arc<dyn Collector>, config: Config) -> Result<Scheduler, Error> {
+        if !(0.0..=0.5).contains(&config.pause_threshold) {
+            return Err(Error::InvalidArgument(format!(
+                "gc pause threshold {} must be between 0 and 0.5",
+                config.pause_threshold
+            )));
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let last = Arc::new(Mutex::new(None));
+        let run = Run {
+            collector,
+            config,
+            last: last.clone(),
+        };
+        let handle = thread::Builder::new()
+            .name("gc-scheduler".to_string())
+            .spawn(move || run.run(rx))
+            .map_err(|e| Error::Unknown(format!("failed to start gc scheduler: {}", e)))?;
+
+        Ok(Scheduler {
+            tx,
+            last,
+            handle: Some(handle),
+        })
+    }
+
+    /// mutation_callback records a mutation of the store, dirty being set
+    /// when the mutation removed references.
+    pub fn mutation_callback(&self, dirty: bool) {
+        let _ = self.tx.send(Event::Mutation { dirty });
+    }
+
+    /// mutation_hook returns a callback recording the mutations of a store,
+    /// to register on the stores of the collector.
+    pub fn mutation_hook(&self) -> MutationCallback {
+        let tx = self.tx.clone();
+        Arc::new(move |dirty| {
+            let _ = tx.send(Event::Mutation { dirty });
+        })
+    }
+
+    /// schedule_and_wait runs a collection as soon as possible and returns
+    /// its stats.
+    pub fn schedule_and_wait(&self) -> Result<Stats, Error> {
+        let (tx, rx) = mpsc::channel();
+        let stopped = || Error::Unavailable("gc scheduler stopped".to_string());
+        self.tx.send(Event::Schedule(tx)).map_err(|_| stopped())?;
+        rx.recv().map_err(|_| stopped())?
+    }
+
+    /// last_stats returns the stats of the last successful collection.
+    pub fn last_stats(&self) -> Option<Stats> {
+        self.last.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let _ = self.tx.send(Event::Stop);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Run {
+    collector: Arc<dyn Collector>,
+    config: Config,
+    last: Arc<Mutex<Option<Stats>>>,
+}
+
+impl Run {
+    fn run(&self, rx: Receiver<Event>) {
+        let mut mutations = 0;
+        let mut deletions = 0;
+        let mut waiters: Vec<Sender<Result<Stats, Error>>> = Vec::new();
+        // the earliest time a collection triggered by mutations may run
+        let mut next_allowed = Instant::now();
+
+        let mut scheduled = if self.config.startup_delay.is_zero() {
+            None
+        } else {
+            Some(Instant::now() + self.config.startup_delay)
+        };
+
+        loop {
+            let event = match scheduled {
+                Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let at = match event {
+                Ok(Event::Mutation { dirty }) => {
+                    mutations += 1;
+                    if dirty {
+                        deletions += 1;
+                    }
+                    let delayed = Instant::now() + self.config.schedule_delay;
+                    if self.config.deletion_threshold > 0 && deletions >= self.config.deletion_threshold {
+                        Some(delayed)
+                    } else if self.config.mutation_threshold > 0 && mutations >= self.config.mutation_threshold {
+                        Some(delayed.max(next_allowed))
+                    } else {
+                        None
+                    }
+                }
+                Ok(Event::Schedule(waiter)) => {
+                    waiters.push(waiter);
+                    Some(Instant::now())
+                }
+                Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) => None,
+            };
+            if let Some(at) = at {
+                scheduled = Some(scheduled.map_or(at, |s| s.min(at)));
+            }
+
+            if scheduled.is_none_or(|s| s > Instant::now()) {
+                continue;
+            }
+            scheduled = None;
+            mutations = 0;
+            deletions = 0;
+
+            let started = Instant::now();
+            let result = self.collector.garbage_collect();
+            match &result {
+                Ok(stats) => {
+                    log::info!(
+                        "garbage collected in {:?}: {} content, {} snapshots and {} leases deleted",
+                        stats.elapsed(),
+                        stats.content_deleted,
+                        stats.snapshots_deleted,
+                        stats.leases_deleted
+                    );
+                    if self.config.pause_threshold > 0.0 {
+                        next_allowed = started + started.elapsed().div_f64(self.config.pause_threshold);
+                    }
+                    *self.last.lock().unwrap() = Some(stats.clone());
+                }
+                Err(e) => log::error!("garbage collection failed: {}", e),
+            }
+            for waiter in waiters.drain(..) {
+                let _ = waiter.send(result.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use super::errdefs::Error;
+
+/// MAX_LENGTH is the maximum length of an identifier.
+const MAX_LENGTH: usize = 76;
+
+/// validate returns an error if the identifier is not valid.
+///
+/// Identifiers are used for namespaces, containers, leases and other named
+/// objects. They start and end with a letter or a digit and may contain dots,
+/// underscores and dashes in between, never twice in a row.
+pub fn validate(s: &str) -> Result<(), Error> {
+    if s.is_empty() {
+        return Err(Error::InvalidArgument("identifier must not be empty".to_string()));
+    }
+    if s.len() > MAX_LENGTH {
+        return Err(Error::InvalidArgument(format!(
+            "identifier {:?} greater than maximum length ({} characters)",
+            s, MAX_LENGTH
+        )));
+    }
+
+    let separator = |b: u8| matches!(b, b'.' | b'_' | b'-');
+    let bytes = s.as_bytes();
+    let valid = bytes.iter().all(|b| b.is_ascii_alphanumeric() || separator(*b))
+        && !separator(bytes[0])
+        && !separator(bytes[bytes.len() - 1])
+        && !bytes.windows(2).any(|w| separator(w[0]) && separator(w[1]));
+    if !valid {
+        return Err(Error::InvalidArgument(format!(
+            "identifier {:?} must match [A-Za-z0-9]+(?:[._-][A-Za-z0-9]+)*",
+            s
+        )));
+    }
+    Ok(())
+}
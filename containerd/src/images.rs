@@ -0,0 +1,286 @@
+pub mod archive;
+pub mod oci;
+pub mod unpack;
+
+use super::api::services::images::v1 as api;
+use super::api::types::Descriptor;
+use super::content;
+use super::context::Context;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use super::labels;
+use super::platforms::MatchComparer;
+use super::protobuf;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+/// Image provides the model for how containerd views container images.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    /// name of the image.
+    ///
+    /// To be pulled, it must be a reference compatible with resolvers.
+    ///
+    /// This field is required.
+    pub name: String,
+
+    /// labels provide runtime decoration for the image record.
+    ///
+    /// There is no default behavior for how these labels are propagated. They
+    /// only decorate the static metadata object.
+    ///
+    /// This field is optional.
+    pub labels: HashMap<String, String>,
+
+    /// target describes the root content for this image. Typically, this is
+    /// a manifest, index or manifest list.
+    pub target: Descriptor,
+
+    /// created_at is the time at which the image was created.
+    pub created_at: OffsetDateTime,
+
+    /// updated_at is the time at which the image was updated.
+    pub updated_at: OffsetDateTime,
+}
+
+impl Image {
+    pub fn new(name: &str, target: Descriptor) -> Image {
+        Image {
+            name: name.to_string(),
+            labels: HashMap::new(),
+            target,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+}
+
+/// Store and interact with images
+pub trait Store: Send + Sync {
+    /// get an image by name.
+    fn get(&self, ctx: &Context, name: &str) -> Result<Image, Error>;
+
+    /// list returns images that match one or more of the provided filters.
+    fn list(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Image>, Error>;
+
+    /// create an image in the store. The name must not be in use.
+    fn create(&self, ctx: &Context, image: Image) -> Result<Image, Error>;
+
+    /// update will replace the data in the store with the provided image. If
+    /// one or more fieldpaths are provided, only those fields will be updated.
+    fn update(&self, ctx: &Context, image: Image, fieldpaths: &[&str]) -> Result<Image, Error>;
+
+    /// delete the image by name.
+    fn delete(&self, ctx: &Context, name: &str) -> Result<(), Error>;
+}
+
+/// validate checks the required fields of an image.
+pub fn validate(image: &Image) -> Result<(), Error> {
+    if image.name.is_empty() {
+        return Err(Error::InvalidArgument("image name must not be empty".to_string()));
+    }
+
+    for (k, v) in &image.labels {
+        super::labels::validate(k, v).map_err(|e| {
+            Error::InvalidArgument(format!("image.labels: {}", e))
+        })?;
+    }
+
+    validate_target(&image.target)
+}
+
+fn validate_target(target: &Descriptor) -> Result<(), Error> {
+    super::digest::validate(&target.digest)
+        .map_err(|e| Error::InvalidArgument(format!("target: {}", e)))?;
+
+    if target.size < 0 {
+        return Err(Error::InvalidArgument("target: size must not be negative".to_string()));
+    }
+
+    if target.media_type.is_empty() {
+        return Err(Error::InvalidArgument("target: media type must not be empty".to_string()));
+    }
+
+    Ok(())
+}
+
+/// children returns the immediate children of content described by the
+/// descriptor: the config and layers of a manifest or the manifests of an
+/// index. Other content has no children.
+pub fn children(store: &dyn content::Store, ctx: &Context, desc: &Descriptor) -> Result<Vec<oci::Descriptor>, Error> {
+    if oci::is_manifest(&desc.media_type) {
+        let p = content::read_blob(store, ctx, desc)?;
+        let manifest: oci::Manifest = decode(&p, desc)?;
+
+        let mut children = Vec::with_capacity(manifest.layers.len() + 1);
+        children.push(manifest.config);
+        children.extend(manifest.layers);
+        Ok(children)
+    } else if oci::is_index(&desc.media_type) {
+        let p = content::read_blob(store, ctx, desc)?;
+        let index: oci::Index = decode(&p, desc)?;
+        Ok(index.manifests)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// set_children_labels labels the content of desc with a garbage collection
+/// reference to each of its children, so that they are kept for as long as the
+/// parent is.
+pub fn set_children_labels(
+    store: &dyn content::Store,
+    ctx: &Context,
+    desc: &Descriptor,
+    children: &[oci::Descriptor],
+) -> Result<(), Error> {
+    if children.is_empty() {
+        return Ok(());
+    }
+
+    let mut info = store.info(ctx, &desc.digest)?;
+    let mut fieldpaths = Vec::with_capacity(children.len());
+    for (i, child) in children.iter().enumerate() {
+        let key = format!("{}.{}", labels::GC_REF_CONTENT_PREFIX, i);
+        info.labels.insert(key.clone(), child.descriptor.digest.clone());
+        fieldpaths.push(format!("labels.{}", key));
+    }
+
+    let fieldpaths: Vec<&str> = fieldpaths.iter().map(|s| s.as_str()).collect();
+    store.update(ctx, info, &fieldpaths)?;
+    Ok(())
+}
+
+/// manifest resolves the manifest of the target for the platform.
+///
+/// The manifests of an index are tried from the most to the least preferred
+/// platform. A manifest that is not described with a platform is matched on
+/// the platform of its config.
+pub fn manifest(
+    store: &dyn content::Store,
+    ctx: &Context,
+    target: &Descriptor,
+    platform: &dyn MatchComparer,
+) -> Result<oci::Manifest, Error> {
+    resolve_manifest(store, ctx, &oci::Descriptor::from(target.clone()), platform)
+}
+
+fn resolve_manifest(
+    store: &dyn content::Store,
+    ctx: &Context,
+    desc: &oci::Descriptor,
+    platform: &dyn MatchComparer,
+) -> Result<oci::Manifest, Error> {
+    let target = &desc.descriptor;
+    if oci::is_manifest(&target.media_type) {
+        let manifest: oci::Manifest = decode(&content::read_blob(store, ctx, target)?, target)?;
+        if desc.platform.is_none() {
+            let config = config(store, ctx, &manifest)?;
+            let candidate = crate::api::types::Platform {
+                os: config.os,
+                architecture: config.architecture,
+                variant: config.variant,
+            };
+            if !candidate.os.is_empty() && !platform.matches(&candidate) {
+                return Err(Error::NotFound(format!(
+                    "manifest {}: platform {} not matched",
+                    target.digest,
+                    super::platforms::format(&candidate)
+                )));
+            }
+        }
+        return Ok(manifest);
+    }
+
+    if !oci::is_index(&target.media_type) {
+        return Err(Error::InvalidArgument(format!(
+            "unexpected media type {} for {}",
+            target.media_type, target.digest
+        )));
+    }
+
+    let index: oci::Index = decode(&content::read_blob(store, ctx, target)?, target)?;
+    let mut candidates: Vec<oci::Descriptor> = index
+        .manifests
+        .into_iter()
+        .filter(|d| d.platform.as_ref().is_none_or(|p| platform.matches(p)))
+        .collect();
+    candidates.sort_by(|a, b| match (&a.platform, &b.platform) {
+        (Some(a), Some(b)) if platform.less(a, b) => Ordering::Less,
+        (Some(a), Some(b)) if platform.less(b, a) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        _ => Ordering::Equal,
+    });
+
+    for candidate in &candidates {
+        match resolve_manifest(store, ctx, candidate, platform) {
+            // a manifest without platform may be for another one than asked
+            Err(e) if e.is_not_found() && candidate.platform.is_none() => continue,
+            result => return result,
+        }
+    }
+
+    Err(Error::NotFound(format!("manifest for platform in index {}", target.digest)))
+}
+
+/// config reads the image config referenced by the manifest.
+pub fn config(store: &dyn content::Store, ctx: &Context, manifest: &oci::Manifest) -> Result<oci::ImageConfig, Error> {
+    let desc = &manifest.config.descriptor;
+    decode(&content::read_blob(store, ctx, desc)?, desc)
+}
+
+/// decode unmarshals the JSON content described by desc.
+pub(crate) fn decode<T: serde::de::DeserializeOwned>(p: &[u8], desc: &Descriptor) -> Result<T, Error> {
+    serde_json::from_slice(p).map_err(|e| {
+        Error::InvalidArgument(format!("failed to decode {} {}: {}", desc.media_type, desc.digest, e))
+    })
+}
+
+/// Adaptor for images exposes the `name`, `target.digest`,
+/// `target.mediatype` and `labels.<key>` field paths to filters.
+impl Adaptor for Image {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        let value = match fieldpath.first()?.as_str() {
+            "name" => self.name.clone(),
+            "target" => match fieldpath.get(1)?.as_str() {
+                "digest" => self.target.digest.clone(),
+                "mediatype" => self.target.media_type.clone(),
+                _ => return None,
+            },
+            "labels" => return filters::check_map(&fieldpath[1..], &self.labels),
+            _ => return None,
+        };
+
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+}
+
+impl From<Image> for api::Image {
+    fn from(image: Image) -> Self {
+        api::Image {
+            name: image.name,
+            labels: image.labels,
+            target: Some(image.target),
+            created_at: Some(protobuf::to_timestamp(image.created_at)),
+            updated_at: Some(protobuf::to_timestamp(image.updated_at)),
+        }
+    }
+}
+
+impl From<api::Image> for Image {
+    fn from(image: api::Image) -> Self {
+        Image {
+            name: image.name,
+            labels: image.labels,
+            target: image.target.unwrap_or_default(),
+            created_at: protobuf::from_timestamp(image.created_at.as_ref()),
+            updated_at: protobuf::from_timestamp(image.updated_at.as_ref()),
+        }
+    }
+}
@@ -0,0 +1,508 @@
+mod docker;
+
+use super::oci::{self, Descriptor as OciDescriptor};
+use super::{Image, Store as ImageStore};
+use crate::api::types::{Descriptor, Platform};
+use crate::content::{self, Store as ContentStore};
+use crate::context::Context;
+use crate::digest;
+use crate::errdefs::Error;
+use crate::platforms::{self, Matcher};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// MAX_METADATA_SIZE limits the size of the oci-layout, index.json and
+/// manifest.json files of an archive, which are read in memory.
+const MAX_METADATA_SIZE: u64 = 4 << 20;
+
+/// ImportOptions configure how an image layout is imported.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    // base_name names images whose ref name annotation only carries a tag,
+    // for example `docker.io/library/alpine` for a ref name of `3.18`
+    pub base_name: Option<String>,
+}
+
+/// ExportOptions configure which content of the images is exported.
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    // platforms restricts the manifests of an index that get exported, all
+    // manifests are exported when empty
+    pub platforms: Vec<Platform>,
+    // all_platforms exports the manifests of every platform, regardless of
+    // the platforms option
+    pub all_platforms: bool,
+}
+
+/// import_index imports an OCI image layout tarball (`oci-layout`,
+/// `index.json` and `blobs/`) into the content store and creates an image for
+/// every manifest of the index that carries a name annotation.
+///
+/// Tarballs written by `docker save` (`manifest.json`, `<id>.json` configs and
+/// `layer.tar` files) are imported as well: their images are converted into
+/// OCI manifests and named after their repo tags.
+///
+/// The blobs are streamed into the content store and verified against their
+/// digest, the imported images are returned.
+pub fn import_index(
+    content: &dyn ContentStore,
+    images: &dyn ImageStore,
+    ctx: &Context,
+    r: &mut dyn Read,
+    opts: &ImportOptions,
+) -> Result<Vec<Image>, Error> {
+    let mut layout = Layout::default();
+
+    let mut archive = tar::Archive::new(r);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let size = entry.header().size()?;
+        layout.add(content, ctx, &path, size, &mut entry)?;
+    }
+
+    layout.finish(content, images, ctx, opts)
+}
+
+/// import_dir imports an OCI image layout or an extracted `docker save`
+/// directory, see import_index.
+pub fn import_dir(
+    content: &dyn ContentStore,
+    images: &dyn ImageStore,
+    ctx: &Context,
+    dir: &Path,
+    opts: &ImportOptions,
+) -> Result<Vec<Image>, Error> {
+    let mut layout = Layout::default();
+    add_dir(content, ctx, &mut layout, dir, "")?;
+    layout.finish(content, images, ctx, opts)
+}
+
+fn add_dir(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    layout: &mut Layout,
+    dir: &Path,
+    prefix: &str,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            add_dir(content, ctx, layout, &entry.path(), &format!("{}/", path))?;
+        } else if file_type.is_file() {
+            let mut f = File::open(entry.path())?;
+            let size = f.metadata()?.len();
+            layout.add(content, ctx, &path, size, &mut f)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Layout collects the parts of an image layout while its files are read.
+#[derive(Default)]
+struct Layout {
+    layout: Option<Vec<u8>>,
+    index: Option<Vec<u8>>,
+    // manifest.json of a `docker save` archive
+    manifest: Option<Vec<u8>>,
+    // descriptors of the files written to the content store by their path
+    files: HashMap<String, Descriptor>,
+}
+
+impl Layout {
+    fn add(
+        &mut self,
+        content: &dyn ContentStore,
+        ctx: &Context,
+        path: &str,
+        size: u64,
+        r: &mut dyn Read,
+    ) -> Result<(), Error> {
+        let path = path.trim_start_matches("./");
+
+        if path == oci::IMAGE_LAYOUT_FILE {
+            self.layout = Some(read_all(r, path, size)?);
+        } else if path == oci::IMAGE_INDEX_FILE {
+            self.index = Some(read_all(r, path, size)?);
+        } else if let Some(blob) = path.strip_prefix("blobs/") {
+            let dgst = match blob.split_once('/') {
+                Some((algorithm, encoded)) => format!("{}:{}", algorithm, encoded),
+                None => return Ok(()),
+            };
+            if digest::validate(&dgst).is_err() {
+                log::debug!("skipping unknown blob {:?} in image layout", path);
+                return Ok(());
+            }
+
+            let desc = Descriptor {
+                digest: dgst.clone(),
+                size: size as i64,
+                ..Default::default()
+            };
+            content::write_blob(content, ctx, &format!("import-{}", dgst), r, &desc, HashMap::new())?;
+            self.files.insert(path.to_string(), desc);
+        } else if path == docker::MANIFEST_FILE {
+            self.manifest = Some(read_all(r, path, size)?);
+        } else if docker::is_untyped_file(path) {
+            let desc = write_untyped(content, ctx, path, size, r)?;
+            self.files.insert(path.to_string(), desc);
+        }
+
+        Ok(())
+    }
+
+    fn finish(
+        self,
+        content: &dyn ContentStore,
+        images: &dyn ImageStore,
+        ctx: &Context,
+        opts: &ImportOptions,
+    ) -> Result<Vec<Image>, Error> {
+        let layout: oci::ImageLayout = match (self.layout, self.manifest) {
+            (Some(p), _) => serde_json::from_slice(&p)
+                .map_err(|e| Error::InvalidArgument(format!("failed to decode {}: {}", oci::IMAGE_LAYOUT_FILE, e)))?,
+            (None, Some(p)) => return docker::import(content, images, ctx, &p, &self.files),
+            (None, None) => {
+                return Err(Error::InvalidArgument(format!(
+                    "neither {} nor {} file is present",
+                    oci::IMAGE_LAYOUT_FILE,
+                    docker::MANIFEST_FILE
+                )))
+            }
+        };
+        if layout.image_layout_version != oci::IMAGE_LAYOUT_VERSION {
+            return Err(Error::NotImplemented(format!(
+                "unsupported OCI image layout version {:?}",
+                layout.image_layout_version
+            )));
+        }
+
+        let p = match self.index {
+            Some(p) => p,
+            None => return Err(Error::InvalidArgument(format!("{} file is missing", oci::IMAGE_INDEX_FILE))),
+        };
+
+        // the index is kept in the content store so that it can be used as
+        // the target of an image
+        let index_desc = Descriptor {
+            media_type: oci::MEDIA_TYPE_IMAGE_INDEX.to_string(),
+            digest: digest::from_bytes(&p),
+            size: p.len() as i64,
+            ..Default::default()
+        };
+        content::write_blob(
+            content,
+            ctx,
+            &format!("import-{}", index_desc.digest),
+            &mut Cursor::new(&p),
+            &index_desc,
+            HashMap::new(),
+        )?;
+
+        let index: oci::Index = super::decode(&p, &index_desc)?;
+
+        let mut visited = HashSet::new();
+        set_gc_labels(content, ctx, &index_desc, &mut visited)?;
+
+        let mut imported = Vec::new();
+        for m in index.manifests {
+            let name = match image_name(&m, opts) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            imported.push(register(images, ctx, &name, m.descriptor)?);
+        }
+
+        Ok(imported)
+    }
+}
+
+/// write_untyped stores a file whose digest is not known in advance into the
+/// content store and returns its descriptor. The media type is left for the
+/// caller to fill in.
+fn write_untyped(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    path: &str,
+    size: u64,
+    r: &mut dyn Read,
+) -> Result<Descriptor, Error> {
+    let mut w = content.writer(
+        ctx,
+        content::WriterOpts {
+            reference: format!("import-{}", path),
+            desc: Descriptor::default(),
+        },
+    )?;
+
+    // a previous import of the same path may have been interrupted, its data
+    // cannot be trusted to be from the same file
+    if w.status()?.offset != 0 {
+        w.truncate(0)?;
+    }
+    std::io::copy(r, &mut w)?;
+
+    let desc = Descriptor {
+        digest: w.digest(),
+        size: size as i64,
+        ..Default::default()
+    };
+    match w.commit(size as i64, &desc.digest, HashMap::new()) {
+        Err(e) if e.is_already_exists() => {}
+        result => result?,
+    }
+
+    Ok(desc)
+}
+
+/// register creates the image or points an existing image of the same name to
+/// the new target.
+fn register(images: &dyn ImageStore, ctx: &Context, name: &str, target: Descriptor) -> Result<Image, Error> {
+    let image = Image::new(name, target);
+    match images.create(ctx, image.clone()) {
+        Err(e) if e.is_already_exists() => images.update(ctx, image, &["target"]),
+        result => result,
+    }
+}
+
+/// set_gc_labels walks the content tree from desc and labels every parent
+/// with references to its children. Content missing from the layout is
+/// skipped.
+fn set_gc_labels(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    desc: &Descriptor,
+    visited: &mut HashSet<String>,
+) -> Result<(), Error> {
+    if !visited.insert(desc.digest.clone()) {
+        return Ok(());
+    }
+
+    let children = match super::children(content, ctx, desc) {
+        Ok(children) => children,
+        Err(e) if e.is_not_found() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for child in &children {
+        set_gc_labels(content, ctx, &child.descriptor, visited)?;
+    }
+
+    super::set_children_labels(content, ctx, desc, &children)
+}
+
+/// image_name returns the name of the image for a manifest of the index.
+fn image_name(desc: &OciDescriptor, opts: &ImportOptions) -> Option<String> {
+    let annotations = &desc.descriptor.annotations;
+    if let Some(name) = annotations.get(oci::ANNOTATION_IMAGE_NAME) {
+        return Some(name.clone());
+    }
+
+    let reference = annotations.get(oci::ANNOTATION_REF_NAME)?;
+    match &opts.base_name {
+        Some(base) if !reference.contains(['/', ':', '@']) => Some(format!("{}:{}", base, reference)),
+        _ => Some(reference.clone()),
+    }
+}
+
+/// export writes the images with their content as an OCI image layout
+/// tarball.
+pub fn export(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    images: &[Image],
+    w: &mut dyn Write,
+    opts: &ExportOptions,
+) -> Result<(), Error> {
+    let mut tw = TarWriter {
+        builder: tar::Builder::new(w),
+        dirs: HashSet::new(),
+    };
+    export_to(content, ctx, images, &mut tw, opts)?;
+    tw.builder.finish()?;
+    Ok(())
+}
+
+/// export_dir writes the images with their content as an OCI image layout
+/// directory. The directory is created if it does not exist.
+pub fn export_dir(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    images: &[Image],
+    dir: &Path,
+    opts: &ExportOptions,
+) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let mut dw = DirWriter { root: dir.to_path_buf() };
+    export_to(content, ctx, images, &mut dw, opts)
+}
+
+fn export_to(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    images: &[Image],
+    lw: &mut dyn LayoutWriter,
+    opts: &ExportOptions,
+) -> Result<(), Error> {
+    let mut index = oci::Index {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_INDEX.to_string(),
+        ..Default::default()
+    };
+
+    let mut blobs = BTreeMap::new();
+    for image in images {
+        collect(content, ctx, &image.target, opts, &mut blobs)?;
+
+        let mut desc = image.target.clone();
+        desc.annotations
+            .insert(oci::ANNOTATION_IMAGE_NAME.to_string(), image.name.clone());
+        if let Some(tag) = reference_tag(&image.name) {
+            desc.annotations
+                .insert(oci::ANNOTATION_REF_NAME.to_string(), tag.to_string());
+        }
+        index.manifests.push(desc.into());
+    }
+
+    let layout = oci::ImageLayout {
+        image_layout_version: oci::IMAGE_LAYOUT_VERSION.to_string(),
+    };
+    let p = serde_json::to_vec(&layout).map_err(|e| Error::Unknown(e.to_string()))?;
+    lw.write_file(oci::IMAGE_LAYOUT_FILE, p.len() as u64, &mut Cursor::new(&p))?;
+
+    for desc in blobs.values() {
+        let (algorithm, encoded) = digest::split(&desc.digest)?;
+        let mut r = content.reader(ctx, &desc.digest)?;
+        let path = format!("{}/{}/{}", oci::IMAGE_BLOBS_DIR, algorithm, encoded);
+        lw.write_file(&path, desc.size as u64, &mut r)?;
+    }
+
+    let p = serde_json::to_vec(&index).map_err(|e| Error::Unknown(e.to_string()))?;
+    lw.write_file(oci::IMAGE_INDEX_FILE, p.len() as u64, &mut Cursor::new(&p))
+}
+
+/// collect adds desc and the content it references to blobs. Manifests of an
+/// index that do not match the requested platforms are left out.
+fn collect(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    desc: &Descriptor,
+    opts: &ExportOptions,
+    blobs: &mut BTreeMap<String, Descriptor>,
+) -> Result<(), Error> {
+    if blobs.contains_key(&desc.digest) {
+        return Ok(());
+    }
+    blobs.insert(desc.digest.clone(), desc.clone());
+
+    let index = oci::is_index(&desc.media_type);
+    for child in super::children(content, ctx, desc)? {
+        if index && !matches_platform(opts, child.platform.as_ref()) {
+            continue;
+        }
+        collect(content, ctx, &child.descriptor, opts, blobs)?;
+    }
+
+    Ok(())
+}
+
+fn matches_platform(opts: &ExportOptions, platform: Option<&Platform>) -> bool {
+    if opts.all_platforms || opts.platforms.is_empty() {
+        return true;
+    }
+
+    let platform = match platform {
+        Some(p) => p,
+        None => return true,
+    };
+
+    // a platform also exports the compatible platforms it is able to run
+    opts.platforms
+        .iter()
+        .any(|want| platforms::only(want.clone()).matches(platform))
+}
+
+/// reference_tag returns the tag of an image name, if it has one.
+fn reference_tag(name: &str) -> Option<&str> {
+    let name = name.split('@').next()?;
+    let last = name.rsplit('/').next()?;
+    last.split_once(':').map(|(_, tag)| tag)
+}
+
+/// LayoutWriter stores the files of an image layout.
+trait LayoutWriter {
+    fn write_file(&mut self, path: &str, size: u64, r: &mut dyn Read) -> Result<(), Error>;
+}
+
+struct TarWriter<'a> {
+    builder: tar::Builder<&'a mut dyn Write>,
+    dirs: HashSet<String>,
+}
+
+impl LayoutWriter for TarWriter<'_> {
+    fn write_file(&mut self, path: &str, size: u64, r: &mut dyn Read) -> Result<(), Error> {
+        // add the parent directories first, tools extracting the archive
+        // expect them
+        let mut parent = String::new();
+        for component in path.split('/').rev().skip(1).collect::<Vec<_>>().into_iter().rev() {
+            parent.push_str(component);
+            parent.push('/');
+            if self.dirs.insert(parent.clone()) {
+                let mut header = tar::Header::new_ustar();
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                self.builder.append_data(&mut header, &parent, std::io::empty())?;
+            }
+        }
+
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(0o444);
+        header.set_size(size);
+        self.builder.append_data(&mut header, path, r)?;
+        Ok(())
+    }
+}
+
+struct DirWriter {
+    root: PathBuf,
+}
+
+impl LayoutWriter for DirWriter {
+    fn write_file(&mut self, path: &str, _size: u64, r: &mut dyn Read) -> Result<(), Error> {
+        let target = self.root.join(path);
+        fs::create_dir_all(target.parent().unwrap())?;
+
+        let mut f = File::create(&target)?;
+        std::io::copy(r, &mut f)?;
+        f.sync_all()?;
+        Ok(())
+    }
+}
+
+/// read_all reads a metadata file of the archive, which must not be larger
+/// than MAX_METADATA_SIZE.
+fn read_all(r: &mut dyn Read, path: &str, size: u64) -> Result<Vec<u8>, Error> {
+    let mut p = Vec::with_capacity(size.min(MAX_METADATA_SIZE) as usize);
+    // one byte past the limit tells a file which is too large
+    r.take(MAX_METADATA_SIZE + 1).read_to_end(&mut p)?;
+    if p.len() as u64 > MAX_METADATA_SIZE {
+        return Err(Error::InvalidArgument(format!(
+            "{} is too large, it exceeds {} bytes",
+            path, MAX_METADATA_SIZE
+        )));
+    }
+    Ok(p)
+}
@@ -0,0 +1,168 @@
+use crate::api::types::Descriptor;
+use crate::content::{self, Store as ContentStore};
+use crate::context::Context;
+use crate::digest::{self, Digester};
+use crate::errdefs::Error;
+use crate::images::{self, oci, Image, Store as ImageStore};
+use crate::labels;
+use crate::reference;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// MANIFEST_FILE lists the images of a `docker save` archive.
+pub(super) const MANIFEST_FILE: &str = "manifest.json";
+
+/// ManifestEntry is an image of the manifest.json file, the paths are relative
+/// to the root of the archive.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ManifestEntry {
+    config: String,
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+/// is_untyped_file reports whether the file of a `docker save` archive may be
+/// referenced from manifest.json, that is an image config or a layer.
+pub(super) fn is_untyped_file(path: &str) -> bool {
+    path.ends_with(".json") || path.ends_with(".tar")
+}
+
+/// import converts the images listed in manifest.json into OCI manifests and
+/// registers them under their normalized repo tags.
+///
+/// The configs and layers must already be in the content store, files maps
+/// their path in the archive to their descriptor.
+pub(super) fn import(
+    content: &dyn ContentStore,
+    images: &dyn ImageStore,
+    ctx: &Context,
+    p: &[u8],
+    files: &HashMap<String, Descriptor>,
+) -> Result<Vec<Image>, Error> {
+    let entries: Vec<ManifestEntry> = serde_json::from_slice(p)
+        .map_err(|e| Error::InvalidArgument(format!("failed to decode {}: {}", MANIFEST_FILE, e)))?;
+
+    let mut imported = Vec::new();
+    for entry in entries {
+        let target = convert(content, ctx, &entry, files)?;
+
+        let tags = entry.repo_tags.unwrap_or_default();
+        if tags.is_empty() {
+            log::debug!("skipping untagged image with config {:?}", entry.config);
+        }
+        for tag in tags {
+            let name = reference::parse_docker_ref(&tag)?;
+            imported.push(super::register(images, ctx, &name, target.clone())?);
+        }
+    }
+
+    Ok(imported)
+}
+
+/// convert writes an OCI manifest for the entry and returns its descriptor.
+fn convert(
+    content: &dyn ContentStore,
+    ctx: &Context,
+    entry: &ManifestEntry,
+    files: &HashMap<String, Descriptor>,
+) -> Result<Descriptor, Error> {
+    let mut config = lookup(files, &entry.config)?;
+    config.media_type = oci::MEDIA_TYPE_IMAGE_CONFIG.to_string();
+
+    let p = content::read_blob(content, ctx, &config)?;
+    let image_config: oci::ImageConfig = images::decode(&p, &config)?;
+
+    let diff_ids = &image_config.rootfs.diff_ids;
+    if diff_ids.len() != entry.layers.len() {
+        return Err(Error::InvalidArgument(format!(
+            "image config {} lists {} diff IDs, but the manifest has {} layers",
+            config.digest,
+            diff_ids.len(),
+            entry.layers.len()
+        )));
+    }
+
+    let mut layers = Vec::with_capacity(entry.layers.len());
+    for (path, expected) in entry.layers.iter().zip(diff_ids) {
+        let mut layer = lookup(files, path)?;
+        let diff_id = detect_layer(content, ctx, &mut layer)?;
+        if &diff_id != expected {
+            return Err(Error::InvalidArgument(format!(
+                "layer {:?} has diff ID {}, but the image config expects {}",
+                path, diff_id, expected
+            )));
+        }
+        layers.push(layer.into());
+    }
+
+    let manifest = oci::Manifest {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        config: config.into(),
+        layers,
+        annotations: HashMap::new(),
+    };
+    let p = serde_json::to_vec(&manifest).map_err(|e| Error::Unknown(e.to_string()))?;
+
+    let desc = Descriptor {
+        media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        digest: digest::from_bytes(&p),
+        size: p.len() as i64,
+        ..Default::default()
+    };
+    content::write_blob(
+        content,
+        ctx,
+        &format!("import-{}", desc.digest),
+        &mut Cursor::new(&p),
+        &desc,
+        HashMap::new(),
+    )?;
+
+    let mut children = vec![manifest.config];
+    children.extend(manifest.layers);
+    images::set_children_labels(content, ctx, &desc, &children)?;
+
+    Ok(desc)
+}
+
+/// detect_layer sets the media type of the layer from its compression and
+/// returns its diff ID, the digest of the uncompressed layer.
+fn detect_layer(content: &dyn ContentStore, ctx: &Context, layer: &mut Descriptor) -> Result<String, Error> {
+    let mut r = content.reader(ctx, &layer.digest)?;
+
+    let mut magic = [0u8; 2];
+    let compressed = match r.read_exact(&mut magic) {
+        Ok(_) => magic == [0x1f, 0x8b],
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e.into()),
+    };
+
+    if !compressed {
+        layer.media_type = oci::MEDIA_TYPE_IMAGE_LAYER.to_string();
+        return Ok(layer.digest.clone());
+    }
+
+    layer.media_type = oci::MEDIA_TYPE_IMAGE_LAYER_GZIP.to_string();
+
+    r.seek(SeekFrom::Start(0))?;
+    let mut digester = Digester::new();
+    std::io::copy(&mut flate2::read::GzDecoder::new(r), &mut digester)?;
+    let diff_id = digester.digest();
+
+    let mut info = content.info(ctx, &layer.digest)?;
+    info.labels.insert(labels::LABEL_UNCOMPRESSED.to_string(), diff_id.clone());
+    content.update(ctx, info, &[&format!("labels.{}", labels::LABEL_UNCOMPRESSED)])?;
+
+    Ok(diff_id)
+}
+
+fn lookup(files: &HashMap<String, Descriptor>, path: &str) -> Result<Descriptor, Error> {
+    match files.get(path.trim_start_matches("./")) {
+        Some(desc) => Ok(desc.clone()),
+        None => Err(Error::InvalidArgument(format!("{:?} is referenced but missing from the archive", path))),
+    }
+}
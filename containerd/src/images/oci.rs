@@ -0,0 +1,138 @@
+use crate::api::types;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const MEDIA_TYPE_IMAGE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub const MEDIA_TYPE_IMAGE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const MEDIA_TYPE_IMAGE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+pub const MEDIA_TYPE_IMAGE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+pub const MEDIA_TYPE_IMAGE_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+pub const MEDIA_TYPE_IMAGE_LAYER_ZSTD: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
+
+pub const MEDIA_TYPE_DOCKER_SCHEMA2_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+pub const MEDIA_TYPE_DOCKER_SCHEMA2_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+pub const MEDIA_TYPE_DOCKER_SCHEMA2_CONFIG: &str = "application/vnd.docker.container.image.v1+json";
+pub const MEDIA_TYPE_DOCKER_SCHEMA2_LAYER: &str = "application/vnd.docker.image.rootfs.diff.tar";
+pub const MEDIA_TYPE_DOCKER_SCHEMA2_LAYER_GZIP: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+pub const MEDIA_TYPE_DOCKER_SCHEMA2_LAYER_FOREIGN_GZIP: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
+
+/// ANNOTATION_REF_NAME is the annotation key for the name of the reference
+/// for a target, usually the tag.
+pub const ANNOTATION_REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// ANNOTATION_IMAGE_NAME is the annotation key for the full image name of a
+/// target, set by containerd on export.
+pub const ANNOTATION_IMAGE_NAME: &str = "io.containerd.image.name";
+
+/// IMAGE_LAYOUT_FILE is the file name of the OCI image layout marker.
+pub const IMAGE_LAYOUT_FILE: &str = "oci-layout";
+
+/// IMAGE_LAYOUT_VERSION is the version of the OCI image layout.
+pub const IMAGE_LAYOUT_VERSION: &str = "1.0.0";
+
+/// IMAGE_INDEX_FILE is the file name of the entry point of an image layout.
+pub const IMAGE_INDEX_FILE: &str = "index.json";
+
+/// IMAGE_BLOBS_DIR is the directory of the content addressable blobs.
+pub const IMAGE_BLOBS_DIR: &str = "blobs";
+
+/// Descriptor describes the disposition of targeted content. It extends the
+/// API descriptor with the platform an index entry was built for.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Descriptor {
+    #[serde(flatten)]
+    pub descriptor: types::Descriptor,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<types::Platform>,
+}
+
+impl From<types::Descriptor> for Descriptor {
+    fn from(descriptor: types::Descriptor) -> Self {
+        Descriptor {
+            descriptor,
+            platform: None,
+        }
+    }
+}
+
+/// ImageLayout is the structure in the "oci-layout" file, found in the root
+/// of an OCI Image-layout directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageLayout {
+    pub image_layout_version: String,
+}
+
+/// Index references manifests for various platforms.
+/// This structure provides `application/vnd.oci.image.index.v1+json` mediatype when marshalled to JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: i32,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub media_type: String,
+
+    pub manifests: Vec<Descriptor>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+/// Manifest provides `application/vnd.oci.image.manifest.v1+json` mediatype structure when marshalled to JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: i32,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub media_type: String,
+
+    pub config: Descriptor,
+
+    pub layers: Vec<Descriptor>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+/// is_index reports whether the media type is an OCI index or a Docker manifest list.
+pub fn is_index(media_type: &str) -> bool {
+    media_type == MEDIA_TYPE_IMAGE_INDEX || media_type == MEDIA_TYPE_DOCKER_SCHEMA2_MANIFEST_LIST
+}
+
+/// is_manifest reports whether the media type is an OCI or Docker schema 2 manifest.
+pub fn is_manifest(media_type: &str) -> bool {
+    media_type == MEDIA_TYPE_IMAGE_MANIFEST || media_type == MEDIA_TYPE_DOCKER_SCHEMA2_MANIFEST
+}
+
+/// ImageConfig is the JSON structure which describes some basic information
+/// about the image. Only the fields containerd relies on are decoded, the
+/// config blob itself is always kept as it was received.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageConfig {
+    #[serde(default)]
+    pub architecture: String,
+
+    #[serde(default)]
+    pub os: String,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub variant: String,
+
+    pub rootfs: RootFs,
+}
+
+/// RootFs describes a layer content addresses
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RootFs {
+    /// type is the type of the rootfs.
+    #[serde(rename = "type")]
+    pub fs_type: String,
+
+    /// diff_ids is an array of layer content hashes (DiffIDs), in order from bottom-most to top-most.
+    pub diff_ids: Vec<String>,
+}
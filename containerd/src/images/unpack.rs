@@ -0,0 +1,217 @@
+use super::{oci, Image};
+use crate::api::types::Descriptor;
+use crate::content;
+use crate::context::Context;
+use crate::diff::Applier;
+use crate::digest;
+use crate::errdefs::Error;
+use crate::labels;
+use crate::platforms::MatchComparer;
+use crate::snapshots::{self, Snapshotter};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use time::OffsetDateTime;
+
+/// chain_id returns the chain ID of the layers with the given diff IDs.
+///
+/// The chain ID of a single layer is its diff ID, the chain ID of the layers
+/// up to n is the digest of `<chain ID of n-1> <diff ID of n>`.
+pub fn chain_id(diff_ids: &[String]) -> String {
+    chain_ids(diff_ids).pop().unwrap_or_default()
+}
+
+/// chain_ids returns the chain ID of every layer of the diff IDs, from the
+/// bottom-most to the top-most.
+pub fn chain_ids(diff_ids: &[String]) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::with_capacity(diff_ids.len());
+    for diff_id in diff_ids {
+        let id = match chain.last() {
+            Some(parent) => digest::from_bytes(format!("{} {}", parent, diff_id).as_bytes()),
+            None => diff_id.clone(),
+        };
+        chain.push(id);
+    }
+    chain
+}
+
+/// Unpacker extracts the layers of images into a snapshotter, each layer
+/// becomes a committed snapshot named after its chain ID.
+pub struct Unpacker {
+    content: Arc<dyn content::Store>,
+    snapshotter_name: String,
+    snapshotter: Arc<dyn Snapshotter>,
+    applier: Arc<dyn Applier>,
+    // chain IDs being unpacked, so concurrent unpacks of the same layers
+    // wait for each other instead of extracting them twice
+    locks: KeyedMutex,
+}
+
+impl Unpacker {
+    pub fn new(
+        content: Arc<dyn content::Store>,
+        snapshotter_name: &str,
+        snapshotter: Arc<dyn Snapshotter>,
+        applier: Arc<dyn Applier>,
+    ) -> Unpacker {
+        Unpacker {
+            content,
+            snapshotter_name: snapshotter_name.to_string(),
+            snapshotter,
+            applier,
+            locks: KeyedMutex::default(),
+        }
+    }
+
+    /// unpack resolves the manifest of the image for the platform and applies
+    /// its layers, skipping the ones already unpacked. The chain ID of the
+    /// top-most layer is returned, it is the parent for container snapshots.
+    ///
+    /// The config of the image is labeled with a garbage collection reference
+    /// to the snapshot, so that it is kept for as long as the image is.
+    pub fn unpack(&self, ctx: &Context, image: &Image, platform: &dyn MatchComparer) -> Result<String, Error> {
+        let store = self.content.as_ref();
+        let manifest = super::manifest(store, ctx, &image.target, platform)?;
+        let config = super::config(store, ctx, &manifest)?;
+
+        let diff_ids = &config.rootfs.diff_ids;
+        if diff_ids.len() != manifest.layers.len() {
+            return Err(Error::InvalidArgument(format!(
+                "image {:?}: mismatched image rootfs and manifest layers, {} diff IDs for {} layers",
+                image.name,
+                diff_ids.len(),
+                manifest.layers.len()
+            )));
+        }
+
+        let mut parent = String::new();
+        for ((layer, diff_id), chain_id) in manifest.layers.iter().zip(diff_ids).zip(chain_ids(diff_ids)) {
+            ctx.check_deadline()?;
+            self.apply_layer(ctx, &layer.descriptor, diff_id, &chain_id, &parent)?;
+            parent = chain_id;
+        }
+
+        if !parent.is_empty() {
+            self.set_snapshot_label(ctx, &manifest.config, &parent)?;
+        }
+
+        Ok(parent)
+    }
+
+    /// apply_layer extracts the layer into a snapshot committed as chain_id on
+    /// top of parent, unless it already exists.
+    fn apply_layer(
+        &self,
+        ctx: &Context,
+        layer: &Descriptor,
+        diff_id: &str,
+        chain_id: &str,
+        parent: &str,
+    ) -> Result<(), Error> {
+        let _guard = self.locks.lock(format!("{}/{}", ctx.namespace_required()?, chain_id));
+
+        if self.exists(ctx, chain_id)? {
+            return Ok(());
+        }
+
+        let key = unpack_key(chain_id);
+        let mounts = match self.snapshotter.prepare(ctx, &key, parent, HashMap::new()) {
+            Ok(mounts) => mounts,
+            // the snapshot may have been unpacked meanwhile by another process
+            Err(e) if e.is_already_exists() && self.exists(ctx, chain_id)? => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let result = self
+            .applier
+            .apply(ctx, layer, &mounts)
+            .and_then(|diff| {
+                if diff.digest != diff_id {
+                    return Err(Error::FailedPrecondition(format!(
+                        "wrong diff id calculated on extraction {:?}, expected {:?}",
+                        diff.digest, diff_id
+                    )));
+                }
+                self.snapshotter.commit(ctx, chain_id, &key, HashMap::new())
+            });
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let Err(e) = self.snapshotter.remove(ctx, &key) {
+                    log::warn!("failed to remove snapshot {:?} after failed unpack: {}", key, e);
+                }
+                // committed by another process while the layer was applied
+                if e.is_already_exists() {
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    fn exists(&self, ctx: &Context, key: &str) -> Result<bool, Error> {
+        match self.snapshotter.stat(ctx, key) {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// set_snapshot_label references the snapshot from the image config.
+    fn set_snapshot_label(&self, ctx: &Context, config: &oci::Descriptor, chain_id: &str) -> Result<(), Error> {
+        let key = format!("{}.{}", labels::GC_REF_SNAPSHOT_PREFIX, self.snapshotter_name);
+
+        let mut info = self.content.info(ctx, &config.descriptor.digest)?;
+        if info.labels.get(&key).map(|v| v.as_str()) == Some(chain_id) {
+            return Ok(());
+        }
+        info.labels.insert(key.clone(), chain_id.to_string());
+        self.content.update(ctx, info, &[format!("labels.{}", key).as_str()])?;
+        Ok(())
+    }
+}
+
+/// unpack_key returns a unique key for the active snapshot a layer is
+/// extracted into, `extract-<unique> <chain ID>`.
+fn unpack_key(chain_id: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}-{}-{} {}",
+        snapshots::UNPACK_KEY_PREFIX,
+        OffsetDateTime::now_utc().unix_timestamp_nanos(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        chain_id
+    )
+}
+
+/// KeyedMutex provides mutual exclusion per key.
+#[derive(Default)]
+struct KeyedMutex {
+    locked: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+impl KeyedMutex {
+    fn lock(&self, key: String) -> KeyedGuard<'_> {
+        let mut locked = self.locked.lock().unwrap();
+        while locked.contains(&key) {
+            locked = self.released.wait(locked).unwrap();
+        }
+        locked.insert(key.clone());
+        KeyedGuard { mutex: self, key }
+    }
+}
+
+struct KeyedGuard<'a> {
+    mutex: &'a KeyedMutex,
+    key: String,
+}
+
+impl Drop for KeyedGuard<'_> {
+    fn drop(&mut self) {
+        self.mutex.locked.lock().unwrap().remove(&self.key);
+        self.mutex.released.notify_all();
+    }
+}
@@ -0,0 +1,52 @@
+use super::errdefs::Error;
+use std::collections::HashMap;
+
+/// MAX_LABEL_SIZE is the maximum size of a label key and value combined.
+const MAX_LABEL_SIZE: usize = 4096;
+
+/// validate a label's key and value are under 4096 bytes
+pub fn validate(key: &str, value: &str) -> Result<(), Error> {
+    if key.len() + value.len() > MAX_LABEL_SIZE {
+        let prefix: String = key.chars().take(10).collect();
+        return Err(Error::InvalidArgument(format!(
+            "label key and value length ({} bytes) greater than maximum size ({} bytes), key: {}",
+            key.len() + value.len(),
+            MAX_LABEL_SIZE,
+            prefix
+        )));
+    }
+    Ok(())
+}
+
+/// validate_all validates every label of the map.
+pub fn validate_all(labels: &HashMap<String, String>) -> Result<(), Error> {
+    for (k, v) in labels {
+        validate(k, v)?;
+    }
+    Ok(())
+}
+
+/// GC_ROOT marks a content or snapshot object as a garbage collection root,
+/// it is kept along with everything it references.
+pub const GC_ROOT: &str = "containerd.io/gc.root";
+
+/// GC_REF_CONTENT_PREFIX is the label prefix referencing content from a
+/// content or snapshot object, followed by a unique suffix.
+pub const GC_REF_CONTENT_PREFIX: &str = "containerd.io/gc.ref.content";
+
+/// GC_REF_SNAPSHOT_PREFIX is the label prefix referencing a snapshot from a
+/// content object, followed by the name of the snapshotter.
+pub const GC_REF_SNAPSHOT_PREFIX: &str = "containerd.io/gc.ref.snapshot";
+
+/// GC_EXPIRE is the label of a lease holding the RFC 3339 time after which
+/// the lease no longer protects its resources.
+pub const GC_EXPIRE: &str = "containerd.io/gc.expire";
+
+/// LABEL_DISTRIBUTION_SOURCE_PREFIX is the label prefix recording the
+/// repositories content was fetched from, followed by the registry host. The
+/// value is a comma separated list of repositories.
+pub const LABEL_DISTRIBUTION_SOURCE_PREFIX: &str = "containerd.io/distribution.source";
+
+/// LABEL_UNCOMPRESSED is added to compressed layer contents.
+/// The value is digest of the uncompressed content.
+pub const LABEL_UNCOMPRESSED: &str = "containerd.io/uncompressed";
@@ -0,0 +1,424 @@
+use super::api::services::leases::v1 as api;
+use super::content::{self, Reader, Status, Writer, WriterOpts};
+use super::context::Context;
+use super::digest;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use super::identifiers;
+use super::labels;
+use super::mount::Mount;
+use super::protobuf;
+use super::snapshots::{self, Usage};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+/// GRPC_HEADER is the gRPC metadata key carrying the lease of a request.
+pub const GRPC_HEADER: &str = "containerd-lease";
+
+/// RESOURCE_CONTENT is the type of content resources, identified by digest.
+pub const RESOURCE_CONTENT: &str = "content";
+
+/// RESOURCE_INGESTS is the type of ingest resources, identified by the
+/// reference of the ingest.
+pub const RESOURCE_INGESTS: &str = "ingests";
+
+/// RESOURCE_SNAPSHOTS_PREFIX is the prefix of the type of snapshot
+/// resources, followed by a slash and the name of the snapshotter. Snapshots
+/// are identified by key.
+pub const RESOURCE_SNAPSHOTS_PREFIX: &str = "snapshots";
+
+/// Lease retains resources to prevent cleanup before the resources can be
+/// fully referenced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub id: String,
+    pub created_at: OffsetDateTime,
+    pub labels: HashMap<String, String>,
+}
+
+impl Lease {
+    pub fn new(id: &str) -> Lease {
+        Lease {
+            id: id.to_string(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// with_expiration sets the expiration of the lease to the given duration
+    /// from now.
+    pub fn with_expiration(mut self, expiration: Duration) -> Lease {
+        let expire = (OffsetDateTime::now_utc() + expiration)
+            .format(&Rfc3339)
+            .expect("RFC 3339 formatting of a UTC time");
+        self.labels.insert(labels::GC_EXPIRE.to_string(), expire);
+        self
+    }
+
+    /// expires_at returns the time after which the lease expires, if it has
+    /// a valid expiration label.
+    pub fn expires_at(&self) -> Option<OffsetDateTime> {
+        let expire = self.labels.get(labels::GC_EXPIRE)?;
+        OffsetDateTime::parse(expire, &Rfc3339).ok()
+    }
+
+    /// is_expired reports whether the lease expired at the given time. A lease
+    /// with an invalid expiration label never expires.
+    pub fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.expires_at().is_some_and(|expire| expire <= now)
+    }
+}
+
+/// generate_id returns a new lease identifier, unique for the process.
+pub fn generate_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = OffsetDateTime::now_utc().unix_timestamp_nanos();
+    format!("{}-{}", now, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Resource represents low level resource of image, like content, ingest and
+/// snapshotter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Resource {
+    pub id: String,
+    /// kind is the type of the resource: `content`, `ingests` or
+    /// `snapshots/<snapshotter>`.
+    pub kind: String,
+}
+
+impl Resource {
+    pub fn new(kind: &str, id: &str) -> Resource {
+        Resource {
+            id: id.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+
+    /// snapshot returns the resource of the snapshot key of a snapshotter.
+    pub fn snapshot(snapshotter: &str, key: &str) -> Resource {
+        Resource::new(&format!("{}/{}", RESOURCE_SNAPSHOTS_PREFIX, snapshotter), key)
+    }
+}
+
+/// validate_resource checks the type of the resource is supported and its
+/// identifier is valid for the type.
+pub fn validate_resource(resource: &Resource) -> Result<(), Error> {
+    if resource.id.is_empty() {
+        return Err(Error::InvalidArgument("resource id must not be empty".to_string()));
+    }
+    match resource.kind.split_once('/') {
+        None if resource.kind == RESOURCE_CONTENT => digest::validate(&resource.id),
+        None if resource.kind == RESOURCE_INGESTS => Ok(()),
+        Some((RESOURCE_SNAPSHOTS_PREFIX, snapshotter)) if !snapshotter.is_empty() && !snapshotter.contains('/') => {
+            Ok(())
+        }
+        _ => Err(Error::NotImplemented(format!(
+            "unsupported resource type {:?}",
+            resource.kind
+        ))),
+    }
+}
+
+/// validate checks the identifier and labels of a lease.
+pub fn validate(lease: &Lease) -> Result<(), Error> {
+    identifiers::validate(&lease.id)?;
+    labels::validate_all(&lease.labels)
+}
+
+/// Manager is used to create, list, and remove leases.
+pub trait Manager: Send + Sync {
+    /// create creates the lease, the identifier must not be in use.
+    fn create(&self, ctx: &Context, lease: Lease) -> Result<Lease, Error>;
+
+    /// delete deletes the lease, its resources are no longer protected.
+    fn delete(&self, ctx: &Context, id: &str) -> Result<(), Error>;
+
+    /// list returns the leases that match one or more of the provided
+    /// filters.
+    fn list(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Lease>, Error>;
+
+    /// add_resource references the resource by the lease.
+    fn add_resource(&self, ctx: &Context, id: &str, resource: Resource) -> Result<(), Error>;
+
+    /// delete_resource dereferences the resource by the lease.
+    fn delete_resource(&self, ctx: &Context, id: &str, resource: Resource) -> Result<(), Error>;
+
+    /// list_resources lists all the resources referenced by the lease.
+    fn list_resources(&self, ctx: &Context, id: &str) -> Result<Vec<Resource>, Error>;
+}
+
+/// Adaptor for leases exposes the `id` and `labels.<key>` field paths to
+/// filters.
+impl Adaptor for Lease {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        match fieldpath.first()?.as_str() {
+            "id" if !self.id.is_empty() => Some(self.id.clone()),
+            "labels" => filters::check_map(&fieldpath[1..], &self.labels),
+            _ => None,
+        }
+    }
+}
+
+impl From<Lease> for api::Lease {
+    fn from(lease: Lease) -> Self {
+        api::Lease {
+            id: lease.id,
+            created_at: Some(protobuf::to_timestamp(lease.created_at)),
+            labels: lease.labels,
+        }
+    }
+}
+
+impl From<api::Lease> for Lease {
+    fn from(lease: api::Lease) -> Self {
+        Lease {
+            id: lease.id,
+            created_at: protobuf::from_timestamp(lease.created_at.as_ref()),
+            labels: lease.labels,
+        }
+    }
+}
+
+impl From<Resource> for api::Resource {
+    fn from(resource: Resource) -> Self {
+        api::Resource {
+            id: resource.id,
+            r#type: resource.kind,
+        }
+    }
+}
+
+impl From<api::Resource> for Resource {
+    fn from(resource: api::Resource) -> Self {
+        Resource {
+            id: resource.id,
+            kind: resource.r#type,
+        }
+    }
+}
+
+/// LeasedContentStore adds the ingests and content written through it to the
+/// lease of the context, so that they are kept until they are referenced by
+/// other objects. Content that already exists is added to the lease as well.
+///
+/// Writes made with a context without lease are not recorded.
+pub struct LeasedContentStore {
+    store: Arc<dyn content::Store>,
+    manager: Arc<dyn Manager>,
+}
+
+impl LeasedContentStore {
+    pub fn new(store: Arc<dyn content::Store>, manager: Arc<dyn Manager>) -> LeasedContentStore {
+        LeasedContentStore { store, manager }
+    }
+}
+
+impl content::Store for LeasedContentStore {
+    fn info(&self, ctx: &Context, digest: &str) -> Result<content::Info, Error> {
+        self.store.info(ctx, digest)
+    }
+
+    fn update(&self, ctx: &Context, info: content::Info, fieldpaths: &[&str]) -> Result<content::Info, Error> {
+        self.store.update(ctx, info, fieldpaths)
+    }
+
+    fn walk(
+        &self,
+        ctx: &Context,
+        filters: &[&str],
+        f: &mut dyn FnMut(content::Info) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.store.walk(ctx, filters, f)
+    }
+
+    fn delete(&self, ctx: &Context, digest: &str) -> Result<(), Error> {
+        self.store.delete(ctx, digest)
+    }
+
+    fn reader(&self, ctx: &Context, digest: &str) -> Result<Box<dyn Reader>, Error> {
+        self.store.reader(ctx, digest)
+    }
+
+    fn status(&self, ctx: &Context, reference: &str) -> Result<Status, Error> {
+        self.store.status(ctx, reference)
+    }
+
+    fn list_statuses(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Status>, Error> {
+        self.store.list_statuses(ctx, filters)
+    }
+
+    fn abort(&self, ctx: &Context, reference: &str) -> Result<(), Error> {
+        self.store.abort(ctx, reference)
+    }
+
+    fn writer(&self, ctx: &Context, opts: WriterOpts) -> Result<Box<dyn Writer>, Error> {
+        let lease = match ctx.lease() {
+            Some(lease) => lease,
+            None => return self.store.writer(ctx, opts),
+        };
+
+        if !opts.reference.is_empty() {
+            let ingest = Resource::new(RESOURCE_INGESTS, &opts.reference);
+            self.manager.add_resource(ctx, lease, ingest)?;
+        }
+
+        let writer = match self.store.writer(ctx, opts.clone()) {
+            Ok(writer) => writer,
+            Err(e) if e.is_already_exists() && !opts.desc.digest.is_empty() => {
+                let resource = Resource::new(RESOURCE_CONTENT, &opts.desc.digest);
+                self.manager.add_resource(ctx, lease, resource)?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Box::new(LeasedWriter {
+            writer,
+            manager: self.manager.clone(),
+            ctx: ctx.clone(),
+        }))
+    }
+}
+
+/// LeasedWriter adds the committed content to the lease of the context.
+struct LeasedWriter {
+    writer: Box<dyn Writer>,
+    manager: Arc<dyn Manager>,
+    ctx: Context,
+}
+
+impl Write for LeasedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Writer for LeasedWriter {
+    fn digest(&self) -> String {
+        self.writer.digest()
+    }
+
+    fn commit(&mut self, size: i64, expected: &str, labels: HashMap<String, String>) -> Result<(), Error> {
+        let result = self.writer.commit(size, expected, labels);
+        let committed = match &result {
+            Ok(()) => self.writer.digest(),
+            Err(e) if e.is_already_exists() => expected.to_string(),
+            Err(_) => return result,
+        };
+        if let (Some(lease), false) = (self.ctx.lease(), committed.is_empty()) {
+            let resource = Resource::new(RESOURCE_CONTENT, &committed);
+            self.manager.add_resource(&self.ctx, lease, resource)?;
+        }
+        result
+    }
+
+    fn status(&self) -> Result<Status, Error> {
+        self.writer.status()
+    }
+
+    fn truncate(&mut self, size: i64) -> Result<(), Error> {
+        self.writer.truncate(size)
+    }
+}
+
+/// LeasedSnapshotter adds the snapshots created through it to the lease of
+/// the context, so that they are kept until they are referenced by other
+/// objects. Committed snapshots that already exist are added to the lease as
+/// well.
+///
+/// Snapshots created with a context without lease are not recorded.
+pub struct LeasedSnapshotter {
+    snapshotter: Arc<dyn snapshots::Snapshotter>,
+    name: String,
+    manager: Arc<dyn Manager>,
+}
+
+impl LeasedSnapshotter {
+    /// new wraps the snapshotter registered under name.
+    pub fn new(snapshotter: Arc<dyn snapshots::Snapshotter>, name: &str, manager: Arc<dyn Manager>) -> LeasedSnapshotter {
+        LeasedSnapshotter {
+            snapshotter,
+            name: name.to_string(),
+            manager,
+        }
+    }
+
+    fn add(&self, ctx: &Context, key: &str) -> Result<(), Error> {
+        match ctx.lease() {
+            Some(lease) => self
+                .manager
+                .add_resource(ctx, lease, Resource::snapshot(&self.name, key)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl snapshots::Snapshotter for LeasedSnapshotter {
+    fn stat(&self, ctx: &Context, key: &str) -> Result<snapshots::Info, Error> {
+        self.snapshotter.stat(ctx, key)
+    }
+
+    fn update(&self, ctx: &Context, info: snapshots::Info, fieldpaths: &[&str]) -> Result<snapshots::Info, Error> {
+        self.snapshotter.update(ctx, info, fieldpaths)
+    }
+
+    fn usage(&self, ctx: &Context, key: &str) -> Result<Usage, Error> {
+        self.snapshotter.usage(ctx, key)
+    }
+
+    fn mounts(&self, ctx: &Context, key: &str) -> Result<Vec<Mount>, Error> {
+        self.snapshotter.mounts(ctx, key)
+    }
+
+    fn prepare(
+        &self,
+        ctx: &Context,
+        key: &str,
+        parent: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Vec<Mount>, Error> {
+        self.add(ctx, key)?;
+        self.snapshotter.prepare(ctx, key, parent, labels)
+    }
+
+    fn view(
+        &self,
+        ctx: &Context,
+        key: &str,
+        parent: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Vec<Mount>, Error> {
+        self.add(ctx, key)?;
+        self.snapshotter.view(ctx, key, parent, labels)
+    }
+
+    fn commit(&self, ctx: &Context, name: &str, key: &str, labels: HashMap<String, String>) -> Result<(), Error> {
+        let result = self.snapshotter.commit(ctx, name, key, labels);
+        match &result {
+            Ok(()) => self.add(ctx, name)?,
+            Err(e) if e.is_already_exists() => self.add(ctx, name)?,
+            Err(_) => {}
+        }
+        result
+    }
+
+    fn remove(&self, ctx: &Context, key: &str) -> Result<(), Error> {
+        self.snapshotter.remove(ctx, key)
+    }
+
+    fn walk(
+        &self,
+        ctx: &Context,
+        filters: &[&str],
+        f: &mut dyn FnMut(snapshots::Info) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.snapshotter.walk(ctx, filters, f)
+    }
+}
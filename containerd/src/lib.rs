@@ -1,10 +1,36 @@
-mod runtime;
-mod containers;
 pub mod api;
+pub mod archive;
+pub mod containers;
+pub mod content;
+pub mod context;
+pub mod dialer;
+pub mod diff;
+pub mod digest;
+pub mod errdefs;
+pub mod events;
+pub mod filters;
+pub mod gc;
+pub mod identifiers;
+pub mod images;
+pub mod labels;
+pub mod leases;
+pub mod metadata;
 pub mod mount;
+pub mod namespaces;
+pub mod platforms;
+pub mod protobuf;
+pub mod reference;
+pub mod remotes;
+pub mod runtime;
+pub mod server;
+pub mod services;
+pub mod snapshots;
+pub mod ttrpc;
 
 //TODO: Find out how we can include google/rpc/status.proto
 pub mod plugin {
+    include!(concat!(env!("OUT_DIR"), "/containerd.plugin.rs"));
+
     pub mod rpc {
         include!(concat!(env!("OUT_DIR"), "/containerd.plugin.rpc.rs"));
     }
@@ -0,0 +1,7 @@
+mod buckets;
+pub mod containers;
+pub mod db;
+pub mod gc;
+pub mod images;
+pub mod leases;
+pub mod namespaces;
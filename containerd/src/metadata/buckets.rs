@@ -0,0 +1,171 @@
+//! The layout of the metadata database.
+//!
+//! Objects are stored per schema version and namespace, then by type and
+//! identifier:
+//!
+//! ```text
+//! v1/
+//!   <namespace>/
+//!     labels/<key>          namespace label value
+//!     containers/
+//!       <id>/
+//!         labels/<key>      label value
+//!         image             image reference
+//!         runtime/
+//!           name            runtime name
+//!           options         protobuf Any
+//!         spec              protobuf Any
+//!         snapshotKey       snapshot key
+//!         snapshotter       snapshotter name
+//!         sandboxid         sandbox id
+//!         createdat         unix nanoseconds, big-endian i128
+//!         updatedat         unix nanoseconds, big-endian i128
+//!         extensions/<key>  protobuf Any
+//!     leases/
+//!       <id>/
+//!         labels/<key>      label value
+//!         createdat         unix nanoseconds, big-endian i128
+//!         content/<digest>
+//!         ingests/<ref>
+//!         snapshots/<snapshotter>/<key>
+//! ```
+
+use super::db::Bucket;
+use crate::errdefs::Error;
+use prost::Message;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+pub(crate) const BUCKET_KEY_VERSION: &[u8] = b"v1";
+pub(crate) const BUCKET_KEY_OBJECT_CONTAINERS: &[u8] = b"containers";
+pub(crate) const BUCKET_KEY_OBJECT_LEASES: &[u8] = b"leases";
+pub(crate) const BUCKET_KEY_OBJECT_LABELS: &[u8] = b"labels";
+pub(crate) const BUCKET_KEY_OBJECT_RUNTIME: &[u8] = b"runtime";
+pub(crate) const BUCKET_KEY_OBJECT_EXTENSIONS: &[u8] = b"extensions";
+
+pub(crate) const BUCKET_KEY_IMAGE: &[u8] = b"image";
+pub(crate) const BUCKET_KEY_NAME: &[u8] = b"name";
+pub(crate) const BUCKET_KEY_OPTIONS: &[u8] = b"options";
+pub(crate) const BUCKET_KEY_SPEC: &[u8] = b"spec";
+pub(crate) const BUCKET_KEY_SNAPSHOT_KEY: &[u8] = b"snapshotKey";
+pub(crate) const BUCKET_KEY_SNAPSHOTTER: &[u8] = b"snapshotter";
+pub(crate) const BUCKET_KEY_SANDBOX_ID: &[u8] = b"sandboxid";
+pub(crate) const BUCKET_KEY_CREATED_AT: &[u8] = b"createdat";
+pub(crate) const BUCKET_KEY_UPDATED_AT: &[u8] = b"updatedat";
+
+/// containers_path returns the keys of the containers bucket of the
+/// namespace.
+pub(crate) fn containers_path(namespace: &str) -> [&[u8]; 3] {
+    [BUCKET_KEY_VERSION, namespace.as_bytes(), BUCKET_KEY_OBJECT_CONTAINERS]
+}
+
+/// leases_path returns the keys of the leases bucket of the namespace.
+pub(crate) fn leases_path(namespace: &str) -> [&[u8]; 3] {
+    [BUCKET_KEY_VERSION, namespace.as_bytes(), BUCKET_KEY_OBJECT_LEASES]
+}
+
+/// read_string returns the value of the key as a string, empty if missing.
+pub(crate) fn read_string(bkt: &Bucket, key: &[u8]) -> String {
+    bkt.get(key)
+        .map(|v| String::from_utf8_lossy(v).into_owned())
+        .unwrap_or_default()
+}
+
+/// write_string sets the key to the value, or removes it if the value is
+/// empty.
+pub(crate) fn write_string(bkt: &mut Bucket, key: &[u8], value: &str) -> Result<(), Error> {
+    if value.is_empty() {
+        return bkt.delete(key);
+    }
+    bkt.put(key, value.as_bytes())
+}
+
+pub(crate) fn read_timestamp(bkt: &Bucket, key: &[u8]) -> Result<OffsetDateTime, Error> {
+    let v = match bkt.get(key) {
+        Some(v) => v,
+        None => return Ok(OffsetDateTime::UNIX_EPOCH),
+    };
+    let nanos = <[u8; 16]>::try_from(v)
+        .map(i128::from_be_bytes)
+        .map_err(|_| Error::Unknown(format!("invalid timestamp of {:?}", String::from_utf8_lossy(key))))?;
+    OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|e| Error::Unknown(e.to_string()))
+}
+
+pub(crate) fn write_timestamp(bkt: &mut Bucket, key: &[u8], t: OffsetDateTime) -> Result<(), Error> {
+    bkt.put(key, &t.unix_timestamp_nanos().to_be_bytes())
+}
+
+pub(crate) fn read_any(bkt: &Bucket, key: &[u8]) -> Result<prost_types::Any, Error> {
+    match bkt.get(key) {
+        Some(v) => decode_any(key, v),
+        None => Ok(Default::default()),
+    }
+}
+
+pub(crate) fn write_any(bkt: &mut Bucket, key: &[u8], any: &prost_types::Any) -> Result<(), Error> {
+    if any.type_url.is_empty() && any.value.is_empty() {
+        return bkt.delete(key);
+    }
+    bkt.put(key, &any.encode_to_vec())
+}
+
+/// read_labels returns the values of the nested bucket of the key.
+pub(crate) fn read_labels(bkt: &Bucket, key: &[u8]) -> HashMap<String, String> {
+    match bkt.bucket(key) {
+        Some(labels) => labels
+            .values()
+            .map(|(k, v)| {
+                (
+                    String::from_utf8_lossy(k).into_owned(),
+                    String::from_utf8_lossy(v).into_owned(),
+                )
+            })
+            .collect(),
+        None => HashMap::new(),
+    }
+}
+
+/// write_labels replaces the nested bucket of the key with the labels, the
+/// bucket is removed when there are none.
+pub(crate) fn write_labels(bkt: &mut Bucket, key: &[u8], labels: &HashMap<String, String>) -> Result<(), Error> {
+    if bkt.bucket(key).is_some() {
+        bkt.delete_bucket(key)?;
+    }
+    if labels.is_empty() {
+        return Ok(());
+    }
+    let lbkt = bkt.create_bucket(key)?;
+    for (k, v) in labels {
+        lbkt.put(k.as_bytes(), v.as_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_extensions(bkt: &Bucket) -> Result<HashMap<String, prost_types::Any>, Error> {
+    let mut extensions = HashMap::new();
+    if let Some(ebkt) = bkt.bucket(BUCKET_KEY_OBJECT_EXTENSIONS) {
+        for (k, v) in ebkt.values() {
+            extensions.insert(String::from_utf8_lossy(k).into_owned(), decode_any(k, v)?);
+        }
+    }
+    Ok(extensions)
+}
+
+pub(crate) fn write_extensions(bkt: &mut Bucket, extensions: &HashMap<String, prost_types::Any>) -> Result<(), Error> {
+    if bkt.bucket(BUCKET_KEY_OBJECT_EXTENSIONS).is_some() {
+        bkt.delete_bucket(BUCKET_KEY_OBJECT_EXTENSIONS)?;
+    }
+    if extensions.is_empty() {
+        return Ok(());
+    }
+    let ebkt = bkt.create_bucket(BUCKET_KEY_OBJECT_EXTENSIONS)?;
+    for (k, v) in extensions {
+        ebkt.put(k.as_bytes(), &v.encode_to_vec())?;
+    }
+    Ok(())
+}
+
+fn decode_any(key: &[u8], v: &[u8]) -> Result<prost_types::Any, Error> {
+    prost_types::Any::decode(v)
+        .map_err(|e| Error::Unknown(format!("failed to decode {:?}: {}", String::from_utf8_lossy(key), e)))
+}
@@ -0,0 +1,154 @@
+use super::buckets::{
+    containers_path, read_any, read_extensions, read_labels, read_string, read_timestamp, write_any, write_extensions,
+    write_labels, write_string, write_timestamp, BUCKET_KEY_CREATED_AT, BUCKET_KEY_IMAGE, BUCKET_KEY_NAME,
+    BUCKET_KEY_OBJECT_LABELS, BUCKET_KEY_OBJECT_RUNTIME, BUCKET_KEY_OPTIONS, BUCKET_KEY_SANDBOX_ID,
+    BUCKET_KEY_SNAPSHOTTER, BUCKET_KEY_SNAPSHOT_KEY, BUCKET_KEY_SPEC, BUCKET_KEY_UPDATED_AT,
+};
+use super::db::{Bucket, DB};
+use crate::containers::{self, Container, RuntimeInfo};
+use crate::context::Context;
+use crate::errdefs::Error;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+/// ContainerStore keeps the containers in the metadata database, under
+/// `v1/<namespace>/containers/<id>`.
+pub struct ContainerStore {
+    db: Arc<DB>,
+}
+
+impl ContainerStore {
+    pub fn new(db: Arc<DB>) -> ContainerStore {
+        ContainerStore { db }
+    }
+}
+
+impl containers::Store for ContainerStore {
+    fn get(&self, ctx: &Context, id: &str) -> Result<Container, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        self.db.view(|tx| {
+            let bkt = tx
+                .path(&containers_path(namespace))
+                .and_then(|bkt| bkt.bucket(id.as_bytes()))
+                .ok_or_else(|| Error::NotFound(format!("container {:?} in namespace {:?}", id, namespace)))?;
+            read_container(id, bkt)
+        })
+    }
+
+    fn list(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Container>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let filter = crate::filters::parse_all(filters)?;
+
+        self.db.view(|tx| {
+            let bkt = match tx.path(&containers_path(namespace)) {
+                Some(bkt) => bkt,
+                None => return Ok(Vec::new()),
+            };
+
+            let mut matched = Vec::new();
+            for (id, cbkt) in bkt.buckets() {
+                let container = read_container(&String::from_utf8_lossy(id), cbkt)?;
+                if filter.matches(&container) {
+                    matched.push(container);
+                }
+            }
+            Ok(matched)
+        })
+    }
+
+    fn create(&self, ctx: &Context, mut container: Container) -> Result<Container, Error> {
+        let namespace = ctx.namespace_required()?;
+        containers::validate(&container)?;
+
+        self.db.update(|tx| {
+            let bkt = tx.create_path(&containers_path(namespace))?;
+            let cbkt = bkt.create_bucket(container.id.as_bytes()).map_err(|e| match e {
+                Error::AlreadyExists(_) => Error::AlreadyExists(format!("container {:?}", container.id)),
+                e => e,
+            })?;
+
+            container.created_at = OffsetDateTime::now_utc();
+            container.updated_at = container.created_at;
+            write_container(cbkt, &container)?;
+            Ok(container)
+        })
+    }
+
+    fn update(&self, ctx: &Context, container: Container, fieldpaths: &[&str]) -> Result<Container, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        if container.id.is_empty() {
+            return Err(Error::InvalidArgument("must specify a container id".to_string()));
+        }
+
+        self.db.update(|tx| {
+            let cbkt = tx
+                .path_mut(&containers_path(namespace))
+                .and_then(|bkt| bkt.bucket_mut(container.id.as_bytes()))
+                .ok_or_else(|| Error::NotFound(format!("container {:?}", container.id)))?;
+            let mut updated = read_container(&container.id, cbkt)?;
+            containers::update_fields(&mut updated, container, fieldpaths)?;
+            containers::validate(&updated)?;
+
+            updated.updated_at = OffsetDateTime::now_utc();
+            write_container(cbkt, &updated)?;
+            Ok(updated)
+        })
+    }
+
+    fn delete(&self, ctx: &Context, id: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+
+        self.db.update(|tx| {
+            let bkt = tx
+                .path_mut(&containers_path(namespace))
+                .filter(|bkt| bkt.bucket(id.as_bytes()).is_some())
+                .ok_or_else(|| Error::NotFound(format!("container {:?} in namespace {:?}", id, namespace)))?;
+            self.db.mark_dirty();
+            bkt.delete_bucket(id.as_bytes())
+        })
+    }
+}
+
+fn read_container(id: &str, bkt: &Bucket) -> Result<Container, Error> {
+    let runtime = match bkt.bucket(BUCKET_KEY_OBJECT_RUNTIME) {
+        Some(rbkt) => RuntimeInfo {
+            name: read_string(rbkt, BUCKET_KEY_NAME),
+            options: read_any(rbkt, BUCKET_KEY_OPTIONS)?,
+        },
+        None => RuntimeInfo::default(),
+    };
+
+    Ok(Container {
+        id: id.to_string(),
+        labels: read_labels(bkt, BUCKET_KEY_OBJECT_LABELS),
+        image: read_string(bkt, BUCKET_KEY_IMAGE),
+        runtime,
+        spec: read_any(bkt, BUCKET_KEY_SPEC)?,
+        snapshot_key: read_string(bkt, BUCKET_KEY_SNAPSHOT_KEY),
+        snapshotter: read_string(bkt, BUCKET_KEY_SNAPSHOTTER),
+        created_at: read_timestamp(bkt, BUCKET_KEY_CREATED_AT)?,
+        updated_at: read_timestamp(bkt, BUCKET_KEY_UPDATED_AT)?,
+        extensions: read_extensions(bkt)?,
+        sandbox_id: read_string(bkt, BUCKET_KEY_SANDBOX_ID),
+    })
+}
+
+fn write_container(bkt: &mut Bucket, container: &Container) -> Result<(), Error> {
+    write_labels(bkt, BUCKET_KEY_OBJECT_LABELS, &container.labels)?;
+    write_string(bkt, BUCKET_KEY_IMAGE, &container.image)?;
+
+    let rbkt = bkt.create_bucket_if_not_exists(BUCKET_KEY_OBJECT_RUNTIME)?;
+    write_string(rbkt, BUCKET_KEY_NAME, &container.runtime.name)?;
+    write_any(rbkt, BUCKET_KEY_OPTIONS, &container.runtime.options)?;
+
+    write_any(bkt, BUCKET_KEY_SPEC, &container.spec)?;
+    write_string(bkt, BUCKET_KEY_SNAPSHOT_KEY, &container.snapshot_key)?;
+    write_string(bkt, BUCKET_KEY_SNAPSHOTTER, &container.snapshotter)?;
+    write_string(bkt, BUCKET_KEY_SANDBOX_ID, &container.sandbox_id)?;
+    write_timestamp(bkt, BUCKET_KEY_CREATED_AT, container.created_at)?;
+    write_timestamp(bkt, BUCKET_KEY_UPDATED_AT, container.updated_at)?;
+    write_extensions(bkt, &container.extensions)
+}
@@ -0,0 +1,379 @@
+use crate::errdefs::Error;
+use crate::gc::{MutationCallback, MutationCallbacks};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// MAGIC starts every database file.
+const MAGIC: &[u8; 8] = b"ctrdmeta";
+
+/// FORMAT_VERSION is the version of the file encoding, unrelated to the
+/// version of the schema stored in it.
+const FORMAT_VERSION: u32 = 1;
+
+const KIND_VALUE: u8 = 0;
+const KIND_BUCKET: u8 = 1;
+
+/// DB is an embedded key-value database organized as nested buckets, in the
+/// spirit of bolt: keys are ordered byte strings holding either a value or a
+/// bucket of more keys.
+///
+/// The whole database is kept in memory. Transactions run on a copy of the
+/// tree and a successful update is written to disk before it becomes visible,
+/// so that a failed update, or a crash in the middle of one, leaves the
+/// previous state in place. Updates are serialized, views run concurrently.
+pub struct DB {
+    path: PathBuf,
+    root: RwLock<Bucket>,
+    // set by the update in progress when it removed references
+    dirty: AtomicBool,
+    mutation_callbacks: MutationCallbacks,
+    // holds the lock on the database for as long as it is open
+    _lock: File,
+}
+
+impl DB {
+    /// open opens the database at path, creating it if it does not exist.
+    ///
+    /// The database can only be opened once at a time, Unavailable is
+    /// returned while another DB holds it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<DB, Error> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension("lock"))?;
+        // SAFETY: the descriptor is owned by lock and stays open with it.
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(Error::Unavailable(format!(
+                "metadata database {} is in use",
+                path.display()
+            )));
+        }
+
+        let root = match fs::read(&path) {
+            Ok(p) => decode(&p).map_err(|e| Error::Unknown(format!("metadata database {}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Bucket::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(DB {
+            path,
+            root: RwLock::new(root),
+            dirty: AtomicBool::new(false),
+            mutation_callbacks: MutationCallbacks::default(),
+            _lock: lock,
+        })
+    }
+
+    /// path returns the location of the database file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// view runs f with a read-only view of the root bucket.
+    pub fn view<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Bucket) -> Result<T, Error>,
+    {
+        let root = self.root.read().unwrap();
+        f(&root)
+    }
+
+    /// update runs f in a read-write transaction on the root bucket. The
+    /// changes are committed if f succeeds and discarded otherwise, the
+    /// mutation callbacks are called once they are committed.
+    pub fn update<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Bucket) -> Result<T, Error>,
+    {
+        let mut root = self.root.write().unwrap();
+        let mut tx = root.clone();
+        let result = f(&mut tx);
+        let dirty = self.dirty.swap(false, Ordering::Relaxed);
+        let result = result?;
+        if tx == *root {
+            return Ok(result);
+        }
+        self.write(&tx)?;
+        *root = tx;
+        drop(root);
+
+        self.mutation_callbacks.notify(dirty);
+        Ok(result)
+    }
+
+    /// mark_dirty marks the update in progress as removing references, which
+    /// the mutation callbacks are told about.
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// register_mutation_callback registers a callback called after every
+    /// committed update.
+    pub fn register_mutation_callback(&self, f: MutationCallback) {
+        self.mutation_callbacks.register(f);
+    }
+
+    /// write replaces the database file with the encoded tree.
+    fn write(&self, root: &Bucket) -> Result<(), Error> {
+        let tmp = self.path.with_extension("tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&encode(root))?;
+        f.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        if let Some(parent) = self.path.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Value(Vec<u8>),
+    Bucket(Bucket),
+}
+
+/// Bucket is a collection of ordered keys, each holding a value or a nested
+/// bucket.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bucket {
+    entries: BTreeMap<Vec<u8>, Entry>,
+}
+
+impl Bucket {
+    /// get returns the value of the key, None if it is missing or a bucket.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        match self.entries.get(key) {
+            Some(Entry::Value(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// put sets the value of the key.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidArgument("bucket key must not be empty".to_string()));
+        }
+        if let Some(Entry::Bucket(_)) = self.entries.get(key) {
+            return Err(incompatible(key));
+        }
+        self.entries.insert(key.to_vec(), Entry::Value(value.to_vec()));
+        Ok(())
+    }
+
+    /// delete removes the value of the key, it is not an error for the key to
+    /// be missing.
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        match self.entries.get(key) {
+            Some(Entry::Bucket(_)) => Err(incompatible(key)),
+            Some(Entry::Value(_)) => {
+                self.entries.remove(key);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// bucket returns the nested bucket of the key.
+    pub fn bucket(&self, key: &[u8]) -> Option<&Bucket> {
+        match self.entries.get(key) {
+            Some(Entry::Bucket(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// bucket_mut returns the nested bucket of the key for writing.
+    pub fn bucket_mut(&mut self, key: &[u8]) -> Option<&mut Bucket> {
+        match self.entries.get_mut(key) {
+            Some(Entry::Bucket(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// create_bucket creates the nested bucket of the key, which must not
+    /// exist yet.
+    pub fn create_bucket(&mut self, key: &[u8]) -> Result<&mut Bucket, Error> {
+        if self.entries.contains_key(key) {
+            return Err(Error::AlreadyExists(format!(
+                "bucket {:?}",
+                String::from_utf8_lossy(key)
+            )));
+        }
+        self.create_bucket_if_not_exists(key)
+    }
+
+    /// create_bucket_if_not_exists returns the nested bucket of the key,
+    /// creating it if needed.
+    pub fn create_bucket_if_not_exists(&mut self, key: &[u8]) -> Result<&mut Bucket, Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidArgument("bucket key must not be empty".to_string()));
+        }
+        match self
+            .entries
+            .entry(key.to_vec())
+            .or_insert_with(|| Entry::Bucket(Bucket::default()))
+        {
+            Entry::Bucket(b) => Ok(b),
+            Entry::Value(_) => Err(incompatible(key)),
+        }
+    }
+
+    /// delete_bucket removes the nested bucket of the key with all its
+    /// content.
+    pub fn delete_bucket(&mut self, key: &[u8]) -> Result<(), Error> {
+        match self.entries.get(key) {
+            Some(Entry::Bucket(_)) => {
+                self.entries.remove(key);
+                Ok(())
+            }
+            Some(Entry::Value(_)) => Err(incompatible(key)),
+            None => Err(Error::NotFound(format!("bucket {:?}", String::from_utf8_lossy(key)))),
+        }
+    }
+
+    /// path returns the bucket nested under each of the keys in turn.
+    pub fn path(&self, keys: &[&[u8]]) -> Option<&Bucket> {
+        keys.iter().try_fold(self, |bkt, key| bkt.bucket(key))
+    }
+
+    /// path_mut returns the bucket nested under each of the keys in turn for
+    /// writing.
+    pub fn path_mut(&mut self, keys: &[&[u8]]) -> Option<&mut Bucket> {
+        keys.iter().try_fold(self, |bkt, key| bkt.bucket_mut(key))
+    }
+
+    /// create_path returns the bucket nested under each of the keys in turn,
+    /// creating the missing ones.
+    pub fn create_path(&mut self, keys: &[&[u8]]) -> Result<&mut Bucket, Error> {
+        keys.iter()
+            .try_fold(self, |bkt, key| bkt.create_bucket_if_not_exists(key))
+    }
+
+    /// values returns the keys holding a value, in order.
+    pub fn values(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries.iter().filter_map(|(k, e)| match e {
+            Entry::Value(v) => Some((k.as_slice(), v.as_slice())),
+            Entry::Bucket(_) => None,
+        })
+    }
+
+    /// buckets returns the keys holding a nested bucket, in order.
+    pub fn buckets(&self) -> impl Iterator<Item = (&[u8], &Bucket)> {
+        self.entries.iter().filter_map(|(k, e)| match e {
+            Entry::Bucket(b) => Some((k.as_slice(), b)),
+            Entry::Value(_) => None,
+        })
+    }
+
+    /// is_empty reports whether the bucket holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn incompatible(key: &[u8]) -> Error {
+    Error::InvalidArgument(format!("incompatible value for key {:?}", String::from_utf8_lossy(key)))
+}
+
+/// encode returns the database file for the tree: the magic, the format
+/// version, the tree and the sha256 checksum of everything before it.
+fn encode(root: &Bucket) -> Vec<u8> {
+    let mut p = MAGIC.to_vec();
+    p.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    encode_bucket(&mut p, root);
+    let sum = Sha256::digest(&p);
+    p.extend_from_slice(&sum);
+    p
+}
+
+fn encode_bucket(p: &mut Vec<u8>, bkt: &Bucket) {
+    p.extend_from_slice(&(bkt.entries.len() as u32).to_be_bytes());
+    for (key, entry) in &bkt.entries {
+        p.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        p.extend_from_slice(key);
+        match entry {
+            Entry::Value(v) => {
+                p.push(KIND_VALUE);
+                p.extend_from_slice(&(v.len() as u32).to_be_bytes());
+                p.extend_from_slice(v);
+            }
+            Entry::Bucket(b) => {
+                p.push(KIND_BUCKET);
+                encode_bucket(p, b);
+            }
+        }
+    }
+}
+
+fn decode(p: &[u8]) -> Result<Bucket, String> {
+    if p.len() < MAGIC.len() + 4 + 32 || &p[..MAGIC.len()] != MAGIC {
+        return Err("not a metadata database".to_string());
+    }
+    let (data, sum) = p.split_at(p.len() - 32);
+    if Sha256::digest(data).as_slice() != sum {
+        return Err("checksum mismatch".to_string());
+    }
+
+    let mut d = Decoder {
+        p: &data[MAGIC.len()..],
+    };
+    let version = d.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(format!("unsupported format version {}", version));
+    }
+    let root = d.bucket()?;
+    if !d.p.is_empty() {
+        return Err("trailing data".to_string());
+    }
+    Ok(root)
+}
+
+struct Decoder<'a> {
+    p: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.p.len() < n {
+            return Err("unexpected end of data".to_string());
+        }
+        let (head, tail) = self.p.split_at(n);
+        self.p = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let n = self.u32()? as usize;
+        Ok(self.take(n)?.to_vec())
+    }
+
+    fn bucket(&mut self) -> Result<Bucket, String> {
+        let mut bkt = Bucket::default();
+        for _ in 0..self.u32()? {
+            let key = self.bytes()?;
+            let entry = match self.take(1)?[0] {
+                KIND_VALUE => Entry::Value(self.bytes()?),
+                KIND_BUCKET => Entry::Bucket(self.bucket()?),
+                kind => return Err(format!("unknown entry kind {}", kind)),
+            };
+            bkt.entries.insert(key, entry);
+        }
+        Ok(bkt)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::containers;
+use crate::content;
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::gc::{self, Node, ResourceType, Stats};
+use crate::images;
+use crate::leases::{self, Resource};
+use crate::snapshots::Snapshotter;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+use time::OffsetDateTime;
+
+/// Namespaces lists the namespaces to collect.
+pub type Namespaces = Arc<dyn Fn() -> Result<Vec<String>, Error> + Send + Sync>;
+
+/// GarbageCollector collects the content and the snapshots of the stores.
+///
+/// The roots of each namespace are the targets of its images, the snapshots
+/// and the references of its containers, the resources of its leases that
+/// have not expired, and the content and snapshots labeled with
+/// `containerd.io/gc.root`. The content and snapshots reachable from the roots
+/// through their parents and `containerd.io/gc.ref.*` labels are kept, the
+/// others are removed along with the expired leases.
+///
+/// The stores are not locked during a collection, objects created after it
+/// started are kept. Writers should hold a lease until their objects are
+/// referenced.
+pub struct GarbageCollector {
+    namespaces: Namespaces,
+    content: Arc<dyn content::Store>,
+    images: Arc<dyn images::Store>,
+    containers: Arc<dyn containers::Store>,
+    leases: Arc<dyn leases::Manager>,
+    snapshotters: HashMap<String, Arc<dyn Snapshotter>>,
+}
+
+impl GarbageCollector {
+    pub fn new(
+        namespaces: Namespaces,
+        content: Arc<dyn content::Store>,
+        images: Arc<dyn images::Store>,
+        containers: Arc<dyn containers::Store>,
+        leases: Arc<dyn leases::Manager>,
+    ) -> GarbageCollector {
+        GarbageCollector {
+            namespaces,
+            content,
+            images,
+            containers,
+            leases,
+            snapshotters: HashMap::new(),
+        }
+    }
+
+    /// with_snapshotter adds a snapshotter to collect under its name.
+    pub fn with_snapshotter(mut self, name: &str, snapshotter: Arc<dyn Snapshotter>) -> GarbageCollector {
+        self.snapshotters.insert(name.to_string(), snapshotter);
+        self
+    }
+
+    /// roots returns the roots of the namespace, along with the identifiers
+    /// of its expired leases.
+    fn roots(&self, ctx: &Context, now: OffsetDateTime) -> Result<(Vec<Node>, Vec<String>), Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut roots = Vec::new();
+        let mut expired = Vec::new();
+
+        for image in self.images.list(ctx, &[])? {
+            roots.push(Node::content(namespace, &image.target.digest));
+        }
+
+        for container in self.containers.list(ctx, &[])? {
+            if !container.snapshotter.is_empty() && !container.snapshot_key.is_empty() {
+                roots.push(Node::snapshot(
+                    namespace,
+                    &container.snapshotter,
+                    &container.snapshot_key,
+                ));
+            }
+            roots.extend(gc::references(namespace, &container.labels));
+        }
+
+        for lease in self.leases.list(ctx, &[])? {
+            if lease.is_expired(now) {
+                expired.push(lease.id);
+                continue;
+            }
+            for resource in self.leases.list_resources(ctx, &lease.id)? {
+                roots.extend(resource_node(namespace, &resource));
+            }
+        }
+
+        self.content.walk(ctx, &[], &mut |info| {
+            if gc::is_root(&info.labels) {
+                roots.push(Node::content(namespace, &info.digest));
+            }
+            Ok(())
+        })?;
+
+        for (name, snapshotter) in &self.snapshotters {
+            snapshotter.walk(ctx, &[], &mut |info| {
+                if gc::is_root(&info.labels) {
+                    roots.push(Node::snapshot(namespace, name, &info.name));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok((roots, expired))
+    }
+
+    /// references returns the nodes referenced by the node. Missing objects
+    /// reference nothing.
+    fn references(&self, node: &Node) -> Result<Vec<Node>, Error> {
+        let ctx = Context::new().with_namespace(&node.namespace);
+        match node.kind {
+            ResourceType::Content => match self.content.info(&ctx, &node.key) {
+                Ok(info) => Ok(gc::references(&node.namespace, &info.labels)),
+                Err(Error::NotFound(_)) => Ok(Vec::new()),
+                Err(e) => Err(e),
+            },
+            ResourceType::Snapshot => {
+                let (name, key) = match node.snapshot_key() {
+                    Some(k) => k,
+                    None => return Ok(Vec::new()),
+                };
+                let snapshotter = match self.snapshotters.get(name) {
+                    Some(s) => s,
+                    None => return Ok(Vec::new()),
+                };
+                let info = match snapshotter.stat(&ctx, key) {
+                    Ok(info) => info,
+                    Err(Error::NotFound(_)) => return Ok(Vec::new()),
+                    Err(e) => return Err(e),
+                };
+                let mut refs = gc::references(&node.namespace, &info.labels);
+                if !info.parent.is_empty() {
+                    refs.push(Node::snapshot(&node.namespace, name, &info.parent));
+                }
+                Ok(refs)
+            }
+        }
+    }
+
+    /// sweep removes the content and snapshots of the namespace that are
+    /// not reachable and were created before the collection started.
+    fn sweep(
+        &self,
+        ctx: &Context,
+        reachable: &HashSet<Node>,
+        started: OffsetDateTime,
+        stats: &mut Stats,
+    ) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut unreachable = Vec::new();
+        self.content.walk(ctx, &[], &mut |info| {
+            if info.created_at <= started && !reachable.contains(&Node::content(namespace, &info.digest)) {
+                unreachable.push(info.digest);
+            }
+            Ok(())
+        })?;
+        for dgst in unreachable {
+            match self.content.delete(ctx, &dgst) {
+                Ok(()) => stats.content_deleted += 1,
+                Err(Error::NotFound(_)) => {}
+                Err(e) => log::warn!("failed to remove content {:?}: {}", dgst, e),
+            }
+        }
+
+        for (name, snapshotter) in &self.snapshotters {
+            let mut unreachable = Vec::new();
+            snapshotter.walk(ctx, &[], &mut |info| {
+                if info.created_at <= started && !reachable.contains(&Node::snapshot(namespace, name, &info.name)) {
+                    unreachable.push(info.name);
+                }
+                Ok(())
+            })?;
+
+            // children have to be removed before their parents, retry the
+            // snapshots with children until no more can be removed
+            while !unreachable.is_empty() {
+                let attempted = unreachable.len();
+                let mut remaining = Vec::new();
+                for key in unreachable {
+                    match snapshotter.remove(ctx, &key) {
+                        Ok(()) => stats.snapshots_deleted += 1,
+                        Err(Error::NotFound(_)) => {}
+                        Err(Error::FailedPrecondition(_)) => remaining.push(key),
+                        Err(e) => log::warn!("failed to remove snapshot {:?} of {}: {}", key, name, e),
+                    }
+                }
+                if remaining.len() == attempted {
+                    for key in &remaining {
+                        log::warn!("failed to remove snapshot {:?} of {}: still has children", key, name);
+                    }
+                    break;
+                }
+                unreachable = remaining;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl gc::Collector for GarbageCollector {
+    fn garbage_collect(&self) -> Result<Stats, Error> {
+        let mut stats = Stats::default();
+        let started = OffsetDateTime::now_utc();
+        let mark = Instant::now();
+
+        let namespaces = (self.namespaces)()?;
+        let mut roots = Vec::new();
+        let mut expired = HashMap::new();
+        for namespace in &namespaces {
+            let (r, e) = self.roots(&Context::new().with_namespace(namespace), started)?;
+            roots.extend(r);
+            expired.insert(namespace.as_str(), e);
+        }
+        let reachable = gc::tricolor(roots, |node| self.references(node))?;
+        stats.mark = mark.elapsed();
+
+        let sweep = Instant::now();
+        for namespace in &namespaces {
+            let ctx = Context::new().with_namespace(namespace);
+            for id in &expired[namespace.as_str()] {
+                match self.leases.delete(&ctx, id) {
+                    Ok(()) => stats.leases_deleted += 1,
+                    Err(Error::NotFound(_)) => {}
+                    Err(e) => log::warn!("failed to remove expired lease {:?}: {}", id, e),
+                }
+            }
+            self.sweep(&ctx, &reachable, started, &mut stats)?;
+        }
+        stats.sweep = sweep.elapsed();
+
+        Ok(stats)
+    }
+}
+
+/// resource_node returns the node of a lease resource, ingests are not
+/// collected.
+fn resource_node(namespace: &str, resource: &Resource) -> Option<Node> {
+    match resource.kind.split_once('/') {
+        None if resource.kind == leases::RESOURCE_CONTENT => Some(Node::content(namespace, &resource.id)),
+        Some((leases::RESOURCE_SNAPSHOTS_PREFIX, snapshotter)) => {
+            Some(Node::snapshot(namespace, snapshotter, &resource.id))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,188 @@
+use crate::api::events::{ImageCreate, ImageDelete, ImageUpdate};
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::events::{self, Publisher};
+use crate::gc::{MutationCallback, MutationCallbacks};
+use crate::images::{self, Image};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use time::OffsetDateTime;
+
+/// ImageStore keeps image records per namespace and publishes an event for
+/// every change made to them.
+pub struct ImageStore {
+    // images indexed by namespace and then by name
+    images: RwLock<HashMap<String, HashMap<String, Image>>>,
+    publisher: Arc<dyn Publisher>,
+    mutation_callbacks: MutationCallbacks,
+}
+
+impl ImageStore {
+    pub fn new(publisher: Arc<dyn Publisher>) -> ImageStore {
+        ImageStore {
+            images: RwLock::new(HashMap::new()),
+            publisher,
+            mutation_callbacks: MutationCallbacks::default(),
+        }
+    }
+
+    /// register_mutation_callback registers a callback called after every
+    /// change made to the images, deletions being dirty.
+    pub fn register_mutation_callback(&self, f: MutationCallback) {
+        self.mutation_callbacks.register(f);
+    }
+
+    fn publish(&self, namespace: &str, topic: &str, event: prost_types::Any) {
+        if let Err(e) = self.publisher.publish(namespace, topic, event) {
+            log::warn!("failed to publish {} event: {}", topic, e);
+        }
+    }
+}
+
+impl images::Store for ImageStore {
+    fn get(&self, ctx: &Context, name: &str) -> Result<Image, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let images = self.images.read().unwrap();
+        match images.get(namespace).and_then(|ns| ns.get(name)) {
+            Some(image) => Ok(image.clone()),
+            None => Err(Error::NotFound(format!("image {:?}", name))),
+        }
+    }
+
+    fn list(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Image>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let filter = crate::filters::parse_all(filters)?;
+
+        let images = self.images.read().unwrap();
+        let mut matched: Vec<Image> = match images.get(namespace) {
+            Some(ns) => ns.values().filter(|image| filter.matches(*image)).cloned().collect(),
+            None => Vec::new(),
+        };
+        matched.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(matched)
+    }
+
+    fn create(&self, ctx: &Context, mut image: Image) -> Result<Image, Error> {
+        let namespace = ctx.namespace_required()?;
+        images::validate(&image)?;
+
+        {
+            let mut images = self.images.write().unwrap();
+            let ns = images.entry(namespace.to_string()).or_default();
+            if ns.contains_key(&image.name) {
+                return Err(Error::AlreadyExists(format!("image {:?}", image.name)));
+            }
+
+            image.created_at = OffsetDateTime::now_utc();
+            image.updated_at = image.created_at;
+            ns.insert(image.name.clone(), image.clone());
+        }
+        self.mutation_callbacks.notify(false);
+
+        self.publish(
+            namespace,
+            "/images/create",
+            events::marshal(&ImageCreate {
+                name: image.name.clone(),
+                labels: image.labels.clone(),
+            }),
+        );
+
+        Ok(image)
+    }
+
+    fn update(&self, ctx: &Context, image: Image, fieldpaths: &[&str]) -> Result<Image, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        if image.name.is_empty() {
+            return Err(Error::InvalidArgument("image name is required for update".to_string()));
+        }
+
+        let updated = {
+            let mut images = self.images.write().unwrap();
+            let current = match images.get_mut(namespace).and_then(|ns| ns.get_mut(&image.name)) {
+                Some(current) => current,
+                None => return Err(Error::NotFound(format!("image {:?}", image.name))),
+            };
+
+            let mut updated = current.clone();
+            if fieldpaths.is_empty() {
+                // full replace of the mutable fields
+                updated.labels = image.labels;
+                updated.target = image.target;
+            } else {
+                for path in fieldpaths {
+                    if let Some(key) = path.strip_prefix("labels.") {
+                        match image.labels.get(key) {
+                            Some(value) => updated.labels.insert(key.to_string(), value.clone()),
+                            None => updated.labels.remove(key),
+                        };
+                        continue;
+                    }
+
+                    if let Some(key) = path.strip_prefix("annotations.") {
+                        match image.target.annotations.get(key) {
+                            Some(value) => updated.target.annotations.insert(key.to_string(), value.clone()),
+                            None => updated.target.annotations.remove(key),
+                        };
+                        continue;
+                    }
+
+                    match *path {
+                        "labels" => updated.labels = image.labels.clone(),
+                        "target" => updated.target = image.target.clone(),
+                        "annotations" => updated.target.annotations = image.target.annotations.clone(),
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "cannot update {:?} field on image {:?}",
+                                path, image.name
+                            )))
+                        }
+                    }
+                }
+            }
+
+            images::validate(&updated)?;
+
+            updated.updated_at = OffsetDateTime::now_utc();
+            *current = updated.clone();
+            updated
+        };
+        self.mutation_callbacks.notify(false);
+
+        self.publish(
+            namespace,
+            "/images/update",
+            events::marshal(&ImageUpdate {
+                name: updated.name.clone(),
+                labels: updated.labels.clone(),
+            }),
+        );
+
+        Ok(updated)
+    }
+
+    fn delete(&self, ctx: &Context, name: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+
+        {
+            let mut images = self.images.write().unwrap();
+            let removed = images.get_mut(namespace).and_then(|ns| ns.remove(name));
+            if removed.is_none() {
+                return Err(Error::NotFound(format!("image {:?}", name)));
+            }
+        }
+        self.mutation_callbacks.notify(true);
+
+        self.publish(
+            namespace,
+            "/images/delete",
+            events::marshal(&ImageDelete { name: name.to_string() }),
+        );
+
+        Ok(())
+    }
+}
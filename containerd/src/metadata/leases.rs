@@ -0,0 +1,161 @@
+use super::buckets::{
+    leases_path, read_labels, read_timestamp, write_labels, write_timestamp, BUCKET_KEY_CREATED_AT,
+    BUCKET_KEY_OBJECT_LABELS,
+};
+use super::db::{Bucket, DB};
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::leases::{self, Lease, Resource};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+/// LeaseManager keeps the leases in the metadata database, under
+/// `v1/<namespace>/leases/<id>`, along with the resources they reference.
+/// Leases and their resources are kept across restarts.
+///
+/// Expired leases are kept until they are deleted, the garbage collector no
+/// longer considers their resources referenced.
+pub struct LeaseManager {
+    db: Arc<DB>,
+}
+
+impl LeaseManager {
+    pub fn new(db: Arc<DB>) -> LeaseManager {
+        LeaseManager { db }
+    }
+}
+
+impl leases::Manager for LeaseManager {
+    fn create(&self, ctx: &Context, mut lease: Lease) -> Result<Lease, Error> {
+        let namespace = ctx.namespace_required()?;
+        leases::validate(&lease)?;
+
+        self.db.update(|tx| {
+            let bkt = tx.create_path(&leases_path(namespace))?;
+            let lbkt = bkt.create_bucket(lease.id.as_bytes()).map_err(|e| match e {
+                Error::AlreadyExists(_) => Error::AlreadyExists(format!("lease {:?}", lease.id)),
+                e => e,
+            })?;
+
+            lease.created_at = OffsetDateTime::now_utc();
+            write_timestamp(lbkt, BUCKET_KEY_CREATED_AT, lease.created_at)?;
+            write_labels(lbkt, BUCKET_KEY_OBJECT_LABELS, &lease.labels)?;
+            Ok(lease)
+        })
+    }
+
+    fn delete(&self, ctx: &Context, id: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+
+        self.db.update(|tx| {
+            let bkt = tx
+                .path_mut(&leases_path(namespace))
+                .filter(|bkt| bkt.bucket(id.as_bytes()).is_some())
+                .ok_or_else(|| Error::NotFound(format!("lease {:?}", id)))?;
+            self.db.mark_dirty();
+            bkt.delete_bucket(id.as_bytes())
+        })
+    }
+
+    fn list(&self, ctx: &Context, filters: &[&str]) -> Result<Vec<Lease>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        let filter = crate::filters::parse_all(filters)?;
+
+        self.db.view(|tx| {
+            let bkt = match tx.path(&leases_path(namespace)) {
+                Some(bkt) => bkt,
+                None => return Ok(Vec::new()),
+            };
+
+            // buckets are ordered by key, so are the leases by id
+            let mut matched = Vec::new();
+            for (id, lbkt) in bkt.buckets() {
+                let lease = read_lease(&String::from_utf8_lossy(id), lbkt)?;
+                if filter.matches(&lease) {
+                    matched.push(lease);
+                }
+            }
+            Ok(matched)
+        })
+    }
+
+    fn add_resource(&self, ctx: &Context, id: &str, resource: Resource) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        leases::validate_resource(&resource)?;
+
+        self.db.update(|tx| {
+            let lbkt = lease_bucket_mut(tx, namespace, id)?;
+            let kind: Vec<&[u8]> = resource.kind.split('/').map(str::as_bytes).collect();
+            lbkt.create_path(&kind)?.put(resource.id.as_bytes(), &[])
+        })
+    }
+
+    fn delete_resource(&self, ctx: &Context, id: &str, resource: Resource) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        leases::validate_resource(&resource)?;
+
+        self.db.update(|tx| {
+            let lbkt = lease_bucket_mut(tx, namespace, id)?;
+            let kind: Vec<&[u8]> = resource.kind.split('/').map(str::as_bytes).collect();
+            match lbkt.path_mut(&kind) {
+                Some(rbkt) => {
+                    self.db.mark_dirty();
+                    rbkt.delete(resource.id.as_bytes())
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn list_resources(&self, ctx: &Context, id: &str) -> Result<Vec<Resource>, Error> {
+        let namespace = ctx.namespace_required()?;
+
+        self.db.view(|tx| {
+            let lbkt = tx
+                .path(&leases_path(namespace))
+                .and_then(|bkt| bkt.bucket(id.as_bytes()))
+                .ok_or_else(|| Error::NotFound(format!("lease {:?}", id)))?;
+            Ok(read_resources(lbkt).into_iter().collect())
+        })
+    }
+}
+
+fn lease_bucket_mut<'a>(tx: &'a mut Bucket, namespace: &str, id: &str) -> Result<&'a mut Bucket, Error> {
+    tx.path_mut(&leases_path(namespace))
+        .and_then(|bkt| bkt.bucket_mut(id.as_bytes()))
+        .ok_or_else(|| Error::NotFound(format!("lease {:?}", id)))
+}
+
+fn read_lease(id: &str, bkt: &Bucket) -> Result<Lease, Error> {
+    Ok(Lease {
+        id: id.to_string(),
+        created_at: read_timestamp(bkt, BUCKET_KEY_CREATED_AT)?,
+        labels: read_labels(bkt, BUCKET_KEY_OBJECT_LABELS),
+    })
+}
+
+/// read_resources returns the resources of the lease bucket, stored under
+/// the buckets of their type.
+fn read_resources(bkt: &Bucket) -> BTreeSet<Resource> {
+    let mut resources = BTreeSet::new();
+    for kind in [leases::RESOURCE_CONTENT, leases::RESOURCE_INGESTS] {
+        if let Some(rbkt) = bkt.bucket(kind.as_bytes()) {
+            resources.extend(
+                rbkt.values()
+                    .map(|(id, _)| Resource::new(kind, &String::from_utf8_lossy(id))),
+            );
+        }
+    }
+    if let Some(sbkt) = bkt.bucket(leases::RESOURCE_SNAPSHOTS_PREFIX.as_bytes()) {
+        for (snapshotter, rbkt) in sbkt.buckets() {
+            let snapshotter = String::from_utf8_lossy(snapshotter);
+            resources.extend(
+                rbkt.values()
+                    .map(|(key, _)| Resource::snapshot(&snapshotter, &String::from_utf8_lossy(key))),
+            );
+        }
+    }
+    resources
+}
@@ -0,0 +1,227 @@
+use super::buckets::{
+    read_labels, write_labels, BUCKET_KEY_OBJECT_CONTAINERS, BUCKET_KEY_OBJECT_LABELS, BUCKET_KEY_VERSION,
+};
+use super::db::{Bucket, DB};
+use crate::api::events::{NamespaceCreate, NamespaceDelete, NamespaceUpdate};
+use crate::content;
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::events::{self, Publisher};
+use crate::images;
+use crate::namespaces::{self, Namespace};
+use crate::snapshots::Snapshotter;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// NamespaceStore keeps the namespaces in the metadata database, as the
+/// `v1/<namespace>` buckets with their labels, and publishes an event for
+/// every change made to them.
+///
+/// A namespace is only deleted once it holds no more containers, and the
+/// stores it is given hold no more images, content or snapshots in it. The
+/// check and the removal happen in one update of the database.
+pub struct NamespaceStore {
+    db: Arc<DB>,
+    publisher: Arc<dyn Publisher>,
+    images: Arc<dyn images::Store>,
+    content: Arc<dyn content::Store>,
+    snapshotters: HashMap<String, Arc<dyn Snapshotter>>,
+}
+
+impl NamespaceStore {
+    pub fn new(
+        db: Arc<DB>,
+        publisher: Arc<dyn Publisher>,
+        images: Arc<dyn images::Store>,
+        content: Arc<dyn content::Store>,
+    ) -> NamespaceStore {
+        NamespaceStore {
+            db,
+            publisher,
+            images,
+            content,
+            snapshotters: HashMap::new(),
+        }
+    }
+
+    /// with_snapshotter adds a snapshotter whose snapshots keep namespaces
+    /// from being deleted.
+    pub fn with_snapshotter(mut self, name: &str, snapshotter: Arc<dyn Snapshotter>) -> NamespaceStore {
+        self.snapshotters.insert(name.to_string(), snapshotter);
+        self
+    }
+
+    fn publish(&self, namespace: &str, topic: &str, event: prost_types::Any) {
+        if let Err(e) = self.publisher.publish(namespace, topic, event) {
+            log::warn!("failed to publish {} event: {}", topic, e);
+        }
+    }
+
+    /// remaining returns the types of the resources still held in the bucket
+    /// of the namespace or in the other stores.
+    fn remaining(&self, namespace: &str, bkt: &Bucket) -> Result<Vec<&'static str>, Error> {
+        let ctx = Context::new().with_namespace(namespace);
+        let mut remaining = Vec::new();
+        if bkt.bucket(BUCKET_KEY_OBJECT_CONTAINERS).is_some_and(|b| !b.is_empty()) {
+            remaining.push("containers");
+        }
+        if !self.images.list(&ctx, &[])?.is_empty() {
+            remaining.push("images");
+        }
+
+        let mut found = false;
+        self.content.walk(&ctx, &[], &mut |_| {
+            found = true;
+            Ok(())
+        })?;
+        if found {
+            remaining.push("content");
+        }
+
+        let mut found = false;
+        for snapshotter in self.snapshotters.values() {
+            snapshotter.walk(&ctx, &[], &mut |_| {
+                found = true;
+                Ok(())
+            })?;
+        }
+        if found {
+            remaining.push("snapshots");
+        }
+
+        Ok(remaining)
+    }
+}
+
+impl namespaces::Store for NamespaceStore {
+    fn create(&self, namespace: Namespace) -> Result<Namespace, Error> {
+        namespaces::validate(&namespace)?;
+
+        self.db.update(|tx| {
+            let bkt = tx.create_bucket_if_not_exists(BUCKET_KEY_VERSION)?;
+            let nbkt = bkt.create_bucket(namespace.name.as_bytes()).map_err(|e| match e {
+                Error::AlreadyExists(_) => Error::AlreadyExists(format!("namespace {:?}", namespace.name)),
+                e => e,
+            })?;
+            write_labels(nbkt, BUCKET_KEY_OBJECT_LABELS, &namespace.labels)
+        })?;
+
+        self.publish(
+            &namespace.name,
+            "/namespaces/create",
+            events::marshal(&NamespaceCreate {
+                name: namespace.name.clone(),
+                labels: namespace.labels.clone(),
+            }),
+        );
+
+        Ok(namespace)
+    }
+
+    fn get(&self, name: &str) -> Result<Namespace, Error> {
+        self.db
+            .view(|tx| match tx.path(&[BUCKET_KEY_VERSION, name.as_bytes()]) {
+                Some(bkt) => Ok(read_namespace(name, bkt)),
+                None => Err(Error::NotFound(format!("namespace {:?}", name))),
+            })
+    }
+
+    fn list(&self, filters: &[&str]) -> Result<Vec<Namespace>, Error> {
+        let filter = crate::filters::parse_all(filters)?;
+
+        self.db.view(|tx| {
+            let bkt = match tx.bucket(BUCKET_KEY_VERSION) {
+                Some(bkt) => bkt,
+                None => return Ok(Vec::new()),
+            };
+            Ok(bkt
+                .buckets()
+                .map(|(name, nbkt)| read_namespace(&String::from_utf8_lossy(name), nbkt))
+                .filter(|ns| filter.matches(ns))
+                .collect())
+        })
+    }
+
+    fn update(&self, namespace: Namespace, fieldpaths: &[&str]) -> Result<Namespace, Error> {
+        let updated = self.db.update(|tx| {
+            let bkt = tx
+                .path_mut(&[BUCKET_KEY_VERSION, namespace.name.as_bytes()])
+                .ok_or_else(|| Error::NotFound(format!("namespace {:?}", namespace.name)))?;
+
+            let mut updated = read_namespace(&namespace.name, bkt);
+            if fieldpaths.is_empty() {
+                updated.labels = namespace.labels;
+            } else {
+                for path in fieldpaths {
+                    if let Some(key) = path.strip_prefix("labels.") {
+                        match namespace.labels.get(key) {
+                            Some(value) => updated.labels.insert(key.to_string(), value.clone()),
+                            None => updated.labels.remove(key),
+                        };
+                        continue;
+                    }
+
+                    match *path {
+                        "labels" => updated.labels = namespace.labels.clone(),
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "cannot update {:?} field on namespace {:?}",
+                                path, namespace.name
+                            )))
+                        }
+                    }
+                }
+            }
+
+            namespaces::validate(&updated)?;
+
+            write_labels(bkt, BUCKET_KEY_OBJECT_LABELS, &updated.labels)?;
+            Ok(updated)
+        })?;
+
+        self.publish(
+            &updated.name,
+            "/namespaces/update",
+            events::marshal(&NamespaceUpdate {
+                name: updated.name.clone(),
+                labels: updated.labels.clone(),
+            }),
+        );
+
+        Ok(updated)
+    }
+
+    fn delete(&self, name: &str) -> Result<(), Error> {
+        self.db.update(|tx| {
+            let bkt = tx
+                .path_mut(&[BUCKET_KEY_VERSION])
+                .filter(|bkt| bkt.bucket(name.as_bytes()).is_some())
+                .ok_or_else(|| Error::NotFound(format!("namespace {:?}", name)))?;
+
+            let remaining = self.remaining(name, bkt.bucket(name.as_bytes()).unwrap())?;
+            if !remaining.is_empty() {
+                return Err(Error::FailedPrecondition(format!(
+                    "namespace {:?} must be empty, but it still has {}",
+                    name,
+                    remaining.join(", ")
+                )));
+            }
+            self.db.mark_dirty();
+            bkt.delete_bucket(name.as_bytes())
+        })?;
+
+        self.publish(
+            name,
+            "/namespaces/delete",
+            events::marshal(&NamespaceDelete { name: name.to_string() }),
+        );
+
+        Ok(())
+    }
+}
+
+fn read_namespace(name: &str, bkt: &Bucket) -> Namespace {
+    let mut namespace = Namespace::new(name);
+    namespace.labels = read_labels(bkt, BUCKET_KEY_OBJECT_LABELS);
+    namespace
+}
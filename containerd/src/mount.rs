@@ -11,6 +11,7 @@ static PAGE_SIZE: usize = 4096;
 
 /// Mount is the lingua franca of containerd. A mount represents a
 /// serialized mount syscall. Components either emit or consume mounts.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Mount {
     // Type specifies the host-specific of the mount.
     fs_type: String,
@@ -23,8 +24,35 @@ pub struct Mount {
 }
 
 impl Mount {
-    pub fn all(mounts: &mut [Mount], target: &str) -> Result<(), String> {
-        todo!()
+    pub fn new<P: Into<PathBuf>>(fs_type: &str, source: P, options: Vec<String>) -> Mount {
+        Mount {
+            fs_type: fs_type.to_string(),
+            source: source.into(),
+            options,
+        }
+    }
+
+    /// fs_type returns the type of the mount, for example `overlay` or `bind`.
+    pub fn fs_type(&self) -> &str {
+        &self.fs_type
+    }
+
+    /// source returns the path or device mounted from.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// options returns the fstab-style options of the mount.
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+
+    /// all mounts all the mounts on the target, in order.
+    pub fn all(mounts: &[Mount], target: &str) -> Result<(), String> {
+        for mount in mounts {
+            mount.mount(target)?;
+        }
+        Ok(())
     }
 
     /// mount to the provided target path.
@@ -34,9 +62,9 @@ impl Mount {
     pub fn mount(&self, target: &str) -> Result<(), String> {
         for binary in ALLOWED_HELPER_BINARIES {
             // ALLOWED_HELPER_BINARIES = "mount.fuse", typePrefix = "fuse."
-            let type_prefix = binary.strip_prefix("mount.").unwrap();
-            if type_prefix.starts_with(&self.fs_type) {
-                return self.mount_with_helper(binary, type_prefix, target);
+            let type_prefix = format!("{}.", binary.strip_prefix("mount.").unwrap());
+            if self.fs_type.starts_with(&type_prefix) {
+                return self.mount_with_helper(binary, &type_prefix, target);
             }
         }
 
@@ -68,7 +96,7 @@ impl Mount {
         let ptypes: u64 = libc::MS_SHARED | libc::MS_PRIVATE | libc::MS_SLAVE | libc::MS_UNBINDABLE;
 
         // Ensure propagation type change flags aren't included in other calls.
-        let oflags = flags ^ !ptypes;
+        let oflags = flags & !ptypes;
 
         // In the case of remounting with changed data (data != ""), need to call mount (moby/moby#34077).
         if (flags & libc::MS_REMOUNT) == 0 || data != "" {
@@ -89,7 +117,7 @@ impl Mount {
                 source.as_path(),
                 target,
                 self.fs_type.as_str(),
-                flags,
+                oflags,
                 data.as_str(),
             ) {
                 Ok(_) => {} // no value
@@ -101,27 +129,17 @@ impl Mount {
             //change the propogation type
             let pflags = ptypes | libc::MS_REC | libc::MS_SILENT;
 
-            let flags_pflags = match sys_mount::MountFlags::from_bits(flags & pflags) {
-                Some(f) => f,
-                None => return Err(format!("Unable to convert flags and pflags from bits")), // we know this works
-            };
-            match sys_mount::Mount::new("", target, "", flags_pflags, Some(&data)) {
-                Ok(_) => {}
-                Err(e) => return Err(format!("Mount failed with error: {:?}", e)),
+            if let Err(e) = mount_syscall(None, target, None, flags & pflags, None) {
+                return Err(format!("failed to change propagation of {}: {}", target, e));
             }
         }
 
         let broflags = libc::MS_BIND | libc::MS_RDONLY;
 
         if oflags & broflags == broflags {
-            let flags_broflags = match sys_mount::MountFlags::from_bits(oflags | libc::MS_REMOUNT) {
-                Some(f) => f,
-                None => return Err(format!("Unable to convert flags and pflags from bits")), // we know this works
-            };
-
-            match sys_mount::Mount::new("", target, "", flags_broflags, None) {
-                Ok(_) => {}
-                Err(e) => return Err(format!("Mount failed with error: {:?}", e)),
+            // a bind mount ignores the read only flag, remount it to apply it
+            if let Err(e) = mount_syscall(None, target, None, oflags | libc::MS_REMOUNT, None) {
+                return Err(format!("failed to remount {} read only: {}", target, e));
             }
         }
 
@@ -171,14 +189,20 @@ impl Mount {
         Ok(())
     }
 
-    // TODO: Implement this if only required
+    // TODO: run the helper binary, as containerd does
     fn mount_with_helper(
         &self,
         helper_binary: &str,
         type_prefix: &str,
         target: &str,
     ) -> Result<(), String> {
-        todo!()
+        Err(format!(
+            "failed to mount {:?} on {}: {} mounts need the {} helper, which is not supported",
+            self.source,
+            target,
+            type_prefix.trim_end_matches('.'),
+            helper_binary
+        ))
     }
 
     /// option_size returns the byte size of options of mount.
@@ -200,15 +224,12 @@ impl Mount {
         flags: u64,
         data: &str,
     ) -> Result<(), String> {
-        //TODO: bad code, fix the if statment logic
-        if chdir.to_str().unwrap().eq("") {
+        if chdir.as_os_str().is_empty() {
             // Attempt to mount the src device to the dest directory.
-            let mount_flag = match sys_mount::MountFlags::from_bits(flags) {
-                Some(f) => f,
-                None => return Err(format!("Unable to convert flags from bits")), // we know this works
+            return match mount_syscall(Some(source), target, Some(fstype), flags, Some(data)) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("failed to mount {:?} on {}: {}", source, target, e)),
             };
-
-            let mount = sys_mount::Mount::new(source, target, fstype, mount_flag, Some(data));
         }
 
         let file = match File::open(chdir) {
@@ -225,8 +246,22 @@ impl Mount {
             return Err(format!("failed to mountat: {:?} is not dir", chdir));
         };
 
-        //TODO: fork and mount
-        Ok(())
+        // the paths in data are relative to chdir: mount from a thread with a
+        // working directory of its own, the one of the process is left as is
+        let mounted = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    nix::sched::unshare(nix::sched::CloneFlags::CLONE_FS)?;
+                    unistd::chdir(chdir)?;
+                    mount_syscall(Some(source), target, Some(fstype), flags, Some(data))
+                })
+                .join()
+        });
+        match mounted {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(format!("failed to mount {:?} on {}: {}", source, target, e)),
+            Err(_) => Err(format!("failed to mount {:?} on {}: thread panicked", source, target)),
+        }
     }
 
     /// compact_lower_dir_option updates overlay lowdir option and returns the common
@@ -250,14 +285,15 @@ impl Mount {
         // in order to avoid to get snapshots/x, should be back to parent dir.
         // however, there is assumption that the common dir is ${root}/io.containerd.v1.overlayfs/snapshots.
         let common_path = match Path::new(&common_dir).parent() {
-            Some(x) => x.join("/"),
-            // returns None if path terminates in root
-            None => return None,
+            Some(x) if x != Path::new("/") && x != Path::new("") => x.to_path_buf(),
+            // the lower dirs have nothing but the root in common
+            _ => return None,
         };
+        let prefix = format!("{}/", common_path.display());
 
         let mut new_dirs: Vec<String> = Vec::new();
         for dir in dirs {
-            new_dirs.push(dir[common_dir.chars().count()..].to_string());
+            new_dirs.push(dir.strip_prefix(&prefix)?.to_string());
         }
 
         let mut new_opts = [&opts[..idx], &opts[idx + 1..]].concat();
@@ -303,13 +339,10 @@ impl Mount {
             }
         }
 
-        let min_count = min.chars().count();
-        let max_count = max.chars().count();
-
         // find out the common part between min and max
-        for x in min_count..max_count {
-            if min.chars().nth(x).unwrap() != max.chars().nth(x).unwrap() {
-                return min[..x].to_string();
+        for (x, (a, b)) in min.bytes().zip(max.bytes()).enumerate() {
+            if a != b {
+                return String::from_utf8_lossy(&min.as_bytes()[..x]).into_owned();
             }
         }
         return min.to_string();
@@ -343,26 +376,26 @@ impl Mount {
             ("atime", Flag::new(true, libc::MS_NOATIME)),
             ("bind", Flag::new(false, libc::MS_BIND)),
             ("defaults", Flag::new(false, 0)),
-            ("dev", Flag::new(false, libc::MS_NODEV)),
-            ("diratime", Flag::new(false, libc::MS_NODIRATIME)),
+            ("dev", Flag::new(true, libc::MS_NODEV)),
+            ("diratime", Flag::new(true, libc::MS_NODIRATIME)),
             ("dirsync", Flag::new(false, libc::MS_DIRSYNC)),
-            ("exec", Flag::new(false, libc::MS_NOEXEC)),
+            ("exec", Flag::new(true, libc::MS_NOEXEC)),
             ("mand", Flag::new(false, libc::MS_MANDLOCK)),
             ("noatime", Flag::new(false, libc::MS_NOATIME)),
             ("nodev", Flag::new(false, libc::MS_NODEV)),
             ("nodiratime", Flag::new(false, libc::MS_NODIRATIME)),
             ("noexec", Flag::new(false, libc::MS_NOEXEC)),
-            ("nomand", Flag::new(false, libc::MS_MANDLOCK)),
-            ("norelatime", Flag::new(false, libc::MS_RELATIME)),
-            ("nostrictatime", Flag::new(false, libc::MS_STRICTATIME)),
+            ("nomand", Flag::new(true, libc::MS_MANDLOCK)),
+            ("norelatime", Flag::new(true, libc::MS_RELATIME)),
+            ("nostrictatime", Flag::new(true, libc::MS_STRICTATIME)),
             ("nosuid", Flag::new(false, libc::MS_NOSUID)),
             ("rbind", Flag::new(false, libc::MS_BIND | libc::MS_REC)),
             ("relatime", Flag::new(false, libc::MS_RELATIME)),
             ("remount", Flag::new(false, libc::MS_REMOUNT)),
             ("ro", Flag::new(false, libc::MS_RDONLY)),
-            ("rw", Flag::new(false, libc::MS_RDONLY)),
+            ("rw", Flag::new(true, libc::MS_RDONLY)),
             ("strictatime", Flag::new(false, libc::MS_STRICTATIME)),
-            ("suid", Flag::new(false, libc::MS_NOSUID)),
+            ("suid", Flag::new(true, libc::MS_NOSUID)),
             ("sync", Flag::new(false, libc::MS_SYNCHRONOUS)),
         ]);
 
@@ -375,7 +408,7 @@ impl Mount {
             if f.is_some() && f.unwrap().flag != 0 {
                 let f = f.unwrap();
                 if f.clear {
-                    flag = !(f.flag ^ f.flag);
+                    flag &= !f.flag;
                 } else {
                     flag |= f.flag;
                 }
@@ -389,3 +422,94 @@ impl Mount {
         (flag, data.join(","), losetup)
     }
 }
+
+/// mount_syscall runs the mount syscall with the MS_* flags.
+fn mount_syscall(
+    source: Option<&Path>,
+    target: &str,
+    fstype: Option<&str>,
+    flags: u64,
+    data: Option<&str>,
+) -> nix::Result<()> {
+    let flags = nix::mount::MsFlags::from_bits_truncate(flags);
+    nix::mount::mount(source, target, fstype, flags, data)
+}
+
+/// unmount_recursive unmounts the target and all the mounts below it, the
+/// deepest first. A target which is not mounted, or does not exist, is left
+/// as is.
+pub fn unmount_recursive(target: &Path, flags: i32) -> Result<(), String> {
+    let target = match target.canonicalize() {
+        Ok(target) => target,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("failed to resolve {:?}: {}", target, e)),
+    };
+    let flags = match sys_mount::UnmountFlags::from_bits(flags) {
+        Some(f) => f,
+        None => return Err("Unable to convert flags from bits".to_string()),
+    };
+
+    let mut mount_points = mount_points_below(&target)?;
+    mount_points.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    for mount_point in mount_points {
+        match sys_mount::unmount(&mount_point, flags) {
+            Ok(_) => {}
+            // the mount point went away with the mount it was on
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {}
+            Err(e) => return Err(format!("failed to unmount target {:?}: {}", mount_point, e)),
+        }
+    }
+    Ok(())
+}
+
+/// mount_points_below returns the mount points of the mount table of the
+/// process which are the path or below it.
+fn mount_points_below(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let info = match std::fs::read_to_string("/proc/self/mountinfo") {
+        Ok(info) => info,
+        Err(e) => return Err(format!("failed to read mountinfo: {}", e)),
+    };
+    // the mount point is the fifth field, with its whitespace and
+    // backslashes escaped in octal
+    Ok(info
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape_mount_point)
+        .filter(|mount_point| mount_point.starts_with(path))
+        .collect())
+}
+
+fn unescape_mount_point(field: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match (bytes[i], octal) {
+            (b'\\', Some(digits)) => {
+                unescaped.push(digits.iter().fold(0u8, |n, d| n.wrapping_mul(8) + (d - b'0')));
+                i += 4;
+            }
+            (b, _) => {
+                unescaped.push(b);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(std::ffi::OsString::from_vec(unescaped))
+}
+
+/// The API mount of a mount has no target, the consumer of the mount picks
+/// it.
+impl From<&Mount> for crate::api::types::Mount {
+    fn from(m: &Mount) -> Self {
+        crate::api::types::Mount {
+            r#type: m.fs_type.clone(),
+            source: m.source.to_string_lossy().into_owned(),
+            target: String::new(),
+            options: m.options.clone(),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use super::api::services::namespaces::v1 as api;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use super::identifiers;
+use super::labels;
+use std::collections::HashMap;
+
+/// NAMESPACE_ENV_VAR is the environment variable overriding the default
+/// namespace.
+pub const NAMESPACE_ENV_VAR: &str = "CONTAINERD_NAMESPACE";
+
+/// DEFAULT is the name of the default namespace.
+pub const DEFAULT: &str = "default";
+
+/// GRPC_HEADER is the gRPC metadata key carrying the namespace of a request.
+pub const GRPC_HEADER: &str = "containerd-namespace";
+
+/// TTRPC_HEADER is the ttrpc metadata key carrying the namespace of a
+/// request, shims read it to scope the events they publish.
+pub const TTRPC_HEADER: &str = "containerd-namespace-ttrpc";
+
+/// default_namespace returns the namespace of the environment variable, or
+/// the default namespace if it is not set.
+pub fn default_namespace() -> String {
+    match std::env::var(NAMESPACE_ENV_VAR) {
+        Ok(namespace) if !namespace.is_empty() => namespace,
+        _ => DEFAULT.to_string(),
+    }
+}
+
+/// Namespace isolates the objects of a tenant: images, containers, content,
+/// snapshots and leases are only visible within their namespace.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Namespace {
+    pub name: String,
+    /// labels provide metadata extension for a namespace, they are fully
+    /// mutable.
+    pub labels: HashMap<String, String>,
+}
+
+impl Namespace {
+    pub fn new(name: &str) -> Namespace {
+        Namespace {
+            name: name.to_string(),
+            labels: HashMap::new(),
+        }
+    }
+}
+
+/// Store provides introspection about namespaces.
+///
+/// Note that these are slightly different than other objects, which are record
+/// oriented. A namespace is really just a name and a set of labels. Objects
+/// that belong to a namespace are returned when the namespace is assigned to a
+/// given context.
+pub trait Store: Send + Sync {
+    /// create a namespace, the name must not be in use.
+    fn create(&self, namespace: Namespace) -> Result<Namespace, Error>;
+
+    /// get returns the namespace with its labels.
+    fn get(&self, name: &str) -> Result<Namespace, Error>;
+
+    /// list returns the namespaces that match one or more of the provided
+    /// filters.
+    fn list(&self, filters: &[&str]) -> Result<Vec<Namespace>, Error>;
+
+    /// update replaces the labels of the namespace. If one or more fieldpaths
+    /// are provided, only those labels will be updated.
+    fn update(&self, namespace: Namespace, fieldpaths: &[&str]) -> Result<Namespace, Error>;
+
+    /// delete the namespace. The namespace must be empty.
+    fn delete(&self, name: &str) -> Result<(), Error>;
+}
+
+/// validate checks the name and the labels of a namespace.
+pub fn validate(namespace: &Namespace) -> Result<(), Error> {
+    identifiers::validate(&namespace.name)?;
+    labels::validate_all(&namespace.labels)
+}
+
+/// Adaptor for namespaces exposes the `name` and `labels.<key>` field paths
+/// to filters.
+impl Adaptor for Namespace {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        match fieldpath.first()?.as_str() {
+            "name" if !self.name.is_empty() => Some(self.name.clone()),
+            "labels" => filters::check_map(&fieldpath[1..], &self.labels),
+            _ => None,
+        }
+    }
+}
+
+impl From<Namespace> for api::Namespace {
+    fn from(namespace: Namespace) -> Self {
+        api::Namespace {
+            name: namespace.name,
+            labels: namespace.labels,
+        }
+    }
+}
+
+impl From<api::Namespace> for Namespace {
+    fn from(namespace: api::Namespace) -> Self {
+        Namespace {
+            name: namespace.name,
+            labels: namespace.labels,
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use super::api::types::Platform;
+use super::errdefs::Error;
+
+/// KNOWN_OS lists the operating systems a single component specifier is
+/// recognized as.
+const KNOWN_OS: &[&str] = &[
+    "aix", "android", "darwin", "dragonfly", "freebsd", "hurd", "illumos", "ios", "js", "linux", "nacl", "netbsd",
+    "openbsd", "plan9", "solaris", "windows", "zos",
+];
+
+/// KNOWN_ARCH lists the architectures a single component specifier is
+/// recognized as, after normalization.
+const KNOWN_ARCH: &[&str] = &[
+    "386", "amd64", "amd64p32", "arm", "armbe", "arm64", "arm64be", "loong64", "mips", "mipsle", "mips64", "mips64le",
+    "mips64p32", "mips64p32le", "ppc", "ppc64", "ppc64le", "riscv", "riscv64", "s390", "s390x", "sparc", "sparc64",
+    "wasm",
+];
+
+/// parse a platform specifier of the form `<os>|<arch>|<os>/<arch>[/<variant>]`
+/// into a normalized platform.
+///
+/// A single component is taken as an operating system if it is a known one,
+/// the architecture of the host is filled in. Otherwise it must be a known
+/// architecture and the operating system of the host is used.
+pub fn parse(specifier: &str) -> Result<Platform, Error> {
+    let invalid = |reason: &str| Error::InvalidArgument(format!("invalid platform {:?}: {}", specifier, reason));
+
+    let parts: Vec<&str> = specifier.split('/').collect();
+    for part in &parts {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-')) {
+            return Err(invalid("must be alphanumeric components separated by '/'"));
+        }
+    }
+
+    let platform = match parts[..] {
+        [component] => {
+            let component = component.to_lowercase();
+            if KNOWN_OS.contains(&normalize_os(&component).as_str()) {
+                let host = default_spec();
+                Platform {
+                    os: component,
+                    architecture: host.architecture,
+                    variant: host.variant,
+                }
+            } else {
+                let platform = normalize(Platform {
+                    os: default_spec().os,
+                    architecture: component,
+                    variant: String::new(),
+                });
+                if !KNOWN_ARCH.contains(&platform.architecture.as_str()) {
+                    return Err(invalid("unknown operating system or architecture"));
+                }
+                platform
+            }
+        }
+        [os, architecture] => Platform {
+            os: os.to_lowercase(),
+            architecture: architecture.to_lowercase(),
+            variant: String::new(),
+        },
+        [os, architecture, variant] => Platform {
+            os: os.to_lowercase(),
+            architecture: architecture.to_lowercase(),
+            variant: variant.to_lowercase(),
+        },
+        _ => return Err(invalid("cannot parse platform specifier")),
+    };
+
+    Ok(normalize(platform))
+}
+
+/// format returns the specifier of the platform, `<os>/<arch>[/<variant>]`.
+///
+/// A platform without an operating system formats as `unknown`.
+pub fn format(platform: &Platform) -> String {
+    if platform.os.is_empty() {
+        return "unknown".to_string();
+    }
+
+    [platform.os.as_str(), platform.architecture.as_str(), platform.variant.as_str()]
+        .iter()
+        .filter(|s| !s.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// normalize the platform, so that aliases of the operating system and the
+/// architecture compare equal.
+pub fn normalize(platform: Platform) -> Platform {
+    let (architecture, variant) = normalize_arch(&platform.architecture, &platform.variant);
+    Platform {
+        os: normalize_os(&platform.os),
+        architecture,
+        variant,
+    }
+}
+
+/// normalize_os returns the canonical name of the operating system.
+pub fn normalize_os(os: &str) -> String {
+    let os = os.to_lowercase();
+    match os.as_str() {
+        "macos" => "darwin".to_string(),
+        _ => os,
+    }
+}
+
+/// normalize_arch returns the canonical architecture and variant, following
+/// the names used in the OCI image specification.
+pub fn normalize_arch(architecture: &str, variant: &str) -> (String, String) {
+    let architecture = architecture.to_lowercase();
+    let variant = variant.to_lowercase();
+    match architecture.as_str() {
+        "i386" | "i686" | "x86" => ("386".to_string(), String::new()),
+        "x86_64" | "x86-64" | "amd64" => {
+            let variant = if variant == "v1" { String::new() } else { variant };
+            ("amd64".to_string(), variant)
+        }
+        "aarch64" | "arm64" => {
+            // v8 is the only variant of arm64 that does not need to be spelled out
+            let variant = match variant.as_str() {
+                "8" | "v8" => String::new(),
+                _ => variant,
+            };
+            ("arm64".to_string(), variant)
+        }
+        "armhf" => ("arm".to_string(), "v7".to_string()),
+        "armel" => ("arm".to_string(), "v6".to_string()),
+        "arm" => {
+            let variant = match variant.as_str() {
+                "" | "7" => "v7".to_string(),
+                "5" | "6" | "8" => format!("v{}", variant),
+                _ => variant,
+            };
+            ("arm".to_string(), variant)
+        }
+        _ => (architecture, variant),
+    }
+}
+
+/// default_spec returns the normalized platform of the host.
+pub fn default_spec() -> Platform {
+    normalize(Platform {
+        os: std::env::consts::OS.to_string(),
+        architecture: std::env::consts::ARCH.to_string(),
+        variant: cpu_variant(),
+    })
+}
+
+/// default_string returns the specifier of the host platform.
+pub fn default_string() -> String {
+    format(&default_spec())
+}
+
+/// cpu_variant returns the variant of the host cpu. It is only known for arm,
+/// where it is read from the architecture reported in `/proc/cpuinfo`.
+fn cpu_variant() -> String {
+    if std::env::consts::ARCH != "arm" {
+        return String::new();
+    }
+
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    let architecture = cpuinfo
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == "CPU architecture")
+        .map(|(_, value)| value.trim().to_string())
+        .unwrap_or_default();
+
+    match architecture.as_str() {
+        "8" | "AArch64" => "v8",
+        "7" => "v7",
+        "6" => "v6",
+        "5" => "v5",
+        _ => "",
+    }
+    .to_string()
+}
+
+/// Matcher decides whether a platform can be used.
+pub trait Matcher {
+    fn matches(&self, platform: &Platform) -> bool;
+}
+
+/// MatchComparer is a matcher that can also rank the platforms it matches.
+pub trait MatchComparer: Matcher {
+    /// less returns true when a is preferred over b.
+    fn less(&self, a: &Platform, b: &Platform) -> bool;
+}
+
+/// Ordered matches any of the platforms it was created with, the earlier a
+/// platform is in the list the more it is preferred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ordered {
+    platforms: Vec<Platform>,
+}
+
+impl Ordered {
+    /// rank returns the position of the first platform matching the given
+    /// one, a lower rank is preferred.
+    pub fn rank(&self, platform: &Platform) -> Option<usize> {
+        let platform = normalize(platform.clone());
+        self.platforms.iter().position(|p| *p == platform)
+    }
+
+    /// sort orders the candidates by preference, the ones that do not match
+    /// are moved to the end.
+    pub fn sort<T>(&self, candidates: &mut [T], platform: impl Fn(&T) -> &Platform) {
+        candidates.sort_by_key(|c| self.rank(platform(c)).unwrap_or(usize::MAX));
+    }
+}
+
+impl Matcher for Ordered {
+    fn matches(&self, platform: &Platform) -> bool {
+        self.rank(platform).is_some()
+    }
+}
+
+impl MatchComparer for Ordered {
+    fn less(&self, a: &Platform, b: &Platform) -> bool {
+        match (self.rank(a), self.rank(b)) {
+            (Some(a), Some(b)) => a < b,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// ordered returns a matcher for exactly the given platforms, preferring them
+/// in the order they are given.
+pub fn ordered(platforms: impl IntoIterator<Item = Platform>) -> Ordered {
+    Ordered {
+        platforms: platforms.into_iter().map(normalize).collect(),
+    }
+}
+
+/// only returns a matcher for the platform and the platforms it is able to
+/// run, preferring the closest one.
+///
+/// An arm variant also runs the older variants (v8, v7, v6 down to v5),
+/// arm64 runs 32-bit arm and amd64 runs 386.
+pub fn only(platform: Platform) -> Ordered {
+    ordered(platform_vector(normalize(platform)))
+}
+
+/// only_strict returns a matcher for the platform alone, without the
+/// compatible fallbacks of [only].
+pub fn only_strict(platform: Platform) -> Ordered {
+    ordered([platform])
+}
+
+/// default_matcher returns the matcher for the host platform.
+pub fn default_matcher() -> Ordered {
+    only(default_spec())
+}
+
+/// platform_vector returns the platform followed by the platforms it can run,
+/// in order of preference.
+fn platform_vector(platform: Platform) -> Vec<Platform> {
+    let mut vector = vec![platform.clone()];
+    match platform.architecture.as_str() {
+        "amd64" => vector.push(Platform {
+            architecture: "386".to_string(),
+            variant: String::new(),
+            ..platform
+        }),
+        "arm" => {
+            let version = platform.variant.trim_start_matches('v').parse::<u32>().unwrap_or(0);
+            for v in (5..version).rev() {
+                vector.push(Platform {
+                    variant: format!("v{}", v),
+                    ..platform.clone()
+                });
+            }
+        }
+        "arm64" => {
+            let variant = if platform.variant.is_empty() { "v8".to_string() } else { platform.variant.clone() };
+            vector.extend(platform_vector(Platform {
+                architecture: "arm".to_string(),
+                variant,
+                ..platform
+            }));
+        }
+        _ => {}
+    }
+    vector
+}
@@ -0,0 +1,22 @@
+use time::OffsetDateTime;
+
+/// to_timestamp creates a protobuf Timestamp from OffsetDateTime.
+pub fn to_timestamp(from: OffsetDateTime) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: from.unix_timestamp(),
+        nanos: from.nanosecond() as i32,
+    }
+}
+
+/// from_timestamp creates OffsetDateTime from a protobuf Timestamp.
+///
+/// A missing or out of range timestamp maps to the unix epoch.
+pub fn from_timestamp(from: Option<&prost_types::Timestamp>) -> OffsetDateTime {
+    let ts = match from {
+        Some(ts) => ts,
+        None => return OffsetDateTime::UNIX_EPOCH,
+    };
+
+    let nanos = ts.seconds as i128 * 1_000_000_000 + ts.nanos as i128;
+    OffsetDateTime::from_unix_timestamp_nanos(nanos).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
@@ -0,0 +1,204 @@
+use super::errdefs::Error;
+
+const DEFAULT_DOMAIN: &str = "docker.io";
+const LEGACY_DEFAULT_DOMAIN: &str = "index.docker.io";
+const OFFICIAL_REPO_PREFIX: &str = "library/";
+const DEFAULT_TAG: &str = "latest";
+
+/// parse_docker_ref normalizes the image reference following the docker
+/// convention, so that `busybox` becomes `docker.io/library/busybox:latest`.
+///
+/// A reference carrying a digest keeps only the digest, the tag is dropped.
+pub fn parse_docker_ref(s: &str) -> Result<String, Error> {
+    let invalid = |reason: &str| Error::InvalidArgument(format!("invalid reference {:?}: {}", s, reason));
+
+    if s.is_empty() {
+        return Err(invalid("reference must not be empty"));
+    }
+
+    let (name, dgst) = match s.split_once('@') {
+        Some((name, dgst)) => {
+            super::digest::validate(dgst)?;
+            (name, Some(dgst))
+        }
+        None => (s, None),
+    };
+
+    // the tag is separated after the last path component, a colon before it
+    // separates the port of the domain
+    let last = name.rfind('/').map_or(0, |i| i + 1);
+    let (name, tag) = match name.rfind(':') {
+        Some(i) if i >= last => (&name[..i], Some(&name[i + 1..])),
+        _ => (name, None),
+    };
+
+    let (domain, path) = split_domain(name);
+    if path.is_empty() {
+        return Err(invalid("repository name must not be empty"));
+    }
+    if !path.split('/').all(valid_path_component) {
+        return Err(invalid("repository name must be lowercase alphanumeric components"));
+    }
+
+    let mut normalized = format!("{}/{}", domain, path);
+    match (tag, dgst) {
+        (_, Some(dgst)) => {
+            normalized.push('@');
+            normalized.push_str(dgst);
+        }
+        (Some(tag), None) => {
+            if !valid_tag(tag) {
+                return Err(invalid("invalid tag"));
+            }
+            normalized.push(':');
+            normalized.push_str(tag);
+        }
+        (None, None) => {
+            normalized.push(':');
+            normalized.push_str(DEFAULT_TAG);
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// split_domain splits the name into the registry domain and the repository
+/// path, filling in the defaults of the docker hub.
+fn split_domain(name: &str) -> (&str, String) {
+    let (domain, path) = match name.split_once('/') {
+        Some((first, rest))
+            if first.contains(['.', ':']) || first == "localhost" || first.chars().any(|c| c.is_ascii_uppercase()) =>
+        {
+            (first, rest.to_string())
+        }
+        _ => (DEFAULT_DOMAIN, name.to_string()),
+    };
+
+    let domain = if domain == LEGACY_DEFAULT_DOMAIN { DEFAULT_DOMAIN } else { domain };
+    if domain == DEFAULT_DOMAIN && !path.contains('/') {
+        return (domain, format!("{}{}", OFFICIAL_REPO_PREFIX, path));
+    }
+
+    (domain, path)
+}
+
+/// valid_path_component matches `[a-z0-9]+(?:(?:[._]|__|[-]*)[a-z0-9]+)*`.
+fn valid_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() => {}
+        _ => return false,
+    }
+
+    bytes
+        .iter()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// valid_tag matches `[\w][\w.-]{0,127}`.
+fn valid_tag(tag: &str) -> bool {
+    let bytes = tag.as_bytes();
+    match bytes.first() {
+        Some(b) if b.is_ascii_alphanumeric() || *b == b'_' => {}
+        _ => return false,
+    }
+
+    bytes.len() <= 128 && bytes.iter().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'))
+}
+
+/// Spec defines the main components of a reference specification.
+///
+/// A reference specification is a schema-less URI parsed into common
+/// components. The two main components, locator and object, are required to be
+/// supported by remotes. It represents a superset of the naming defined in
+/// docker's reference schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    /// locator is the host and path portion of the specification. The host
+    /// portion may refer to an actual host or just a namespace of related
+    /// images.
+    pub locator: String,
+
+    /// object contains the identifier for the remote resource, a tag, a tag
+    /// followed by a digest (`tag@digest`) or only a digest (`@digest`).
+    pub object: String,
+}
+
+impl Spec {
+    /// parse parses the string into a structured ref.
+    pub fn parse(s: &str) -> Result<Spec, Error> {
+        let invalid = |reason: &str| Error::InvalidArgument(format!("invalid reference {:?}: {}", s, reason));
+
+        let (name, dgst) = match s.split_once('@') {
+            Some((name, dgst)) => {
+                super::digest::validate(dgst)?;
+                (name, Some(dgst))
+            }
+            None => (s, None),
+        };
+
+        let (host, _) = match name.split_once('/') {
+            Some((host, path)) if !host.is_empty() && !path.is_empty() => (host, path),
+            _ => return Err(invalid("hostname required")),
+        };
+        if host.contains(char::is_whitespace) {
+            return Err(invalid("invalid hostname"));
+        }
+
+        let last = name.rfind('/').map_or(0, |i| i + 1);
+        let (locator, tag) = match name.rfind(':') {
+            Some(i) if i >= last => (&name[..i], &name[i + 1..]),
+            _ => (name, ""),
+        };
+        if !tag.is_empty() && !valid_tag(tag) {
+            return Err(invalid("invalid tag"));
+        }
+
+        let object = match dgst {
+            Some(dgst) => format!("{}@{}", tag, dgst),
+            None => tag.to_string(),
+        };
+
+        Ok(Spec {
+            locator: locator.to_string(),
+            object,
+        })
+    }
+
+    /// hostname returns the hostname portion of the locator.
+    pub fn hostname(&self) -> &str {
+        self.locator.split('/').next().unwrap_or_default()
+    }
+
+    /// repository returns the path of the locator after the hostname.
+    pub fn repository(&self) -> &str {
+        self.locator.split_once('/').map_or("", |(_, path)| path)
+    }
+
+    /// tag returns the tag of the object, if any.
+    pub fn tag(&self) -> Option<&str> {
+        let tag = self.object.split('@').next().unwrap_or_default();
+        if tag.is_empty() {
+            None
+        } else {
+            Some(tag)
+        }
+    }
+
+    /// digest returns the digest of the object, if any.
+    pub fn digest(&self) -> Option<&str> {
+        self.object.split_once('@').map(|(_, dgst)| dgst)
+    }
+}
+
+impl std::fmt::Display for Spec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.object.is_empty() {
+            return f.write_str(&self.locator);
+        }
+        if self.object.starts_with('@') {
+            return write!(f, "{}{}", self.locator, self.object);
+        }
+        write!(f, "{}:{}", self.locator, self.object)
+    }
+}
@@ -0,0 +1,280 @@
+pub mod docker;
+
+use super::api::types::Descriptor;
+use super::content::{self, WriterOpts};
+use super::context::Context;
+use super::errdefs::Error;
+use super::images::{self, oci, Image};
+use super::labels;
+use super::platforms::Matcher;
+use super::reference::Spec;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+/// Resolver provides remotes based on a locator.
+pub trait Resolver: Send + Sync {
+    /// resolve attempts to resolve the reference into a name and descriptor.
+    ///
+    /// The argument `reference` should be a scheme-less URI representing the
+    /// remote. Structurally, it has a host and path. The "host" can be used to
+    /// directly reference a specific host or be matched against a specific
+    /// handler.
+    ///
+    /// The returned name should be used to identify the referenced entity.
+    /// Depending on the remote namespace, this may be immutable or mutable.
+    /// While the name may differ from reference, it should itself be a valid
+    /// reference.
+    fn resolve(&self, reference: &str) -> Result<(String, Descriptor), Error>;
+
+    /// fetcher returns a new fetcher for the provided reference.
+    /// All content fetched from the returned fetcher will be
+    /// from the namespace referred to by reference.
+    fn fetcher(&self, reference: &str) -> Result<Box<dyn Fetcher>, Error>;
+
+    /// pusher returns a new pusher for the provided reference.
+    /// Manifests are pushed under the object of the reference, by their
+    /// digest when it has no tag.
+    fn pusher(&self, reference: &str) -> Result<Box<dyn Pusher>, Error>;
+}
+
+/// Fetcher fetches content.
+pub trait Fetcher: Send + Sync {
+    /// fetch the resource identified by the descriptor, starting at offset.
+    fn fetch(&self, desc: &Descriptor, offset: u64) -> Result<Box<dyn Read + Send>, Error>;
+}
+
+/// Pusher pushes content.
+pub trait Pusher: Send + Sync {
+    /// push uploads the content described by desc, read from r.
+    /// AlreadyExists is returned when the remote already has the content.
+    ///
+    /// The distribution source annotations of desc name the repositories of
+    /// the same registry the content may be mounted from instead.
+    fn push(&self, desc: &Descriptor, r: &mut dyn content::Reader) -> Result<(), Error>;
+}
+
+/// make_ref_key returns a unique reference for the descriptor. This reference
+/// can be used as a key for the ingest of the content in the content store.
+pub fn make_ref_key(desc: &Descriptor) -> String {
+    let kind = if oci::is_manifest(&desc.media_type) {
+        "manifest"
+    } else if oci::is_index(&desc.media_type) {
+        "index"
+    } else if is_layer_type(&desc.media_type) {
+        "layer"
+    } else if is_config_type(&desc.media_type) {
+        "config"
+    } else {
+        "unknown"
+    };
+    format!("{}-{}", kind, desc.digest)
+}
+
+fn is_layer_type(media_type: &str) -> bool {
+    media_type.starts_with(oci::MEDIA_TYPE_IMAGE_LAYER) || media_type.starts_with(oci::MEDIA_TYPE_DOCKER_SCHEMA2_LAYER)
+}
+
+fn is_config_type(media_type: &str) -> bool {
+    media_type == oci::MEDIA_TYPE_IMAGE_CONFIG || media_type == oci::MEDIA_TYPE_DOCKER_SCHEMA2_CONFIG
+}
+
+/// fetch writes the content described by desc into the content store.
+///
+/// An interrupted ingest of the content is resumed from its offset. The
+/// content is only committed when it matches the digest and size of desc, the
+/// ingest is aborted otherwise.
+pub fn fetch(store: &dyn content::Store, ctx: &Context, fetcher: &dyn Fetcher, desc: &Descriptor) -> Result<(), Error> {
+    ctx.check_deadline()?;
+    let reference = make_ref_key(desc);
+    let mut w = match store.writer(
+        ctx,
+        WriterOpts {
+            reference: reference.clone(),
+            desc: desc.clone(),
+        },
+    ) {
+        Ok(w) => w,
+        Err(e) if e.is_already_exists() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let offset = w.status()?.offset;
+    let result = if desc.size > 0 && offset == desc.size {
+        Ok(())
+    } else {
+        fetcher
+            .fetch(desc, offset as u64)
+            .and_then(|mut r| std::io::copy(&mut r, &mut w).map_err(Error::from))
+            .map(|_| ())
+    };
+
+    let result = result.and_then(|_| w.commit(desc.size, &desc.digest, HashMap::new()));
+    drop(w);
+    match result {
+        Err(e) if e.is_already_exists() => Ok(()),
+        Err(e) if e.is_failed_precondition() => {
+            // the ingest holds unexpected data, it cannot be resumed
+            if let Err(e) = store.abort(ctx, &reference) {
+                log::warn!("failed to abort ingest {:?}: {}", reference, e);
+            }
+            Err(e)
+        }
+        result => result,
+    }
+}
+
+/// fetch_all fetches the content of desc and of its children into the
+/// content store, the manifests of an index only for the matching platforms.
+///
+/// The parents are labeled with garbage collection references to their
+/// children once these are fetched.
+pub fn fetch_all(
+    store: &dyn content::Store,
+    ctx: &Context,
+    fetcher: &dyn Fetcher,
+    desc: &Descriptor,
+    platform: &dyn Matcher,
+) -> Result<(), Error> {
+    fetch_tree(store, ctx, fetcher, desc, platform, None)
+}
+
+/// fetch_tree fetches the tree of desc like [fetch_all], labeling every
+/// fetched content with the repository of the source, if any.
+fn fetch_tree(
+    store: &dyn content::Store,
+    ctx: &Context,
+    fetcher: &dyn Fetcher,
+    desc: &Descriptor,
+    platform: &dyn Matcher,
+    source: Option<&Spec>,
+) -> Result<(), Error> {
+    fetch(store, ctx, fetcher, desc)?;
+    if let Some(source) = source {
+        append_distribution_source_label(store, ctx, source, desc)?;
+    }
+
+    let index = oci::is_index(&desc.media_type);
+    let children: Vec<oci::Descriptor> = images::children(store, ctx, desc)?
+        .into_iter()
+        .filter(|child| !index || child.platform.as_ref().is_none_or(|p| platform.matches(p)))
+        .collect();
+    for child in &children {
+        fetch_tree(store, ctx, fetcher, &child.descriptor, platform, source)?;
+    }
+
+    images::set_children_labels(store, ctx, desc, &children)
+}
+
+/// append_distribution_source_label adds the repository of the source to the
+/// distribution source label of the content for the registry host.
+pub fn append_distribution_source_label(
+    store: &dyn content::Store,
+    ctx: &Context,
+    source: &Spec,
+    desc: &Descriptor,
+) -> Result<(), Error> {
+    let key = format!("{}.{}", labels::LABEL_DISTRIBUTION_SOURCE_PREFIX, source.hostname());
+    let mut info = store.info(ctx, &desc.digest)?;
+    let mut repositories: Vec<&str> = match info.labels.get(&key) {
+        Some(value) => value.split(',').filter(|r| !r.is_empty()).collect(),
+        None => Vec::new(),
+    };
+    if repositories.contains(&source.repository()) {
+        return Ok(());
+    }
+    repositories.push(source.repository());
+    repositories.sort_unstable();
+
+    let value = repositories.join(",");
+    info.labels.insert(key.clone(), value);
+    store.update(ctx, info, &[format!("labels.{}", key).as_str()])?;
+    Ok(())
+}
+
+/// pull resolves the reference, fetches the image content for the platform
+/// and creates or updates the image record pointing at it.
+pub fn pull(
+    store: &dyn content::Store,
+    images: &dyn images::Store,
+    ctx: &Context,
+    resolver: &dyn Resolver,
+    reference: &str,
+    platform: &dyn Matcher,
+) -> Result<Image, Error> {
+    let (name, desc) = resolver.resolve(reference)?;
+    let fetcher = resolver.fetcher(&name)?;
+    let source = Spec::parse(&name)?;
+    fetch_tree(store, ctx, fetcher.as_ref(), &desc, platform, Some(&source))?;
+
+    let image = Image::new(&name, desc);
+    match images.create(ctx, image.clone()) {
+        Err(e) if e.is_already_exists() => images.update(ctx, image, &["target"]),
+        result => result,
+    }
+}
+
+/// push uploads the content of desc and of its children to the reference,
+/// the manifests of an index only for the matching platforms.
+///
+/// Children are pushed before their parents so that the manifests go last,
+/// desc being tagged with the object of the reference. Content the remote
+/// already has is skipped.
+pub fn push(
+    store: &dyn content::Store,
+    ctx: &Context,
+    resolver: &dyn Resolver,
+    reference: &str,
+    desc: &Descriptor,
+    platform: &dyn Matcher,
+) -> Result<(), Error> {
+    let spec = Spec::parse(reference)?;
+    let pusher = resolver.pusher(reference)?;
+    let children_pusher = resolver.pusher(&spec.locator)?;
+
+    let mut pushed = HashSet::new();
+    push_children(store, ctx, children_pusher.as_ref(), desc, platform, &mut pushed)?;
+    push_content(store, ctx, pusher.as_ref(), desc)
+}
+
+/// push_children pushes the trees of the children of desc, skipping the
+/// content already pushed.
+fn push_children(
+    store: &dyn content::Store,
+    ctx: &Context,
+    pusher: &dyn Pusher,
+    desc: &Descriptor,
+    platform: &dyn Matcher,
+    pushed: &mut HashSet<String>,
+) -> Result<(), Error> {
+    let index = oci::is_index(&desc.media_type);
+    for child in images::children(store, ctx, desc)? {
+        if index && !child.platform.as_ref().is_none_or(|p| platform.matches(p)) {
+            continue;
+        }
+        if !pushed.insert(child.descriptor.digest.clone()) {
+            continue;
+        }
+        push_children(store, ctx, pusher, &child.descriptor, platform, pushed)?;
+        push_content(store, ctx, pusher, &child.descriptor)?;
+    }
+    Ok(())
+}
+
+/// push_content pushes a single content, annotated with the distribution
+/// sources recorded in its labels.
+fn push_content(store: &dyn content::Store, ctx: &Context, pusher: &dyn Pusher, desc: &Descriptor) -> Result<(), Error> {
+    ctx.check_deadline()?;
+    let info = store.info(ctx, &desc.digest)?;
+    let mut desc = desc.clone();
+    for (key, value) in info.labels {
+        if key.starts_with(labels::LABEL_DISTRIBUTION_SOURCE_PREFIX) {
+            desc.annotations.insert(key, value);
+        }
+    }
+
+    let mut r = store.reader(ctx, &desc.digest)?;
+    match pusher.push(&desc, r.as_mut()) {
+        Err(e) if e.is_already_exists() => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,688 @@
+pub mod auth;
+pub mod config;
+
+use super::{Fetcher, Pusher, Resolver};
+use crate::content;
+use crate::api::types::Descriptor;
+use crate::digest::{self, Digester};
+use crate::errdefs::Error;
+use crate::images::oci;
+use crate::labels;
+use crate::reference::Spec;
+use auth::Authorizer;
+use std::collections::HashMap;
+use std::io::{Read, SeekFrom};
+use std::sync::Arc;
+
+/// DEFAULT_REGISTRY is the host of the docker hub registry API, references to
+/// `docker.io` are resolved against it.
+const DEFAULT_REGISTRY: &str = "registry-1.docker.io";
+
+/// MAX_MANIFEST_SIZE limits the size of a manifest read to find its digest.
+const MAX_MANIFEST_SIZE: u64 = 4 << 20;
+
+/// MAX_UPLOAD_RESUMES limits how many times a chunked upload is resumed after
+/// a failed chunk.
+const MAX_UPLOAD_RESUMES: usize = 3;
+
+/// HostCapabilities represent the capabilities of the registry host. This also
+/// represents the set of operations for which the registry host may be
+/// trusted to perform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostCapabilities(u8);
+
+impl HostCapabilities {
+    /// PULL represents the capability to fetch manifests and blobs by digest.
+    pub const PULL: HostCapabilities = HostCapabilities(1);
+    /// RESOLVE represents the capability to fetch manifests by name.
+    pub const RESOLVE: HostCapabilities = HostCapabilities(1 << 1);
+    /// PUSH represents the capability to push blobs and manifests.
+    pub const PUSH: HostCapabilities = HostCapabilities(1 << 2);
+    /// ALL is the set of capabilities of an upstream registry.
+    pub const ALL: HostCapabilities = HostCapabilities(0b111);
+
+    /// has checks whether the capabilities include all of the given ones.
+    pub fn has(self, capabilities: HostCapabilities) -> bool {
+        self.0 & capabilities.0 == capabilities.0
+    }
+}
+
+impl std::ops::BitOr for HostCapabilities {
+    type Output = HostCapabilities;
+
+    fn bitor(self, rhs: HostCapabilities) -> HostCapabilities {
+        HostCapabilities(self.0 | rhs.0)
+    }
+}
+
+/// RegistryHost represents a complete configuration for a registry host,
+/// representing the capabilities, authorizations, connection configuration,
+/// and location.
+#[derive(Clone)]
+pub struct RegistryHost {
+    pub client: ureq::Agent,
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+    /// host is the host name and optional port of the registry.
+    pub host: String,
+    /// scheme is either `http` or `https`.
+    pub scheme: String,
+    /// path is the prefix of the API on the host, usually `/v2`.
+    pub path: String,
+    pub capabilities: HostCapabilities,
+    /// header is sent with every request to the host.
+    pub header: HashMap<String, String>,
+}
+
+impl RegistryHost {
+    /// is_mirror reports whether the host serves content of another registry.
+    fn is_mirror(&self, hostname: &str) -> bool {
+        self.host != hostname && !(hostname == "docker.io" && self.host == DEFAULT_REGISTRY)
+    }
+}
+
+/// RegistryHosts fetches the registry hosts for a given namespace, provided
+/// by the host component of a distribution image reference. The hosts are
+/// tried in order, mirrors come before the upstream registry.
+pub type RegistryHosts = Arc<dyn Fn(&str) -> Result<Vec<RegistryHost>, Error> + Send + Sync>;
+
+/// RegistryOptions configures the hosts returned by
+/// [configure_default_registries].
+#[derive(Clone, Default)]
+pub struct RegistryOptions {
+    /// client is used for the requests, a default agent is used if none.
+    pub client: Option<ureq::Agent>,
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+    /// plain_http uses http instead of https for every host. Hosts named
+    /// `localhost` always use http.
+    pub plain_http: bool,
+    /// mirrors lists the mirror endpoints of a host, as `host[:port]` or
+    /// `scheme://host[:port][/path]`. Mirrors are only trusted to pull and
+    /// resolve.
+    pub mirrors: HashMap<String, Vec<String>>,
+}
+
+/// configure_default_registries returns the hosts of a registry: its mirrors
+/// followed by the registry itself, `docker.io` being served by
+/// `registry-1.docker.io`.
+pub fn configure_default_registries(opts: RegistryOptions) -> RegistryHosts {
+    let client = opts.client.clone().unwrap_or_else(|| ureq::AgentBuilder::new().build());
+    Arc::new(move |host: &str| {
+        let mut hosts = Vec::new();
+        for mirror in opts.mirrors.get(host).into_iter().flatten() {
+            let mut mirror_host = registry_host(&client, &opts, mirror)?;
+            mirror_host.capabilities = HostCapabilities::PULL | HostCapabilities::RESOLVE;
+            hosts.push(mirror_host);
+        }
+
+        let upstream = if host == "docker.io" { DEFAULT_REGISTRY } else { host };
+        hosts.push(registry_host(&client, &opts, upstream)?);
+        Ok(hosts)
+    })
+}
+
+/// registry_host creates the host of an endpoint, with all capabilities.
+fn registry_host(client: &ureq::Agent, opts: &RegistryOptions, endpoint: &str) -> Result<RegistryHost, Error> {
+    let (scheme, rest) = match endpoint.split_once("://") {
+        Some((scheme, rest)) if scheme == "http" || scheme == "https" => (scheme.to_string(), rest),
+        Some(_) => return Err(Error::InvalidArgument(format!("unsupported scheme of endpoint {:?}", endpoint))),
+        None => (default_scheme(endpoint, opts.plain_http).to_string(), endpoint),
+    };
+    let (host, path) = match rest.split_once('/') {
+        Some((host, path)) if !path.trim_matches('/').is_empty() => {
+            (host, format!("/{}", path.trim_matches('/')))
+        }
+        Some((host, _)) => (host, "/v2".to_string()),
+        None => (rest, "/v2".to_string()),
+    };
+    if host.is_empty() {
+        return Err(Error::InvalidArgument(format!("invalid endpoint {:?}", endpoint)));
+    }
+
+    Ok(RegistryHost {
+        client: client.clone(),
+        authorizer: opts.authorizer.clone(),
+        host: host.to_string(),
+        scheme,
+        path,
+        capabilities: HostCapabilities::ALL,
+        header: HashMap::new(),
+    })
+}
+
+/// default_scheme returns the scheme of a host configured without one, http
+/// for `localhost` or when plain_http is set.
+fn default_scheme(host: &str, plain_http: bool) -> &'static str {
+    let local = host == "localhost" || host.starts_with("localhost:") || host.starts_with("localhost/");
+    if plain_http || local {
+        "http"
+    } else {
+        "https"
+    }
+}
+
+/// ResolverOptions are used to configure a new docker resolver.
+#[derive(Clone)]
+pub struct ResolverOptions {
+    /// hosts returns the registry host configurations for a namespace.
+    pub hosts: RegistryHosts,
+    /// headers are the HTTP request header fields sent by the resolver.
+    pub headers: HashMap<String, String>,
+    /// chunk_size is the size of the chunks blobs are uploaded in, blobs are
+    /// uploaded with a single request when zero.
+    pub chunk_size: u64,
+}
+
+impl Default for ResolverOptions {
+    fn default() -> Self {
+        ResolverOptions {
+            hosts: configure_default_registries(RegistryOptions::default()),
+            headers: HashMap::new(),
+            chunk_size: 0,
+        }
+    }
+}
+
+/// DockerResolver resolves references against registries speaking the OCI
+/// distribution API.
+pub struct DockerResolver {
+    hosts: RegistryHosts,
+    headers: HashMap<String, String>,
+    chunk_size: u64,
+}
+
+impl DockerResolver {
+    pub fn new(opts: ResolverOptions) -> DockerResolver {
+        DockerResolver {
+            hosts: opts.hosts,
+            headers: opts.headers,
+            chunk_size: opts.chunk_size,
+        }
+    }
+
+    /// base returns the connection to the repository of the reference, with
+    /// the hosts having the capabilities.
+    fn base(&self, reference: &str, capabilities: HostCapabilities) -> Result<(Spec, DockerBase), Error> {
+        let spec = Spec::parse(reference)?;
+        let hosts: Vec<RegistryHost> = (self.hosts)(spec.hostname())?
+            .into_iter()
+            .filter(|host| host.capabilities.has(capabilities))
+            .collect();
+        if hosts.is_empty() {
+            return Err(Error::NotFound(format!("no hosts for {}", spec.hostname())));
+        }
+
+        let base = DockerBase {
+            hostname: spec.hostname().to_string(),
+            repository: spec.repository().to_string(),
+            hosts,
+            headers: self.headers.clone(),
+        };
+        Ok((spec, base))
+    }
+}
+
+impl Resolver for DockerResolver {
+    fn resolve(&self, reference: &str) -> Result<(String, Descriptor), Error> {
+        let (spec, base) = self.base(reference, HostCapabilities::RESOLVE)?;
+
+        let (object, paths) = match (spec.digest(), spec.tag()) {
+            (Some(dgst), _) => (dgst, vec!["manifests", "blobs"]),
+            (None, Some(tag)) => (tag, vec!["manifests"]),
+            (None, None) => return Err(Error::InvalidArgument(format!("{}: object required", reference))),
+        };
+        let accept = [
+            oci::MEDIA_TYPE_DOCKER_SCHEMA2_MANIFEST,
+            oci::MEDIA_TYPE_DOCKER_SCHEMA2_MANIFEST_LIST,
+            oci::MEDIA_TYPE_IMAGE_MANIFEST,
+            oci::MEDIA_TYPE_IMAGE_INDEX,
+            "*/*",
+        ]
+        .join(", ");
+
+        let mut last_err = None;
+        for host in &base.hosts {
+            for path in &paths {
+                let suffix = format!("{}/{}", path, object);
+                let headers = [("Accept", accept.as_str())];
+                let response = match base.request(host, "HEAD", &suffix, &headers) {
+                    Ok(response) => response,
+                    Err(e) if e.is_not_found() => continue,
+                    Err(e) => {
+                        log::info!("trying next host after error resolving {}: {}", reference, e);
+                        last_err = Some(e);
+                        break;
+                    }
+                };
+
+                let desc = match base.descriptor(host, response, &suffix, &headers, spec.digest()) {
+                    Ok(desc) => desc,
+                    Err(e) => {
+                        log::info!("trying next host after error resolving {}: {}", reference, e);
+                        last_err = Some(e);
+                        break;
+                    }
+                };
+                return Ok((reference.to_string(), desc));
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| Error::NotFound(reference.to_string())))
+    }
+
+    fn fetcher(&self, reference: &str) -> Result<Box<dyn Fetcher>, Error> {
+        let (_, base) = self.base(reference, HostCapabilities::PULL)?;
+        Ok(Box::new(DockerFetcher { base }))
+    }
+
+    fn pusher(&self, reference: &str) -> Result<Box<dyn Pusher>, Error> {
+        let (spec, base) = self.base(reference, HostCapabilities::PUSH)?;
+        Ok(Box::new(DockerPusher {
+            base,
+            tag: spec.tag().map(|tag| tag.to_string()),
+            chunk_size: self.chunk_size,
+        }))
+    }
+}
+
+/// DockerBase sends the requests for a repository to its hosts.
+struct DockerBase {
+    hostname: String,
+    repository: String,
+    hosts: Vec<RegistryHost>,
+    headers: HashMap<String, String>,
+}
+
+impl DockerBase {
+    /// url returns the URL of the suffix of the repository API on the host.
+    fn url(&self, host: &RegistryHost, suffix: &str) -> String {
+        format!("{}://{}{}/{}/{}", host.scheme, host.host, host.path, self.repository, suffix)
+    }
+
+    /// request sends a request for the suffix of the repository API to the
+    /// host, answering an authentication challenge once. Statuses other than
+    /// 2xx are returned as errors.
+    fn request(
+        &self,
+        host: &RegistryHost,
+        method: &str,
+        suffix: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<ureq::Response, Error> {
+        self.send(host, method, &self.url(host, suffix), &[], headers, Body::Empty)
+    }
+
+    /// send sends a request with the body to the URL on the host, answering
+    /// an authentication challenge once unless the body is a stream.
+    fn send(
+        &self,
+        host: &RegistryHost,
+        method: &str,
+        url: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        mut body: Body,
+    ) -> Result<ureq::Response, Error> {
+        let mut retried = false;
+        loop {
+            let mut request = host.client.request(method, url);
+            if host.is_mirror(&self.hostname) {
+                request = request.query("ns", &self.hostname);
+            }
+            for (name, value) in query {
+                request = request.query(name, value);
+            }
+            for (name, value) in self.headers.iter().chain(host.header.iter()) {
+                request = request.set(name, value);
+            }
+            for (name, value) in headers {
+                request = request.set(name, value);
+            }
+            if let Some(authorizer) = &host.authorizer {
+                if let Some(authorization) = authorizer.authorize(&host.host)? {
+                    request = request.set("Authorization", &authorization);
+                }
+            }
+
+            let result = match &mut body {
+                Body::Empty => request.call(),
+                Body::Bytes(data) => request.send_bytes(data),
+                Body::Stream(r, size) => {
+                    // the stream is consumed, the request cannot be sent again
+                    retried = true;
+                    request.set("Content-Length", &size.to_string()).send(&mut **r)
+                }
+            };
+            match result {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(401, response)) if !retried && host.authorizer.is_some() => {
+                    let challenge = match response.header("WWW-Authenticate") {
+                        Some(challenge) => challenge.to_string(),
+                        None => return Err(status_error(method, url, 401)),
+                    };
+                    host.authorizer.as_ref().unwrap().add_responses(&host.host, &challenge)?;
+                    retried = true;
+                }
+                Err(ureq::Error::Status(status, _)) => return Err(status_error(method, url, status)),
+                Err(e) => return Err(Error::Unavailable(format!("{} request to {}: {}", method, url, e))),
+            }
+        }
+    }
+
+    /// descriptor builds the descriptor of a resolved object from the
+    /// response headers, the content is read when the registry did not send
+    /// its digest.
+    fn descriptor(
+        &self,
+        host: &RegistryHost,
+        response: ureq::Response,
+        suffix: &str,
+        headers: &[(&str, &str)],
+        expected: Option<&str>,
+    ) -> Result<Descriptor, Error> {
+        let media_type = response.header("Content-Type").unwrap_or_default();
+        let media_type = media_type.split(';').next().unwrap_or_default().trim().to_string();
+        let mut dgst = response.header("Docker-Content-Digest").unwrap_or_default().to_string();
+        let mut size = response.header("Content-Length").and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
+
+        if dgst.is_empty() {
+            if let Some(expected) = expected {
+                dgst = expected.to_string();
+            } else {
+                let response = self.request(host, "GET", suffix, headers)?;
+                let mut digester = Digester::new();
+                // one byte past the limit tells a manifest which is too large
+                let n = std::io::copy(&mut response.into_reader().take(MAX_MANIFEST_SIZE + 1), &mut digester)?;
+                if n > MAX_MANIFEST_SIZE {
+                    return Err(Error::InvalidArgument(format!(
+                        "{}: manifest too large, it exceeds {} bytes",
+                        suffix, MAX_MANIFEST_SIZE
+                    )));
+                }
+                dgst = digester.digest();
+                size = n as i64;
+            }
+        }
+        digest::validate(&dgst)?;
+        if let Some(expected) = expected {
+            if expected != dgst {
+                return Err(Error::FailedPrecondition(format!(
+                    "{}: resolved digest {} does not match {}",
+                    suffix, dgst, expected
+                )));
+            }
+        }
+        if media_type.is_empty() {
+            return Err(Error::InvalidArgument(format!("{}: no media type in response", suffix)));
+        }
+
+        Ok(Descriptor {
+            media_type,
+            digest: dgst,
+            size,
+            ..Default::default()
+        })
+    }
+}
+
+/// Body is the content sent with a request.
+enum Body<'a> {
+    Empty,
+    Bytes(&'a [u8]),
+    /// Stream is read once, with its size.
+    Stream(&'a mut dyn Read, u64),
+}
+
+fn status_error(method: &str, url: &str, status: u16) -> Error {
+    let context = format!("unexpected status {} from {} request to {}", status, method, url);
+    match status {
+        404 => Error::NotFound(context),
+        401 | 403 => Error::FailedPrecondition(context),
+        429 | 502..=504 => Error::Unavailable(context),
+        _ => Error::Unknown(context),
+    }
+}
+
+/// DockerFetcher fetches manifests and blobs of a repository.
+struct DockerFetcher {
+    base: DockerBase,
+}
+
+impl Fetcher for DockerFetcher {
+    fn fetch(&self, desc: &Descriptor, offset: u64) -> Result<Box<dyn Read + Send>, Error> {
+        let manifest = oci::is_manifest(&desc.media_type) || oci::is_index(&desc.media_type);
+        let suffix = if manifest {
+            format!("manifests/{}", desc.digest)
+        } else {
+            format!("blobs/{}", desc.digest)
+        };
+
+        let accept = format!("{}, */*", desc.media_type);
+        let range = format!("bytes={}-", offset);
+        let mut headers = vec![("Accept", accept.as_str())];
+        if offset > 0 {
+            headers.push(("Range", range.as_str()));
+        }
+
+        let mut last_err = None;
+        for host in &self.base.hosts {
+            let response = match self.base.request(host, "GET", &suffix, &headers) {
+                Ok(response) => response,
+                Err(e) => {
+                    if !e.is_not_found() {
+                        log::info!("trying next host after error fetching {}: {}", desc.digest, e);
+                    }
+                    last_err = Some(e);
+                    continue;
+                }
+            };
+
+            let partial = response.status() == 206;
+            let mut r = response.into_reader();
+            if offset > 0 && !partial {
+                // the registry ignored the range, skip to the offset
+                let skipped = std::io::copy(&mut r.by_ref().take(offset), &mut std::io::sink())?;
+                if skipped != offset {
+                    return Err(Error::FailedPrecondition(format!(
+                        "unexpected end of {} while skipping to offset {}",
+                        desc.digest, offset
+                    )));
+                }
+            }
+            return Ok(Box::new(r));
+        }
+
+        Err(last_err.unwrap_or_else(|| Error::NotFound(desc.digest.clone())))
+    }
+}
+
+/// DockerPusher pushes manifests and blobs to a repository of the first host
+/// trusted to push.
+struct DockerPusher {
+    base: DockerBase,
+    /// tag the manifests are pushed under, they are pushed by digest if none.
+    tag: Option<String>,
+    chunk_size: u64,
+}
+
+impl Pusher for DockerPusher {
+    fn push(&self, desc: &Descriptor, r: &mut dyn content::Reader) -> Result<(), Error> {
+        let host = &self.base.hosts[0];
+        if oci::is_manifest(&desc.media_type) || oci::is_index(&desc.media_type) {
+            self.push_manifest(host, desc, r)
+        } else {
+            self.push_blob(host, desc, r)
+        }
+    }
+}
+
+impl DockerPusher {
+    /// exists checks whether the object at the suffix is the content of desc.
+    /// When the registry does not send the digest of an object referenced by
+    /// tag, the object is read to compare its digest.
+    fn exists(&self, host: &RegistryHost, suffix: &str, desc: &Descriptor) -> Result<bool, Error> {
+        let accept = format!("{}, */*", desc.media_type);
+        let headers = [("Accept", accept.as_str())];
+        let response = match self.base.request(host, "HEAD", suffix, &headers) {
+            Ok(response) => response,
+            Err(e) if e.is_not_found() => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if let Some(dgst) = response.header("Docker-Content-Digest") {
+            return Ok(dgst == desc.digest);
+        }
+        // objects referenced by digest are addressed by their content
+        if suffix.ends_with(&format!("/{}", desc.digest)) {
+            return Ok(true);
+        }
+
+        let response = match self.base.request(host, "GET", suffix, &headers) {
+            Ok(response) => response,
+            Err(e) if e.is_not_found() => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut digester = Digester::new();
+        // one byte past the size tells a different object
+        let n = std::io::copy(&mut response.into_reader().take(desc.size as u64 + 1), &mut digester)?;
+        Ok(n == desc.size as u64 && digester.digest() == desc.digest)
+    }
+
+    fn push_manifest(&self, host: &RegistryHost, desc: &Descriptor, r: &mut dyn content::Reader) -> Result<(), Error> {
+        if desc.size < 0 || desc.size as u64 > MAX_MANIFEST_SIZE {
+            return Err(Error::InvalidArgument(format!(
+                "manifest {} too large, {} bytes exceed {} bytes",
+                desc.digest, desc.size, MAX_MANIFEST_SIZE
+            )));
+        }
+        let suffix = format!("manifests/{}", self.tag.as_deref().unwrap_or(&desc.digest));
+        if self.exists(host, &suffix, desc)? {
+            return Err(Error::AlreadyExists(format!("manifest {}", desc.digest)));
+        }
+
+        let mut data = Vec::new();
+        r.take(desc.size as u64).read_to_end(&mut data)?;
+        let headers = [("Content-Type", desc.media_type.as_str())];
+        let response = self.base.send(host, "PUT", &self.base.url(host, &suffix), &[], &headers, Body::Bytes(&data))?;
+        verify_pushed(&response, desc)
+    }
+
+    fn push_blob(&self, host: &RegistryHost, desc: &Descriptor, r: &mut dyn content::Reader) -> Result<(), Error> {
+        if self.exists(host, &format!("blobs/{}", desc.digest), desc)? {
+            return Err(Error::AlreadyExists(format!("blob {}", desc.digest)));
+        }
+
+        let url = self.base.url(host, "blobs/uploads/");
+        let mut response = None;
+        if let Some(from) = self.mount_source(desc) {
+            let query = [("mount", desc.digest.as_str()), ("from", from)];
+            match self.base.send(host, "POST", &url, &query, &[], Body::Bytes(&[])) {
+                Ok(mounted) if mounted.status() == 201 => return Ok(()),
+                Ok(upload) => response = Some(upload),
+                Err(e) => log::info!("failed to mount {} from {}: {}", desc.digest, from, e),
+            }
+        }
+        // the registry starts an upload when the blob cannot be mounted
+        let response = match response {
+            Some(response) => response,
+            None => self.base.send(host, "POST", &url, &[], &[], Body::Bytes(&[]))?,
+        };
+        let location = location(host, &response)?;
+
+        let response = if self.chunk_size == 0 {
+            let headers = [("Content-Type", "application/octet-stream")];
+            let query = [("digest", desc.digest.as_str())];
+            let body = Body::Stream(r, desc.size as u64);
+            self.base.send(host, "PUT", &location, &query, &headers, body)?
+        } else {
+            let location = self.upload_chunks(host, location, desc, r)?;
+            let query = [("digest", desc.digest.as_str())];
+            self.base.send(host, "PUT", &location, &query, &[], Body::Bytes(&[]))?
+        };
+        verify_pushed(&response, desc)
+    }
+
+    /// mount_source returns another repository of the registry the content
+    /// was fetched from, according to the distribution source annotation.
+    fn mount_source<'a>(&self, desc: &'a Descriptor) -> Option<&'a str> {
+        let key = format!("{}.{}", labels::LABEL_DISTRIBUTION_SOURCE_PREFIX, self.base.hostname);
+        desc.annotations
+            .get(&key)?
+            .split(',')
+            .find(|repository| !repository.is_empty() && *repository != self.base.repository)
+    }
+
+    /// upload_chunks sends the content with a PATCH request per chunk and
+    /// returns the location to complete the upload at.
+    ///
+    /// A failed chunk is resumed from the offset the registry reports for the
+    /// upload.
+    fn upload_chunks(
+        &self,
+        host: &RegistryHost,
+        mut location: String,
+        desc: &Descriptor,
+        r: &mut dyn content::Reader,
+    ) -> Result<String, Error> {
+        let size = desc.size as u64;
+        let mut buf = vec![0u8; self.chunk_size.min(size) as usize];
+        let mut offset = 0;
+        let mut resumes = 0;
+        while offset < size {
+            let n = self.chunk_size.min(size - offset);
+            let chunk = &mut buf[..n as usize];
+            r.seek(SeekFrom::Start(offset))?;
+            r.read_exact(chunk)?;
+
+            let range = format!("{}-{}", offset, offset + n - 1);
+            let headers = [("Content-Type", "application/octet-stream"), ("Content-Range", range.as_str())];
+            match self.base.send(host, "PATCH", &location, &[], &headers, Body::Bytes(chunk)) {
+                Ok(response) => {
+                    location = self::location(host, &response)?;
+                    offset += n;
+                }
+                Err(e) if resumes < MAX_UPLOAD_RESUMES && !e.is_not_found() && !e.is_failed_precondition() => {
+                    log::info!("resuming upload of {} after error: {}", desc.digest, e);
+                    resumes += 1;
+                    let response = self.base.send(host, "GET", &location, &[], &[], Body::Empty)?;
+                    offset = upload_offset(&response)?;
+                    location = self::location(host, &response)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(location)
+    }
+}
+
+/// location returns the absolute URL of the Location header of the response.
+fn location(host: &RegistryHost, response: &ureq::Response) -> Result<String, Error> {
+    let location = match response.header("Location") {
+        Some(location) if !location.is_empty() => location,
+        _ => return Err(Error::Unknown(format!("no location in response from {}", response.get_url()))),
+    };
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return Ok(location.to_string());
+    }
+    Ok(format!("{}://{}/{}", host.scheme, host.host, location.trim_start_matches('/')))
+}
+
+/// upload_offset returns the size of the upload from the `Range: 0-<end>`
+/// header of its status, the end being inclusive.
+fn upload_offset(response: &ureq::Response) -> Result<u64, Error> {
+    let range = response.header("Range").unwrap_or("0-0");
+    match range.split_once('-').map(|(_, end)| end.parse::<u64>()) {
+        Some(Ok(0)) => Ok(0),
+        Some(Ok(end)) => Ok(end + 1),
+        _ => Err(Error::Unknown(format!("invalid upload range {:?}", range))),
+    }
+}
+
+/// verify_pushed checks the registry stored the content under its digest.
+fn verify_pushed(response: &ureq::Response, desc: &Descriptor) -> Result<(), Error> {
+    match response.header("Docker-Content-Digest") {
+        Some(dgst) if dgst != desc.digest => Err(Error::FailedPrecondition(format!(
+            "pushed content {} stored with digest {}",
+            desc.digest, dgst
+        ))),
+        _ => Ok(()),
+    }
+}
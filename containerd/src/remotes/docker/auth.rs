@@ -0,0 +1,199 @@
+use crate::errdefs::Error;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Credentials returns the username and secret to authenticate to a host
+/// with, if there are any for it.
+pub type Credentials = Arc<dyn Fn(&str) -> Option<(String, String)> + Send + Sync>;
+
+/// Authorizer is used to authorize HTTP requests based on 401 HTTP responses.
+/// An Authorizer is responsible for caching tokens or credentials used by
+/// requests.
+pub trait Authorizer: Send + Sync {
+    /// authorize returns the value of the Authorization header for a request
+    /// to the host, if the host asked for one.
+    fn authorize(&self, host: &str) -> Result<Option<String>, Error>;
+
+    /// add_responses adds the challenge of a 401 response from the host, so
+    /// that the following requests are authorized.
+    ///
+    /// An error is returned if the challenge cannot be handled or if it was
+    /// already handled, in which case the request should not be retried.
+    fn add_responses(&self, host: &str, challenge: &str) -> Result<(), Error>;
+}
+
+/// Challenge is a parsed `WWW-Authenticate` header.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Challenge {
+    /// scheme is the lowercase authentication scheme, `basic` or `bearer`.
+    pub scheme: String,
+    /// parameters of the challenge, the names are lowercase.
+    pub parameters: HashMap<String, String>,
+}
+
+/// parse_challenge parses the value of a `WWW-Authenticate` header, such as
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+pub fn parse_challenge(header: &str) -> Result<Challenge, Error> {
+    let header = header.trim();
+    let (scheme, rest) = header.split_once(' ').unwrap_or((header, ""));
+    if scheme.is_empty() {
+        return Err(Error::InvalidArgument(format!("invalid challenge {:?}", header)));
+    }
+
+    let mut parameters = HashMap::new();
+    let mut rest = rest.trim_start();
+    while !rest.is_empty() {
+        let (name, value) = match rest.split_once('=') {
+            Some(parts) => parts,
+            None => return Err(Error::InvalidArgument(format!("invalid challenge {:?}", header))),
+        };
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let mut unquoted = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            if let Some((_, c)) = chars.next() {
+                                unquoted.push(c);
+                            }
+                        }
+                        Some((i, '"')) => break i + 1,
+                        Some((_, c)) => unquoted.push(c),
+                        None => return Err(Error::InvalidArgument(format!("invalid challenge {:?}", header))),
+                    }
+                };
+                (unquoted, &quoted[end..])
+            }
+            None => {
+                let end = value.find(',').unwrap_or(value.len());
+                (value[..end].trim().to_string(), &value[end..])
+            }
+        };
+        parameters.insert(name.trim().to_lowercase(), value);
+        rest = remaining.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    Ok(Challenge {
+        scheme: scheme.to_lowercase(),
+        parameters,
+    })
+}
+
+/// TokenResponse is the reply of a token server, the token is either in the
+/// `token` or in the `access_token` field.
+#[derive(Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    access_token: String,
+}
+
+/// HostAuth is the last challenge of a host and the authorization obtained
+/// for it.
+struct HostAuth {
+    challenge: Challenge,
+    authorization: Option<String>,
+}
+
+/// DockerAuthorizer handles the basic and bearer token challenges of docker
+/// registries, using the credentials of the host to authenticate.
+pub struct DockerAuthorizer {
+    client: ureq::Agent,
+    credentials: Option<Credentials>,
+    hosts: Mutex<HashMap<String, HostAuth>>,
+}
+
+impl DockerAuthorizer {
+    pub fn new(client: ureq::Agent, credentials: Option<Credentials>) -> DockerAuthorizer {
+        DockerAuthorizer {
+            client,
+            credentials,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn credentials(&self, host: &str) -> Option<(String, String)> {
+        self.credentials.as_ref().and_then(|credentials| credentials(host))
+    }
+
+    /// fetch_token requests a token from the realm of the bearer challenge.
+    fn fetch_token(&self, host: &str, challenge: &Challenge) -> Result<String, Error> {
+        let realm = match challenge.parameters.get("realm") {
+            Some(realm) if !realm.is_empty() => realm,
+            _ => return Err(Error::InvalidArgument(format!("{}: no realm specified for token auth challenge", host))),
+        };
+
+        let mut request = self.client.get(realm);
+        if let Some(service) = challenge.parameters.get("service") {
+            request = request.query("service", service);
+        }
+        if let Some(scope) = challenge.parameters.get("scope") {
+            for scope in scope.split(' ').filter(|s| !s.is_empty()) {
+                request = request.query("scope", scope);
+            }
+        }
+        if let Some((username, secret)) = self.credentials(host) {
+            request = request.set("Authorization", &basic_authorization(&username, &secret));
+        }
+
+        let response = request.call().map_err(|e| match e {
+            ureq::Error::Status(401, _) => {
+                Error::FailedPrecondition(format!("{}: unable to fetch token from {}: authorization failed", host, realm))
+            }
+            e => Error::Unavailable(format!("{}: unable to fetch token from {}: {}", host, realm, e)),
+        })?;
+        let token: TokenResponse = serde_json::from_reader(response.into_reader())
+            .map_err(|e| Error::Unknown(format!("{}: unable to decode token response: {}", host, e)))?;
+
+        match (token.token, token.access_token) {
+            (token, _) if !token.is_empty() => Ok(token),
+            (_, token) if !token.is_empty() => Ok(token),
+            _ => Err(Error::Unknown(format!("{}: no token in token response", host))),
+        }
+    }
+}
+
+impl Authorizer for DockerAuthorizer {
+    fn authorize(&self, host: &str) -> Result<Option<String>, Error> {
+        let hosts = self.hosts.lock().unwrap();
+        Ok(hosts.get(host).and_then(|auth| auth.authorization.clone()))
+    }
+
+    fn add_responses(&self, host: &str, challenge: &str) -> Result<(), Error> {
+        let challenge = parse_challenge(challenge)?;
+
+        // the same challenge again means the authorization was refused
+        if let Some(auth) = self.hosts.lock().unwrap().get(host) {
+            if auth.challenge == challenge && auth.authorization.is_some() {
+                return Err(Error::FailedPrecondition(format!("{}: authorization failed", host)));
+            }
+        }
+
+        let authorization = match challenge.scheme.as_str() {
+            "bearer" => format!("Bearer {}", self.fetch_token(host, &challenge)?),
+            "basic" => match self.credentials(host) {
+                Some((username, secret)) => basic_authorization(&username, &secret),
+                None => return Err(Error::FailedPrecondition(format!("{}: no basic auth credentials", host))),
+            },
+            scheme => return Err(Error::NotImplemented(format!("{}: unsupported auth scheme {:?}", host, scheme))),
+        };
+
+        self.hosts.lock().unwrap().insert(
+            host.to_string(),
+            HostAuth {
+                challenge,
+                authorization: Some(authorization),
+            },
+        );
+        Ok(())
+    }
+}
+
+fn basic_authorization(username: &str, secret: &str) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, secret));
+    format!("Basic {}", encoded)
+}
@@ -0,0 +1,490 @@
+use super::auth::{Authorizer, Credentials, DockerAuthorizer};
+use super::{default_scheme, HostCapabilities, RegistryHost, RegistryHosts, DEFAULT_REGISTRY};
+use crate::errdefs::Error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::ResolvesClientCert;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// HOSTS_FILE is the name of the host configuration file in a host directory.
+const HOSTS_FILE: &str = "hosts.toml";
+
+/// HostDir returns the directory holding the configuration of a registry
+/// host, if there is one.
+pub type HostDir = Arc<dyn Fn(&str) -> Result<Option<PathBuf>, Error> + Send + Sync>;
+
+/// HostOptions configures the hosts returned by [configure_hosts].
+#[derive(Clone, Default)]
+pub struct HostOptions {
+    /// host_dir locates the configuration of a host, hosts without one are
+    /// only served by their upstream registry.
+    pub host_dir: Option<HostDir>,
+    /// credentials are used to answer the authentication challenges of the
+    /// hosts.
+    pub credentials: Option<Credentials>,
+    /// plain_http uses http instead of https for the hosts configured
+    /// without a scheme.
+    pub plain_http: bool,
+}
+
+/// host_dir_from_root returns the directory named after the host under root,
+/// `_default` being used for the hosts without one. The directory of a host
+/// with a port may also be named `<host>_<port>`, for the filesystems which
+/// do not allow `:` in names.
+pub fn host_dir_from_root(root: impl Into<PathBuf>) -> HostDir {
+    let root = root.into();
+    Arc::new(move |host: &str| {
+        let underscored = host.rsplit_once(':').map(|(name, port)| format!("{}_{}", name, port));
+        for name in [Some(host), underscored.as_deref(), Some("_default")]
+            .into_iter()
+            .flatten()
+        {
+            let dir = root.join(name);
+            match std::fs::metadata(&dir) {
+                Ok(metadata) if metadata.is_dir() => return Ok(Some(dir)),
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::Unknown(format!("{}: {}", dir.display(), e))),
+            }
+        }
+        Ok(None)
+    })
+}
+
+/// configure_hosts returns the hosts of a registry as configured in its host
+/// directory, in the order of the `host` entries of `hosts.toml` followed by
+/// the `server`. The server defaults to the registry itself, `docker.io`
+/// being served by `registry-1.docker.io`.
+///
+/// A host directory without `hosts.toml` configures the TLS of the registry
+/// with the legacy layout: CA certificates in `*.crt` files and client
+/// certificates in `*.cert` files next to their `*.key`.
+///
+/// The authorizer of a host is created once and shared by the hosts returned
+/// afterwards, so that the tokens it obtained are reused.
+pub fn configure_hosts(opts: HostOptions) -> RegistryHosts {
+    let default_client = ureq::AgentBuilder::new().build();
+    let authorizers: Mutex<HashMap<String, Arc<dyn Authorizer>>> = Mutex::new(HashMap::new());
+    Arc::new(move |host: &str| {
+        let dir = match &opts.host_dir {
+            Some(host_dir) => host_dir(host)?,
+            None => None,
+        };
+        let configs = match &dir {
+            Some(dir) => load_host_dir(dir)?,
+            None => vec![HostConfig::default()],
+        };
+
+        let upstream = if host == "docker.io" { DEFAULT_REGISTRY } else { host };
+        let mut hosts = Vec::with_capacity(configs.len());
+        for config in configs {
+            let (scheme, hostname, path) = match config.endpoint.clone() {
+                Some(endpoint) => endpoint,
+                None => (None, upstream.to_string(), "/v2".to_string()),
+            };
+            let scheme = scheme.unwrap_or_else(|| default_scheme(&hostname, opts.plain_http).to_string());
+            let client = if config.has_tls() {
+                config.client()?
+            } else {
+                default_client.clone()
+            };
+            let authorizer = authorizers
+                .lock()
+                .unwrap()
+                .entry(format!("{}://{}", scheme, hostname))
+                .or_insert_with(|| Arc::new(DockerAuthorizer::new(client.clone(), opts.credentials.clone())))
+                .clone();
+
+            hosts.push(RegistryHost {
+                client,
+                authorizer: Some(authorizer),
+                host: hostname,
+                scheme,
+                path,
+                capabilities: config.capabilities,
+                header: config.header,
+            });
+        }
+        Ok(hosts)
+    })
+}
+
+/// HostFileConfig is an entry of `hosts.toml`, the top level configuring the
+/// server.
+#[derive(Deserialize, Default)]
+struct HostFileConfig {
+    /// capabilities of the host, among `pull`, `resolve` and `push`.
+    capabilities: Option<Vec<String>>,
+    /// ca are the CA certificates trusted for the host, in PEM files.
+    ca: Option<OneOrMany>,
+    /// client are the client certificates presented to the host, each either
+    /// a PEM file holding the certificate and its key or a pair of files.
+    client: Option<ClientCerts>,
+    skip_verify: Option<bool>,
+    /// header is sent with every request to the host.
+    #[serde(default)]
+    header: HashMap<String, OneOrMany>,
+    /// override_path uses the path of the host as the API root instead of
+    /// appending `/v2` to it.
+    #[serde(default)]
+    override_path: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClientCert {
+    File(String),
+    Pair([String; 2]),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClientCerts {
+    One(String),
+    Many(Vec<ClientCert>),
+}
+
+/// HostConfig is the configuration of a host loaded from a host directory.
+struct HostConfig {
+    /// endpoint is the scheme, host and API path of the host, none for the
+    /// upstream registry. The scheme is left to the defaults when missing.
+    endpoint: Option<(Option<String>, String, String)>,
+    capabilities: HostCapabilities,
+    ca_certs: Vec<PathBuf>,
+    /// client_certs are pairs of certificate and key files.
+    client_certs: Vec<(PathBuf, PathBuf)>,
+    skip_verify: bool,
+    header: HashMap<String, String>,
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        HostConfig {
+            endpoint: None,
+            capabilities: HostCapabilities::ALL,
+            ca_certs: Vec::new(),
+            client_certs: Vec::new(),
+            skip_verify: false,
+            header: HashMap::new(),
+        }
+    }
+}
+
+impl HostConfig {
+    fn has_tls(&self) -> bool {
+        self.skip_verify || !self.ca_certs.is_empty() || !self.client_certs.is_empty()
+    }
+
+    /// client returns an agent trusting the CA certificates of the host in
+    /// addition to the default roots, presenting the client certificate
+    /// issued by a CA the host accepts.
+    fn client(&self) -> Result<ureq::Agent, Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Unknown(format!("tls: {}", e)))?;
+
+        let builder = if self.skip_verify {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipVerify(provider.clone())))
+        } else {
+            let mut roots = rustls::RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            for path in &self.ca_certs {
+                for cert in read_certs(path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|e| Error::InvalidArgument(format!("{}: {}", path.display(), e)))?;
+                }
+            }
+            builder.with_root_certificates(roots)
+        };
+
+        let config = if self.client_certs.is_empty() {
+            builder.with_no_client_auth()
+        } else {
+            let mut keys = Vec::with_capacity(self.client_certs.len());
+            for (cert, key) in &self.client_certs {
+                let chain = read_certs(cert)?;
+                let key = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+                let key = provider
+                    .key_provider
+                    .load_private_key(key)
+                    .map_err(|e| Error::InvalidArgument(format!("{}: {}", cert.display(), e)))?;
+                keys.push(Arc::new(CertifiedKey::new(chain, key)));
+            }
+            builder.with_client_cert_resolver(Arc::new(ClientCertResolver(keys)))
+        };
+        Ok(ureq::AgentBuilder::new().tls_config(Arc::new(config)).build())
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|e| pem_error(path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(path, e))?;
+    if certs.is_empty() {
+        return Err(Error::InvalidArgument(format!("{}: no certificates", path.display())));
+    }
+    Ok(certs)
+}
+
+fn pem_error(path: &Path, e: pem::Error) -> Error {
+    Error::InvalidArgument(format!("{}: {}", path.display(), e))
+}
+
+/// load_host_dir loads the configuration of the hosts from the directory.
+fn load_host_dir(dir: &Path) -> Result<Vec<HostConfig>, Error> {
+    let path = dir.join(HOSTS_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(data) => {
+            parse_hosts_file(dir, &data).map_err(|e| Error::InvalidArgument(format!("{}: {}", path.display(), e)))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => load_cert_files(dir).map(|config| vec![config]),
+        Err(e) => Err(Error::Unknown(format!("{}: {}", path.display(), e))),
+    }
+}
+
+fn parse_hosts_file(dir: &Path, data: &str) -> Result<Vec<HostConfig>, String> {
+    let mut table: toml::Table = data.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    let server = match table.remove("server") {
+        Some(toml::Value::String(server)) => Some(server),
+        Some(_) => return Err("server must be a string".to_string()),
+        None => None,
+    };
+
+    let mut hosts = Vec::new();
+    match table.remove("host") {
+        Some(toml::Value::Table(entries)) => {
+            for (name, entry) in entries {
+                let config: HostFileConfig = entry
+                    .try_into()
+                    .map_err(|e: toml::de::Error| format!("host {:?}: {}", name, e))?;
+                hosts.push(parse_host_config(dir, Some(&name), config).map_err(|e| format!("host {:?}: {}", name, e))?);
+            }
+        }
+        Some(_) => return Err("host must be a table".to_string()),
+        None => {}
+    }
+
+    // the server comes last, configured by the remaining top level keys
+    let config: HostFileConfig = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+    hosts.push(parse_host_config(dir, server.as_deref(), config)?);
+    Ok(hosts)
+}
+
+fn parse_host_config(dir: &Path, endpoint: Option<&str>, config: HostFileConfig) -> Result<HostConfig, String> {
+    let mut result = HostConfig::default();
+    if let Some(endpoint) = endpoint {
+        result.endpoint = Some(parse_endpoint(endpoint, config.override_path)?);
+    }
+
+    if let Some(capabilities) = config.capabilities {
+        let mut parsed = HostCapabilities(0);
+        for capability in capabilities {
+            parsed = parsed
+                | match capability.as_str() {
+                    "pull" => HostCapabilities::PULL,
+                    "resolve" => HostCapabilities::RESOLVE,
+                    "push" => HostCapabilities::PUSH,
+                    _ => return Err(format!("unknown capability {:?}", capability)),
+                };
+        }
+        result.capabilities = parsed;
+    }
+
+    let resolve = |file: &str| dir.join(file);
+    result.ca_certs = config
+        .ca
+        .map(OneOrMany::into_vec)
+        .unwrap_or_default()
+        .iter()
+        .map(|f| resolve(f))
+        .collect();
+    result.client_certs = match config.client {
+        Some(ClientCerts::One(file)) => vec![(resolve(&file), resolve(&file))],
+        Some(ClientCerts::Many(certs)) => certs
+            .into_iter()
+            .map(|cert| match cert {
+                ClientCert::File(file) => (resolve(&file), resolve(&file)),
+                ClientCert::Pair([cert, key]) => (resolve(&cert), resolve(&key)),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    result.skip_verify = config.skip_verify.unwrap_or(false);
+    result.header = config
+        .header
+        .into_iter()
+        .map(|(name, values)| (name, values.into_vec().join(", ")))
+        .collect();
+    Ok(result)
+}
+
+/// parse_endpoint splits `[scheme://]host[/path]` into its scheme, host and
+/// API path.
+fn parse_endpoint(endpoint: &str, override_path: bool) -> Result<(Option<String>, String, String), String> {
+    let (scheme, rest) = match endpoint.split_once("://") {
+        Some((scheme, rest)) if scheme == "http" || scheme == "https" => (Some(scheme.to_string()), rest),
+        Some(_) => return Err(format!("unsupported scheme of endpoint {:?}", endpoint)),
+        None => (None, endpoint),
+    };
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    if host.is_empty() {
+        return Err(format!("invalid endpoint {:?}", endpoint));
+    }
+
+    let path = path.trim_matches('/');
+    let path = if override_path {
+        if path.is_empty() {
+            return Err(format!("override_path requires a path in endpoint {:?}", endpoint));
+        }
+        format!("/{}", path)
+    } else if path.is_empty() || path == "v2" {
+        "/v2".to_string()
+    } else if path.ends_with("/v2") {
+        format!("/{}", path)
+    } else {
+        format!("/{}/v2", path)
+    };
+    Ok((scheme, host.to_string(), path))
+}
+
+/// load_cert_files configures the upstream registry with the certificates of
+/// the legacy directory layout.
+fn load_cert_files(dir: &Path) -> Result<HostConfig, Error> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| Error::Unknown(format!("{}: {}", dir.display(), e)))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| Error::Unknown(format!("{}: {}", dir.display(), e)))?;
+    files.sort();
+
+    let mut config = HostConfig::default();
+    for file in files {
+        match file.extension().and_then(|ext| ext.to_str()) {
+            Some("crt") => config.ca_certs.push(file),
+            Some("cert") => {
+                let key = file.with_extension("key");
+                if !key.exists() {
+                    return Err(Error::InvalidArgument(format!(
+                        "missing key {} for client certificate",
+                        key.display()
+                    )));
+                }
+                config.client_certs.push((file, key));
+            }
+            Some("key") if !file.with_extension("cert").exists() => {
+                return Err(Error::InvalidArgument(format!(
+                    "missing client certificate {} for key",
+                    file.with_extension("cert").display()
+                )));
+            }
+            _ => {}
+        }
+    }
+    Ok(config)
+}
+
+/// ClientCertResolver presents the first client certificate issued by a CA
+/// the server accepts, or the first one it can sign with when the server
+/// does not tell the CAs it accepts or none matches.
+#[derive(Debug)]
+struct ClientCertResolver(Vec<Arc<CertifiedKey>>);
+
+impl ResolvesClientCert for ClientCertResolver {
+    fn resolve(&self, root_hint_subjects: &[&[u8]], sigschemes: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        let usable: Vec<_> = self
+            .0
+            .iter()
+            .filter(|key| key.key.choose_scheme(sigschemes).is_some())
+            .collect();
+        usable
+            .iter()
+            .find(|key| {
+                // the issuer of a certificate is the DER encoded name of the
+                // CA, as sent by the server
+                key.cert
+                    .iter()
+                    .any(|cert| root_hint_subjects.iter().any(|subject| contains(cert, subject)))
+            })
+            .or_else(|| usable.first())
+            .map(|key| Arc::clone(key))
+    }
+
+    fn has_certs(&self) -> bool {
+        !self.0.is_empty()
+    }
+}
+
+fn contains(data: &[u8], part: &[u8]) -> bool {
+    !part.is_empty() && data.windows(part.len()).any(|window| window == part)
+}
+
+/// SkipVerify accepts any server certificate, the signatures of the
+/// handshake are still verified.
+#[derive(Debug)]
+struct SkipVerify(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
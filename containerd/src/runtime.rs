@@ -1,56 +1,83 @@
 pub mod events;
 pub mod monitor;
+pub mod runc;
 pub mod task;
+pub mod v2;
 
+use super::context::Context;
+use super::errdefs::Error;
 use super::mount;
-use time::Time;
+use std::sync::Arc;
+use time::OffsetDateTime;
 
 /// IO holds process IO information
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct IO {
-    stdin: String,
-    stdout: String,
-    stderr: String,
-    terminal: bool,
+    pub stdin: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub terminal: bool,
 }
 
 /// CreateOpts contains task creation data
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct CreateOpts {
     // spec is the OCI runtime spec
-    spec: prost_types::Any,
+    pub spec: prost_types::Any,
     // root_fs mounts to perform to gain access to the container's filesystem
-    root_fs: Vec<mount::Mount>,
+    pub root_fs: Vec<mount::Mount>,
     // IO for the container's main process
-    io: IO,
+    pub io: IO,
     // checkpoint digest to restore container state
-    checkpoint: String,
+    pub checkpoint: String,
     // runtime_options for the runtime
-    runtime_options: prost_types::Any,
+    pub runtime_options: prost_types::Any,
     // task_options received for the task
-    task_options: prost_types::Any,
+    pub task_options: prost_types::Any,
     // runtime name to use (e.g. `io.containerd.NAME.VERSION`).
     // As an alternative full abs path to binary may be specified instead.
-    runtime: String,
+    pub runtime: String,
     // sandbox_id is an optional ID of sandbox this container belongs to
-    sandbox_id: String,
+    pub sandbox_id: String,
 }
 
 /// Exit information for a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Exit {
-    pid: u32,
-    status: u32,
-    timestamp: Time,
+    pub pid: u32,
+    pub status: u32,
+    pub timestamp: OffsetDateTime,
 }
 
 /// PlatformRuntime is responsible for the creation and management of
-/// tasks and processes for a platform.
-pub trait PlatformRuntime {
+/// tasks and processes for a platform. Runtimes are held as
+/// `Arc<dyn PlatformRuntime>`, keyed by their id, and their tasks are shared
+/// across the handlers of the servers.
+#[async_trait::async_trait]
+pub trait PlatformRuntime: Send + Sync {
     // id of the runtime
-    fn id() -> String;
-    // create creates a task with the provided id and options
-    fn create(opts: CreateOpts) -> Result<Box<dyn task::Task>, String>;
-    // tasks returns all the current tasks for the runtime.
+    fn id(&self) -> String;
+    // create creates a task with the provided id and options, in the
+    // namespace of the context
+    async fn create(&self, ctx: &Context, id: &str, opts: CreateOpts) -> Result<Arc<dyn task::Task>, Error>;
+    // get returns the task with the id in the namespace of the context
+    async fn get(&self, ctx: &Context, id: &str) -> Result<Arc<dyn task::Task>, Error>;
+    // tasks returns all the current tasks for the runtime, of the namespace
+    // of the context unless all is set.
     // Any container runs at most one task at a time.
-    fn task(all: bool) -> Result<Vec<Box<dyn task::Task>>, String>;
+    async fn tasks(&self, ctx: &Context, all: bool) -> Result<Vec<Arc<dyn task::Task>>, Error>;
     // delete remove a task.
-    fn delete(task_id: &str) -> Result<*mut Exit, String>;
+    async fn delete(&self, ctx: &Context, task_id: &str) -> Result<Exit, Error>;
+}
+
+/// blocking runs f on the threads tokio keeps for blocking calls, such as
+/// running the binary of a runtime or mounting a root filesystem.
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Unknown(format!("blocking call: {}", e)))?
 }
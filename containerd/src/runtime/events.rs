@@ -1,3 +1,10 @@
+use crate::errdefs::Error;
+use crate::events;
+use std::fmt;
+use std::str::FromStr;
+
+/// EventTopic lists the topics of the task events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EventTopic {
     // TaskCreate for task create
     TaskCreate,
@@ -24,7 +31,7 @@ pub enum EventTopic {
 }
 
 impl EventTopic {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             EventTopic::TaskCreate => "/tasks/create",
             EventTopic::TaskStart => "/tasks/start",
@@ -39,4 +46,38 @@ impl EventTopic {
             EventTopic::TaskUnknown => "/tasks/?",
         }
     }
+
+    /// type_url returns the type url of the events published on the topic,
+    /// None for TaskUnknown.
+    pub fn type_url(&self) -> Option<&'static str> {
+        events::lookup_topic(self.as_str()).map(|r| r.type_url)
+    }
+}
+
+impl FromStr for EventTopic {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let topic = match s {
+            "/tasks/create" => EventTopic::TaskCreate,
+            "/tasks/start" => EventTopic::TaskStart,
+            "/tasks/oom" => EventTopic::TaskOOM,
+            "/tasks/exit" => EventTopic::TaskExit,
+            "/tasks/delete" => EventTopic::TaskDelete,
+            "/tasks/exec-added" => EventTopic::TaskExecAdded,
+            "/tasks/exec-started" => EventTopic::TaskExecStarted,
+            "/tasks/paused" => EventTopic::TaskPaused,
+            "/tasks/resumed" => EventTopic::TaskResumed,
+            "/tasks/checkpointed" => EventTopic::TaskCheckpointed,
+            "/tasks/?" => EventTopic::TaskUnknown,
+            _ => return Err(Error::InvalidArgument(format!("unknown task event topic {:?}", s))),
+        };
+        Ok(topic)
+    }
+}
+
+impl fmt::Display for EventTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
@@ -0,0 +1,214 @@
+//! Runc runs tasks with an OCI runtime binary such as runc or crun directly,
+//! without a shim in between. It is meant for development and tests: the
+//! tasks do not survive a restart of the process running them, and they
+//! have no terminals.
+//!
+//! The runtime makes its process the subreaper of the processes of the
+//! containers, so that it can collect their exits once runc leaves them
+//! behind.
+
+mod cli;
+mod reaper;
+mod task;
+
+use self::cli::Runc;
+use self::reaper::Exits;
+use self::task::{read_pid, Container, RuncTask};
+use super::task::{Status, Task};
+use super::v2::Bundle;
+use super::{blocking, CreateOpts, Exit, PlatformRuntime};
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::identifiers;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// RUNTIME_ID is the id of the runc runtime.
+pub const RUNTIME_ID: &str = "io.containerd.runtime.runc.task";
+
+/// RuncConfig is the configuration of the runc runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuncConfig {
+    // binary is the OCI runtime binary, looked up on PATH unless it is a path
+    pub binary: PathBuf,
+    // runtime_root is the directory the binary keeps the state of the
+    // containers in, by namespace
+    pub runtime_root: PathBuf,
+    // root is the directory holding the working directories of the tasks
+    pub root: PathBuf,
+    // state is the directory holding the bundles of the tasks, by namespace
+    pub state: PathBuf,
+}
+
+impl Default for RuncConfig {
+    fn default() -> Self {
+        RuncConfig {
+            binary: PathBuf::from("runc"),
+            runtime_root: PathBuf::from("/run/containerd/runc"),
+            root: PathBuf::from("/var/lib/containerd/io.containerd.runtime.runc.task"),
+            state: PathBuf::from("/run/containerd/io.containerd.runtime.runc.task"),
+        }
+    }
+}
+
+/// RuncRuntime creates the tasks as containers of the runtime binary. The
+/// root filesystem of a task is mounted on its bundle before the container
+/// is created.
+pub struct RuncRuntime {
+    config: RuncConfig,
+    exits: Arc<Exits>,
+    tasks: Mutex<BTreeMap<(String, String), RuncTask>>,
+}
+
+impl RuncRuntime {
+    /// new creates the runtime, which makes this process a subreaper.
+    pub fn new(config: RuncConfig) -> Result<RuncRuntime, Error> {
+        reaper::set_subreaper()?;
+        Ok(RuncRuntime {
+            config,
+            exits: Arc::default(),
+            tasks: Mutex::default(),
+        })
+    }
+
+    fn runc(&self, namespace: &str) -> Runc {
+        Runc {
+            binary: self.config.binary.clone(),
+            root: self.config.runtime_root.join(namespace),
+        }
+    }
+
+    /// delete_container deletes the container of the task, which must have
+    /// stopped or not have started, and its bundle.
+    async fn delete_container(&self, container: Arc<Container>) -> Result<Exit, Error> {
+        if self.exits.get(container.pid).is_none() {
+            let c = container.clone();
+            let status = blocking(move || c.runc.state(&c.bundle.id)?.status()).await?;
+            if status != Status::CreatedStatus && status != Status::StoppedStatus {
+                return Err(Error::FailedPrecondition(format!(
+                    "task {} must be stopped before deletion: {:?}",
+                    container.bundle.id, status
+                )));
+            }
+        }
+        // a created container is waiting to be started, force kills it
+        let c = container.clone();
+        match blocking(move || c.runc.delete(&c.bundle.id, true)).await {
+            Err(e) if !e.is_not_found() => return Err(e),
+            _ => {}
+        }
+        let exit = self.exits.wait(container.pid).await;
+        let c = container.clone();
+        blocking(move || c.bundle.delete()).await?;
+
+        self.exits.forget(container.pid);
+        container.clear();
+        Ok(exit)
+    }
+}
+
+/// create_container creates the container of the bundle and starts watching
+/// its init process.
+fn create_container(runc: Runc, exits: Arc<Exits>, bundle: Bundle, opts: CreateOpts) -> Result<Container, Error> {
+    if !opts.root_fs.is_empty() {
+        bundle.mount(&opts.root_fs)?;
+    }
+    let pid_file = bundle.path.join("init.pid");
+    let pid = runc
+        .create(&bundle.id, &bundle.path, &pid_file, &opts.io)
+        .and_then(|()| read_pid(&pid_file));
+    let pid = match pid {
+        Ok(pid) => pid,
+        Err(e) => {
+            if let Err(e) = runc.delete(&bundle.id, true) {
+                if !e.is_not_found() {
+                    log::warn!("failed to delete container {}: {}", bundle.id, e);
+                }
+            }
+            return Err(e);
+        }
+    };
+    exits.watch(pid);
+    Ok(Container {
+        bundle,
+        runc,
+        exits,
+        io: opts.io,
+        pid,
+        processes: Mutex::default(),
+    })
+}
+
+#[async_trait::async_trait]
+impl PlatformRuntime for RuncRuntime {
+    fn id(&self) -> String {
+        RUNTIME_ID.to_string()
+    }
+
+    async fn create(&self, ctx: &Context, id: &str, opts: CreateOpts) -> Result<Arc<dyn Task>, Error> {
+        let namespace = ctx.namespace_required()?;
+        identifiers::validate(id)?;
+        let key = (namespace.to_string(), id.to_string());
+        if self.tasks.lock().unwrap().contains_key(&key) {
+            return Err(Error::AlreadyExists(format!("task {}", id)));
+        }
+        if opts.io.terminal {
+            return Err(Error::NotImplemented(
+                "the runc runtime does not support terminals".to_string(),
+            ));
+        }
+
+        let (config, runc, exits) = (self.config.clone(), self.runc(namespace), self.exits.clone());
+        let (namespace, id) = key.clone();
+        let container = blocking(move || {
+            let bundle = Bundle::new(&config.root, &config.state, &namespace, &id, &opts.spec)?;
+            create_container(runc, exits, bundle.clone(), opts).inspect_err(|_| {
+                if let Err(e) = bundle.delete() {
+                    log::warn!("failed to delete bundle {}: {}", bundle.path.display(), e);
+                }
+            })
+        })
+        .await?;
+        let task = RuncTask {
+            container: Arc::new(container),
+        };
+        self.tasks.lock().unwrap().insert(key, task.clone());
+        Ok(Arc::new(task))
+    }
+
+    async fn get(&self, ctx: &Context, id: &str) -> Result<Arc<dyn Task>, Error> {
+        let namespace = ctx.namespace_required()?;
+        let tasks = self.tasks.lock().unwrap();
+        match tasks.get(&(namespace.to_string(), id.to_string())) {
+            Some(task) => Ok(Arc::new(task.clone())),
+            None => Err(Error::NotFound(format!("task {}", id))),
+        }
+    }
+
+    async fn tasks(&self, ctx: &Context, all: bool) -> Result<Vec<Arc<dyn Task>>, Error> {
+        let namespace = if all { None } else { Some(ctx.namespace_required()?) };
+        let tasks = self.tasks.lock().unwrap();
+        Ok(tasks
+            .iter()
+            .filter(|((ns, _), _)| namespace.is_none_or(|namespace| ns == namespace))
+            .map(|(_, task)| Arc::new(task.clone()) as Arc<dyn Task>)
+            .collect())
+    }
+
+    async fn delete(&self, ctx: &Context, task_id: &str) -> Result<Exit, Error> {
+        let namespace = ctx.namespace_required()?;
+        let key = (namespace.to_string(), task_id.to_string());
+        let task = self
+            .tasks
+            .lock()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("task {}", task_id)))?;
+
+        let exit = self.delete_container(task.container).await?;
+        self.tasks.lock().unwrap().remove(&key);
+        Ok(exit)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::errdefs::Error;
+use crate::runtime::task::Status;
+use crate::runtime::IO;
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Runc runs the commands of an OCI runtime binary with the command line of
+/// runc, which crun shares.
+pub(crate) struct Runc {
+    // binary is the path of the runtime binary
+    pub binary: PathBuf,
+    // root is the directory the runtime keeps the state of its containers in
+    pub root: PathBuf,
+}
+
+/// RuncState is the state of a container, as printed by `runc state`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RuncState {
+    pub status: String,
+}
+
+impl RuncState {
+    /// status returns the status of the container, runc reports a container
+    /// it is still creating as `creating`.
+    pub fn status(&self) -> Result<Status, Error> {
+        match self.status.as_str() {
+            "creating" | "created" => Ok(Status::CreatedStatus),
+            "running" => Ok(Status::RunningStatus),
+            "stopped" => Ok(Status::StoppedStatus),
+            "paused" => Ok(Status::PausedStatus),
+            "pausing" => Ok(Status::PausingStatus),
+            status => Err(Error::Unknown(format!("unknown container status {:?}", status))),
+        }
+    }
+}
+
+/// process_stdio opens the stdio of a process from the paths of its IO, the
+/// streams without a path are connected to /dev/null.
+fn process_stdio(io: &IO) -> Result<[Stdio; 3], Error> {
+    let open = |path: &str, write: bool| -> Result<Stdio, Error> {
+        if path.is_empty() {
+            return Ok(Stdio::null());
+        }
+        let file = if write {
+            OpenOptions::new().create(true).append(true).open(path)
+        } else {
+            File::open(path)
+        };
+        file.map(Stdio::from)
+            .map_err(|e| Error::InvalidArgument(format!("open process io {}: {}", path, e)))
+    };
+    Ok([
+        open(&io.stdin, false)?,
+        open(&io.stdout, true)?,
+        open(&io.stderr, true)?,
+    ])
+}
+
+impl Runc {
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.binary);
+        cmd.arg("--root").arg(&self.root);
+        cmd
+    }
+
+    /// run runs the command with the arguments and returns its output.
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>, Error> {
+        let output = self
+            .command()
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| Error::Unknown(format!("run {}: {}", self.binary.display(), e)))?;
+        if !output.status.success() {
+            return Err(self.error(args, output.status, &String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(output.stdout)
+    }
+
+    /// run_with_io runs a command starting a process of a container, which
+    /// inherits the stdio of the command. The command logs its errors to the
+    /// log file instead, which is removed afterwards.
+    fn run_with_io(&self, log: &Path, args: &[&str], io: &IO) -> Result<(), Error> {
+        let [stdin, stdout, stderr] = process_stdio(io)?;
+        let status = self
+            .command()
+            .arg("--log")
+            .arg(log)
+            .args(["--log-format", "json"])
+            .args(args)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr)
+            .status()
+            .map_err(|e| Error::Unknown(format!("run {}: {}", self.binary.display(), e)));
+        let messages = log_errors(log);
+        let _ = fs::remove_file(log);
+        match status? {
+            status if status.success() => Ok(()),
+            status => Err(self.error(args, status, &messages)),
+        }
+    }
+
+    fn error(&self, args: &[&str], status: std::process::ExitStatus, stderr: &str) -> Error {
+        let stderr = stderr.trim();
+        let message = format!("{} {}: {}: {}", self.binary.display(), args.join(" "), status, stderr);
+        if stderr.contains("does not exist") || stderr.contains("not found") {
+            Error::NotFound(message)
+        } else {
+            Error::Unknown(message)
+        }
+    }
+
+    /// create creates the container of the bundle, and writes the pid of its
+    /// init process to the pid file. The init process waits for `start`.
+    pub fn create(&self, id: &str, bundle: &Path, pid_file: &Path, io: &IO) -> Result<(), Error> {
+        let (bundle_arg, pid_arg) = (bundle.to_string_lossy(), pid_file.to_string_lossy());
+        let args = ["create", "--bundle", &bundle_arg, "--pid-file", &pid_arg, id];
+        self.run_with_io(&bundle.join("log.json"), &args, io)
+    }
+
+    /// exec starts a process in the container, described by the OCI process
+    /// spec in process_file, and writes its pid to the pid file.
+    pub fn exec(&self, id: &str, log: &Path, process_file: &Path, pid_file: &Path, io: &IO) -> Result<(), Error> {
+        let (process_arg, pid_arg) = (process_file.to_string_lossy(), pid_file.to_string_lossy());
+        let args = [
+            "exec",
+            "--detach",
+            "--process",
+            &process_arg,
+            "--pid-file",
+            &pid_arg,
+            id,
+        ];
+        self.run_with_io(log, &args, io)
+    }
+
+    /// start starts the init process of a created container.
+    pub fn start(&self, id: &str) -> Result<(), Error> {
+        self.run(&["start", id]).map(|_| ())
+    }
+
+    /// state returns the state of the container.
+    pub fn state(&self, id: &str) -> Result<RuncState, Error> {
+        let out = self.run(&["state", id])?;
+        serde_json::from_slice(&out).map_err(|e| Error::Unknown(format!("invalid state of container {}: {}", id, e)))
+    }
+
+    /// kill sends the signal to the init process of the container, or to all
+    /// its processes.
+    pub fn kill(&self, id: &str, signal: u32, all: bool) -> Result<(), Error> {
+        let signal = signal.to_string();
+        let mut args = vec!["kill"];
+        if all {
+            args.push("--all");
+        }
+        args.extend([id, &signal]);
+        self.run(&args).map(|_| ())
+    }
+
+    /// delete deletes the container, force kills its processes first.
+    pub fn delete(&self, id: &str, force: bool) -> Result<(), Error> {
+        let mut args = vec!["delete"];
+        if force {
+            args.push("--force");
+        }
+        args.push(id);
+        self.run(&args).map(|_| ())
+    }
+
+    /// ps returns the pids of the processes of the container.
+    pub fn ps(&self, id: &str) -> Result<Vec<u32>, Error> {
+        let out = self.run(&["ps", "--format", "json", id])?;
+        serde_json::from_slice(&out)
+            .map_err(|e| Error::Unknown(format!("invalid processes of container {}: {}", id, e)))
+    }
+
+    pub fn pause(&self, id: &str) -> Result<(), Error> {
+        self.run(&["pause", id]).map(|_| ())
+    }
+
+    pub fn resume(&self, id: &str) -> Result<(), Error> {
+        self.run(&["resume", id]).map(|_| ())
+    }
+}
+
+/// log_errors returns the messages of the errors in a JSON log of runc, or
+/// the log itself if it is not JSON.
+fn log_errors(log: &Path) -> String {
+    #[derive(Deserialize)]
+    struct Entry {
+        level: String,
+        msg: String,
+    }
+
+    let Ok(contents) = fs::read_to_string(log) else {
+        return String::new();
+    };
+    let mut messages = Vec::new();
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) if entry.level == "error" || entry.level == "fatal" => messages.push(entry.msg),
+            Ok(_) => {}
+            Err(_) => return contents,
+        }
+    }
+    messages.join(": ")
+}
@@ -0,0 +1,105 @@
+use crate::errdefs::Error;
+use crate::runtime::Exit;
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+
+/// set_subreaper makes this process the subreaper of its descendants: the
+/// processes runc leaves behind as it exits, such as the init processes of
+/// the containers, become children of this process, which can wait for them.
+pub(crate) fn set_subreaper() -> Result<(), Error> {
+    // SAFETY: PR_SET_CHILD_SUBREAPER takes no pointer arguments.
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(Error::Unknown(format!("set child subreaper: {}", e)));
+    }
+    Ok(())
+}
+
+/// Exits collects the exits of the processes of the containers. A process
+/// killed by a signal exits with 128 plus the signal, like in a shell.
+#[derive(Default)]
+pub(crate) struct Exits {
+    exits: Mutex<HashMap<u32, Exit>>,
+    exited: Notify,
+}
+
+impl Exits {
+    /// watch reaps the process in the background once it exits and records
+    /// its exit.
+    pub fn watch(self: &Arc<Self>, pid: u32) {
+        let exits = self.clone();
+        let watcher = thread::Builder::new()
+            .name(format!("reaper-{}", pid))
+            .spawn(move || exits.record(pid, reap(pid)));
+        if let Err(e) = watcher {
+            log::error!("failed to watch process {}: {}", pid, e);
+        }
+    }
+
+    fn record(&self, pid: u32, status: u32) {
+        let exit = Exit {
+            pid,
+            status,
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        self.exits.lock().unwrap().insert(pid, exit);
+        self.exited.notify_waiters();
+    }
+
+    /// get returns the exit of the process, if it exited.
+    pub fn get(&self, pid: u32) -> Option<Exit> {
+        self.exits.lock().unwrap().get(&pid).copied()
+    }
+
+    /// wait waits for the process to exit and returns its exit.
+    pub async fn wait(&self, pid: u32) -> Exit {
+        loop {
+            // notified is created before the exits are checked, so that an
+            // exit recorded in between still wakes it
+            let exited = self.exited.notified();
+            if let Some(exit) = self.get(pid) {
+                return exit;
+            }
+            exited.await;
+        }
+    }
+
+    /// forget drops the exit of a process, whose pid may be reused.
+    pub fn forget(&self, pid: u32) {
+        self.exits.lock().unwrap().remove(&pid);
+    }
+}
+
+/// reap waits for the process to exit and returns its exit status.
+fn reap(pid: u32) -> u32 {
+    loop {
+        match waitpid(Pid::from_raw(pid as i32), None) {
+            Ok(WaitStatus::Exited(_, code)) => return code as u32,
+            Ok(WaitStatus::Signaled(_, signal, _)) => return 128 + signal as u32,
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(Errno::ECHILD) => {
+                // not a child, which happens when the process was not
+                // reparented to this one: all that can be done is to wait
+                // for it to go away
+                log::warn!("process {} is not a child, its exit status is unknown", pid);
+                let proc = format!("/proc/{}", pid);
+                while Path::new(&proc).exists() {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                return 255;
+            }
+            Err(e) => {
+                log::error!("failed to wait for process {}: {}", pid, e);
+                return 255;
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use super::cli::Runc;
+use super::reaper::Exits;
+use crate::errdefs::Error;
+use crate::identifiers;
+use crate::runtime::task::{ConsoleSize, ExecOpts, ExecProcess, Process, ProcessInfo, State, Status, Task};
+use crate::runtime::v2::Bundle;
+use crate::runtime::{blocking, Exit, IO};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+/// Container is a container created by the runtime binary, with the exec
+/// processes started in it.
+pub(crate) struct Container {
+    pub bundle: Bundle,
+    pub runc: Runc,
+    pub exits: Arc<Exits>,
+    pub io: IO,
+    // pid of the init process
+    pub pid: u32,
+    pub processes: Mutex<HashMap<String, RuncProcess>>,
+}
+
+impl Container {
+    /// clear drops the exec processes of the deleted container.
+    pub fn clear(&self) {
+        let processes: Vec<RuncProcess> = self.processes.lock().unwrap().drain().map(|(_, p)| p).collect();
+        for process in processes {
+            self.exits.forget(process.pid());
+        }
+    }
+}
+
+/// read_pid reads the pid file the runtime binary wrote for a process.
+pub(crate) fn read_pid(path: &Path) -> Result<u32, Error> {
+    let contents = fs::read_to_string(path)?;
+    contents
+        .trim()
+        .parse()
+        .map_err(|e| Error::Unknown(format!("invalid pid file {}: {}", path.display(), e)))
+}
+
+fn stopped(exit: Exit, io: &IO) -> State {
+    State {
+        status: Status::StoppedStatus,
+        pid: exit.pid,
+        exit_status: exit.status,
+        exited_at: exit.timestamp,
+        stdin: io.stdin.clone(),
+        stdout: io.stdout.clone(),
+        stderr: io.stderr.clone(),
+        terminal: io.terminal,
+    }
+}
+
+/// RuncTask is a task run by the runtime binary, its init process is the
+/// init process of the container.
+#[derive(Clone)]
+pub(crate) struct RuncTask {
+    pub container: Arc<Container>,
+}
+
+#[async_trait::async_trait]
+impl Process for RuncTask {
+    fn id(&self) -> String {
+        self.container.bundle.id.clone()
+    }
+
+    async fn state(&self) -> Result<State, Error> {
+        let c = self.container.clone();
+        if let Some(exit) = c.exits.get(c.pid) {
+            return Ok(stopped(exit, &c.io));
+        }
+        let status = blocking({
+            let c = c.clone();
+            move || c.runc.state(&c.bundle.id)?.status()
+        })
+        .await?;
+        Ok(State {
+            status,
+            pid: c.pid,
+            exit_status: 0,
+            exited_at: OffsetDateTime::UNIX_EPOCH,
+            stdin: c.io.stdin.clone(),
+            stdout: c.io.stdout.clone(),
+            stderr: c.io.stderr.clone(),
+            terminal: c.io.terminal,
+        })
+    }
+
+    async fn kill(&self, signal: u32, all: bool) -> Result<(), Error> {
+        let c = self.container.clone();
+        blocking(move || c.runc.kill(&c.bundle.id, signal, all)).await
+    }
+
+    async fn resize_pty(&self, _: ConsoleSize) -> Result<(), Error> {
+        Err(Error::NotImplemented(
+            "the runc runtime does not support terminals".to_string(),
+        ))
+    }
+
+    async fn close_io(&self) -> Result<(), Error> {
+        Err(Error::NotImplemented(
+            "the stdin of the process is held by the process".to_string(),
+        ))
+    }
+
+    async fn start(&self) -> Result<(), Error> {
+        let c = self.container.clone();
+        blocking(move || c.runc.start(&c.bundle.id)).await
+    }
+
+    async fn wait(&self) -> Result<Exit, Error> {
+        Ok(self.container.exits.wait(self.container.pid).await)
+    }
+}
+
+#[async_trait::async_trait]
+impl Task for RuncTask {
+    async fn pid(&self) -> Result<u32, Error> {
+        Ok(self.container.pid)
+    }
+
+    fn namespace(&self) -> String {
+        self.container.bundle.namespace.clone()
+    }
+
+    async fn pause(&self) -> Result<(), Error> {
+        let c = self.container.clone();
+        blocking(move || c.runc.pause(&c.bundle.id)).await
+    }
+
+    async fn resume(&self) -> Result<(), Error> {
+        let c = self.container.clone();
+        blocking(move || c.runc.resume(&c.bundle.id)).await
+    }
+
+    async fn exec(&self, id: &str, opts: ExecOpts) -> Result<Arc<dyn ExecProcess>, Error> {
+        identifiers::validate(id)?;
+        if opts.io.terminal {
+            return Err(Error::NotImplemented(
+                "the runc runtime does not support terminals".to_string(),
+            ));
+        }
+        let process = RuncProcess {
+            container: self.container.clone(),
+            id: id.to_string(),
+            io: opts.io,
+            pid: Arc::default(),
+        };
+        {
+            let mut processes = self.container.processes.lock().unwrap();
+            if processes.contains_key(id) {
+                return Err(Error::AlreadyExists(format!("process {}", id)));
+            }
+            processes.insert(id.to_string(), process.clone());
+        }
+        let spec = process.file("json");
+        if let Err(e) = blocking(move || Ok(fs::write(spec, opts.spec.value)?)).await {
+            self.container.processes.lock().unwrap().remove(id);
+            return Err(e);
+        }
+        Ok(Arc::new(process))
+    }
+
+    async fn pids(&self) -> Result<Vec<ProcessInfo>, Error> {
+        let c = self.container.clone();
+        let pids = blocking(move || c.runc.ps(&c.bundle.id)).await?;
+        Ok(pids
+            .into_iter()
+            .map(|pid| ProcessInfo {
+                pid,
+                info: prost_types::Any::default(),
+            })
+            .collect())
+    }
+
+    async fn check_point(&self, _: &str, _: Option<&prost_types::Any>) -> Result<(), Error> {
+        Err(Error::NotImplemented(
+            "the runc runtime does not support checkpoints".to_string(),
+        ))
+    }
+
+    async fn update(&self, _: Option<&prost_types::Any>, _: HashMap<String, String>) -> Result<(), Error> {
+        Err(Error::NotImplemented(
+            "the runc runtime does not support updates".to_string(),
+        ))
+    }
+
+    async fn process(&self, id: &str) -> Result<Arc<dyn ExecProcess>, Error> {
+        match self.container.processes.lock().unwrap().get(id) {
+            Some(process) => Ok(Arc::new(process.clone())),
+            None => Err(Error::NotFound(format!("process {}", id))),
+        }
+    }
+
+    async fn stats(&self) -> Result<prost_types::Any, Error> {
+        Err(Error::NotImplemented(
+            "the runc runtime does not support stats".to_string(),
+        ))
+    }
+}
+
+/// RuncProcess is an exec process of a container, started by `runc exec`
+/// once the process is started.
+#[derive(Clone)]
+pub(crate) struct RuncProcess {
+    container: Arc<Container>,
+    id: String,
+    io: IO,
+    // pid is zero until the process is started
+    pid: Arc<AtomicU32>,
+}
+
+impl RuncProcess {
+    /// file returns the path of a file of the process in the bundle.
+    fn file(&self, extension: &str) -> PathBuf {
+        self.container
+            .bundle
+            .path
+            .join(format!("exec-{}.{}", self.id, extension))
+    }
+
+    fn pid(&self) -> u32 {
+        self.pid.load(Ordering::Relaxed)
+    }
+
+    fn started_pid(&self) -> Result<u32, Error> {
+        match self.pid() {
+            0 => Err(Error::FailedPrecondition(format!("process {} is not started", self.id))),
+            pid => Ok(pid),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Process for RuncProcess {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn state(&self) -> Result<State, Error> {
+        let pid = self.pid();
+        if let Some(exit) = Some(pid)
+            .filter(|pid| *pid != 0)
+            .and_then(|pid| self.container.exits.get(pid))
+        {
+            return Ok(stopped(exit, &self.io));
+        }
+        Ok(State {
+            status: if pid == 0 {
+                Status::CreatedStatus
+            } else {
+                Status::RunningStatus
+            },
+            pid,
+            exit_status: 0,
+            exited_at: OffsetDateTime::UNIX_EPOCH,
+            stdin: self.io.stdin.clone(),
+            stdout: self.io.stdout.clone(),
+            stderr: self.io.stderr.clone(),
+            terminal: self.io.terminal,
+        })
+    }
+
+    async fn kill(&self, signal: u32, _all: bool) -> Result<(), Error> {
+        let pid = self.started_pid()?;
+        if self.container.exits.get(pid).is_some() {
+            return Err(Error::NotFound(format!("process {} already finished", self.id)));
+        }
+        let signal =
+            Signal::try_from(signal as i32).map_err(|e| Error::InvalidArgument(format!("signal {}: {}", signal, e)))?;
+        signal::kill(Pid::from_raw(pid as i32), signal)
+            .map_err(|e| Error::Unknown(format!("kill process {}: {}", self.id, e)))?;
+        Ok(())
+    }
+
+    async fn resize_pty(&self, _: ConsoleSize) -> Result<(), Error> {
+        Err(Error::NotImplemented(
+            "the runc runtime does not support terminals".to_string(),
+        ))
+    }
+
+    async fn close_io(&self) -> Result<(), Error> {
+        Err(Error::NotImplemented(
+            "the stdin of the process is held by the process".to_string(),
+        ))
+    }
+
+    async fn start(&self) -> Result<(), Error> {
+        if self.pid() != 0 {
+            return Err(Error::FailedPrecondition(format!(
+                "process {} already started",
+                self.id
+            )));
+        }
+        let process = self.clone();
+        let pid = blocking(move || {
+            let c = &process.container;
+            let pid_file = process.file("pid");
+            c.runc.exec(
+                &c.bundle.id,
+                &process.file("log"),
+                &process.file("json"),
+                &pid_file,
+                &process.io,
+            )?;
+            read_pid(&pid_file)
+        })
+        .await?;
+        self.container.exits.watch(pid);
+        self.pid.store(pid, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn wait(&self) -> Result<Exit, Error> {
+        Ok(self.container.exits.wait(self.started_pid()?).await)
+    }
+}
+
+#[async_trait::async_trait]
+impl ExecProcess for RuncProcess {
+    async fn delete(&self) -> Result<Exit, Error> {
+        let pid = self.pid();
+        let exit = match pid {
+            0 => Exit {
+                pid,
+                status: 0,
+                timestamp: OffsetDateTime::now_utc(),
+            },
+            pid => self.container.exits.get(pid).ok_or_else(|| {
+                Error::FailedPrecondition(format!("process {} must be stopped before deletion", self.id))
+            })?,
+        };
+        self.container.processes.lock().unwrap().remove(&self.id);
+        self.container.exits.forget(pid);
+        for extension in ["json", "pid"] {
+            let _ = fs::remove_file(self.file(extension));
+        }
+        Ok(exit)
+    }
+}
@@ -1,79 +1,94 @@
 use core::str;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use time::Time;
+use time::OffsetDateTime;
+
+use crate::errdefs::Error;
 
 /// TaskInfo provides task specific information
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct TaskInfo {
-    id:String,
-    runtime: String,
-    spec: Vec<u8>,
-    namespace: String,
+    pub id: String,
+    pub runtime: String,
+    pub spec: Vec<u8>,
+    pub namespace: String,
 }
 
 /// Process is a runtime object for an executing process inside a container
-pub trait Process {
+#[async_trait::async_trait]
+pub trait Process: Send + Sync {
     // id of the process
     fn id(&self) -> String;
     // state returns the process state
-    fn state(&self) -> Result<State, String>;
+    async fn state(&self) -> Result<State, Error>;
     // kill signals a container
-    fn kill(&self, signal: u32, all: bool) -> Result<(), String>;
+    async fn kill(&self, signal: u32, all: bool) -> Result<(), Error>;
     // resize_pty resizes the processes pty/console
-    fn resize_pty(&self, size: ConsoleSize) -> Result<(), String>;
+    async fn resize_pty(&self, size: ConsoleSize) -> Result<(), Error>;
     // close_io closes the processes IO
-    fn close_io(&self) -> Result<(), String>;
+    async fn close_io(&self) -> Result<(), Error>;
     // start the container's user defined process
-    fn start(&self) -> Result<(), String>;
+    async fn start(&self) -> Result<(), Error>;
     // wait for the process to exit
-    fn wait(&self) -> Result<super::Exit, String>;
+    async fn wait(&self) -> Result<super::Exit, Error>;
 }
 
 /// ExecProcess is a process spawned in container via Task.Exec call.
 /// The only difference from a regular `Process` is that exec process can delete self,
 /// while task process requires slightly more complex logic and needs to be deleted through the task manager.
+#[async_trait::async_trait]
 pub trait ExecProcess: Process {
     // delete deletes the process
-    fn delete(&self) -> Result<super::Exit, String>;
+    async fn delete(&self) -> Result<super::Exit, Error>;
 }
 
-/// Task is the runtime object for an executing container
-pub trait Task {
+/// Task is the runtime object for an executing container, the process of the
+/// task being its init process
+#[async_trait::async_trait]
+pub trait Task: Process {
     // pid of the process
-    fn pid(&self) -> Result<u32, String>;
+    async fn pid(&self) -> Result<u32, Error>;
     // namespace that the task exists in
     fn namespace(&self) -> String;
     // pause pauses the container process
-    fn pause(&self) -> Result<(), String>;
+    async fn pause(&self) -> Result<(), Error>;
     // resume unpauses the container process
-    fn resume(&self) -> Result<(), String>;
+    async fn resume(&self) -> Result<(), Error>;
     // exec adds a process into the container
-    fn exec(&self, id: &str, opts: ExecOpts) -> Result<Box<dyn ExecProcess>, String>;
+    async fn exec(&self, id: &str, opts: ExecOpts) -> Result<Arc<dyn ExecProcess>, Error>;
     // pids returns all pids
-    fn pids(&self) -> Result<Vec<ProcessInfo>, String>;
+    async fn pids(&self) -> Result<Vec<ProcessInfo>, Error>;
     // check_point checkpoints a container to an image with live system data
-    fn check_point(&self, path: &str, opts: *mut prost_types::Any) -> Result<(), String>;
+    async fn check_point(&self, path: &str, opts: Option<&prost_types::Any>) -> Result<(), Error>;
     // update sets the provided resources to a running task
-    fn update(&self, resources: *mut prost_types::Any, annotations: HashMap<String, String>) -> Result<(), String>;
+    async fn update(
+        &self,
+        resources: Option<&prost_types::Any>,
+        annotations: HashMap<String, String>,
+    ) -> Result<(), Error>;
     // process returns a process within the task for the provided id
-    fn process(&self, id: &str) -> Result<Box<dyn ExecProcess>, String>;
+    async fn process(&self, id: &str) -> Result<Arc<dyn ExecProcess>, Error>;
     // stats returns runtime specific metrics for a task
-    fn stats(&self) -> Result<*mut prost_types::Any, String>;
+    async fn stats(&self) -> Result<prost_types::Any, Error>;
 }
 
 /// ExecOpts provides additional options for additional processes running in a task
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct ExecOpts {
-    spec: *mut prost_types::Any,
-    io: super::IO,
+    pub spec: prost_types::Any,
+    pub io: super::IO,
 }
 
 /// ConsoleSize of a pty or windows terminal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct ConsoleSize {
-    width: u32,
-    height: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
 /// Status is the runtime status of a task and/or process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     // CreatedStatus when a process has been created
     CreatedStatus,
@@ -90,28 +105,30 @@ pub enum Status {
 }
 
 /// State information for a process
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     // status is the current status of the container
-    status: Status,
+    pub status: Status,
     // pid is the main process id for the container
-    pid: u32,
+    pub pid: u32,
     // exit_status of the process
     // Only valid if the Status is Stopped
-    exit_status: u32,
+    pub exit_status: u32,
     // ExitedAt is the time at which the process exited
     // Only valid if the Status is Stopped
-    exited_at: Time,
-    stdin: String,
-    stdout: String,
-    stderr: String,
-    terminal: bool,
+    pub exited_at: OffsetDateTime,
+    pub stdin: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub terminal: bool,
 }
 
 /// ProcessInfo holds platform specific process information
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProcessInfo {
     // pid is the process ID
-    pid: u32,
+    pub pid: u32,
     // info includes additional process information
     // info varies by platform
-    info: prost_types::Any,
+    pub info: prost_types::Any,
 }
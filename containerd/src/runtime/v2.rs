@@ -0,0 +1,19 @@
+//! V2 runs every task in its own shim, a process started from the shim binary
+//! of the runtime of the task which manages the task on behalf of containerd.
+//!
+//! A shim is started with `<binary> -namespace <ns> -address <address>
+//! -publish-binary <binary> -id <id> start` in the bundle of the task. It
+//! prints the address of its ttrpc server, over which containerd then creates
+//! and manages the task with the `containerd.task.v2.Task` service.
+//!
+//! The bundle of the task is created before the shim is started, the shim
+//! mounts the root filesystem of the task itself.
+
+mod binary;
+mod bundle;
+mod manager;
+mod shim;
+
+pub use binary::binary_name;
+pub use bundle::{Bundle, CONFIG_FILENAME};
+pub use manager::{ManagerConfig, ShimManager, RUNTIME_ID};
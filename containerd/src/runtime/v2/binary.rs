@@ -0,0 +1,171 @@
+use crate::api::task::v2::DeleteResponse;
+use crate::errdefs::Error;
+use crate::protobuf;
+use crate::runtime::Exit;
+use prost::Message;
+use serde::Deserialize;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// binary_name returns the name of the shim binary of a runtime, named like
+/// `io.containerd.runc.v2`: `containerd-shim-runc-v2`. None is returned if
+/// the runtime name is not of that form.
+pub fn binary_name(runtime: &str) -> Option<String> {
+    let parts: Vec<&str> = runtime.split('.').collect();
+    if parts.len() < 2 || parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    Some(format!(
+        "containerd-shim-{}-{}",
+        parts[parts.len() - 2],
+        parts[parts.len() - 1]
+    ))
+}
+
+/// resolve returns the path of the shim binary of a runtime: the runtime
+/// itself if it is an absolute path, otherwise its binary name looked up in
+/// the directories of path.
+pub(crate) fn resolve(runtime: &str, path: &[PathBuf]) -> Result<PathBuf, Error> {
+    if Path::new(runtime).is_absolute() {
+        return Ok(PathBuf::from(runtime));
+    }
+    let name =
+        binary_name(runtime).ok_or_else(|| Error::InvalidArgument(format!("invalid runtime name {:?}", runtime)))?;
+    path.iter()
+        .map(|dir| dir.join(&name))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .ok_or_else(|| Error::NotFound(format!("runtime {:?}: binary {} not installed on path", runtime, name)))
+}
+
+/// BootstrapParams is the answer of a shim to `start`, newer shims print it
+/// as JSON while older ones only print their address.
+#[derive(Debug, Deserialize)]
+struct BootstrapParams {
+    #[serde(default)]
+    version: u32,
+    address: String,
+    #[serde(default)]
+    protocol: String,
+}
+
+/// Binary runs the commands of a shim binary for a task. The commands
+/// block until the shim answers.
+#[derive(Debug, Clone)]
+pub(crate) struct Binary {
+    pub path: PathBuf,
+    pub namespace: String,
+    pub id: String,
+    pub bundle: PathBuf,
+    // address of the containerd gRPC socket
+    pub address: String,
+    // ttrpc_address of the containerd ttrpc socket shims forward events to
+    pub ttrpc_address: String,
+    // publish_binary is the binary shims run to publish events
+    pub publish_binary: String,
+}
+
+impl Binary {
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.current_dir(&self.bundle)
+            .args(["-namespace", &self.namespace, "-address", &self.address])
+            .args(["-publish-binary", &self.publish_binary, "-id", &self.id])
+            .args(args)
+            .env("TTRPC_ADDRESS", &self.ttrpc_address)
+            .env("GRPC_ADDRESS", &self.address)
+            .env("NAMESPACE", &self.namespace)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        cmd
+    }
+
+    /// run runs the shim with the arguments and returns what it printed on
+    /// stdout. The options are written to its stdin when there are any.
+    fn run(&self, args: &[&str], opts: Option<&prost_types::Any>) -> Result<Vec<u8>, Error> {
+        let mut cmd = self.command(args);
+        if opts.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
+        let name = self.path.display();
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Error::Unknown(format!("start shim {}: {}", name, e)))?;
+        if let (Some(mut stdin), Some(opts)) = (child.stdin.take(), opts) {
+            stdin
+                .write_all(&opts.encode_to_vec())
+                .map_err(|e| Error::Unknown(format!("write options to shim {}: {}", name, e)))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| Error::Unknown(format!("wait for shim {}: {}", name, e)))?;
+        if !output.status.success() {
+            let mut out = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                out = format!("{} {}", out, stderr.trim()).trim().to_string();
+            }
+            return Err(Error::Unknown(format!(
+                "shim {} {}: {}: {}",
+                name,
+                args.join(" "),
+                output.status,
+                out
+            )));
+        }
+        Ok(output.stdout)
+    }
+
+    /// start starts the shim of the task and returns the path of the socket
+    /// its ttrpc server listens on. The address is also written to the
+    /// `address` file of the bundle.
+    pub fn start(&self, opts: Option<&prost_types::Any>) -> Result<PathBuf, Error> {
+        let out = self.run(&["start"], opts)?;
+        let out = String::from_utf8_lossy(&out);
+        let out = out.trim();
+        let params = if out.starts_with('{') {
+            serde_json::from_str::<BootstrapParams>(out)
+                .map_err(|e| Error::Unknown(format!("invalid shim bootstrap parameters {:?}: {}", out, e)))?
+        } else {
+            BootstrapParams {
+                version: 1,
+                address: out.to_string(),
+                protocol: "ttrpc".to_string(),
+            }
+        };
+        if !params.protocol.is_empty() && params.protocol != "ttrpc" {
+            return Err(Error::NotImplemented(format!(
+                "shim protocol {:?} of bootstrap version {}",
+                params.protocol, params.version
+            )));
+        }
+        let socket = params
+            .address
+            .strip_prefix("unix://")
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| Error::Unknown(format!("invalid shim address {:?}", params.address)))?;
+
+        std::fs::write(self.bundle.join("address"), &params.address)?;
+        Ok(PathBuf::from(socket))
+    }
+
+    /// delete runs the cleanup of the shim for a task whose shim is gone, and
+    /// returns the exit of the task the shim reports.
+    pub fn delete(&self) -> Result<Exit, Error> {
+        let bundle = self.bundle.to_string_lossy();
+        let out = self.run(&["-bundle", &bundle, "delete"], None)?;
+        let response = DeleteResponse::decode(out.as_slice())
+            .map_err(|e| Error::Unknown(format!("invalid shim delete response: {}", e)))?;
+        Ok(Exit {
+            pid: response.pid,
+            status: response.exit_status,
+            timestamp: protobuf::from_timestamp(response.exited_at.as_ref()),
+        })
+    }
+}
@@ -0,0 +1,153 @@
+use crate::errdefs::Error;
+use crate::identifiers;
+use crate::mount::{self, Mount};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// CONFIG_FILENAME is the name of the OCI runtime spec in a bundle.
+pub const CONFIG_FILENAME: &str = "config.json";
+
+/// Bundle is the OCI bundle of a task, `<state>/<namespace>/<id>`. It holds
+/// the spec of the task in `config.json`, the root filesystem mount point
+/// `rootfs` and a `work` symlink to the working directory of the task, which
+/// lives in the persistent root instead of the state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    pub id: String,
+    pub namespace: String,
+    // path of the bundle
+    pub path: PathBuf,
+}
+
+impl Bundle {
+    /// new creates the bundle of the task in state and its working directory
+    /// in root, and writes the spec to the bundle. Whatever was created is
+    /// removed again if it fails partway.
+    pub fn new(root: &Path, state: &Path, namespace: &str, id: &str, spec: &prost_types::Any) -> Result<Bundle, Error> {
+        identifiers::validate(namespace)?;
+        identifiers::validate(id)?;
+        let bundle = Bundle {
+            id: id.to_string(),
+            namespace: namespace.to_string(),
+            path: state.join(namespace).join(id),
+        };
+        let mut created = Vec::new();
+        if let Err(e) = bundle.create(&root.join(namespace).join(id), spec, &mut created) {
+            for path in created.iter().rev() {
+                if let Err(e) = fs::remove_dir_all(path) {
+                    log::warn!("failed to remove {}: {}", path.display(), e);
+                }
+            }
+            return Err(e);
+        }
+        Ok(bundle)
+    }
+
+    fn create(&self, work: &Path, spec: &prost_types::Any, created: &mut Vec<PathBuf>) -> Result<(), Error> {
+        let mut dirs = fs::DirBuilder::new();
+        dirs.recursive(true).mode(0o711);
+        if let Some(parent) = self.path.parent() {
+            dirs.create(parent)?;
+        }
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&self.path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => Error::AlreadyExists(format!("bundle {}", self.path.display())),
+                _ => e.into(),
+            })?;
+        created.push(self.path.clone());
+
+        if let Some(parent) = work.parent() {
+            dirs.create(parent)?;
+        }
+        fs::DirBuilder::new().mode(0o711).create(self.rootfs())?;
+        let mut dir = fs::DirBuilder::new();
+        dir.mode(0o711);
+        if let Err(e) = dir.create(work) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(e.into());
+            }
+            // the working directory of a previous task of the same id
+            fs::remove_dir_all(work)?;
+            dir.create(work)?;
+        }
+        created.push(work.to_path_buf());
+        std::os::unix::fs::symlink(work, self.path.join("work"))?;
+
+        if !spec.value.is_empty() {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o666)
+                .open(self.path.join(CONFIG_FILENAME))?
+                .write_all(&spec.value)?;
+        }
+        Ok(())
+    }
+
+    /// rootfs returns the mount point of the root filesystem of the task.
+    pub fn rootfs(&self) -> PathBuf {
+        self.path.join("rootfs")
+    }
+
+    /// mount mounts the root filesystem of the task on the rootfs of the
+    /// bundle, for runtimes which do not mount it themselves. The mounts
+    /// already made are undone if one fails.
+    pub fn mount(&self, mounts: &[Mount]) -> Result<(), Error> {
+        let rootfs = self.rootfs();
+        if let Err(e) = Mount::all(mounts, &rootfs.to_string_lossy()) {
+            if let Err(e) = mount::unmount_recursive(&rootfs, 0) {
+                log::warn!("failed to unmount rootfs {}: {}", rootfs.display(), e);
+            }
+            return Err(Error::Unknown(format!("mount rootfs of task {}: {}", self.id, e)));
+        }
+        Ok(())
+    }
+
+    /// delete unmounts the root filesystem of the task and removes the
+    /// bundle and the working directory of the task.
+    pub fn delete(&self) -> Result<(), Error> {
+        let work = fs::read_link(self.path.join("work"));
+        let rootfs = self.rootfs();
+        mount::unmount_recursive(&rootfs, 0)
+            .map_err(|e| Error::Unknown(format!("unmount rootfs {}: {}", rootfs.display(), e)))?;
+        // the rootfs is removed on its own first, so that whatever is still
+        // mounted there is never removed with the bundle
+        if let Err(e) = fs::remove_dir(&rootfs) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(Error::FailedPrecondition(format!(
+                    "remove bundle rootfs {}: {}",
+                    rootfs.display(),
+                    e
+                )));
+            }
+        }
+        let result = atomic_delete(&self.path);
+        // the working directory is removed even if the bundle could not be
+        if let Ok(work) = work {
+            atomic_delete(&work)?;
+        }
+        result
+    }
+}
+
+/// atomic_delete renames the directory to a hidden name before removing it,
+/// so that a partially removed directory is never mistaken for a live one.
+fn atomic_delete(path: &Path) -> Result<(), Error> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(Error::InvalidArgument(format!("cannot delete {}", path.display())));
+    };
+    let mut hidden = std::ffi::OsString::from(".");
+    hidden.push(name);
+    let hidden = parent.join(hidden);
+    match fs::rename(path, &hidden) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    fs::remove_dir_all(&hidden)?;
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use super::binary::{self, Binary};
+use super::bundle::Bundle;
+use super::shim::{Shim, ShimTask};
+use crate::api::task::v2::{CreateTaskRequest, TaskClient};
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::identifiers;
+use crate::runtime::task::Task;
+use crate::runtime::{blocking, CreateOpts, Exit, PlatformRuntime};
+use crate::ttrpc;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// RUNTIME_ID is the id of the shim v2 runtime.
+pub const RUNTIME_ID: &str = "io.containerd.runtime.v2.task";
+
+/// ManagerConfig is the configuration of a shim manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagerConfig {
+    // root is the directory holding the working directories of the tasks
+    pub root: PathBuf,
+    // state is the directory holding the bundles of the tasks, by namespace
+    pub state: PathBuf,
+    // address of the containerd gRPC socket, passed to the shims
+    pub address: String,
+    // ttrpc_address of the containerd ttrpc socket shims forward events to
+    pub ttrpc_address: String,
+    // publish_binary is the binary shims run to publish events
+    pub publish_binary: String,
+    // path lists the directories searched for the shim binaries
+    pub path: Vec<PathBuf>,
+}
+
+impl Default for ManagerConfig {
+    fn default() -> Self {
+        ManagerConfig {
+            root: PathBuf::from("/var/lib/containerd/io.containerd.runtime.v2.task"),
+            state: PathBuf::from("/run/containerd/io.containerd.runtime.v2.task"),
+            address: crate::server::DEFAULT_ADDRESS.to_string(),
+            ttrpc_address: format!("{}.ttrpc", crate::server::DEFAULT_ADDRESS),
+            publish_binary: "containerd".to_string(),
+            path: std::env::var_os("PATH")
+                .map(|path| std::env::split_paths(&path).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// ShimManager runs a shim per task: `create` starts the shim binary of the
+/// runtime of the task, which serves the task over ttrpc until the task is
+/// deleted.
+pub struct ShimManager {
+    config: ManagerConfig,
+    tasks: Mutex<BTreeMap<(String, String), ShimTask>>,
+}
+
+impl ShimManager {
+    pub fn new(config: ManagerConfig) -> ShimManager {
+        ShimManager {
+            config,
+            tasks: Mutex::default(),
+        }
+    }
+
+    fn binary(&self, path: &Path, bundle: &Bundle) -> Binary {
+        Binary {
+            path: path.to_path_buf(),
+            namespace: bundle.namespace.clone(),
+            id: bundle.id.clone(),
+            bundle: bundle.path.clone(),
+            address: self.config.address.clone(),
+            ttrpc_address: self.config.ttrpc_address.clone(),
+            publish_binary: self.config.publish_binary.clone(),
+        }
+    }
+
+    async fn start(&self, namespace: &str, id: &str, opts: CreateOpts) -> Result<ShimTask, Error> {
+        let path = binary::resolve(&opts.runtime, &self.config.path)?;
+        let (root, state) = (self.config.root.clone(), self.config.state.clone());
+        let (ns, bundle_id, spec) = (namespace.to_string(), id.to_string(), opts.spec.clone());
+        let bundle = blocking(move || Bundle::new(&root, &state, &ns, &bundle_id, &spec)).await?;
+
+        let binary = self.binary(&path, &bundle);
+        let runtime_options = Some(opts.runtime_options.clone()).filter(|o| !o.type_url.is_empty());
+        let started = {
+            let binary = binary.clone();
+            blocking(move || binary.start(runtime_options.as_ref())).await
+        };
+        let result = match started {
+            Ok(socket) => self.create_task(&path, bundle.clone(), &socket, &opts).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let (namespace, id) = (namespace.to_string(), id.to_string());
+            let cleanup = blocking(move || {
+                // the shim may have started, let it clean up after itself
+                if bundle.path.join("address").exists() {
+                    if let Err(e) = binary.delete() {
+                        log::warn!("failed to clean up shim of task {} in {}: {}", id, namespace, e);
+                    }
+                }
+                if let Err(e) = bundle.delete() {
+                    log::warn!("failed to delete bundle {}: {}", bundle.path.display(), e);
+                }
+                Ok(())
+            });
+            if let Err(e) = cleanup.await {
+                log::warn!("failed to clean up after the shim: {}", e);
+            }
+        }
+        result
+    }
+
+    /// create_task connects to the shim listening on socket and creates the
+    /// task of the bundle in it. The root filesystem is mounted by the shim.
+    async fn create_task(
+        &self,
+        binary: &Path,
+        bundle: Bundle,
+        socket: &Path,
+        opts: &CreateOpts,
+    ) -> Result<ShimTask, Error> {
+        let client = ttrpc::Client::connect(socket).await?;
+        let req = CreateTaskRequest {
+            id: bundle.id.clone(),
+            bundle: bundle.path.to_string_lossy().into_owned(),
+            rootfs: opts.root_fs.iter().map(Into::into).collect(),
+            terminal: opts.io.terminal,
+            stdin: opts.io.stdin.clone(),
+            stdout: opts.io.stdout.clone(),
+            stderr: opts.io.stderr.clone(),
+            checkpoint: opts.checkpoint.clone(),
+            parent_checkpoint: String::new(),
+            options: Some(opts.task_options.clone()).filter(|o| !o.type_url.is_empty()),
+        };
+        let shim = Arc::new(Shim {
+            bundle,
+            binary: binary.to_path_buf(),
+            client: TaskClient::new(client),
+        });
+        let ctx = Context::new().with_namespace(&shim.bundle.namespace);
+        match shim.client.create(&ctx, &req).await {
+            Ok(response) => Ok(ShimTask::new(shim, response.pid)),
+            Err(e) => {
+                let task = ShimTask::new(shim, 0);
+                if let Err(e) = task.shutdown().await {
+                    let bundle = &task.shim().bundle;
+                    log::warn!(
+                        "failed to shut down shim of task {} in {}: {}",
+                        bundle.id,
+                        bundle.namespace,
+                        e
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// delete_task deletes the task in its shim, or has the shim binary
+    /// clean up if the shim is gone, then deletes the bundle of the task.
+    async fn delete_task(&self, task: &ShimTask) -> Result<Exit, Error> {
+        let shim = task.shim();
+        let exit = match task.delete().await {
+            Ok(exit) => {
+                if let Err(e) = task.shutdown().await {
+                    log::warn!("failed to shut down shim of task {}: {}", shim.bundle.id, e);
+                }
+                exit
+            }
+            Err(Error::Unavailable(_)) => {
+                let binary = self.binary(&shim.binary, &shim.bundle);
+                blocking(move || binary.delete()).await?
+            }
+            Err(e) => return Err(e),
+        };
+        let bundle = shim.bundle.clone();
+        blocking(move || bundle.delete()).await?;
+        Ok(exit)
+    }
+}
+
+#[async_trait::async_trait]
+impl PlatformRuntime for ShimManager {
+    fn id(&self) -> String {
+        RUNTIME_ID.to_string()
+    }
+
+    async fn create(&self, ctx: &Context, id: &str, opts: CreateOpts) -> Result<Arc<dyn Task>, Error> {
+        let namespace = ctx.namespace_required()?;
+        identifiers::validate(id)?;
+        let key = (namespace.to_string(), id.to_string());
+        if self.tasks.lock().unwrap().contains_key(&key) {
+            return Err(Error::AlreadyExists(format!("task {}", id)));
+        }
+
+        let task = self.start(namespace, id, opts).await?;
+        self.tasks.lock().unwrap().insert(key, task.clone());
+        Ok(Arc::new(task))
+    }
+
+    async fn get(&self, ctx: &Context, id: &str) -> Result<Arc<dyn Task>, Error> {
+        let namespace = ctx.namespace_required()?;
+        let tasks = self.tasks.lock().unwrap();
+        match tasks.get(&(namespace.to_string(), id.to_string())) {
+            Some(task) => Ok(Arc::new(task.clone())),
+            None => Err(Error::NotFound(format!("task {}", id))),
+        }
+    }
+
+    async fn tasks(&self, ctx: &Context, all: bool) -> Result<Vec<Arc<dyn Task>>, Error> {
+        let namespace = if all { None } else { Some(ctx.namespace_required()?) };
+        let tasks = self.tasks.lock().unwrap();
+        Ok(tasks
+            .iter()
+            .filter(|((ns, _), _)| namespace.is_none_or(|namespace| ns == namespace))
+            .map(|(_, task)| Arc::new(task.clone()) as Arc<dyn Task>)
+            .collect())
+    }
+
+    async fn delete(&self, ctx: &Context, task_id: &str) -> Result<Exit, Error> {
+        let namespace = ctx.namespace_required()?;
+        let key = (namespace.to_string(), task_id.to_string());
+        let task = self
+            .tasks
+            .lock()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("task {}", task_id)))?;
+
+        let exit = self.delete_task(&task).await?;
+        self.tasks.lock().unwrap().remove(&key);
+        Ok(exit)
+    }
+}
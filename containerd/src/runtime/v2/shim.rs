@@ -0,0 +1,357 @@
+use super::bundle::Bundle;
+use crate::api::task::v2::{
+    CheckpointTaskRequest, CloseIoRequest, ConnectRequest, DeleteRequest, ExecProcessRequest, KillRequest,
+    PauseRequest, PidsRequest, ResizePtyRequest, ResumeRequest, ShutdownRequest, StartRequest, StateRequest,
+    StatsRequest, TaskClient, UpdateTaskRequest, WaitRequest,
+};
+use crate::api::v1::types::Status as ApiStatus;
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::protobuf;
+use crate::runtime::task::{ConsoleSize, ExecOpts, ExecProcess, Process, ProcessInfo, State, Status, Task};
+use crate::runtime::Exit;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Shim is the connection to the shim of a task.
+pub(crate) struct Shim {
+    pub bundle: Bundle,
+    // binary is the shim binary, run to clean up once the shim is gone
+    pub binary: PathBuf,
+    pub client: TaskClient,
+}
+
+impl Shim {
+    fn ctx(&self) -> Context {
+        Context::new().with_namespace(&self.bundle.namespace)
+    }
+}
+
+/// ShimProcess is a process of a task managed by its shim, the init process
+/// of the task has an empty exec id.
+#[derive(Clone)]
+pub(crate) struct ShimProcess {
+    shim: Arc<Shim>,
+    exec_id: String,
+    pid: Arc<AtomicU32>,
+}
+
+impl ShimProcess {
+    fn new(shim: Arc<Shim>, exec_id: &str) -> ShimProcess {
+        ShimProcess {
+            shim,
+            exec_id: exec_id.to_string(),
+            pid: Arc::default(),
+        }
+    }
+
+    async fn state(&self) -> Result<State, Error> {
+        let shim = &self.shim;
+        let req = StateRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+        };
+        let response = shim.client.state(&shim.ctx(), &req).await?;
+        let status = match ApiStatus::from_i32(response.status) {
+            Some(ApiStatus::Created) => Status::CreatedStatus,
+            Some(ApiStatus::Running) => Status::RunningStatus,
+            Some(ApiStatus::Stopped) => Status::StoppedStatus,
+            Some(ApiStatus::Paused) => Status::PausedStatus,
+            Some(ApiStatus::Pausing) => Status::PausingStatus,
+            _ => {
+                return Err(Error::Unknown(format!(
+                    "process {:?} of task {}: unknown status {}",
+                    self.exec_id, shim.bundle.id, response.status
+                )))
+            }
+        };
+        Ok(State {
+            status,
+            pid: response.pid,
+            exit_status: response.exit_status,
+            exited_at: protobuf::from_timestamp(response.exited_at.as_ref()),
+            stdin: response.stdin,
+            stdout: response.stdout,
+            stderr: response.stderr,
+            terminal: response.terminal,
+        })
+    }
+
+    async fn pid(&self) -> Result<u32, Error> {
+        match self.pid.load(Ordering::Relaxed) {
+            0 => Ok(self.state().await?.pid),
+            pid => Ok(pid),
+        }
+    }
+
+    async fn delete(&self) -> Result<Exit, Error> {
+        let shim = &self.shim;
+        let req = DeleteRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+        };
+        let response = shim.client.delete(&shim.ctx(), &req).await?;
+        Ok(Exit {
+            pid: response.pid,
+            status: response.exit_status,
+            timestamp: protobuf::from_timestamp(response.exited_at.as_ref()),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Process for ShimProcess {
+    fn id(&self) -> String {
+        if self.exec_id.is_empty() {
+            self.shim.bundle.id.clone()
+        } else {
+            self.exec_id.clone()
+        }
+    }
+
+    async fn state(&self) -> Result<State, Error> {
+        ShimProcess::state(self).await
+    }
+
+    async fn kill(&self, signal: u32, all: bool) -> Result<(), Error> {
+        let shim = &self.shim;
+        let req = KillRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+            signal,
+            all,
+        };
+        shim.client.kill(&shim.ctx(), &req).await
+    }
+
+    async fn resize_pty(&self, size: ConsoleSize) -> Result<(), Error> {
+        let shim = &self.shim;
+        let req = ResizePtyRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+            width: size.width,
+            height: size.height,
+        };
+        shim.client.resize_pty(&shim.ctx(), &req).await
+    }
+
+    async fn close_io(&self) -> Result<(), Error> {
+        let shim = &self.shim;
+        let req = CloseIoRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+            stdin: true,
+        };
+        shim.client.close_io(&shim.ctx(), &req).await
+    }
+
+    async fn start(&self) -> Result<(), Error> {
+        let shim = &self.shim;
+        let req = StartRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+        };
+        let response = shim.client.start(&shim.ctx(), &req).await?;
+        self.pid.store(response.pid, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn wait(&self) -> Result<Exit, Error> {
+        let shim = &self.shim;
+        let pid = self.pid().await?;
+        let req = WaitRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: self.exec_id.clone(),
+        };
+        let response = shim.client.wait(&shim.ctx(), &req).await?;
+        Ok(Exit {
+            pid,
+            status: response.exit_status,
+            timestamp: protobuf::from_timestamp(response.exited_at.as_ref()),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ExecProcess for ShimProcess {
+    async fn delete(&self) -> Result<Exit, Error> {
+        ShimProcess::delete(self).await
+    }
+}
+
+/// ShimTask is a task managed by its shim, over ttrpc.
+#[derive(Clone)]
+pub(crate) struct ShimTask {
+    init: ShimProcess,
+}
+
+impl ShimTask {
+    pub fn new(shim: Arc<Shim>, pid: u32) -> ShimTask {
+        let init = ShimProcess::new(shim, "");
+        init.pid.store(pid, Ordering::Relaxed);
+        ShimTask { init }
+    }
+
+    pub fn shim(&self) -> &Shim {
+        &self.init.shim
+    }
+
+    /// delete deletes the task in its shim and returns its exit.
+    pub async fn delete(&self) -> Result<Exit, Error> {
+        self.init.delete().await
+    }
+
+    /// shutdown asks the shim to exit now that it has no task left. The shim
+    /// closes the connection as it exits, which is not an error.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        let shim = self.shim();
+        let req = ShutdownRequest {
+            id: shim.bundle.id.clone(),
+            now: true,
+        };
+        match shim.client.shutdown(&shim.ctx(), &req).await {
+            Err(Error::Unavailable(_)) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Process for ShimTask {
+    fn id(&self) -> String {
+        self.init.id()
+    }
+
+    async fn state(&self) -> Result<State, Error> {
+        Process::state(&self.init).await
+    }
+
+    async fn kill(&self, signal: u32, all: bool) -> Result<(), Error> {
+        self.init.kill(signal, all).await
+    }
+
+    async fn resize_pty(&self, size: ConsoleSize) -> Result<(), Error> {
+        self.init.resize_pty(size).await
+    }
+
+    async fn close_io(&self) -> Result<(), Error> {
+        self.init.close_io().await
+    }
+
+    async fn start(&self) -> Result<(), Error> {
+        self.init.start().await
+    }
+
+    async fn wait(&self) -> Result<Exit, Error> {
+        self.init.wait().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Task for ShimTask {
+    async fn pid(&self) -> Result<u32, Error> {
+        let shim = self.shim();
+        match self.init.pid.load(Ordering::Relaxed) {
+            0 => {
+                let req = ConnectRequest {
+                    id: shim.bundle.id.clone(),
+                };
+                Ok(shim.client.connect(&shim.ctx(), &req).await?.task_pid)
+            }
+            pid => Ok(pid),
+        }
+    }
+
+    fn namespace(&self) -> String {
+        self.shim().bundle.namespace.clone()
+    }
+
+    async fn pause(&self) -> Result<(), Error> {
+        let shim = self.shim();
+        let req = PauseRequest {
+            id: shim.bundle.id.clone(),
+        };
+        shim.client.pause(&shim.ctx(), &req).await
+    }
+
+    async fn resume(&self) -> Result<(), Error> {
+        let shim = self.shim();
+        let req = ResumeRequest {
+            id: shim.bundle.id.clone(),
+        };
+        shim.client.resume(&shim.ctx(), &req).await
+    }
+
+    async fn exec(&self, id: &str, opts: ExecOpts) -> Result<Arc<dyn ExecProcess>, Error> {
+        let shim = self.shim();
+        let req = ExecProcessRequest {
+            id: shim.bundle.id.clone(),
+            exec_id: id.to_string(),
+            terminal: opts.io.terminal,
+            stdin: opts.io.stdin,
+            stdout: opts.io.stdout,
+            stderr: opts.io.stderr,
+            spec: Some(opts.spec),
+        };
+        shim.client.exec(&shim.ctx(), &req).await?;
+        Ok(Arc::new(ShimProcess::new(self.init.shim.clone(), id)))
+    }
+
+    async fn pids(&self) -> Result<Vec<ProcessInfo>, Error> {
+        let shim = self.shim();
+        let req = PidsRequest {
+            id: shim.bundle.id.clone(),
+        };
+        let response = shim.client.pids(&shim.ctx(), &req).await?;
+        Ok(response
+            .processes
+            .into_iter()
+            .map(|p| ProcessInfo {
+                pid: p.pid,
+                info: p.info.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn check_point(&self, path: &str, opts: Option<&prost_types::Any>) -> Result<(), Error> {
+        let shim = self.shim();
+        let req = CheckpointTaskRequest {
+            id: shim.bundle.id.clone(),
+            path: path.to_string(),
+            options: opts.cloned(),
+        };
+        shim.client.checkpoint(&shim.ctx(), &req).await
+    }
+
+    async fn update(
+        &self,
+        resources: Option<&prost_types::Any>,
+        annotations: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let shim = self.shim();
+        let req = UpdateTaskRequest {
+            id: shim.bundle.id.clone(),
+            resources: resources.cloned(),
+            annotations,
+        };
+        shim.client.update(&shim.ctx(), &req).await
+    }
+
+    async fn process(&self, id: &str) -> Result<Arc<dyn ExecProcess>, Error> {
+        // the state of the process tells whether the shim knows it
+        let process = ShimProcess::new(self.init.shim.clone(), id);
+        process.state().await?;
+        Ok(Arc::new(process))
+    }
+
+    async fn stats(&self) -> Result<prost_types::Any, Error> {
+        let shim = self.shim();
+        let req = StatsRequest {
+            id: shim.bundle.id.clone(),
+        };
+        let response = shim.client.stats(&shim.ctx(), &req).await?;
+        Ok(response.stats.unwrap_or_default())
+    }
+}
@@ -0,0 +1,153 @@
+//! Server serves the containerd gRPC API on a Unix socket.
+//!
+//! The services are added to a [`Builder`], which creates the socket with the
+//! configured ownership and permissions and serves until shut down:
+//!
+//! ```ignore
+//! Builder::new(Config::default())
+//!     .add_service(EventsServer::new(events::Service::new(exchange)))
+//!     .add_service(VersionServer::new(version::Service))
+//!     .serve(shutdown)
+//!     .await?;
+//! ```
+
+use crate::errdefs::Error;
+use std::convert::Infallible;
+use std::fs;
+use std::future::Future;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Request, Response};
+use tonic::codegen::Service;
+use tonic::server::NamedService;
+use tonic::transport::server::Router;
+use tonic::transport::{Body, Server};
+
+/// DEFAULT_ADDRESS is the default path of the gRPC socket.
+pub const DEFAULT_ADDRESS: &str = "/run/containerd/containerd.sock";
+
+/// DEFAULT_MODE is the default permission of the gRPC socket, only its owner
+/// and group may connect.
+pub const DEFAULT_MODE: u32 = 0o660;
+
+/// Config is the configuration of the gRPC socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    // address is the path of the socket, its directory is created if missing
+    pub address: PathBuf,
+    // uid owns the socket, the owner is left as is when None
+    pub uid: Option<u32>,
+    // gid owns the socket, the group is left as is when None
+    pub gid: Option<u32>,
+    // mode is the permission of the socket
+    pub mode: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: PathBuf::from(DEFAULT_ADDRESS),
+            uid: None,
+            gid: None,
+            mode: DEFAULT_MODE,
+        }
+    }
+}
+
+/// Builder collects the services to serve on the socket of a config.
+pub struct Builder {
+    config: Config,
+    router: Option<Router>,
+}
+
+impl Builder {
+    pub fn new(config: Config) -> Builder {
+        Builder { config, router: None }
+    }
+
+    /// add_service adds a generated service, such as
+    /// `EventsServer::new(service)`, to the server.
+    pub fn add_service<S>(mut self, service: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.router = Some(match self.router.take() {
+            Some(router) => router.add_service(service),
+            None => Server::builder().add_service(service),
+        });
+        self
+    }
+
+    /// serve listens on the socket and serves the services until signal
+    /// completes. Requests to a service that was not added fail with
+    /// Unimplemented. The socket is removed once the server stopped.
+    pub async fn serve<F>(self, signal: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()>,
+    {
+        let router = self
+            .router
+            .ok_or_else(|| Error::FailedPrecondition("no service to serve".to_string()))?;
+        let listener = listen(&self.config)?;
+        listener.set_nonblocking(true)?;
+        let incoming = UnixListenerStream::new(tokio::net::UnixListener::from_std(listener)?);
+
+        let result = router
+            .serve_with_incoming_shutdown(incoming, signal)
+            .await
+            .map_err(|e| Error::Unknown(format!("serve {}: {}", self.config.address.display(), e)));
+        if let Err(e) = fs::remove_file(&self.config.address) {
+            log::warn!("failed to remove socket {}: {}", self.config.address.display(), e);
+        }
+        result
+    }
+}
+
+/// listen creates the socket of the config and listens on it. A socket left
+/// over by a server that is gone is replaced, while a socket another server
+/// still accepts connections on is AlreadyExists.
+pub fn listen(config: &Config) -> Result<UnixListener, Error> {
+    let path = config.address.as_path();
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o711)
+            .create(dir)
+            .map_err(|e| Error::Unknown(format!("create socket directory {}: {}", dir.display(), e)))?;
+    }
+    remove_stale(path)?;
+
+    let listener =
+        UnixListener::bind(path).map_err(|e| Error::Unknown(format!("listen on {}: {}", path.display(), e)))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(config.mode))?;
+    if config.uid.is_some() || config.gid.is_some() {
+        std::os::unix::fs::chown(path, config.uid, config.gid)
+            .map_err(|e| Error::Unknown(format!("chown {}: {}", path.display(), e)))?;
+    }
+    Ok(listener)
+}
+
+/// remove_stale removes the socket at path unless a server listens on it.
+fn remove_stale(path: &Path) -> Result<(), Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::AlreadyExists(format!("{} is not a socket", path.display())));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(Error::AlreadyExists(format!("address {} is in use", path.display())));
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
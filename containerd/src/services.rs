@@ -0,0 +1,24 @@
+//! Services implement the containerd gRPC API on top of the stores and the
+//! event exchange.
+
+pub mod events;
+pub mod version;
+
+use crate::context::{Context, Metadata};
+use crate::errdefs::Error;
+use tonic::metadata::KeyAndValueRef;
+
+/// context returns the context of an incoming request, from the namespace,
+/// lease and deadline of its metadata. Requests without a namespace are in
+/// default_namespace, unless it is empty.
+pub(crate) fn context<T>(request: &tonic::Request<T>, default_namespace: &str) -> Result<Context, Error> {
+    let mut md = Metadata::new();
+    for kv in request.metadata().iter() {
+        if let KeyAndValueRef::Ascii(key, value) = kv {
+            if let Ok(value) = value.to_str() {
+                md.entry(key.as_str().to_string()).or_default().push(value.to_string());
+            }
+        }
+    }
+    Context::from_grpc_metadata(&md, default_namespace)
+}
@@ -0,0 +1,113 @@
+use crate::api::services::events::v1::events_server::Events;
+use crate::api::services::events::v1::{Envelope, ForwardRequest, PublishRequest, SubscribeRequest};
+use crate::api::services::ttrpc::events::v1 as ttrpc;
+use crate::context::Context;
+use crate::errdefs::Error;
+use crate::events::exchange::{Exchange, SubscribeOpts, Subscription};
+use crate::events::Publisher;
+use crate::namespaces;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// Service serves the events API from an exchange.
+pub struct Service {
+    exchange: Arc<Exchange>,
+    default_namespace: String,
+}
+
+impl Service {
+    /// new returns a service publishing the events of requests without a
+    /// namespace in `namespaces::default_namespace()`.
+    pub fn new(exchange: Arc<Exchange>) -> Service {
+        Service {
+            exchange,
+            default_namespace: namespaces::default_namespace(),
+        }
+    }
+
+    /// with_default_namespace sets the namespace of the requests without
+    /// one, an empty namespace rejects them.
+    pub fn with_default_namespace(mut self, namespace: &str) -> Service {
+        self.default_namespace = namespace.to_string();
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl Events for Service {
+    /// publish packs the event into an envelope with the namespace of the
+    /// request and broadcasts it.
+    async fn publish(&self, request: Request<PublishRequest>) -> Result<Response<()>, Status> {
+        let ctx = super::context(&request, &self.default_namespace)?;
+        let namespace = ctx.namespace_required()?;
+        let req = request.into_inner();
+        let event = req
+            .event
+            .ok_or_else(|| Error::InvalidArgument("event must not be empty".to_string()))?;
+
+        self.exchange.publish(namespace, &req.topic, event)?;
+        Ok(Response::new(()))
+    }
+
+    /// forward broadcasts an envelope built by another publisher, a shim for
+    /// example, with its own timestamp and namespace.
+    async fn forward(&self, request: Request<ForwardRequest>) -> Result<Response<()>, Status> {
+        let envelope = request
+            .into_inner()
+            .envelope
+            .ok_or_else(|| Error::InvalidArgument("envelope must not be empty".to_string()))?;
+
+        self.exchange.forward(envelope.into())?;
+        Ok(Response::new(()))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<Envelope, Status>>;
+
+    /// subscribe streams the events matching any of the filters, from all
+    /// namespaces unless a filter restricts them.
+    async fn subscribe(&self, request: Request<SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status> {
+        let req = request.into_inner();
+        let filters: Vec<&str> = req.filters.iter().map(String::as_str).collect();
+        let sub = self.exchange.subscribe(&filters, SubscribeOpts::default())?;
+
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(stream_events(sub, tx));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// The ttrpc events service receives the events forwarded by the shims.
+#[async_trait::async_trait]
+impl ttrpc::Events for Service {
+    async fn forward(&self, _: &Context, req: ttrpc::ForwardRequest) -> Result<(), Error> {
+        let envelope = req
+            .envelope
+            .ok_or_else(|| Error::InvalidArgument("envelope must not be empty".to_string()))?;
+        self.exchange.forward(envelope.into())
+    }
+}
+
+/// stream_events sends the events of the subscription to the stream of the
+/// client, until either of them is closed. Dropping the subscription on
+/// return unsubscribes it.
+async fn stream_events(sub: Subscription, tx: mpsc::Sender<Result<Envelope, Status>>) {
+    loop {
+        let result = tokio::select! {
+            result = sub.recv_async() => result,
+            _ = tx.closed() => return,
+        };
+        match result {
+            Ok(envelope) => {
+                if tx.send(Ok(envelope.into())).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(Err(e.into())).await;
+                return;
+            }
+        }
+    }
+}
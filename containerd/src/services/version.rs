@@ -0,0 +1,17 @@
+use crate::api::services::version::v1::version_server::Version;
+use crate::api::services::version::v1::VersionResponse;
+use tonic::{Request, Response, Status};
+
+/// Service serves the version API with the version the daemon was built with.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Service;
+
+#[tonic::async_trait]
+impl Version for Service {
+    async fn version(&self, _: Request<()>) -> Result<Response<VersionResponse>, Status> {
+        Ok(Response::new(VersionResponse {
+            version: crate::VERSION.to_string(),
+            revision: crate::REVISION.to_string(),
+        }))
+    }
+}
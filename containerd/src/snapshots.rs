@@ -0,0 +1,159 @@
+use super::context::Context;
+use super::errdefs::Error;
+use super::filters::{self, Adaptor};
+use super::mount::Mount;
+use std::collections::HashMap;
+use std::fmt;
+use time::OffsetDateTime;
+
+/// UNPACK_KEY_PREFIX is the beginning of the key format used for snapshots
+/// that will have image content unpacked into them.
+pub const UNPACK_KEY_PREFIX: &str = "extract";
+
+/// Kind identifies the kind of snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Kind {
+    #[default]
+    Unknown,
+    View,
+    Active,
+    Committed,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Kind::Unknown => "Unknown",
+            Kind::View => "View",
+            Kind::Active => "Active",
+            Kind::Committed => "Committed",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Info provides information about a particular snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Info {
+    /// kind of the snapshot, active and view snapshots can be mounted
+    /// read-write and read-only, committed ones can only be parents.
+    pub kind: Kind,
+    /// name of key of snapshot
+    pub name: String,
+    /// parent is the name of the committed parent, empty for the base.
+    pub parent: String,
+    /// labels for the snapshot, these are mutable.
+    pub labels: HashMap<String, String>,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl Info {
+    pub fn new(kind: Kind, name: &str, parent: &str) -> Info {
+        Info {
+            kind,
+            name: name.to_string(),
+            parent: parent.to_string(),
+            labels: HashMap::new(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+}
+
+/// Usage defines statistics for disk resources consumed by the snapshot.
+///
+/// These resources only include the resources consumed by the snapshot itself
+/// and does not include resources usage by the parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+    /// inodes is the number of inodes in use.
+    pub inodes: i64,
+    /// size is the number of bytes in use.
+    pub size: i64,
+}
+
+/// Snapshotter defines the methods required to implement a snapshot
+/// snapshotter for allocating, snapshotting and mounting filesystem changesets.
+///
+/// A snapshot is prepared from a committed parent into an active snapshot
+/// identified by a key. Once the changes are made through the mounts of the
+/// active snapshot, it is committed under a name, which can be the parent of
+/// further snapshots.
+pub trait Snapshotter: Send + Sync {
+    /// stat returns the info for an active or committed snapshot by name or
+    /// key.
+    fn stat(&self, ctx: &Context, key: &str) -> Result<Info, Error>;
+
+    /// update updates the info for a snapshot.
+    ///
+    /// Only mutable properties of a snapshot may be updated.
+    fn update(&self, ctx: &Context, info: Info, fieldpaths: &[&str]) -> Result<Info, Error>;
+
+    /// usage returns the resource usage of an active or committed snapshot
+    /// excluding the usage of parent snapshots.
+    fn usage(&self, ctx: &Context, key: &str) -> Result<Usage, Error>;
+
+    /// mounts returns the mounts for the active snapshot transaction
+    /// identified by key.
+    fn mounts(&self, ctx: &Context, key: &str) -> Result<Vec<Mount>, Error>;
+
+    /// prepare creates an active snapshot identified by key descending from
+    /// the provided parent. The returned mounts can be used to mount the
+    /// snapshot to capture changes.
+    ///
+    /// If a parent is provided, after performing the mounts, the destination
+    /// will start with the content of the parent. An empty parent starts from
+    /// an empty directory.
+    ///
+    /// AlreadyExists is returned when the key is in use.
+    fn prepare(
+        &self,
+        ctx: &Context,
+        key: &str,
+        parent: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Vec<Mount>, Error>;
+
+    /// view behaves identically to prepare except the result may not be
+    /// committed back to the snapshotter. The mounts are read-only.
+    fn view(&self, ctx: &Context, key: &str, parent: &str, labels: HashMap<String, String>)
+        -> Result<Vec<Mount>, Error>;
+
+    /// commit captures the changes between key and its parent into a snapshot
+    /// identified by name. The active snapshot key is removed.
+    ///
+    /// AlreadyExists is returned when a snapshot with the name exists.
+    fn commit(&self, ctx: &Context, name: &str, key: &str, labels: HashMap<String, String>) -> Result<(), Error>;
+
+    /// remove the committed or active snapshot by the provided key.
+    ///
+    /// FailedPrecondition is returned if the snapshot is a parent of another
+    /// snapshot.
+    fn remove(&self, ctx: &Context, key: &str) -> Result<(), Error>;
+
+    /// walk will call f for each snapshot in the snapshotter which match the
+    /// provided filters. If no filters are given all items will be walked.
+    fn walk(&self, ctx: &Context, filters: &[&str], f: &mut dyn FnMut(Info) -> Result<(), Error>)
+        -> Result<(), Error>;
+}
+
+/// Adaptor for snapshot info exposes the `kind`, `name`, `parent` and
+/// `labels.<key>` field paths to filters.
+impl Adaptor for Info {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        let value = match fieldpath.first()?.as_str() {
+            "kind" => self.kind.to_string(),
+            "name" => self.name.clone(),
+            "parent" => self.parent.clone(),
+            "labels" => return filters::check_map(&fieldpath[1..], &self.labels),
+            _ => return None,
+        };
+
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+}
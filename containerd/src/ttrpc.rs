@@ -0,0 +1,79 @@
+//! Ttrpc is the lightweight RPC protocol containerd speaks with its shims.
+//!
+//! Requests and responses are protobuf messages, as with gRPC, but they are
+//! framed directly on the connection: every message is preceded by a header
+//! holding the length of its payload, the id of the stream it belongs to, its
+//! type and its flags. Clients use odd, increasing stream ids, each request
+//! opens a stream which the response of the server ends. Streaming methods
+//! exchange data messages on the stream before the response.
+
+pub mod client;
+mod frame;
+pub mod server;
+
+pub use client::{Client, ClientStream};
+pub use frame::{KeyValue, Request, Response, MESSAGE_LENGTH_MAX};
+pub use server::{Server, ServerStream, ServiceDesc};
+
+/// service defines the trait of a ttrpc service, its client and the function
+/// registering an implementation of the trait on a server. The methods of
+/// the trait default to NotImplemented.
+macro_rules! service {
+    (
+        $(#[$attr:meta])*
+        service $trait:ident = $name:literal, $client:ident, $register:ident {
+            $(fn $method:ident($req:ty) -> $resp:ty = $rpc:literal;)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[::async_trait::async_trait]
+        pub trait $trait: Send + Sync + 'static {
+            $(
+                async fn $method(
+                    &self,
+                    _ctx: &$crate::context::Context,
+                    _req: $req,
+                ) -> Result<$resp, $crate::errdefs::Error> {
+                    Err($crate::errdefs::Error::NotImplemented(concat!($name, "/", $rpc).to_string()))
+                }
+            )*
+        }
+
+        #[doc = concat!("Client of the `", $name, "` service.")]
+        #[derive(Clone)]
+        pub struct $client {
+            client: $crate::ttrpc::Client,
+        }
+
+        impl $client {
+            pub fn new(client: $crate::ttrpc::Client) -> Self {
+                $client { client }
+            }
+
+            $(
+                pub async fn $method(
+                    &self,
+                    ctx: &$crate::context::Context,
+                    req: &$req,
+                ) -> Result<$resp, $crate::errdefs::Error> {
+                    self.client.call(ctx, $name, $rpc, req).await
+                }
+            )*
+        }
+
+        #[doc = concat!("Registers the implementation of the `", $name, "` service on the server.")]
+        pub fn $register<T: $trait>(server: &mut $crate::ttrpc::Server, service: ::std::sync::Arc<T>) {
+            let desc = $crate::ttrpc::ServiceDesc::new();
+            $(
+                let s = service.clone();
+                let desc = desc.method($rpc, move |ctx: $crate::context::Context, req: $req| {
+                    let s = s.clone();
+                    async move { s.$method(&ctx, req).await }
+                });
+            )*
+            server.register($name, desc);
+        }
+    };
+}
+
+pub(crate) use service;
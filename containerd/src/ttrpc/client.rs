@@ -0,0 +1,308 @@
+use super::frame::{
+    self, Frame, Received, Request, Response, FLAG_NO_DATA, FLAG_REMOTE_CLOSED, FLAG_REMOTE_OPEN, MESSAGE_LENGTH_MAX,
+    MESSAGE_TYPE_DATA, MESSAGE_TYPE_REQUEST, MESSAGE_TYPE_RESPONSE,
+};
+use crate::context::{Context, Metadata};
+use crate::errdefs::Error;
+use prost::Message;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::net::unix::OwnedReadHalf;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+
+/// Client sends the requests of its callers over a single connection,
+/// each on its own stream. Cloning the client shares the connection.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    tx: mpsc::UnboundedSender<Frame>,
+    streams: Mutex<Streams>,
+}
+
+#[derive(Default)]
+struct Streams {
+    next_id: u32,
+    receivers: HashMap<u32, mpsc::UnboundedSender<Result<Frame, Error>>>,
+    closed: Option<Error>,
+}
+
+impl Client {
+    /// connect connects to the ttrpc server listening on the socket at
+    /// address.
+    pub async fn connect<P: AsRef<Path>>(address: P) -> Result<Client, Error> {
+        let address = address.as_ref();
+        let stream = UnixStream::connect(address)
+            .await
+            .map_err(|e| Error::Unavailable(format!("ttrpc: connect to {}: {}", address.display(), e)))?;
+        Ok(Client::new(stream))
+    }
+
+    /// new returns a client over an established connection. It must be called
+    /// within a tokio runtime, which runs the reads and writes.
+    pub fn new(stream: UnixStream) -> Client {
+        let (r, w) = stream.into_split();
+        let inner = Arc::new(Inner {
+            tx: frame::spawn_writer(w),
+            streams: Mutex::new(Streams {
+                next_id: 1,
+                ..Default::default()
+            }),
+        });
+        // the reader only holds the client weakly: once the last clone is
+        // dropped the connection is shut down and the server closes it
+        tokio::spawn(read_frames(Arc::downgrade(&inner), r));
+        Client { inner }
+    }
+
+    /// is_closed returns whether the connection is closed, no more request
+    /// can be sent then.
+    pub fn is_closed(&self) -> bool {
+        self.inner.streams.lock().unwrap().closed.is_some()
+    }
+
+    /// request sends a request to the method of the service and returns the
+    /// payload of the response. The namespace and deadline of the context
+    /// are sent along, waiting for the response stops at the deadline.
+    pub async fn request(
+        &self,
+        ctx: &Context,
+        service: &str,
+        method: &str,
+        payload: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut stream = self.open(ctx, service, method, 0, payload)?;
+        let frame = stream.next().await?;
+        if frame.kind != MESSAGE_TYPE_RESPONSE {
+            return Err(Error::Unknown(format!(
+                "ttrpc: unexpected message of type {} on stream {}",
+                frame.kind, stream.id
+            )));
+        }
+        let response = Response::decode(frame.payload.as_slice())
+            .map_err(|e| Error::Unknown(format!("ttrpc: invalid response: {}", e)))?;
+        frame::check(response.status)?;
+        Ok(response.payload)
+    }
+
+    /// call sends the message to the method of the service and decodes the
+    /// response.
+    pub async fn call<Req, Resp>(&self, ctx: &Context, service: &str, method: &str, req: &Req) -> Result<Resp, Error>
+    where
+        Req: Message,
+        Resp: Message + Default,
+    {
+        let payload = self.request(ctx, service, method, req.encode_to_vec()).await?;
+        Resp::decode(payload.as_slice()).map_err(|e| Error::Unknown(format!("ttrpc: invalid response: {}", e)))
+    }
+
+    /// stream opens a stream to a method the client sends messages to, with
+    /// ClientStream::send until ClientStream::close_send.
+    pub fn stream(&self, ctx: &Context, service: &str, method: &str) -> Result<ClientStream, Error> {
+        let inner = self.open(ctx, service, method, FLAG_REMOTE_OPEN, Vec::new())?;
+        Ok(ClientStream { inner, done: false })
+    }
+
+    /// server_stream opens a stream to a method the client only sends req
+    /// to, the server streams its responses back.
+    pub fn server_stream<Req: Message>(
+        &self,
+        ctx: &Context,
+        service: &str,
+        method: &str,
+        req: &Req,
+    ) -> Result<ClientStream, Error> {
+        let inner = self.open(ctx, service, method, FLAG_REMOTE_CLOSED, req.encode_to_vec())?;
+        Ok(ClientStream { inner, done: false })
+    }
+
+    /// open allocates a stream and sends the request opening it.
+    fn open(&self, ctx: &Context, service: &str, method: &str, flags: u8, payload: Vec<u8>) -> Result<Stream, Error> {
+        ctx.check_deadline()?;
+        let mut md = Metadata::new();
+        ctx.to_ttrpc_metadata(&mut md);
+        let request = Request {
+            service: service.to_string(),
+            method: method.to_string(),
+            payload,
+            timeout_nano: ctx
+                .timeout()
+                .map_or(0, |t| t.as_nanos().clamp(1, i64::MAX as u128) as i64),
+            metadata: frame::from_metadata(md),
+        };
+        let payload = request.encode_to_vec();
+        if payload.len() > MESSAGE_LENGTH_MAX {
+            return Err(frame::too_large(payload.len()));
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = {
+            let mut streams = self.inner.streams.lock().unwrap();
+            if let Some(e) = &streams.closed {
+                return Err(e.clone());
+            }
+            let id = streams.next_id;
+            streams.next_id = id
+                .checked_add(2)
+                .ok_or_else(|| Error::Unavailable("ttrpc: stream ids exhausted".to_string()))?;
+            streams.receivers.insert(id, tx);
+            id
+        };
+        let stream = Stream {
+            client: self.inner.clone(),
+            id,
+            rx,
+            deadline: ctx.deadline(),
+        };
+        self.inner.send(Frame::new(id, MESSAGE_TYPE_REQUEST, flags, payload))?;
+        Ok(stream)
+    }
+}
+
+impl Inner {
+    fn send(&self, frame: Frame) -> Result<(), Error> {
+        self.tx.send(frame).map_err(|_| self.closed())
+    }
+
+    fn closed(&self) -> Error {
+        let streams = self.streams.lock().unwrap();
+        streams
+            .closed
+            .clone()
+            .unwrap_or_else(|| Error::Unavailable("ttrpc: connection closed".to_string()))
+    }
+}
+
+/// read_frames routes the frames received on the connection to the streams
+/// they belong to, until the connection is closed.
+async fn read_frames(client: std::sync::Weak<Inner>, mut r: OwnedReadHalf) {
+    let err = loop {
+        let received = frame::read_frame(&mut r).await;
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        let (id, end, delivered) = match received {
+            Ok(Received::Frame(frame)) => (frame.stream_id, frame.kind == MESSAGE_TYPE_RESPONSE, Ok(frame)),
+            Ok(Received::TooLarge(frame, length)) => (frame.stream_id, true, Err(frame::too_large(length))),
+            Err(e) => break e,
+        };
+        let mut streams = client.streams.lock().unwrap();
+        match streams.receivers.get(&id) {
+            Some(tx) => {
+                let _ = tx.send(delivered);
+                if end {
+                    streams.receivers.remove(&id);
+                }
+            }
+            None => log::debug!("ttrpc: message for unknown stream {}", id),
+        }
+    };
+
+    if let Some(client) = client.upgrade() {
+        let mut streams = client.streams.lock().unwrap();
+        let reason = if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            "ttrpc: connection closed".to_string()
+        } else {
+            format!("ttrpc: connection closed: {}", err)
+        };
+        streams.closed = Some(Error::Unavailable(reason));
+        // dropping the senders wakes up the streams waiting for a message
+        streams.receivers.clear();
+    }
+}
+
+/// Stream is a stream of the client, unregistered when dropped.
+struct Stream {
+    client: Arc<Inner>,
+    id: u32,
+    rx: mpsc::UnboundedReceiver<Result<Frame, Error>>,
+    deadline: Option<Instant>,
+}
+
+impl Stream {
+    /// next returns the next frame received on the stream, waiting until the
+    /// deadline of the stream at most.
+    async fn next(&mut self) -> Result<Frame, Error> {
+        let received = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), self.rx.recv())
+                .await
+                .map_err(|_| Error::DeadlineExceeded("ttrpc: context deadline exceeded".to_string()))?,
+            None => self.rx.recv().await,
+        };
+        match received {
+            Some(frame) => frame,
+            None => Err(self.client.closed()),
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.client.streams.lock().unwrap().receivers.remove(&self.id);
+    }
+}
+
+/// ClientStream is a stream opened by the client, the server sends messages
+/// until it ends the stream with its status.
+pub struct ClientStream {
+    inner: Stream,
+    done: bool,
+}
+
+impl ClientStream {
+    /// send sends a message to the server.
+    pub fn send<M: Message>(&self, msg: &M) -> Result<(), Error> {
+        let payload = msg.encode_to_vec();
+        if payload.len() > MESSAGE_LENGTH_MAX {
+            return Err(frame::too_large(payload.len()));
+        }
+        self.inner
+            .client
+            .send(Frame::new(self.inner.id, MESSAGE_TYPE_DATA, 0, payload))
+    }
+
+    /// close_send tells the server that the client will not send more
+    /// messages.
+    pub fn close_send(&self) -> Result<(), Error> {
+        self.inner.client.send(Frame::new(
+            self.inner.id,
+            MESSAGE_TYPE_DATA,
+            FLAG_REMOTE_CLOSED | FLAG_NO_DATA,
+            Vec::new(),
+        ))
+    }
+
+    /// recv returns the next message sent by the server, None once the server
+    /// ended the stream successfully.
+    pub async fn recv<M: Message + Default>(&mut self) -> Result<Option<M>, Error> {
+        while !self.done {
+            let frame = self.inner.next().await?;
+            match frame.kind {
+                MESSAGE_TYPE_DATA if frame.flags & FLAG_NO_DATA != 0 => {}
+                MESSAGE_TYPE_DATA => {
+                    return M::decode(frame.payload.as_slice())
+                        .map(Some)
+                        .map_err(|e| Error::Unknown(format!("ttrpc: invalid message: {}", e)));
+                }
+                MESSAGE_TYPE_RESPONSE => {
+                    self.done = true;
+                    let response = Response::decode(frame.payload.as_slice())
+                        .map_err(|e| Error::Unknown(format!("ttrpc: invalid response: {}", e)))?;
+                    frame::check(response.status)?;
+                }
+                kind => {
+                    return Err(Error::Unknown(format!(
+                        "ttrpc: unexpected message of type {} on stream {}",
+                        kind, self.inner.id
+                    )))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
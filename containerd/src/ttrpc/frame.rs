@@ -0,0 +1,195 @@
+use crate::context::Metadata;
+use crate::errdefs::Error;
+use crate::plugin::rpc::Status;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// HEADER_LENGTH is the length of the header preceding every message: the
+/// length of the payload and the stream id as big endian u32, then the type
+/// and the flags of the message.
+const HEADER_LENGTH: usize = 10;
+
+/// MESSAGE_LENGTH_MAX is the largest payload a peer accepts.
+pub const MESSAGE_LENGTH_MAX: usize = 4 << 20;
+
+pub(crate) const MESSAGE_TYPE_REQUEST: u8 = 0x1;
+pub(crate) const MESSAGE_TYPE_RESPONSE: u8 = 0x2;
+pub(crate) const MESSAGE_TYPE_DATA: u8 = 0x3;
+
+// FLAG_REMOTE_CLOSED is set once the sender will not send more data
+pub(crate) const FLAG_REMOTE_CLOSED: u8 = 0x1;
+// FLAG_REMOTE_OPEN is set on the request of a stream the client sends data on
+pub(crate) const FLAG_REMOTE_OPEN: u8 = 0x2;
+// FLAG_NO_DATA is set on a data message without payload
+pub(crate) const FLAG_NO_DATA: u8 = 0x4;
+
+/// Request is the payload of a request message.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Request {
+    #[prost(string, tag = "1")]
+    pub service: String,
+    #[prost(string, tag = "2")]
+    pub method: String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub payload: Vec<u8>,
+    /// timeout_nano is the time left before the deadline of the request, 0
+    /// if it has none.
+    #[prost(int64, tag = "4")]
+    pub timeout_nano: i64,
+    #[prost(message, repeated, tag = "5")]
+    pub metadata: Vec<KeyValue>,
+}
+
+/// KeyValue is a metadata entry of a request, a key with several values is
+/// sent as several entries.
+#[derive(Clone, PartialEq, Eq, prost::Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+/// Response is the payload of a response message, it ends a stream.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Response {
+    #[prost(message, optional, tag = "1")]
+    pub status: Option<Status>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub payload: Vec<u8>,
+}
+
+/// to_metadata folds the entries of a request into metadata.
+pub(crate) fn to_metadata(entries: Vec<KeyValue>) -> Metadata {
+    let mut md = Metadata::new();
+    for kv in entries {
+        md.entry(kv.key.to_lowercase()).or_default().push(kv.value);
+    }
+    md
+}
+
+/// from_metadata flattens metadata into the entries of a request.
+pub(crate) fn from_metadata(md: Metadata) -> Vec<KeyValue> {
+    let mut entries: Vec<KeyValue> = md
+        .into_iter()
+        .flat_map(|(key, values)| {
+            values.into_iter().map(move |value| KeyValue {
+                key: key.clone(),
+                value,
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    entries
+}
+
+/// status returns the status of an error, code 0 for success.
+pub(crate) fn status(result: Result<(), Error>) -> Status {
+    match result {
+        Ok(()) => Status::default(),
+        Err(e) => {
+            let status = tonic::Status::from(e);
+            Status {
+                code: status.code() as i32,
+                message: status.message().to_string(),
+                details: Vec::new(),
+            }
+        }
+    }
+}
+
+/// check returns the error of a status, if it is not successful.
+pub(crate) fn check(status: Option<Status>) -> Result<(), Error> {
+    match status {
+        Some(status) if status.code != 0 => {
+            Err(tonic::Status::new(tonic::Code::from(status.code), status.message).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Frame is a message of a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub stream_id: u32,
+    pub kind: u8,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(stream_id: u32, kind: u8, flags: u8, payload: Vec<u8>) -> Frame {
+        Frame {
+            stream_id,
+            kind,
+            flags,
+            payload,
+        }
+    }
+}
+
+/// Received is the outcome of reading a frame whose header could be read.
+pub(crate) enum Received {
+    Frame(Frame),
+    // TooLarge carries the header of a frame whose payload was discarded
+    TooLarge(Frame, usize),
+}
+
+/// read_frame reads the next frame of the connection. The payload of a frame
+/// larger than MESSAGE_LENGTH_MAX is discarded so that the connection stays
+/// usable, the peer is told with an error on that stream.
+pub(crate) async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> std::io::Result<Received> {
+    let mut header = [0u8; HEADER_LENGTH];
+    r.read_exact(&mut header).await?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let mut frame = Frame::new(
+        u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+        header[8],
+        header[9],
+        Vec::new(),
+    );
+
+    if length > MESSAGE_LENGTH_MAX {
+        tokio::io::copy(&mut r.take(length as u64), &mut tokio::io::sink()).await?;
+        return Ok(Received::TooLarge(frame, length));
+    }
+    frame.payload = vec![0; length];
+    r.read_exact(&mut frame.payload).await?;
+    Ok(Received::Frame(frame))
+}
+
+/// write_frame writes the frame to the connection.
+pub(crate) async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, frame: &Frame) -> std::io::Result<()> {
+    let mut p = Vec::with_capacity(HEADER_LENGTH + frame.payload.len());
+    p.extend_from_slice(&(frame.payload.len() as u32).to_be_bytes());
+    p.extend_from_slice(&frame.stream_id.to_be_bytes());
+    p.push(frame.kind);
+    p.push(frame.flags);
+    p.extend_from_slice(&frame.payload);
+    w.write_all(&p).await?;
+    w.flush().await
+}
+
+/// too_large returns the error of a payload over MESSAGE_LENGTH_MAX.
+pub(crate) fn too_large(length: usize) -> Error {
+    Error::InvalidArgument(format!(
+        "message length {} exceeds maximum message size of {}",
+        length, MESSAGE_LENGTH_MAX
+    ))
+}
+
+/// spawn_writer writes the frames sent on the returned channel to the
+/// connection, one at a time, until the channel or the connection is closed.
+pub(crate) fn spawn_writer<W: AsyncWrite + Unpin + Send + 'static>(mut w: W) -> mpsc::UnboundedSender<Frame> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Frame>();
+    tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if let Err(e) = write_frame(&mut w, &frame).await {
+                log::debug!("ttrpc: failed to write message on stream {}: {}", frame.stream_id, e);
+                return;
+            }
+        }
+        let _ = w.shutdown().await;
+    });
+    tx
+}
@@ -0,0 +1,303 @@
+use super::frame::{
+    self, Frame, Received, Request, Response, FLAG_NO_DATA, FLAG_REMOTE_CLOSED, FLAG_REMOTE_OPEN, MESSAGE_LENGTH_MAX,
+    MESSAGE_TYPE_DATA, MESSAGE_TYPE_REQUEST, MESSAGE_TYPE_RESPONSE,
+};
+use crate::context::Context;
+use crate::errdefs::Error;
+use prost::Message;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type MethodHandler = Arc<dyn Fn(Context, Vec<u8>) -> BoxFuture<Result<Vec<u8>, Error>> + Send + Sync>;
+type StreamHandler = Arc<dyn Fn(Context, ServerStream) -> BoxFuture<Result<(), Error>> + Send + Sync>;
+
+/// ServiceDesc describes the methods of a service: the methods answering a
+/// single request with a single response, and the streams.
+#[derive(Clone, Default)]
+pub struct ServiceDesc {
+    methods: HashMap<String, MethodHandler>,
+    streams: HashMap<String, StreamHandler>,
+}
+
+impl ServiceDesc {
+    pub fn new() -> ServiceDesc {
+        ServiceDesc::default()
+    }
+
+    /// method adds a method whose request is decoded into Req and whose
+    /// response is encoded from Resp.
+    pub fn method<Req, Resp, F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        Req: Message + Default + 'static,
+        Resp: Message + 'static,
+        F: Fn(Context, Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp, Error>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let method: MethodHandler = Arc::new(move |ctx, payload: Vec<u8>| {
+            let handler = handler.clone();
+            Box::pin(async move {
+                let req = Req::decode(payload.as_slice())
+                    .map_err(|e| Error::InvalidArgument(format!("ttrpc: invalid request: {}", e)))?;
+                Ok(handler(ctx, req).await?.encode_to_vec())
+            })
+        });
+        self.methods.insert(name.to_string(), method);
+        self
+    }
+
+    /// stream adds a streaming method, the handler receives the messages of
+    /// the client and sends its own on the stream. The stream ends with the
+    /// status of the handler once it returns.
+    pub fn stream<F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(Context, ServerStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let stream: StreamHandler = Arc::new(move |ctx, stream| Box::pin(handler(ctx, stream)));
+        self.streams.insert(name.to_string(), stream);
+        self
+    }
+}
+
+/// Server serves the registered services to the ttrpc clients connecting to
+/// its listener.
+#[derive(Clone, Default)]
+pub struct Server {
+    services: HashMap<String, ServiceDesc>,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// register registers the service under its fully qualified name, for
+    /// example `containerd.task.v2.Task`.
+    pub fn register(&mut self, name: &str, desc: ServiceDesc) {
+        self.services.insert(name.to_string(), desc);
+    }
+
+    /// serve accepts connections on the listener and serves them until
+    /// signal completes, the open connections are closed then.
+    pub async fn serve<F>(self, listener: UnixListener, signal: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()>,
+    {
+        let services = Arc::new(self.services);
+        let mut connections = JoinSet::new();
+        tokio::pin!(signal);
+        loop {
+            tokio::select! {
+                _ = &mut signal => return Ok(()),
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        connections.spawn(serve_connection(services.clone(), stream));
+                    }
+                    Err(e) => {
+                        // running out of file descriptors is temporary,
+                        // back off instead of spinning
+                        log::warn!("ttrpc: failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                },
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+    }
+}
+
+/// serve_connection reads the requests of a connection and runs each of
+/// them in its own task, until the client closes the connection.
+async fn serve_connection(services: Arc<HashMap<String, ServiceDesc>>, stream: UnixStream) {
+    let (mut r, w) = stream.into_split();
+    let tx = frame::spawn_writer(w);
+    let mut streams: HashMap<u32, mpsc::UnboundedSender<Vec<u8>>> = HashMap::new();
+    let mut handlers = JoinSet::new();
+    let mut last_id = 0;
+
+    loop {
+        // read_frame is not cancel safe, the finished handlers are reaped
+        // between frames instead of selecting on them
+        while handlers.try_join_next().is_some() {}
+
+        let frame = match frame::read_frame(&mut r).await {
+            Ok(Received::Frame(frame)) => frame,
+            Ok(Received::TooLarge(frame, length)) => {
+                respond(&tx, frame.stream_id, Err(frame::too_large(length)));
+                continue;
+            }
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                    log::debug!("ttrpc: failed to read message: {}", e);
+                }
+                break;
+            }
+        };
+        let id = frame.stream_id;
+
+        match frame.kind {
+            MESSAGE_TYPE_REQUEST => {
+                if id % 2 != 1 {
+                    let err = Error::InvalidArgument(format!("ttrpc: stream id {} must be odd", id));
+                    respond(&tx, id, Err(err));
+                    continue;
+                }
+                if id <= last_id {
+                    let err = Error::InvalidArgument(format!("ttrpc: stream id {} must be increasing", id));
+                    respond(&tx, id, Err(err));
+                    continue;
+                }
+                last_id = id;
+                let request = match Request::decode(frame.payload.as_slice()) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        respond(
+                            &tx,
+                            id,
+                            Err(Error::InvalidArgument(format!("ttrpc: invalid request: {}", e))),
+                        );
+                        continue;
+                    }
+                };
+                streams.retain(|_, s| !s.is_closed());
+                dispatch(&services, &tx, &mut handlers, &mut streams, id, frame.flags, request);
+            }
+            MESSAGE_TYPE_DATA => match streams.get(&id) {
+                Some(stream) => {
+                    if frame.flags & FLAG_NO_DATA == 0 && stream.send(frame.payload).is_err() {
+                        streams.remove(&id);
+                    }
+                    if frame.flags & FLAG_REMOTE_CLOSED != 0 {
+                        streams.remove(&id);
+                    }
+                }
+                None => log::debug!("ttrpc: data for unknown stream {}", id),
+            },
+            kind => log::debug!("ttrpc: ignoring message of type {} on stream {}", kind, id),
+        }
+    }
+
+    // the client will not send more, let the pending requests complete
+    streams.clear();
+    while handlers.join_next().await.is_some() {}
+}
+
+/// dispatch runs the handler of the request in a task of the connection.
+fn dispatch(
+    services: &HashMap<String, ServiceDesc>,
+    tx: &mpsc::UnboundedSender<Frame>,
+    handlers: &mut JoinSet<()>,
+    streams: &mut HashMap<u32, mpsc::UnboundedSender<Vec<u8>>>,
+    id: u32,
+    flags: u8,
+    request: Request,
+) {
+    let mut ctx = Context::from_ttrpc_metadata(&frame::to_metadata(request.metadata));
+    if request.timeout_nano > 0 {
+        ctx = ctx.with_timeout(Duration::from_nanos(request.timeout_nano as u64));
+    }
+    let Some(service) = services.get(&request.service) else {
+        let err = Error::NotImplemented(format!("ttrpc: service {}", request.service));
+        respond(tx, id, Err(err));
+        return;
+    };
+
+    if let Some(handler) = service.methods.get(&request.method) {
+        let handler = handler.clone();
+        let tx = tx.clone();
+        handlers.spawn(async move {
+            let result = with_deadline(&ctx, handler(ctx.clone(), request.payload)).await;
+            respond(&tx, id, result);
+        });
+    } else if let Some(handler) = service.streams.get(&request.method) {
+        let (data_tx, data_rx) = mpsc::unbounded_channel();
+        if flags & FLAG_REMOTE_OPEN != 0 {
+            streams.insert(id, data_tx);
+        } else {
+            // the request carries the only message of the client
+            let _ = data_tx.send(request.payload);
+        }
+        let stream = ServerStream {
+            id,
+            tx: tx.clone(),
+            rx: data_rx,
+        };
+        let handler = handler.clone();
+        let tx = tx.clone();
+        handlers.spawn(async move {
+            let result = with_deadline(&ctx, handler(ctx.clone(), stream)).await;
+            respond(&tx, id, result.map(|()| Vec::new()));
+        });
+    } else {
+        let err = Error::NotImplemented(format!("ttrpc: method {}/{}", request.service, request.method));
+        respond(tx, id, Err(err));
+    }
+}
+
+/// with_deadline runs the handler until the deadline of the context.
+async fn with_deadline<T>(ctx: &Context, handler: BoxFuture<Result<T, Error>>) -> Result<T, Error> {
+    match ctx.deadline() {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), handler)
+            .await
+            .unwrap_or_else(|_| Err(Error::DeadlineExceeded("ttrpc: context deadline exceeded".to_string()))),
+        None => handler.await,
+    }
+}
+
+/// respond ends the stream with the response of its handler.
+fn respond(tx: &mpsc::UnboundedSender<Frame>, id: u32, result: Result<Vec<u8>, Error>) {
+    let response = match result {
+        Ok(payload) if payload.len() > MESSAGE_LENGTH_MAX => Response {
+            status: Some(frame::status(Err(frame::too_large(payload.len())))),
+            payload: Vec::new(),
+        },
+        Ok(payload) => Response {
+            status: Some(frame::status(Ok(()))),
+            payload,
+        },
+        Err(e) => Response {
+            status: Some(frame::status(Err(e))),
+            payload: Vec::new(),
+        },
+    };
+    let _ = tx.send(Frame::new(id, MESSAGE_TYPE_RESPONSE, 0, response.encode_to_vec()));
+}
+
+/// ServerStream is the stream of a streaming method, on the server side.
+pub struct ServerStream {
+    id: u32,
+    tx: mpsc::UnboundedSender<Frame>,
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl ServerStream {
+    /// recv returns the next message of the client, None once the client
+    /// closed its side of the stream.
+    pub async fn recv<M: Message + Default>(&mut self) -> Result<Option<M>, Error> {
+        match self.rx.recv().await {
+            Some(payload) => M::decode(payload.as_slice())
+                .map(Some)
+                .map_err(|e| Error::InvalidArgument(format!("ttrpc: invalid message: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    /// send sends a message to the client.
+    pub fn send<M: Message>(&self, msg: &M) -> Result<(), Error> {
+        let payload = msg.encode_to_vec();
+        if payload.len() > MESSAGE_LENGTH_MAX {
+            return Err(frame::too_large(payload.len()));
+        }
+        self.tx
+            .send(Frame::new(self.id, MESSAGE_TYPE_DATA, 0, payload))
+            .map_err(|_| Error::Unavailable("ttrpc: connection closed".to_string()))
+    }
+}
@@ -0,0 +1,70 @@
+//! An in-memory container store.
+
+use containerd::containers::{self, Container, Store};
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// MemoryContainerStore keeps the containers per namespace, filters are
+/// ignored.
+#[derive(Default)]
+pub struct MemoryContainerStore {
+    // containers indexed by namespace and id
+    containers: Mutex<HashMap<(String, String), Container>>,
+}
+
+impl Store for MemoryContainerStore {
+    fn get(&self, ctx: &Context, id: &str) -> Result<Container, Error> {
+        let namespace = ctx.namespace_required()?;
+        self.containers
+            .lock()
+            .unwrap()
+            .get(&(namespace.to_string(), id.to_string()))
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("container {:?}", id)))
+    }
+
+    fn list(&self, ctx: &Context, _filters: &[&str]) -> Result<Vec<Container>, Error> {
+        let namespace = ctx.namespace_required()?;
+        let containers = self.containers.lock().unwrap();
+        Ok(containers
+            .iter()
+            .filter(|((ns, _), _)| ns == namespace)
+            .map(|(_, c)| c.clone())
+            .collect())
+    }
+
+    fn create(&self, ctx: &Context, container: Container) -> Result<Container, Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut containers = self.containers.lock().unwrap();
+        let key = (namespace.to_string(), container.id.clone());
+        if containers.contains_key(&key) {
+            return Err(Error::AlreadyExists(format!("container {:?}", container.id)));
+        }
+        containers.insert(key, container.clone());
+        Ok(container)
+    }
+
+    fn update(&self, ctx: &Context, container: Container, fieldpaths: &[&str]) -> Result<Container, Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut containers = self.containers.lock().unwrap();
+        match containers.get_mut(&(namespace.to_string(), container.id.clone())) {
+            Some(current) => {
+                containers::update_fields(current, container, fieldpaths)?;
+                Ok(current.clone())
+            }
+            None => Err(Error::NotFound(format!("container {:?}", container.id))),
+        }
+    }
+
+    fn delete(&self, ctx: &Context, id: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        self.containers
+            .lock()
+            .unwrap()
+            .remove(&(namespace.to_string(), id.to_string()))
+            .map(|_| ())
+            .ok_or_else(|| Error::NotFound(format!("container {:?}", id)))
+    }
+}
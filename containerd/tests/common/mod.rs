@@ -0,0 +1,110 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+pub mod containers;
+pub mod registry;
+pub mod snapshotter;
+
+use containerd::api::types::Descriptor;
+use containerd::content::{self, local, Store as _};
+use containerd::context::Context;
+use containerd::digest;
+use containerd::events::NoopPublisher;
+use containerd::metadata::images::ImageStore;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// NS is the namespace of the tests which only use one.
+pub const NS: &str = "default";
+
+/// ctx returns a context in the namespace.
+pub fn ctx(namespace: &str) -> Context {
+    Context::new().with_namespace(namespace)
+}
+
+/// default_ctx returns a context in NS.
+pub fn default_ctx() -> Context {
+    ctx(NS)
+}
+
+pub fn descriptor(media_type: &str, data: &[u8]) -> Descriptor {
+    Descriptor {
+        media_type: media_type.to_string(),
+        digest: digest::from_bytes(data),
+        size: data.len() as i64,
+        ..Default::default()
+    }
+}
+
+/// is_root reports whether the tests run as root, which mounting needs.
+pub fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+/// TempDir is a directory removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "containerd-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// ImageStores is a content store in a temporary directory along with an
+/// image store.
+pub struct ImageStores {
+    _root: TempDir,
+    pub content: local::Store,
+    pub images: ImageStore,
+}
+
+impl ImageStores {
+    pub fn new() -> ImageStores {
+        let root = TempDir::new();
+        ImageStores {
+            content: local::Store::new(root.path().join("content")).unwrap(),
+            images: ImageStore::new(Arc::new(NoopPublisher {})),
+            _root: root,
+        }
+    }
+
+    /// put writes the data into the content store in NS.
+    pub fn put(&self, media_type: &str, data: &[u8]) -> Descriptor {
+        let desc = descriptor(media_type, data);
+        content::write_blob(
+            &self.content,
+            &default_ctx(),
+            &desc.digest,
+            &mut &data[..],
+            &desc,
+            HashMap::new(),
+        )
+        .unwrap();
+        desc
+    }
+
+    /// has reports whether the content store has the blob in NS.
+    pub fn has(&self, dgst: &str) -> bool {
+        self.content.info(&default_ctx(), dgst).is_ok()
+    }
+}
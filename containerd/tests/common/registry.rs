@@ -0,0 +1,523 @@
+//! A registry stand-in serving the OCI distribution API over plain HTTP on
+//! the loopback interface.
+
+use base64::Engine;
+use containerd::digest;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+/// Auth is the authentication required by the registry.
+#[derive(Clone)]
+pub enum Auth {
+    None,
+    Basic { username: String, password: String },
+    Bearer { username: String, password: String },
+}
+
+/// Request is a request received by the registry.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // header names are lowercase
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct Repository {
+    blobs: HashMap<String, Vec<u8>>,
+    // manifests by tag and by digest, with their media type
+    manifests: HashMap<String, (String, Vec<u8>)>,
+}
+
+/// Upload is a blob upload session of a repository.
+struct Upload {
+    repository: String,
+    data: Vec<u8>,
+}
+
+struct State {
+    auth: Auth,
+    repositories: HashMap<String, Repository>,
+    uploads: HashMap<String, Upload>,
+    next_upload: usize,
+    // the next chunk is only partially stored before failing
+    fail_next_chunk: bool,
+    // manifests are served without their digest
+    omit_digests: bool,
+    requests: Vec<Request>,
+    tokens: Vec<String>,
+}
+
+pub struct Registry {
+    host: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl Registry {
+    /// start serves the registry from a background thread.
+    pub fn start(auth: Auth) -> Registry {
+        Registry::listen(auth, None)
+    }
+
+    /// start_tls serves the registry over TLS from a background thread.
+    pub fn start_tls(auth: Auth, tls: Arc<rustls::ServerConfig>) -> Registry {
+        Registry::listen(auth, Some(tls))
+    }
+
+    fn listen(auth: Auth, tls: Option<Arc<rustls::ServerConfig>>) -> Registry {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(State {
+            auth,
+            repositories: HashMap::new(),
+            uploads: HashMap::new(),
+            next_upload: 0,
+            fail_next_chunk: false,
+            omit_digests: false,
+            requests: Vec::new(),
+            tokens: Vec::new(),
+        }));
+
+        let server = Server {
+            host: host.clone(),
+            state: state.clone(),
+        };
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (server, stream, tls) = (server.clone(), stream.unwrap(), tls.clone());
+                std::thread::spawn(move || match tls {
+                    Some(tls) => {
+                        let conn = rustls::ServerConnection::new(tls).unwrap();
+                        server.serve(rustls::StreamOwned::new(conn, stream))
+                    }
+                    None => server.serve(stream),
+                });
+            }
+        });
+
+        Registry { host, state }
+    }
+
+    /// host returns the address of the registry, `127.0.0.1:<port>`.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// put_blob stores the data in the repository, under its digest unless
+    /// one is given.
+    pub fn put_blob(&self, repository: &str, data: &[u8], dgst: Option<&str>) -> String {
+        let dgst = dgst.map_or_else(|| digest::from_bytes(data), |d| d.to_string());
+        let mut state = self.state.lock().unwrap();
+        let repository = state.repositories.entry(repository.to_string()).or_default();
+        repository.blobs.insert(dgst.clone(), data.to_vec());
+        dgst
+    }
+
+    /// put_manifest stores the manifest in the repository under its digest
+    /// and the tag, if any.
+    pub fn put_manifest(&self, repository: &str, tag: Option<&str>, media_type: &str, data: &[u8]) -> String {
+        let dgst = digest::from_bytes(data);
+        let mut state = self.state.lock().unwrap();
+        let repository = state.repositories.entry(repository.to_string()).or_default();
+        let manifest = (media_type.to_string(), data.to_vec());
+        if let Some(tag) = tag {
+            repository.manifests.insert(tag.to_string(), manifest.clone());
+        }
+        repository.manifests.insert(dgst.clone(), manifest);
+        dgst
+    }
+
+    /// blob returns the blob stored in the repository.
+    pub fn blob(&self, repository: &str, dgst: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.repositories.get(repository)?.blobs.get(dgst).cloned()
+    }
+
+    /// manifest returns the media type and content of the manifest stored
+    /// in the repository under a tag or digest.
+    pub fn manifest(&self, repository: &str, reference: &str) -> Option<(String, Vec<u8>)> {
+        let state = self.state.lock().unwrap();
+        state.repositories.get(repository)?.manifests.get(reference).cloned()
+    }
+
+    /// fail_next_chunk makes the registry store only half of the next chunk
+    /// of an upload and answer with an internal error.
+    pub fn fail_next_chunk(&self) {
+        self.state.lock().unwrap().fail_next_chunk = true;
+    }
+
+    /// omit_digests makes the registry serve manifests without their
+    /// digest, which clients then compute from the content.
+    pub fn omit_digests(&self) {
+        self.state.lock().unwrap().omit_digests = true;
+    }
+
+    /// requests returns the requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+/// Response is written back to the client, closing the connection.
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn body(mut self, body: Vec<u8>) -> Response {
+        self.body = body;
+        self
+    }
+}
+
+#[derive(Clone)]
+struct Server {
+    host: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl Server {
+    fn serve(&self, stream: impl Read + Write) {
+        let mut reader = BufReader::new(stream);
+        let request = match read_request(&mut reader) {
+            Some(request) => request,
+            None => return,
+        };
+        let length = request
+            .headers
+            .get("content-length")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0u8; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        self.state.lock().unwrap().requests.push(request.clone());
+        let head = request.method == "HEAD";
+        let response = self.handle(&request, body);
+        write_response(reader.get_mut(), response, head);
+    }
+
+    fn handle(&self, request: &Request, body: Vec<u8>) -> Response {
+        if request.path == "/token" {
+            return self.token(request);
+        }
+
+        let path = match request.path.strip_prefix("/v2/") {
+            Some(path) => path,
+            None => return Response::new(404),
+        };
+        let repository = ["/manifests/", "/blobs/"]
+            .iter()
+            .find_map(|kind| path.rsplit_once(kind).map(|(repository, _)| repository))
+            .unwrap_or_default();
+
+        if let Some(challenge) = self.challenge(request, repository) {
+            return Response::new(401).header("WWW-Authenticate", &challenge);
+        }
+
+        match request.method.as_str() {
+            "GET" | "HEAD" => {}
+            _ => return self.write(request, path, repository, body),
+        }
+
+        let state = self.state.lock().unwrap();
+        if let Some((_, id)) = path.rsplit_once("/blobs/uploads/") {
+            return match state.uploads.get(id) {
+                Some(upload) => Response::new(204)
+                    .header("Location", &request.path)
+                    .header("Range", &upload_range(&upload.data)),
+                None => Response::new(404),
+            };
+        }
+        let repository = state.repositories.get(repository);
+        if let Some((_, reference)) = path.rsplit_once("/manifests/") {
+            return match repository.and_then(|r| r.manifests.get(reference)) {
+                Some((media_type, data)) if state.omit_digests => {
+                    Response::new(200).header("Content-Type", media_type).body(data.clone())
+                }
+                Some((media_type, data)) => Response::new(200)
+                    .header("Content-Type", media_type)
+                    .header("Docker-Content-Digest", &digest::from_bytes(data))
+                    .body(data.clone()),
+                None => Response::new(404),
+            };
+        }
+        if let Some((_, dgst)) = path.rsplit_once("/blobs/") {
+            let data = match repository.and_then(|r| r.blobs.get(dgst)) {
+                Some(data) => data,
+                None => return Response::new(404),
+            };
+            let offset = request
+                .headers
+                .get("range")
+                .and_then(|range| range.strip_prefix("bytes="))
+                .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+            return match offset {
+                Some(offset) if offset <= data.len() => Response::new(206)
+                    .header("Content-Type", "application/octet-stream")
+                    .header(
+                        "Content-Range",
+                        &format!("bytes {}-{}/{}", offset, data.len() - 1, data.len()),
+                    )
+                    .body(data[offset..].to_vec()),
+                _ => Response::new(200)
+                    .header("Content-Type", "application/octet-stream")
+                    .header("Docker-Content-Digest", dgst)
+                    .body(data.clone()),
+            };
+        }
+
+        Response::new(404)
+    }
+
+    /// write handles the requests storing manifests and uploading blobs.
+    fn write(&self, request: &Request, path: &str, repository: &str, body: Vec<u8>) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        if let Some((_, reference)) = path.rsplit_once("/manifests/") {
+            if request.method != "PUT" {
+                return Response::new(405);
+            }
+            let media_type = request.headers.get("content-type").cloned().unwrap_or_default();
+            let dgst = digest::from_bytes(&body);
+            let manifests = &mut state.repositories.entry(repository.to_string()).or_default().manifests;
+            manifests.insert(reference.to_string(), (media_type.clone(), body.clone()));
+            manifests.insert(dgst.clone(), (media_type, body));
+            return Response::new(201)
+                .header("Location", &format!("/v2/{}/manifests/{}", repository, dgst))
+                .header("Docker-Content-Digest", &dgst);
+        }
+
+        let id = match path.rsplit_once("/blobs/uploads/") {
+            Some((_, id)) => id,
+            None => return Response::new(405),
+        };
+        if request.method == "POST" && id.is_empty() {
+            if let (Some(dgst), Some(from)) = (request.query.get("mount"), request.query.get("from")) {
+                let blob = state.repositories.get(from).and_then(|r| r.blobs.get(dgst)).cloned();
+                if let Some(blob) = blob {
+                    let repo = state.repositories.entry(repository.to_string()).or_default();
+                    repo.blobs.insert(dgst.clone(), blob);
+                    return Response::new(201)
+                        .header("Location", &format!("/v2/{}/blobs/{}", repository, dgst))
+                        .header("Docker-Content-Digest", dgst);
+                }
+            }
+            let id = format!("upload-{}", state.next_upload);
+            state.next_upload += 1;
+            state.uploads.insert(
+                id.clone(),
+                Upload {
+                    repository: repository.to_string(),
+                    data: Vec::new(),
+                },
+            );
+            return Response::new(202)
+                .header("Location", &format!("/v2/{}/blobs/uploads/{}", repository, id))
+                .header("Range", "0-0");
+        }
+
+        let upload = match state.uploads.get_mut(id) {
+            Some(upload) if upload.repository == repository => upload,
+            _ => return Response::new(404),
+        };
+        match request.method.as_str() {
+            "PATCH" => {
+                let start = request
+                    .headers
+                    .get("content-range")
+                    .and_then(|range| range.split_once('-'))
+                    .and_then(|(start, _)| start.parse::<usize>().ok())
+                    .unwrap_or(upload.data.len());
+                if start != upload.data.len() {
+                    return Response::new(416);
+                }
+                if std::mem::take(&mut state.fail_next_chunk) {
+                    upload.data.extend_from_slice(&body[..body.len() / 2]);
+                    return Response::new(500);
+                }
+                upload.data.extend_from_slice(&body);
+                Response::new(202)
+                    .header("Location", &request.path)
+                    .header("Range", &upload_range(&upload.data))
+            }
+            "PUT" => {
+                upload.data.extend_from_slice(&body);
+                let dgst = digest::from_bytes(&upload.data);
+                if request.query.get("digest") != Some(&dgst) {
+                    return Response::new(400);
+                }
+                let upload = state.uploads.remove(id).unwrap();
+                let repo = state.repositories.entry(repository.to_string()).or_default();
+                repo.blobs.insert(dgst.clone(), upload.data);
+                Response::new(201)
+                    .header("Location", &format!("/v2/{}/blobs/{}", repository, dgst))
+                    .header("Docker-Content-Digest", &dgst)
+            }
+            _ => Response::new(405),
+        }
+    }
+
+    /// challenge returns the authentication challenge if the request is not
+    /// authorized.
+    fn challenge(&self, request: &Request, repository: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let authorization = request
+            .headers
+            .get("authorization")
+            .map(|a| a.as_str())
+            .unwrap_or_default();
+        match &state.auth {
+            Auth::None => None,
+            Auth::Basic { username, password } => {
+                if authorization == basic(username, password) {
+                    None
+                } else {
+                    Some("Basic realm=\"test-registry\"".to_string())
+                }
+            }
+            Auth::Bearer { .. } => {
+                let token = authorization.strip_prefix("Bearer ").unwrap_or_default();
+                if state.tokens.iter().any(|t| t == token) {
+                    None
+                } else {
+                    Some(format!(
+                        "Bearer realm=\"http://{}/token\",service=\"test-registry\",scope=\"repository:{}:pull\"",
+                        self.host, repository
+                    ))
+                }
+            }
+        }
+    }
+
+    /// token issues a bearer token to clients with valid credentials.
+    fn token(&self, request: &Request) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let authorized = match &state.auth {
+            Auth::Bearer { username, password } => {
+                request.headers.get("authorization").map(|a| a.as_str()) == Some(basic(username, password).as_str())
+            }
+            _ => false,
+        };
+        if !authorized || request.query.get("service").map(|s| s.as_str()) != Some("test-registry") {
+            return Response::new(401);
+        }
+
+        let token = format!("token-{}", state.tokens.len());
+        state.tokens.push(token.clone());
+        let body = serde_json::to_vec(&serde_json::json!({ "token": token })).unwrap();
+        Response::new(200).header("Content-Type", "application/json").body(body)
+    }
+}
+
+/// upload_range returns the `Range` header of an upload, the end being
+/// inclusive.
+fn upload_range(data: &[u8]) -> String {
+    format!("0-{}", data.len().saturating_sub(1))
+}
+
+fn basic(username: &str, password: &str) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+    format!("Basic {}", encoded)
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .collect();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    Some(Request {
+        method,
+        path: decode(path),
+        query,
+        headers,
+    })
+}
+
+fn write_response(stream: &mut impl Write, response: Response, head: bool) {
+    let mut out = format!("HTTP/1.1 {} Status\r\n", response.status);
+    for (name, value) in &response.headers {
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    out.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+
+    let mut p = out.into_bytes();
+    if !head {
+        p.extend_from_slice(&response.body);
+    }
+    let _ = stream.write_all(&p);
+    let _ = stream.flush();
+}
+
+/// decode percent-decodes a query component.
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,197 @@
+//! An in-memory snapshotter recording the snapshots it is asked for.
+
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use containerd::mount::Mount;
+use containerd::snapshots::{Info, Kind, Snapshotter, Usage};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// MemorySnapshotter keeps the snapshot records per namespace, the mounts
+/// it returns are never performed.
+#[derive(Default)]
+pub struct MemorySnapshotter {
+    // snapshots indexed by namespace and key
+    snapshots: Mutex<HashMap<(String, String), Info>>,
+}
+
+impl MemorySnapshotter {
+    /// committed returns the committed snapshots of every namespace.
+    pub fn committed(&self) -> Vec<Info> {
+        let mut committed: Vec<Info> = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|i| i.kind == Kind::Committed)
+            .cloned()
+            .collect();
+        committed.sort_by(|a, b| a.name.cmp(&b.name));
+        committed
+    }
+
+    /// active returns the number of active snapshots of every namespace.
+    pub fn active(&self) -> usize {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|i| i.kind == Kind::Active)
+            .count()
+    }
+
+    /// keys returns the keys of the snapshots of the namespace, sorted.
+    pub fn keys(&self, namespace: &str) -> Vec<String> {
+        let snapshots = self.snapshots.lock().unwrap();
+        let mut keys: Vec<String> = snapshots
+            .keys()
+            .filter(|(ns, _)| ns == namespace)
+            .map(|(_, k)| k.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn create(
+        &self,
+        namespace: &str,
+        kind: Kind,
+        key: &str,
+        parent: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Vec<Mount>, Error> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots.contains_key(&id(namespace, key)) {
+            return Err(Error::AlreadyExists(format!("snapshot {:?}", key)));
+        }
+        if !parent.is_empty() && snapshots.get(&id(namespace, parent)).map(|i| i.kind) != Some(Kind::Committed) {
+            return Err(Error::NotFound(format!("parent snapshot {:?}", parent)));
+        }
+        let mut info = Info::new(kind, key, parent);
+        info.labels = labels;
+        snapshots.insert(id(namespace, key), info);
+        Ok(vec![Mount::new(
+            "bind",
+            format!("/snapshots/{}", key),
+            vec!["rbind".to_string()],
+        )])
+    }
+}
+
+impl Snapshotter for MemorySnapshotter {
+    fn stat(&self, ctx: &Context, key: &str) -> Result<Info, Error> {
+        let namespace = ctx.namespace_required()?;
+        let snapshots = self.snapshots.lock().unwrap();
+        snapshots
+            .get(&id(namespace, key))
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("snapshot {:?}", key)))
+    }
+
+    fn update(&self, ctx: &Context, info: Info, fieldpaths: &[&str]) -> Result<Info, Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let current = snapshots
+            .get_mut(&id(namespace, &info.name))
+            .ok_or_else(|| Error::NotFound(format!("snapshot {:?}", info.name)))?;
+        for path in fieldpaths {
+            match path.strip_prefix("labels.") {
+                Some(key) => match info.labels.get(key) {
+                    Some(value) => current.labels.insert(key.to_string(), value.clone()),
+                    None => current.labels.remove(key),
+                },
+                None => return Err(Error::InvalidArgument(format!("cannot update {:?}", path))),
+            };
+        }
+        if fieldpaths.is_empty() {
+            current.labels = info.labels;
+        }
+        Ok(current.clone())
+    }
+
+    fn usage(&self, _ctx: &Context, _key: &str) -> Result<Usage, Error> {
+        Ok(Usage::default())
+    }
+
+    fn mounts(&self, _ctx: &Context, key: &str) -> Result<Vec<Mount>, Error> {
+        Ok(vec![Mount::new(
+            "bind",
+            format!("/snapshots/{}", key),
+            vec!["rbind".to_string()],
+        )])
+    }
+
+    fn prepare(
+        &self,
+        ctx: &Context,
+        key: &str,
+        parent: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Vec<Mount>, Error> {
+        let namespace = ctx.namespace_required()?;
+        self.create(namespace, Kind::Active, key, parent, labels)
+    }
+
+    fn view(
+        &self,
+        ctx: &Context,
+        key: &str,
+        parent: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Vec<Mount>, Error> {
+        let namespace = ctx.namespace_required()?;
+        self.create(namespace, Kind::View, key, parent, labels)
+    }
+
+    fn commit(&self, ctx: &Context, name: &str, key: &str, labels: HashMap<String, String>) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots.contains_key(&id(namespace, name)) {
+            return Err(Error::AlreadyExists(format!("snapshot {:?}", name)));
+        }
+        let active = snapshots
+            .remove(&id(namespace, key))
+            .ok_or_else(|| Error::NotFound(format!("snapshot {:?}", key)))?;
+        let mut info = Info::new(Kind::Committed, name, &active.parent);
+        info.labels = labels;
+        snapshots.insert(id(namespace, name), info);
+        Ok(())
+    }
+
+    fn remove(&self, ctx: &Context, key: &str) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots
+            .iter()
+            .any(|((ns, _), info)| ns == namespace && info.parent == key)
+        {
+            return Err(Error::FailedPrecondition(format!("snapshot {:?} has children", key)));
+        }
+        snapshots
+            .remove(&id(namespace, key))
+            .map(|_| ())
+            .ok_or_else(|| Error::NotFound(format!("snapshot {:?}", key)))
+    }
+
+    fn walk(
+        &self,
+        ctx: &Context,
+        _filters: &[&str],
+        f: &mut dyn FnMut(Info) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let namespace = ctx.namespace_required()?;
+        let snapshots: Vec<Info> = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((ns, _), _)| ns == namespace)
+            .map(|(_, info)| info.clone())
+            .collect();
+        snapshots.into_iter().try_for_each(f)
+    }
+}
+
+fn id(namespace: &str, key: &str) -> (String, String) {
+    (namespace.to_string(), key.to_string())
+}
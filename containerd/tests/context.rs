@@ -0,0 +1,108 @@
+use containerd::context::{Context, Metadata, GRPC_TIMEOUT_HEADER};
+use containerd::{leases, namespaces};
+use std::time::{Duration, Instant};
+
+fn metadata(pairs: &[(&str, &str)]) -> Metadata {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), vec![v.to_string()]))
+        .collect()
+}
+
+#[test]
+fn values_are_scoped_to_the_copy() {
+    let base = Context::new().with_namespace("default");
+    let leased = base.with_lease("pull");
+    assert_eq!(base.lease(), None);
+    assert_eq!(leased.lease(), Some("pull"));
+    assert_eq!(leased.namespace(), Some("default"));
+    assert_eq!(leased.with_namespace("other").namespace(), Some("other"));
+
+    // a later deadline does not extend the current one
+    let soon = Instant::now() + Duration::from_secs(10);
+    let ctx = base.with_deadline(soon).with_timeout(Duration::from_secs(60));
+    assert_eq!(ctx.deadline(), Some(soon));
+    let ctx = ctx.with_timeout(Duration::from_secs(1));
+    assert!(ctx.deadline().unwrap() < soon);
+    assert!(ctx.timeout().unwrap() <= Duration::from_secs(1));
+    assert_eq!(base.timeout(), None);
+}
+
+#[test]
+fn namespace_required() {
+    let err = Context::new().namespace_required().unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    let err = Context::new().with_namespace("").namespace_required().unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    let err = Context::new()
+        .with_namespace("tenant/a")
+        .namespace_required()
+        .unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert_eq!(
+        Context::new().with_namespace("tenant-a").namespace_required().unwrap(),
+        "tenant-a"
+    );
+}
+
+#[test]
+fn check_deadline() {
+    let ctx = Context::new().with_namespace("default");
+    ctx.check_deadline().unwrap();
+    ctx.with_timeout(Duration::from_secs(60)).check_deadline().unwrap();
+
+    let err = ctx.with_timeout(Duration::ZERO).check_deadline().unwrap_err();
+    assert!(err.is_deadline_exceeded(), "{}", err);
+    assert_eq!(err.to_string(), "context deadline exceeded: deadline exceeded");
+}
+
+#[test]
+fn grpc_metadata() {
+    let md = metadata(&[
+        (namespaces::GRPC_HEADER, "tenant"),
+        (leases::GRPC_HEADER, "pull"),
+        (GRPC_TIMEOUT_HEADER, "5S"),
+    ]);
+    let ctx = Context::from_grpc_metadata(&md, namespaces::DEFAULT).unwrap();
+    assert_eq!(ctx.namespace(), Some("tenant"));
+    assert_eq!(ctx.lease(), Some("pull"));
+    let timeout = ctx.timeout().unwrap();
+    assert!(timeout > Duration::from_secs(4) && timeout <= Duration::from_secs(5));
+
+    let mut out = Metadata::new();
+    ctx.to_grpc_metadata(&mut out);
+    assert_eq!(out[namespaces::GRPC_HEADER], ["tenant"]);
+    assert_eq!(out[leases::GRPC_HEADER], ["pull"]);
+    let timeout = &out[GRPC_TIMEOUT_HEADER][0];
+    assert!(timeout.ends_with('u'), "{}", timeout);
+    let back = Context::from_grpc_metadata(&out, "").unwrap();
+    assert!(back.deadline().unwrap() <= ctx.deadline().unwrap() + Duration::from_millis(100));
+
+    for (timeout, expected) in [
+        ("1H", Duration::from_secs(3600)),
+        ("2M", Duration::from_secs(120)),
+        ("250m", Duration::from_millis(250)),
+        ("99999999n", Duration::from_nanos(99_999_999)),
+    ] {
+        let ctx = Context::from_grpc_metadata(&metadata(&[(GRPC_TIMEOUT_HEADER, timeout)]), "").unwrap();
+        assert!(ctx.timeout().unwrap() <= expected, "{}", timeout);
+    }
+    for invalid in ["S", "5", "5s", "-5S", "123456789S"] {
+        let err = Context::from_grpc_metadata(&metadata(&[(GRPC_TIMEOUT_HEADER, invalid)]), "").unwrap_err();
+        assert!(err.is_invalid_argument(), "{:?}: {}", invalid, err);
+    }
+}
+
+#[test]
+fn grpc_metadata_defaults() {
+    let ctx = Context::from_grpc_metadata(&Metadata::new(), namespaces::DEFAULT).unwrap();
+    assert_eq!(ctx.namespace(), Some("default"));
+    assert_eq!((ctx.lease(), ctx.deadline()), (None, None));
+
+    let ctx = Context::from_grpc_metadata(&metadata(&[(namespaces::GRPC_HEADER, "")]), "").unwrap();
+    assert_eq!(ctx.namespace(), None);
+
+    let mut out = Metadata::new();
+    ctx.to_grpc_metadata(&mut out);
+    assert!(out.is_empty());
+}
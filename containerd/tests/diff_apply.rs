@@ -0,0 +1,255 @@
+mod common;
+
+use common::{default_ctx, TempDir};
+use containerd::api::types::Descriptor;
+use containerd::content::{self, local};
+use containerd::diff::apply::FileSystemApplier;
+use containerd::diff::Applier;
+use containerd::digest;
+use containerd::images::oci;
+use containerd::mount::Mount;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Layer builds the tarball of a layer.
+struct Layer(tar::Builder<Vec<u8>>);
+
+impl Layer {
+    fn new() -> Layer {
+        Layer(tar::Builder::new(Vec::new()))
+    }
+
+    fn append(mut self, entry_type: tar::EntryType, path: &str, mode: u32, link: &str, data: &[u8]) -> Layer {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(data.len() as u64);
+        header.set_mtime(1_000_000);
+        header.set_uid(0);
+        header.set_gid(0);
+        if !link.is_empty() {
+            header.set_link_name(link).unwrap();
+        }
+        self.0.append_data(&mut header, path, data).unwrap();
+        self
+    }
+
+    fn dir(self, path: &str) -> Layer {
+        self.append(tar::EntryType::Directory, path, 0o755, "", b"")
+    }
+
+    fn file(self, path: &str, data: &str) -> Layer {
+        self.append(tar::EntryType::Regular, path, 0o644, "", data.as_bytes())
+    }
+
+    fn symlink(self, path: &str, target: &str) -> Layer {
+        self.append(tar::EntryType::Symlink, path, 0o777, target, b"")
+    }
+
+    fn hardlink(self, path: &str, target: &str) -> Layer {
+        self.append(tar::EntryType::Link, path, 0o644, target, b"")
+    }
+
+    /// raw_file appends a file whose path is written as is, without the
+    /// checks of the tar builder.
+    fn raw_file(mut self, path: &str, data: &str) -> Layer {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        self.0.append(&header, data.as_bytes()).unwrap();
+        self
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.0.into_inner().unwrap()
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+struct Fixture {
+    root: TempDir,
+    content: Arc<local::Store>,
+    applier: FileSystemApplier,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        let root = TempDir::new();
+        let content = Arc::new(local::Store::new(root.path().join("content")).unwrap());
+        Fixture {
+            applier: FileSystemApplier::new(content.clone()),
+            content,
+            root,
+        }
+    }
+
+    fn put(&self, media_type: &str, data: &[u8]) -> Descriptor {
+        let desc = common::descriptor(media_type, data);
+        content::write_blob(
+            self.content.as_ref(),
+            &default_ctx(),
+            &desc.digest,
+            &mut &data[..],
+            &desc,
+            HashMap::new(),
+        )
+        .unwrap();
+        desc
+    }
+
+    /// dir returns a new directory named name.
+    fn dir(&self, name: &str) -> std::path::PathBuf {
+        let path = self.root.path().join(name);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+fn bind(path: &Path) -> Vec<Mount> {
+    vec![Mount::new("bind", path, vec!["rbind".to_string()])]
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+/// base returns a layer of a few directories, files and links.
+fn base() -> Vec<u8> {
+    Layer::new()
+        .dir("etc")
+        .file("etc/hostname", "base")
+        .file("etc/passwd", "root:x:0:0")
+        .dir("opt")
+        .dir("opt/app")
+        .file("opt/app/old", "old")
+        .file("opt/keep", "keep")
+        .symlink("etc/link", "hostname")
+        .hardlink("etc/passwd.bak", "etc/passwd")
+        .finish()
+}
+
+/// upper returns a layer changing base, compressed with gzip.
+fn upper() -> Vec<u8> {
+    Layer::new()
+        .file("etc/.wh.hostname", "")
+        .file("etc/.wh.missing", "")
+        .dir("opt/app")
+        .file("opt/app/new", "new")
+        .file("opt/app/.wh..wh..opq", "")
+        .append(tar::EntryType::Regular, "bin/tool", 0o755, "", b"#!/bin/sh")
+        .dir("etc/link")
+        .finish()
+}
+
+#[test]
+fn layers_are_applied_with_their_whiteouts() {
+    let fixture = Fixture::new();
+    let root = fixture.dir("rootfs");
+
+    let base = base();
+    let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER, &base);
+    let diff = fixture.applier.apply(&default_ctx(), &desc, &bind(&root)).unwrap();
+    assert_eq!(diff.media_type, oci::MEDIA_TYPE_IMAGE_LAYER);
+    assert_eq!((diff.digest, diff.size), (digest::from_bytes(&base), base.len() as i64));
+    assert_eq!(read(&root.join("etc/hostname")), "base");
+    assert_eq!(fs::read_link(root.join("etc/link")).unwrap(), Path::new("hostname"));
+    let passwd = fs::metadata(root.join("etc/passwd")).unwrap();
+    assert_eq!(passwd.ino(), fs::metadata(root.join("etc/passwd.bak")).unwrap().ino());
+    assert_eq!(passwd.permissions().mode() & 0o777, 0o644);
+    assert_eq!(passwd.mtime(), 1_000_000);
+
+    // the diff ID is the digest of the uncompressed layer
+    let upper = upper();
+    let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER_GZIP, &gzip(&upper));
+    let diff = fixture.applier.apply(&default_ctx(), &desc, &bind(&root)).unwrap();
+    assert_eq!(
+        (diff.digest, diff.size),
+        (digest::from_bytes(&upper), upper.len() as i64)
+    );
+    assert!(!root.join("etc/hostname").exists());
+    assert!(!root.join("etc/.wh.hostname").exists());
+    assert_eq!(read(&root.join("etc/passwd")), "root:x:0:0");
+    // an opaque directory only keeps the content of the layer
+    let names: Vec<_> = fs::read_dir(root.join("opt/app"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["new"]);
+    assert_eq!(read(&root.join("opt/keep")), "keep");
+    let tool = fs::metadata(root.join("bin/tool")).unwrap();
+    assert_eq!(tool.permissions().mode() & 0o777, 0o755);
+    // a directory replaces the symlink instead of being created through it
+    assert!(fs::symlink_metadata(root.join("etc/link")).unwrap().is_dir());
+}
+
+#[test]
+fn layers_cannot_write_outside_of_the_mounts() {
+    let fixture = Fixture::new();
+    let root = fixture.dir("rootfs");
+    let outside = fixture.dir("outside");
+    fs::write(outside.join("secret"), "secret").unwrap();
+
+    let layer = Layer::new().raw_file("../outside/secret", "owned").finish();
+    let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER, &layer);
+    let err = fixture.applier.apply(&default_ctx(), &desc, &bind(&root)).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert_eq!(read(&outside.join("secret")), "secret");
+
+    // nor follow the symlinks of lower layers
+    let base = Layer::new().symlink("escape", outside.to_str().unwrap()).finish();
+    let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER, &base);
+    fixture.applier.apply(&default_ctx(), &desc, &bind(&root)).unwrap();
+    for layer in [
+        Layer::new().file("escape/.wh.secret", "").finish(),
+        Layer::new().file("escape/secret", "owned").finish(),
+    ] {
+        let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER, &layer);
+        fixture.applier.apply(&default_ctx(), &desc, &bind(&root)).unwrap_err();
+        assert_eq!(read(&outside.join("secret")), "secret");
+    }
+}
+
+#[test]
+fn layers_are_applied_on_overlay_mounts() {
+    if !common::is_root() {
+        eprintln!("skipping: mounting needs root");
+        return;
+    }
+    let fixture = Fixture::new();
+    let lower = fixture.dir("lower");
+    let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER, &base());
+    fixture.applier.apply(&default_ctx(), &desc, &bind(&lower)).unwrap();
+
+    let (upper_dir, work) = (fixture.dir("upper"), fixture.dir("work"));
+    let mounts = vec![Mount::new(
+        "overlay",
+        "overlay",
+        vec![
+            format!("workdir={}", work.display()),
+            format!("upperdir={}", upper_dir.display()),
+            format!("lowerdir={}", lower.display()),
+        ],
+    )];
+    let desc = fixture.put(oci::MEDIA_TYPE_IMAGE_LAYER_GZIP, &gzip(&upper()));
+    fixture.applier.apply(&default_ctx(), &desc, &mounts).unwrap();
+
+    // the lower layer is left as is, the upper one records the changes
+    assert_eq!(read(&lower.join("etc/hostname")), "base");
+    let whiteout = fs::symlink_metadata(upper_dir.join("etc/hostname")).unwrap();
+    assert_eq!((whiteout.mode() & libc::S_IFMT, whiteout.rdev()), (libc::S_IFCHR, 0));
+    assert_eq!(read(&upper_dir.join("opt/app/new")), "new");
+    assert!(!upper_dir.join("opt/keep").exists());
+}
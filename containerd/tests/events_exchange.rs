@@ -0,0 +1,252 @@
+use containerd::api::events::{ContainerCreate, TaskExit, TaskStart};
+use containerd::errdefs::Error;
+use containerd::events::exchange::{Exchange, SlowConsumerPolicy, SubscribeOpts};
+use containerd::events::{self, Envelope, Event, Publisher};
+use prost::Message;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+fn exit(container_id: &str) -> TaskExit {
+    TaskExit {
+        container_id: container_id.to_string(),
+        id: container_id.to_string(),
+        pid: 42,
+        exit_status: 1,
+        ..Default::default()
+    }
+}
+
+fn topics(sub: &events::exchange::Subscription) -> Vec<String> {
+    let mut topics = Vec::new();
+    while let Some(envelope) = sub.try_recv().unwrap() {
+        topics.push(format!("{}:{}", envelope.namespace, envelope.topic));
+    }
+    topics
+}
+
+#[test]
+fn publish_wraps_events_in_envelopes() {
+    let exchange = Exchange::new();
+    let sub = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+
+    let before = OffsetDateTime::now_utc();
+    exchange.publish_event("default", "/tasks/exit", &exit("app")).unwrap();
+    let envelope = sub.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert!(envelope.timestamp >= before);
+    assert_eq!(envelope.namespace, "default");
+    assert_eq!(envelope.topic, "/tasks/exit");
+    assert_eq!(envelope.event.type_url, TaskExit::TYPE_URL);
+    assert_eq!(TaskExit::decode(envelope.event.value.as_slice()).unwrap(), exit("app"));
+
+    // publishers only see the trait
+    let publisher: &dyn Publisher = &exchange;
+    publisher
+        .publish("default", "/tasks/start", events::marshal(&TaskStart::default()))
+        .unwrap();
+    assert_eq!(topics(&sub), ["default:/tasks/start"]);
+
+    exchange
+        .forward(Envelope::new(
+            "other",
+            "/containers/create",
+            events::marshal(&ContainerCreate::default()),
+        ))
+        .unwrap();
+    assert_eq!(topics(&sub), ["other:/containers/create"]);
+}
+
+#[test]
+fn invalid_envelopes_are_rejected() {
+    let exchange = Exchange::new();
+    let sub = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+
+    for (namespace, topic) in [
+        ("default", "tasks/exit"),
+        ("default", "/"),
+        ("default", "/tasks//exit"),
+        ("default", "/tasks/exit!"),
+        ("", "/tasks/exit"),
+        ("no spaces", "/tasks/exit"),
+    ] {
+        let err = exchange.publish_event(namespace, topic, &exit("app")).unwrap_err();
+        assert!(err.is_invalid_argument(), "{:?} {:?}: {}", namespace, topic, err);
+    }
+    let err = exchange
+        .forward(Envelope::new("default", "/tasks/exit", Default::default()))
+        .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert!(topics(&sub).is_empty());
+
+    let err = exchange
+        .subscribe(&["topic=="], SubscribeOpts::default())
+        .err()
+        .unwrap();
+    assert!(err.is_invalid_argument(), "{}", err);
+    let opts = SubscribeOpts {
+        buffer: 0,
+        ..Default::default()
+    };
+    assert!(exchange.subscribe(&[], opts).err().unwrap().is_invalid_argument());
+}
+
+#[test]
+fn subscribers_filter_events() {
+    let exchange = Exchange::new();
+    let tasks = exchange
+        .subscribe(
+            &[r#"topic~="^/tasks/",event.container_id==app"#],
+            SubscribeOpts::default(),
+        )
+        .unwrap();
+    let other = exchange
+        .subscribe(&["namespace==other", "event.image"], SubscribeOpts::default())
+        .unwrap();
+    let exits = exchange
+        .subscribe(&["topic==/tasks/exit,event.exit_status!=0"], SubscribeOpts::default())
+        .unwrap();
+
+    exchange.publish_event("default", "/tasks/exit", &exit("app")).unwrap();
+    exchange.publish_event("other", "/tasks/exit", &exit("db")).unwrap();
+    exchange
+        .publish_event(
+            "default",
+            "/tasks/start",
+            &TaskStart {
+                container_id: "app".to_string(),
+                pid: 42,
+            },
+        )
+        .unwrap();
+    exchange
+        .publish_event(
+            "default",
+            "/containers/create",
+            &ContainerCreate {
+                id: "app".to_string(),
+                image: "docker.io/library/alpine:3".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    exchange
+        .publish_event(
+            "default",
+            "/tasks/exit",
+            &TaskExit {
+                container_id: "app".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert_eq!(
+        topics(&tasks),
+        ["default:/tasks/exit", "default:/tasks/start", "default:/tasks/exit"]
+    );
+    assert_eq!(topics(&other), ["other:/tasks/exit", "default:/containers/create"]);
+    assert_eq!(topics(&exits), ["default:/tasks/exit", "other:/tasks/exit"]);
+}
+
+#[test]
+fn slow_consumers_drop_events() {
+    let exchange = Exchange::new();
+    let opts = SubscribeOpts {
+        buffer: 2,
+        policy: SlowConsumerPolicy::Drop,
+        ..Default::default()
+    };
+    let slow = exchange.subscribe(&[], opts).unwrap();
+    let fast = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+
+    for id in ["a", "b", "c", "d"] {
+        exchange.publish_event("default", "/tasks/exit", &exit(id)).unwrap();
+    }
+    assert_eq!(slow.dropped(), 2);
+    assert_eq!(topics(&slow).len(), 2);
+    assert_eq!(fast.dropped(), 0);
+    assert_eq!(topics(&fast).len(), 4);
+
+    // the subscriber stays connected and receives new events
+    exchange.publish_event("default", "/tasks/exit", &exit("e")).unwrap();
+    let envelope = slow.try_recv().unwrap().unwrap();
+    assert_eq!(TaskExit::decode(envelope.event.value.as_slice()).unwrap().id, "e");
+    assert_eq!(exchange.subscribers(), 2);
+}
+
+#[test]
+fn slow_consumers_are_disconnected() {
+    let exchange = Exchange::new();
+    let opts = SubscribeOpts {
+        buffer: 2,
+        policy: SlowConsumerPolicy::Disconnect,
+        ..Default::default()
+    };
+    let slow = exchange.subscribe(&[], opts).unwrap();
+    let fast = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+
+    for id in ["a", "b", "c", "d"] {
+        exchange.publish_event("default", "/tasks/exit", &exit(id)).unwrap();
+    }
+    assert_eq!(exchange.subscribers(), 1);
+    assert_eq!(topics(&fast).len(), 4);
+
+    // the buffered events are delivered before the disconnection is reported
+    for id in ["a", "b"] {
+        let envelope = slow.recv().unwrap();
+        assert_eq!(TaskExit::decode(envelope.event.value.as_slice()).unwrap().id, id);
+    }
+    let err = slow.recv().unwrap_err();
+    assert!(matches!(err, Error::Unavailable(_)), "{}", err);
+    assert!(err.to_string().contains("buffer of 2 events full"), "{}", err);
+    assert!(slow.try_recv().is_err());
+
+    // dropping a subscription unsubscribes it
+    drop(fast);
+    exchange.publish_event("default", "/tasks/exit", &exit("e")).unwrap();
+    assert_eq!(exchange.subscribers(), 0);
+}
+
+#[tokio::test]
+async fn subscriptions_are_received_from_tasks() {
+    let exchange = Arc::new(Exchange::new());
+    let sub = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+    let receiver = tokio::spawn(async move {
+        let envelope = sub.recv_async().await.unwrap();
+        (sub, envelope)
+    });
+    tokio::task::yield_now().await;
+    exchange.publish_event("default", "/tasks/exit", &exit("a")).unwrap();
+    let (sub, envelope) = tokio::time::timeout(Duration::from_secs(5), receiver)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(TaskExit::decode(envelope.event.value.as_slice()).unwrap().id, "a");
+
+    // the buffered events are received before the disconnection is reported
+    let opts = SubscribeOpts {
+        buffer: 1,
+        policy: SlowConsumerPolicy::Disconnect,
+        ..Default::default()
+    };
+    let slow = exchange.subscribe(&[], opts).unwrap();
+    for id in ["b", "c"] {
+        exchange.publish_event("default", "/tasks/exit", &exit(id)).unwrap();
+    }
+    let envelope = slow.recv_async().await.unwrap();
+    assert_eq!(TaskExit::decode(envelope.event.value.as_slice()).unwrap().id, "b");
+    let err = slow.recv_async().await.unwrap_err();
+    assert!(err.to_string().contains("buffer of 1 events full"), "{}", err);
+
+    // and once the exchange is gone
+    drop(sub);
+    let sub = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+    let waiting = tokio::spawn(async move { sub.recv_async().await });
+    tokio::task::yield_now().await;
+    drop(exchange);
+    let result = tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(result, Err(Error::Unavailable(_))), "{:?}", result.err());
+}
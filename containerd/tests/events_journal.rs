@@ -0,0 +1,269 @@
+mod common;
+
+use common::TempDir;
+use containerd::api::events::TaskExit;
+use containerd::errdefs::Error;
+use containerd::events::exchange::{Exchange, SubscribeOpts};
+use containerd::events::journal::{Journal, JournalOpts, Position};
+use containerd::events::{self, Envelope};
+use std::path::Path;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+fn envelope(id: &str) -> Envelope {
+    let exit = TaskExit {
+        container_id: id.to_string(),
+        id: id.to_string(),
+        ..Default::default()
+    };
+    Envelope::new("default", "/tasks/exit", events::marshal(&exit))
+}
+
+fn ids(envelopes: &[Envelope]) -> Vec<(u64, String)> {
+    envelopes
+        .iter()
+        .map(|e| (e.sequence, e.decode::<TaskExit>().unwrap().id))
+        .collect()
+}
+
+fn segments(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|e| e == "log"))
+        .count()
+}
+
+#[test]
+fn append_persists_in_order() {
+    let root = TempDir::new();
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    assert_eq!(journal.next(), 1);
+
+    let mut appended = Vec::new();
+    for id in ["a", "b", "c"] {
+        let mut e = envelope(id);
+        journal.append(&mut e).unwrap();
+        appended.push(e);
+    }
+    assert_eq!(ids(&appended), [(1, "a".into()), (2, "b".into()), (3, "c".into())]);
+
+    let err = Journal::open(root.path(), JournalOpts::default()).err().unwrap();
+    assert!(matches!(err, Error::Unavailable(_)), "{}", err);
+    drop(journal);
+
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    assert_eq!(journal.next(), 4);
+    assert_eq!(journal.read(Position::Sequence(1)).unwrap(), appended);
+    assert_eq!(ids(&journal.read(Position::Sequence(3)).unwrap()), [(3, "c".into())]);
+    assert!(journal.read(Position::Sequence(4)).unwrap().is_empty());
+    let from = Position::Timestamp(appended[1].timestamp);
+    assert_eq!(journal.read(from).unwrap(), appended[1..]);
+
+    let mut e = envelope("d");
+    assert_eq!(journal.append(&mut e).unwrap(), 4);
+}
+
+#[test]
+fn segments_rotate_and_are_retained_by_size() {
+    let root = TempDir::new();
+    let opts = JournalOpts {
+        segment_size: 1,
+        max_size: Some(1024),
+        ..Default::default()
+    };
+    let journal = Journal::open(root.path(), opts.clone()).unwrap();
+    for i in 0..50 {
+        journal.append(&mut envelope(&format!("c{}", i))).unwrap();
+    }
+
+    // a record per segment, the oldest ones were removed
+    assert!(journal.size() <= 1024, "{}", journal.size());
+    let first = journal.first();
+    assert!(first > 1);
+    assert_eq!(segments(root.path()), (51 - first) as usize);
+
+    // reading from before the oldest event starts at the oldest event
+    let envelopes = journal.read(Position::Sequence(1)).unwrap();
+    assert_eq!(envelopes[0].sequence, first);
+    assert_eq!(envelopes.last().unwrap().sequence, 50);
+    assert_eq!(ids(&journal.read(Position::Sequence(48)).unwrap()).len(), 3);
+    drop(journal);
+
+    let journal = Journal::open(root.path(), opts).unwrap();
+    assert_eq!(journal.first(), first);
+    assert_eq!(journal.next(), 51);
+}
+
+#[test]
+fn segments_are_retained_by_age() {
+    let root = TempDir::new();
+    let opts = JournalOpts {
+        segment_size: 1,
+        max_age: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let journal = Journal::open(root.path(), opts).unwrap();
+    for id in ["a", "b"] {
+        let mut e = envelope(id);
+        e.timestamp = OffsetDateTime::now_utc() - Duration::from_secs(7200);
+        journal.append(&mut e).unwrap();
+    }
+    // the active segment is kept however old
+    assert_eq!(journal.first(), 2);
+
+    journal.append(&mut envelope("c")).unwrap();
+    journal.append(&mut envelope("d")).unwrap();
+    assert_eq!(
+        ids(&journal.read(Position::Sequence(1)).unwrap()),
+        [(3, "c".into()), (4, "d".into())]
+    );
+}
+
+#[test]
+fn torn_records_are_truncated() {
+    let root = TempDir::new();
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    journal.append(&mut envelope("a")).unwrap();
+    journal.append(&mut envelope("b")).unwrap();
+    drop(journal);
+
+    let segment = root.path().join(format!("{:020}.log", 1));
+    let mut p = std::fs::read(&segment).unwrap();
+    let size = p.len();
+    // the length of a record whose body was never written
+    p.extend_from_slice(&[0, 0, 1, 0, 7]);
+    std::fs::write(&segment, &p).unwrap();
+
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), size as u64);
+    assert_eq!(journal.append(&mut envelope("c")).unwrap(), 3);
+    assert_eq!(
+        ids(&journal.read(Position::Sequence(1)).unwrap()),
+        [(1, "a".into()), (2, "b".into()), (3, "c".into())]
+    );
+}
+
+/// Tmpfs is a small tmpfs mounted on a directory until it is dropped.
+struct Tmpfs<'a>(&'a Path);
+
+impl<'a> Tmpfs<'a> {
+    fn mount(dir: &'a Path, size: &str) -> Tmpfs<'a> {
+        let flags = nix::mount::MsFlags::empty();
+        nix::mount::mount(Some("tmpfs"), dir, Some("tmpfs"), flags, Some(size)).unwrap();
+        Tmpfs(dir)
+    }
+}
+
+impl Drop for Tmpfs<'_> {
+    fn drop(&mut self) {
+        let _ = nix::mount::umount(self.0);
+    }
+}
+
+#[test]
+fn failed_appends_are_rolled_back() {
+    if !common::is_root() {
+        eprintln!("skipping: mounting needs root");
+        return;
+    }
+    let root = TempDir::new();
+    let _tmpfs = Tmpfs::mount(root.path(), "size=16k");
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+
+    let mut a = envelope("a");
+    journal.append(&mut a).unwrap();
+    // the record only partially fits on the filesystem
+    let mut large = envelope(&"x".repeat(64 << 10));
+    journal.append(&mut large).unwrap_err();
+    assert_eq!(journal.next(), 2);
+    let mut b = envelope("b");
+    journal.append(&mut b).unwrap();
+
+    assert_eq!(
+        ids(&journal.read(Position::Sequence(1)).unwrap()),
+        [(1, "a".into()), (2, "b".into())]
+    );
+    drop(journal);
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    assert_eq!(journal.read(Position::Sequence(1)).unwrap(), [a, b]);
+}
+
+#[test]
+fn subscribers_resume_before_live_events() {
+    let root = TempDir::new();
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    let exchange = Exchange::new().with_journal(journal);
+    for id in ["a", "b", "c"] {
+        exchange.forward(envelope(id)).unwrap();
+    }
+    drop(exchange);
+
+    // after a restart, the subscriber resumes after the last event it saw
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    let exchange = Exchange::new().with_journal(journal);
+    let opts = SubscribeOpts {
+        buffer: 1,
+        from: Some(Position::Sequence(2)),
+        ..Default::default()
+    };
+    let sub = exchange.subscribe(&["event.id!=c"], opts).unwrap();
+    exchange.forward(envelope("d")).unwrap();
+
+    let mut received = Vec::new();
+    while let Some(e) = sub.try_recv().unwrap() {
+        received.push(e);
+    }
+    assert_eq!(ids(&received), [(2, "b".into()), (4, "d".into())]);
+    assert_eq!(sub.dropped(), 0);
+
+    let opts = SubscribeOpts {
+        from: Some(Position::Timestamp(received[1].timestamp)),
+        ..Default::default()
+    };
+    let sub = exchange.subscribe(&[], opts).unwrap();
+    assert_eq!(sub.try_recv().unwrap().unwrap().sequence, 4);
+    assert!(sub.try_recv().unwrap().is_none());
+
+    let opts = SubscribeOpts {
+        from: Some(Position::Sequence(1)),
+        ..Default::default()
+    };
+    let err = Exchange::new().subscribe(&[], opts).err().unwrap();
+    assert!(err.is_failed_precondition(), "{}", err);
+}
+
+#[test]
+fn replays_are_read_a_buffer_at_a_time() {
+    let root = TempDir::new();
+    let journal = Journal::open(root.path(), JournalOpts::default()).unwrap();
+    let exchange = Exchange::new().with_journal(journal);
+    for i in 0..10 {
+        exchange.forward(envelope(&i.to_string())).unwrap();
+    }
+    let journal = exchange.journal().unwrap();
+    assert_eq!(
+        ids(&journal.read_page(Position::Sequence(4), 2).unwrap()),
+        [(4, "3".into()), (5, "4".into())]
+    );
+    assert_eq!(journal.read_page(Position::Sequence(9), 5).unwrap().len(), 2);
+
+    // the live events published during the replay go through the bounded
+    // buffer and its policy
+    let opts = SubscribeOpts {
+        buffer: 2,
+        from: Some(Position::Sequence(1)),
+        ..Default::default()
+    };
+    let sub = exchange.subscribe(&["event.id!=5"], opts).unwrap();
+    assert_eq!(sub.try_recv().unwrap().unwrap().sequence, 1);
+    for id in ["a", "b", "c"] {
+        exchange.forward(envelope(id)).unwrap();
+    }
+    assert_eq!(sub.dropped(), 1);
+
+    let mut received = Vec::new();
+    while let Some(e) = sub.try_recv().unwrap() {
+        received.push(e.sequence);
+    }
+    assert_eq!(received, [2, 3, 4, 5, 7, 8, 9, 10, 11, 12]);
+}
@@ -0,0 +1,92 @@
+use containerd::api::events::{ContainerDelete, ImageCreate, TaskExit, TaskOom, TaskStart};
+use containerd::events::{self, Envelope, Event, TypedEvent};
+use containerd::runtime::events::EventTopic;
+use std::collections::HashSet;
+
+#[test]
+fn envelopes_decode_into_their_type() {
+    let exit = TaskExit {
+        container_id: "app".to_string(),
+        id: "app".to_string(),
+        pid: 42,
+        exit_status: 137,
+        ..Default::default()
+    };
+    let envelope = Envelope::new("default", TaskExit::TOPIC, events::marshal(&exit));
+
+    assert_eq!(envelope.decode::<TaskExit>().unwrap(), exit);
+    let err = envelope.decode::<TaskStart>().unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+
+    let typed = envelope.typed().unwrap();
+    assert_eq!(typed, TypedEvent::TaskExit(exit.clone()));
+    assert_eq!(typed.topic(), "/tasks/exit");
+    assert_eq!(typed.type_url(), "containerd.api.events.TaskExit");
+    assert_eq!(typed.marshal(), envelope.event);
+
+    let oom = TypedEvent::from(TaskOom {
+        container_id: "app".to_string(),
+    });
+    assert_eq!(oom.type_url(), "containerd.api.events.TaskOOM");
+    assert_eq!(TypedEvent::decode(&oom.marshal()).unwrap(), oom);
+
+    let unknown = prost_types::Any {
+        type_url: "example.Event".to_string(),
+        value: vec![],
+    };
+    assert!(TypedEvent::decode(&unknown).unwrap_err().is_not_found());
+    let corrupt = prost_types::Any {
+        type_url: ImageCreate::TYPE_URL.to_string(),
+        value: vec![0xff],
+    };
+    assert!(TypedEvent::decode(&corrupt).unwrap_err().is_invalid_argument());
+}
+
+#[test]
+fn registry_maps_topics_and_type_urls() {
+    let urls: HashSet<_> = events::REGISTRY.iter().map(|r| r.type_url).collect();
+    let topics: HashSet<_> = events::REGISTRY.iter().map(|r| r.topic).collect();
+    assert_eq!(urls.len(), events::REGISTRY.len());
+    assert_eq!(topics.len(), events::REGISTRY.len());
+    for r in events::REGISTRY {
+        events::validate_topic(r.topic).unwrap();
+        assert_eq!(events::lookup_topic(r.topic), Some(r));
+        assert_eq!(events::lookup_type_url(r.type_url), Some(r));
+    }
+
+    let r = events::lookup_type_url(ContainerDelete::TYPE_URL).unwrap();
+    assert_eq!(r.topic, "/containers/delete");
+    assert!(events::lookup_topic("/containers/start").is_none());
+}
+
+#[test]
+fn task_topics() {
+    let topics = [
+        (EventTopic::TaskCreate, Some("TaskCreate")),
+        (EventTopic::TaskStart, Some("TaskStart")),
+        (EventTopic::TaskOOM, Some("TaskOOM")),
+        (EventTopic::TaskExit, Some("TaskExit")),
+        (EventTopic::TaskDelete, Some("TaskDelete")),
+        (EventTopic::TaskExecAdded, Some("TaskExecAdded")),
+        (EventTopic::TaskExecStarted, Some("TaskExecStarted")),
+        (EventTopic::TaskPaused, Some("TaskPaused")),
+        (EventTopic::TaskResumed, Some("TaskResumed")),
+        (EventTopic::TaskCheckpointed, Some("TaskCheckpointed")),
+        (EventTopic::TaskUnknown, None),
+    ];
+    for (topic, message) in topics {
+        assert_eq!(topic.as_str().parse::<EventTopic>().unwrap(), topic);
+        assert_eq!(topic.to_string(), topic.as_str());
+        assert_eq!(
+            topic.type_url(),
+            message.map(|m| format!("containerd.api.events.{}", m)).as_deref(),
+            "{}",
+            topic
+        );
+    }
+
+    for invalid in ["", "tasks/exit", "/tasks/exit/", "/containers/create"] {
+        let err = invalid.parse::<EventTopic>().unwrap_err();
+        assert!(err.is_invalid_argument(), "{:?}: {}", invalid, err);
+    }
+}
@@ -0,0 +1,123 @@
+use containerd::containers::Container;
+use containerd::filters::{self, Adaptor};
+use std::collections::HashMap;
+
+fn container(id: &str, image: &str, labels: &[(&str, &str)]) -> Container {
+    let mut container = Container::new(id, "io.containerd.runc.v2");
+    container.image = image.to_string();
+    container.labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    container
+}
+
+fn matching(containers: &[Container], filters: &[&str]) -> Vec<String> {
+    let filter = filters::parse_all(filters).unwrap();
+    containers
+        .iter()
+        .filter(|c| filter.matches(*c))
+        .map(|c| c.id.clone())
+        .collect()
+}
+
+#[test]
+fn selectors() {
+    let containers = [
+        container(
+            "app",
+            "docker.io/library/alpine:3",
+            &[("com.example", "foo"), ("tier", "gold")],
+        ),
+        container("db", "docker.io/library/postgres:16", &[("com.example", "bar")]),
+        container("proxy", "ghcr.io/example/proxy:1", &[]),
+    ];
+
+    for (filters, expected) in [
+        (vec![], vec!["app", "db", "proxy"]),
+        (vec![r#"labels."com.example"==foo"#], vec!["app"]),
+        (vec!["labels.com.example==bar"], vec!["db"]),
+        (vec![r#"labels."com.example""#], vec!["app", "db"]),
+        (vec!["image~=^docker.io/"], vec!["app", "db"]),
+        (vec!["image~=postgres|proxy"], vec!["db", "proxy"]),
+        (vec!["id!=app"], vec!["db", "proxy"]),
+        (vec!["labels.tier!=gold"], vec!["db", "proxy"]),
+        (vec!["image~=^docker.io/, labels.tier==gold"], vec!["app"]),
+        (vec!["id==app", "id==proxy"], vec!["app", "proxy"]),
+        (vec![r#"id=="db""#, "image~='^ghcr'"], vec!["db", "proxy"]),
+        (vec![r#"labels.`com.example`=="\x66oo""#], vec!["app"]),
+        (vec!["runtime.name==io.containerd.runc.v2,snapshotter"], vec![]),
+    ] {
+        assert_eq!(matching(&containers, &filters), expected, "{:?}", filters);
+    }
+}
+
+#[test]
+fn parse_errors() {
+    for (filter, expected) in [
+        ("", "filters: parse error: []: expected a field or a quoted string"),
+        (
+            "id=",
+            r#"filters: parse error: [id >|=|< ]: expected an operator ("==", "!=" or "~=")"#,
+        ),
+        (
+            "id==",
+            "filters: parse error: [id==]: expected a value or a quoted string",
+        ),
+        (
+            "id==a b",
+            "filters: parse error: [id==a  >|b|< ]: expected a selector separator, got 'b'",
+        ),
+        (
+            "id==a,",
+            "filters: parse error: [id==a,]: expected a field or a quoted string",
+        ),
+        (
+            "labels.",
+            "filters: parse error: [labels.]: expected a field or a quoted string",
+        ),
+        (
+            "1d==a",
+            "filters: parse error: [ >|1|< d==a]: expected a field or a quoted string, got '1'",
+        ),
+        (
+            r#"labels."com==a"#,
+            r#"filters: parse error: [labels. >|"|< com==a]: quoted string not terminated"#,
+        ),
+        (
+            r#"id=="\q""#,
+            r#"filters: parse error: [id==" >|\|< q"]: invalid escape sequence"#,
+        ),
+    ] {
+        let err = filters::parse(filter).unwrap_err();
+        assert!(err.is_invalid_argument(), "{:?}: {}", filter, err);
+        assert_eq!(
+            err.to_string(),
+            format!("{}: invalid argument", expected),
+            "{:?}",
+            filter
+        );
+    }
+
+    let err = filters::parse("image~=(").unwrap_err();
+    assert!(err.to_string().contains("invalid regular expression"), "{}", err);
+    assert!(filters::parse_all(&["id==a", "id=="]).is_err());
+}
+
+/// Fields exposes a map of the field paths joined with dots.
+struct Fields(HashMap<String, String>);
+
+impl Adaptor for Fields {
+    fn field(&self, fieldpath: &[String]) -> Option<String> {
+        self.0.get(&fieldpath.join(".")).cloned()
+    }
+}
+
+#[test]
+fn adaptors_define_the_fields() {
+    let fields = Fields(HashMap::from([
+        ("topic".to_string(), "/tasks/exit".to_string()),
+        ("event.container_id".to_string(), "app".to_string()),
+    ]));
+    let filter = filters::parse(r#"topic~="^/tasks/",event.container_id==app"#).unwrap();
+    assert!(filter.matches(&fields));
+    let filter = filters::parse("topic==/tasks/exit,namespace").unwrap();
+    assert!(!filter.matches(&fields));
+}
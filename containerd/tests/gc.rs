@@ -0,0 +1,476 @@
+mod common;
+
+use common::containers::MemoryContainerStore;
+use common::snapshotter::MemorySnapshotter;
+use common::{default_ctx, TempDir, NS};
+use containerd::containers::{Container, Store as _};
+use containerd::content::{self, local, Store as _};
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use containerd::events::NoopPublisher;
+use containerd::gc::scheduler::{Config, Scheduler};
+use containerd::gc::{self, Collector, Node, Stats};
+use containerd::images::{oci, Image, Store as _};
+use containerd::labels;
+use containerd::leases::{Lease, Manager as _, Resource};
+use containerd::metadata::db::DB;
+use containerd::metadata::gc::GarbageCollector;
+use containerd::metadata::images::ImageStore;
+use containerd::metadata::leases::LeaseManager;
+use containerd::snapshots::Snapshotter as _;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn labels(labels: &[(&str, &str)]) -> HashMap<String, String> {
+    labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+/// Stores are the stores collected by the garbage collector.
+struct Stores {
+    _root: TempDir,
+    content: Arc<local::Store>,
+    images: Arc<ImageStore>,
+    containers: Arc<MemoryContainerStore>,
+    db: Arc<DB>,
+    leases: Arc<LeaseManager>,
+    snapshotter: Arc<MemorySnapshotter>,
+    collector: Arc<GarbageCollector>,
+}
+
+impl Stores {
+    fn new() -> Stores {
+        let root = TempDir::new();
+        let content = Arc::new(local::Store::new(root.path().join("content")).unwrap());
+        let images = Arc::new(ImageStore::new(Arc::new(NoopPublisher {})));
+        let containers = Arc::new(MemoryContainerStore::default());
+        let db = Arc::new(DB::open(root.path().join("meta.db")).unwrap());
+        let leases = Arc::new(LeaseManager::new(db.clone()));
+        let snapshotter = Arc::new(MemorySnapshotter::default());
+        let collector = Arc::new(
+            GarbageCollector::new(
+                Arc::new(|| Ok(vec![NS.to_string(), "other".to_string()])),
+                content.clone(),
+                images.clone(),
+                containers.clone(),
+                leases.clone(),
+            )
+            .with_snapshotter("overlayfs", snapshotter.clone()),
+        );
+
+        Stores {
+            _root: root,
+            content,
+            images,
+            containers,
+            db,
+            leases,
+            snapshotter,
+            collector,
+        }
+    }
+
+    /// write stores the blob in the namespace of the context with the labels and returns its
+    /// digest.
+    fn write(&self, ctx: &Context, data: &[u8], labels: &[(&str, &str)]) -> String {
+        let desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, data);
+        content::write_blob(
+            self.content.as_ref(),
+            ctx,
+            &desc.digest,
+            &mut &data[..],
+            &desc,
+            self::labels(labels),
+        )
+        .unwrap();
+        desc.digest
+    }
+
+    fn commit(&self, name: &str, parent: &str, labels: &[(&str, &str)]) {
+        let key = format!("{}-active", name);
+        self.snapshotter
+            .prepare(&default_ctx(), &key, parent, HashMap::new())
+            .unwrap();
+        self.snapshotter
+            .commit(&default_ctx(), name, &key, self::labels(labels))
+            .unwrap();
+    }
+
+    fn has_content(&self, ctx: &Context, dgst: &str) -> bool {
+        self.content.info(ctx, dgst).is_ok()
+    }
+}
+
+#[test]
+fn references_follow_gc_labels() {
+    let refs = gc::references(
+        NS,
+        &labels(&[
+            ("containerd.io/gc.ref.content", "sha256:a"),
+            ("containerd.io/gc.ref.content.l.0", "sha256:b"),
+            ("containerd.io/gc.ref.snapshot.overlayfs", "layer-1"),
+            ("containerd.io/gc.ref.snapshot.native/1", "layer-2"),
+            ("containerd.io/gc.ref.contents", "sha256:c"),
+            ("containerd.io/gc.ref.snapshot", "layer-3"),
+            ("containerd.io/gc.ref.content.empty", ""),
+            ("purpose", "sha256:d"),
+        ]),
+    );
+    let mut refs: Vec<(gc::ResourceType, String)> = refs.into_iter().map(|n| (n.kind, n.key)).collect();
+    refs.sort();
+    assert_eq!(
+        refs,
+        [
+            (gc::ResourceType::Content, "sha256:a".to_string()),
+            (gc::ResourceType::Content, "sha256:b".to_string()),
+            (gc::ResourceType::Snapshot, "native/layer-2".to_string()),
+            (gc::ResourceType::Snapshot, "overlayfs/layer-1".to_string()),
+        ]
+    );
+
+    let roots = vec![Node::content(NS, "a")];
+    let graph: HashMap<&str, Vec<&str>> = [("a", vec!["b", "c"]), ("b", vec!["a"]), ("d", vec!["a"])].into();
+    let reachable = gc::tricolor(roots, |node| {
+        Ok(graph
+            .get(node.key.as_str())
+            .map(|refs| refs.iter().map(|r| Node::content(NS, r)).collect())
+            .unwrap_or_default())
+    })
+    .unwrap();
+    let mut keys: Vec<&str> = reachable.iter().map(|n| n.key.as_str()).collect();
+    keys.sort();
+    assert_eq!(keys, ["a", "b", "c"]);
+}
+
+#[test]
+fn collect_unreferenced_content_and_snapshots() {
+    let stores = Stores::new();
+
+    // an image whose config references its unpacked snapshot
+    let layer = stores.write(&default_ctx(), b"layer", &[]);
+    let config = stores.write(
+        &default_ctx(),
+        b"config",
+        &[(&format!("{}.overlayfs", labels::GC_REF_SNAPSHOT_PREFIX), "chain-2")],
+    );
+    let manifest = stores.write(
+        &default_ctx(),
+        b"manifest",
+        &[
+            (&format!("{}.0", labels::GC_REF_CONTENT_PREFIX), &config),
+            (&format!("{}.1", labels::GC_REF_CONTENT_PREFIX), &layer),
+        ],
+    );
+    let mut target = common::descriptor(oci::MEDIA_TYPE_IMAGE_MANIFEST, b"manifest");
+    target.digest = manifest.clone();
+    stores
+        .images
+        .create(&default_ctx(), Image::new("docker.io/library/app:v1", target))
+        .unwrap();
+    stores.commit("chain-1", "", &[]);
+    stores.commit("chain-2", "chain-1", &[]);
+
+    // a container running on top of an orphaned layer
+    stores.commit("orphan-base", "", &[]);
+    stores.commit("orphan", "orphan-base", &[]);
+    stores
+        .snapshotter
+        .prepare(&default_ctx(), "c1-rootfs", "orphan", HashMap::new())
+        .unwrap();
+    let mut container = Container::new("c1", "io.containerd.runc.v2");
+    container.snapshotter = "overlayfs".to_string();
+    container.snapshot_key = "c1-rootfs".to_string();
+    let spec = stores.write(&default_ctx(), b"spec", &[]);
+    container
+        .labels
+        .insert(format!("{}.spec", labels::GC_REF_CONTENT_PREFIX), spec.clone());
+    stores.containers.create(&default_ctx(), container).unwrap();
+
+    // unreferenced snapshots, children before their parents
+    stores.commit("unused-1", "", &[]);
+    stores.commit("unused-2", "unused-1", &[]);
+    stores
+        .snapshotter
+        .view(&default_ctx(), "unused-view", "unused-2", HashMap::new())
+        .unwrap();
+
+    // roots by label
+    let root = stores.write(&default_ctx(), b"root", &[(labels::GC_ROOT, "")]);
+    stores
+        .snapshotter
+        .prepare(&default_ctx(), "pinned", "", labels(&[(labels::GC_ROOT, "")]))
+        .unwrap();
+
+    // leased resources, the expired lease is removed with its resources
+    let leased = stores.write(&default_ctx(), b"leased", &[]);
+    stores.leases.create(&default_ctx(), Lease::new("pull")).unwrap();
+    stores
+        .leases
+        .add_resource(&default_ctx(), "pull", Resource::new("content", &leased))
+        .unwrap();
+    stores
+        .snapshotter
+        .prepare(&default_ctx(), "extract-1", "", HashMap::new())
+        .unwrap();
+    stores
+        .leases
+        .add_resource(&default_ctx(), "pull", Resource::snapshot("overlayfs", "extract-1"))
+        .unwrap();
+    let expired = stores.write(&default_ctx(), b"expired", &[]);
+    stores
+        .leases
+        .create(
+            &default_ctx(),
+            Lease::new("old").with_expiration(time::Duration::seconds(-1)),
+        )
+        .unwrap();
+    stores
+        .leases
+        .add_resource(&default_ctx(), "old", Resource::new("content", &expired))
+        .unwrap();
+
+    let orphan = stores.write(&default_ctx(), b"orphan", &[]);
+
+    let stats = stores.collector.garbage_collect().unwrap();
+    assert_eq!(
+        (stats.content_deleted, stats.snapshots_deleted, stats.leases_deleted),
+        (2, 3, 1)
+    );
+    assert!(stats.elapsed() >= stats.mark);
+
+    for dgst in [&layer, &config, &manifest, &spec, &root, &leased] {
+        assert!(stores.has_content(&default_ctx(), dgst), "{}", dgst);
+    }
+    assert!(!stores.has_content(&default_ctx(), &orphan));
+    assert!(!stores.has_content(&default_ctx(), &expired));
+    assert_eq!(
+        stores.snapshotter.keys(NS),
+        [
+            "c1-rootfs",
+            "chain-1",
+            "chain-2",
+            "extract-1",
+            "orphan",
+            "orphan-base",
+            "pinned"
+        ]
+    );
+    let leases: Vec<String> = stores
+        .leases
+        .list(&default_ctx(), &[])
+        .unwrap()
+        .into_iter()
+        .map(|l| l.id)
+        .collect();
+    assert_eq!(leases, ["pull"]);
+
+    // without the image and the lease, only the roots remain
+    stores
+        .images
+        .delete(&default_ctx(), "docker.io/library/app:v1")
+        .unwrap();
+    stores.containers.delete(&default_ctx(), "c1").unwrap();
+    stores.leases.delete(&default_ctx(), "pull").unwrap();
+    let stats = stores.collector.garbage_collect().unwrap();
+    assert_eq!((stats.content_deleted, stats.snapshots_deleted), (5, 6));
+    assert_eq!(stores.snapshotter.keys(NS), ["pinned"]);
+    assert!(stores.has_content(&default_ctx(), &root));
+
+    let stats = stores.collector.garbage_collect().unwrap();
+    assert_eq!((stats.content_deleted, stats.snapshots_deleted), (0, 0));
+}
+
+#[test]
+fn collect_namespaces_independently() {
+    let stores = Stores::new();
+    let shared = stores.write(&default_ctx(), b"shared", &[]);
+    stores.write(&common::ctx("other"), b"shared", &[]);
+
+    let mut target = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"shared");
+    target.digest = shared.clone();
+    stores
+        .images
+        .create(&common::ctx("other"), Image::new("app:v1", target))
+        .unwrap();
+
+    let stats = stores.collector.garbage_collect().unwrap();
+    assert_eq!(stats.content_deleted, 1);
+    assert!(!stores.has_content(&default_ctx(), &shared));
+    assert!(stores.has_content(&common::ctx("other"), &shared));
+    content::read_blob(
+        stores.content.as_ref(),
+        &common::ctx("other"),
+        &common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"shared"),
+    )
+    .unwrap();
+}
+
+/// Counter counts its collections.
+#[derive(Default)]
+struct Counter {
+    runs: AtomicUsize,
+}
+
+impl Collector for Counter {
+    fn garbage_collect(&self) -> Result<Stats, Error> {
+        let runs = self.runs.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(Stats {
+            content_deleted: runs,
+            ..Default::default()
+        })
+    }
+}
+
+fn wait_for_runs(counter: &Counter, runs: usize) {
+    for _ in 0..200 {
+        if counter.runs.load(Ordering::SeqCst) >= runs {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    panic!(
+        "expected {} collections, got {}",
+        runs,
+        counter.runs.load(Ordering::SeqCst)
+    );
+}
+
+#[test]
+fn scheduler_triggers() {
+    let counter = Arc::new(Counter::default());
+    let scheduler = Scheduler::new(
+        counter.clone(),
+        Config {
+            pause_threshold: 0.0,
+            deletion_threshold: 2,
+            mutation_threshold: 5,
+            startup_delay: Duration::from_millis(10),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // startup collection
+    wait_for_runs(&counter, 1);
+    assert_eq!(scheduler.last_stats().unwrap().content_deleted, 1);
+
+    // deletion threshold
+    scheduler.mutation_callback(true);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 1);
+    scheduler.mutation_callback(true);
+    wait_for_runs(&counter, 2);
+
+    // mutation threshold
+    for _ in 0..4 {
+        scheduler.mutation_callback(false);
+    }
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 2);
+    scheduler.mutation_callback(false);
+    wait_for_runs(&counter, 3);
+
+    let stats = scheduler.schedule_and_wait().unwrap();
+    assert_eq!(stats.content_deleted, 4);
+    assert_eq!(scheduler.last_stats(), Some(stats));
+}
+
+#[test]
+fn scheduler_pause_threshold_delays_mutation_triggers() {
+    struct Slow(Counter);
+    impl Collector for Slow {
+        fn garbage_collect(&self) -> Result<Stats, Error> {
+            std::thread::sleep(Duration::from_millis(20));
+            self.0.garbage_collect()
+        }
+    }
+
+    let slow = Arc::new(Slow(Counter::default()));
+    let scheduler = Scheduler::new(
+        slow.clone(),
+        Config {
+            pause_threshold: 0.05,
+            mutation_threshold: 1,
+            startup_delay: Duration::ZERO,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    scheduler.schedule_and_wait().unwrap();
+    // the last collection took at least 20ms, the next one waits 400ms
+    scheduler.mutation_callback(false);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(slow.0.runs.load(Ordering::SeqCst), 1);
+    wait_for_runs(&slow.0, 2);
+
+    // explicit requests are not delayed
+    scheduler.schedule_and_wait().unwrap();
+    assert_eq!(slow.0.runs.load(Ordering::SeqCst), 3);
+
+    let err = Scheduler::new(
+        slow,
+        Config {
+            pause_threshold: 0.8,
+            ..Default::default()
+        },
+    )
+    .err()
+    .unwrap();
+    assert!(err.is_invalid_argument(), "{}", err);
+}
+
+#[test]
+fn store_deletions_schedule_collections() {
+    let stores = Stores::new();
+    let scheduler = Scheduler::new(
+        stores.collector.clone(),
+        Config {
+            pause_threshold: 0.0,
+            deletion_threshold: 1,
+            mutation_threshold: 0,
+            startup_delay: Duration::ZERO,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    stores.content.register_mutation_callback(scheduler.mutation_hook());
+    stores.images.register_mutation_callback(scheduler.mutation_hook());
+    stores.db.register_mutation_callback(scheduler.mutation_hook());
+    let wait_until = |f: &dyn Fn() -> bool| {
+        for _ in 0..200 {
+            if f() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no collection was scheduled");
+    };
+
+    // creations do not schedule collections
+    let layer = stores.write(&default_ctx(), b"layer", &[]);
+    let target = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"layer");
+    stores
+        .images
+        .create(&default_ctx(), Image::new("docker.io/library/app:v1", target))
+        .unwrap();
+    stores.leases.create(&default_ctx(), Lease::new("pull")).unwrap();
+    let leased = stores.write(&default_ctx(), b"leased", &[]);
+    stores
+        .leases
+        .add_resource(&default_ctx(), "pull", Resource::new("content", &leased))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(scheduler.last_stats(), None);
+
+    stores
+        .images
+        .delete(&default_ctx(), "docker.io/library/app:v1")
+        .unwrap();
+    wait_until(&|| !stores.has_content(&default_ctx(), &layer));
+    assert!(stores.has_content(&default_ctx(), &leased));
+
+    stores.leases.delete(&default_ctx(), "pull").unwrap();
+    wait_until(&|| !stores.has_content(&default_ctx(), &leased));
+}
@@ -0,0 +1,369 @@
+mod common;
+
+use common::{default_ctx, ImageStores, TempDir};
+use containerd::api::types::{Descriptor, Platform};
+use containerd::content::{self, Store as _};
+use containerd::digest;
+use containerd::images::archive::{self, ExportOptions, ImportOptions};
+use containerd::images::{oci, Image, Store as _};
+
+/// add_manifest adds a manifest with a config and a layer, which are returned
+/// with it.
+fn add_manifest(stores: &ImageStores, platform: &Platform) -> (Descriptor, Vec<Descriptor>) {
+    let config = serde_json::json!({"architecture": platform.architecture, "os": platform.os});
+    let config = stores.put(oci::MEDIA_TYPE_IMAGE_CONFIG, &serde_json::to_vec(&config).unwrap());
+    let layer = format!("{}/{}", platform.os, platform.architecture);
+    let layer = stores.put(oci::MEDIA_TYPE_IMAGE_LAYER, layer.as_bytes());
+    let manifest = manifest(&config, &layer);
+    (
+        stores.put(oci::MEDIA_TYPE_IMAGE_MANIFEST, &manifest),
+        vec![config, layer],
+    )
+}
+
+fn export(stores: &ImageStores, names: &[&str], opts: &ExportOptions) -> Result<Vec<u8>, containerd::errdefs::Error> {
+    let images: Vec<Image> = names
+        .iter()
+        .map(|name| stores.images.get(&default_ctx(), name).unwrap())
+        .collect();
+    let mut tarball = Vec::new();
+    archive::export(&stores.content, &default_ctx(), &images, &mut tarball, opts)?;
+    Ok(tarball)
+}
+
+fn import(stores: &ImageStores, tarball: &[u8]) -> Vec<Image> {
+    let opts = ImportOptions::default();
+    archive::import_index(
+        &stores.content,
+        &stores.images,
+        &default_ctx(),
+        &mut &tarball[..],
+        &opts,
+    )
+    .unwrap()
+}
+
+fn manifest(config: &Descriptor, layer: &Descriptor) -> Vec<u8> {
+    let manifest = oci::Manifest {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        config: config.clone().into(),
+        layers: vec![layer.clone().into()],
+        ..Default::default()
+    };
+    serde_json::to_vec(&manifest).unwrap()
+}
+
+fn platform(architecture: &str) -> Platform {
+    Platform {
+        os: "linux".to_string(),
+        architecture: architecture.to_string(),
+        ..Default::default()
+    }
+}
+
+/// add_index adds an image of an index of a manifest for linux/amd64 and one
+/// for linux/s390x, whose content is missing as it is after a pull of
+/// linux/amd64. The index and the manifests are returned.
+fn add_index(fixture: &ImageStores, name: &str) -> (Descriptor, Vec<Descriptor>) {
+    let (amd64, _) = add_manifest(fixture, &platform("amd64"));
+    let layer = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"missing layer");
+    let missing = common::descriptor(oci::MEDIA_TYPE_IMAGE_MANIFEST, &manifest(&layer, &layer));
+
+    let index = oci::Index {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_INDEX.to_string(),
+        manifests: vec![
+            oci::Descriptor {
+                descriptor: amd64.clone(),
+                platform: Some(platform("amd64")),
+            },
+            oci::Descriptor {
+                descriptor: missing.clone(),
+                platform: Some(platform("s390x")),
+            },
+        ],
+        ..Default::default()
+    };
+    let index = fixture.put(oci::MEDIA_TYPE_IMAGE_INDEX, &serde_json::to_vec(&index).unwrap());
+    fixture
+        .images
+        .create(&default_ctx(), Image::new(name, index.clone()))
+        .unwrap();
+    (index, vec![amd64, missing])
+}
+
+#[test]
+fn export_and_import_round_trip() {
+    let fixture = ImageStores::new();
+    let (manifest, blobs) = add_manifest(&fixture, &platform("amd64"));
+    let name = "docker.io/library/app:v1";
+    fixture
+        .images
+        .create(&default_ctx(), Image::new(name, manifest.clone()))
+        .unwrap();
+
+    let tarball = export(&fixture, &[name], &ExportOptions::default()).unwrap();
+
+    let imported = ImageStores::new();
+    let images = import(&imported, &tarball);
+    assert_eq!(images.len(), 1);
+    assert_eq!(
+        (images[0].name.as_str(), &images[0].target.digest),
+        (name, &manifest.digest)
+    );
+    assert_eq!(
+        imported.images.get(&default_ctx(), name).unwrap().target.digest,
+        manifest.digest
+    );
+    for blob in &blobs {
+        let data = content::read_blob(&fixture.content, &default_ctx(), blob).unwrap();
+        assert_eq!(
+            content::read_blob(&imported.content, &default_ctx(), blob).unwrap(),
+            data
+        );
+    }
+
+    // the same layout is written to a directory
+    let dir = TempDir::new();
+    let opts = ExportOptions::default();
+    archive::export_dir(&fixture.content, &default_ctx(), &images, dir.path(), &opts).unwrap();
+    let (algorithm, encoded) = digest::split(&manifest.digest).unwrap();
+    assert!(dir
+        .path()
+        .join(oci::IMAGE_BLOBS_DIR)
+        .join(algorithm)
+        .join(encoded)
+        .is_file());
+    let imported = ImageStores::new();
+    let opts = ImportOptions::default();
+    let images = archive::import_dir(&imported.content, &imported.images, &default_ctx(), dir.path(), &opts).unwrap();
+    assert_eq!(images[0].target.digest, manifest.digest);
+}
+
+#[test]
+fn export_filters_the_manifests_of_an_index_by_platform() {
+    let fixture = ImageStores::new();
+    let name = "docker.io/library/app:latest";
+    let (index, manifests) = add_index(&fixture, name);
+    assert!(fixture.has(&manifests[0].digest) && !fixture.has(&manifests[1].digest));
+
+    // the content of the platforms which are not exported is not required
+    let opts = ExportOptions {
+        platforms: vec![platform("amd64")],
+        ..Default::default()
+    };
+    let tarball = export(&fixture, &[name], &opts).unwrap();
+    let imported = ImageStores::new();
+    import(&imported, &tarball);
+    assert_eq!(
+        imported.images.get(&default_ctx(), name).unwrap().target.digest,
+        index.digest
+    );
+    assert!(imported.has(&index.digest) && imported.has(&manifests[0].digest));
+    assert!(!imported.has(&manifests[1].digest));
+
+    // platforms are matched once normalized
+    let opts = ExportOptions {
+        platforms: vec![platform("x86_64")],
+        ..Default::default()
+    };
+    let imported = ImageStores::new();
+    import(&imported, &export(&fixture, &[name], &opts).unwrap());
+    assert!(imported.has(&manifests[0].digest) && !imported.has(&manifests[1].digest));
+
+    // exporting the missing platform fails
+    let opts = ExportOptions {
+        platforms: vec![platform("s390x")],
+        ..Default::default()
+    };
+    assert!(export(&fixture, &[name], &opts).unwrap_err().is_not_found());
+    let opts = ExportOptions {
+        all_platforms: true,
+        ..Default::default()
+    };
+    assert!(export(&fixture, &[name], &opts).unwrap_err().is_not_found());
+}
+
+#[test]
+fn untrusted_sizes_are_bounded() {
+    let fixture = ImageStores::new();
+    let dir = TempDir::new();
+    std::fs::write(
+        dir.path().join(oci::IMAGE_LAYOUT_FILE),
+        r#"{"imageLayoutVersion":"1.0.0"}"#,
+    )
+    .unwrap();
+    std::fs::write(dir.path().join(oci::IMAGE_INDEX_FILE), vec![b' '; (4 << 20) + 1]).unwrap();
+    let err = archive::import_dir(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        dir.path(),
+        &ImportOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert!(err.to_string().contains("index.json is too large"), "{}", err);
+
+    // a descriptor claiming a huge size does not preallocate it
+    let mut desc = fixture.put(oci::MEDIA_TYPE_IMAGE_CONFIG, b"{}");
+    desc.size = i64::MAX;
+    assert_eq!(
+        content::read_blob(&fixture.content, &default_ctx(), &desc).unwrap(),
+        b"{}"
+    );
+}
+
+/// layer returns a layer tarball holding a file with the data.
+fn layer(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, name, data).unwrap();
+    builder.into_inner().unwrap()
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// docker_save returns a tarball shaped as the ones of `docker save`, with
+/// an image of the layers under each list of repo tags. The config of an
+/// image lists the diff IDs.
+fn docker_save(images: &[(Option<&[&str]>, &[String])], layers: &[Vec<u8>]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut append = |path: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, data).unwrap();
+    };
+
+    let layer_paths: Vec<String> = (0..layers.len()).map(|i| format!("layer{}/layer.tar", i)).collect();
+    for (path, data) in layer_paths.iter().zip(layers) {
+        append(path, data);
+    }
+    let mut entries = Vec::new();
+    for (i, (repo_tags, diff_ids)) in images.iter().enumerate() {
+        let config = serde_json::json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "rootfs": {"type": "layers", "diff_ids": diff_ids},
+        });
+        let config = serde_json::to_vec(&config).unwrap();
+        let config_path = format!("{:x}.json", i);
+        append(&config_path, &config);
+        entries.push(serde_json::json!({
+            "Config": config_path,
+            "RepoTags": repo_tags,
+            "Layers": layer_paths,
+        }));
+    }
+    append("manifest.json", &serde_json::to_vec(&entries).unwrap());
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn import_docker_save_archives() {
+    let plain = layer("etc/os-release", b"ID=test");
+    let compressed = layer("bin/app", b"#!/bin/sh");
+    let gzipped = gzip(&compressed);
+    let diff_ids = [digest::from_bytes(&plain), digest::from_bytes(&compressed)];
+    let tarball = docker_save(
+        &[
+            (Some(&["app:v1", "registry.local:5000/team/app"]), &diff_ids),
+            (None, &diff_ids),
+        ],
+        &[plain.clone(), gzipped.clone()],
+    );
+
+    let fixture = ImageStores::new();
+    let images = import(&fixture, &tarball);
+    // the untagged image is converted but not registered
+    let names: Vec<_> = images.iter().map(|image| image.name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["docker.io/library/app:v1", "registry.local:5000/team/app:latest"]
+    );
+    assert_eq!(images[0].target, images[1].target);
+    assert_eq!(images[0].target.media_type, oci::MEDIA_TYPE_IMAGE_MANIFEST);
+    assert!(fixture.images.get(&default_ctx(), "docker.io/library/app:v1").is_ok());
+
+    let manifest: oci::Manifest =
+        serde_json::from_slice(&content::read_blob(&fixture.content, &default_ctx(), &images[0].target).unwrap())
+            .unwrap();
+    assert_eq!(manifest.config.descriptor.media_type, oci::MEDIA_TYPE_IMAGE_CONFIG);
+    let layers: Vec<_> = manifest
+        .layers
+        .iter()
+        .map(|l| {
+            (
+                l.descriptor.media_type.as_str(),
+                l.descriptor.digest.as_str(),
+                l.descriptor.size,
+            )
+        })
+        .collect();
+    assert_eq!(
+        layers,
+        [
+            (
+                oci::MEDIA_TYPE_IMAGE_LAYER,
+                digest::from_bytes(&plain).as_str(),
+                plain.len() as i64
+            ),
+            (
+                oci::MEDIA_TYPE_IMAGE_LAYER_GZIP,
+                digest::from_bytes(&gzipped).as_str(),
+                gzipped.len() as i64
+            ),
+        ]
+    );
+    // the diff ID of the compressed layer is kept for unpacking
+    let info = fixture
+        .content
+        .info(&default_ctx(), &digest::from_bytes(&gzipped))
+        .unwrap();
+    assert_eq!(
+        info.labels[containerd::labels::LABEL_UNCOMPRESSED],
+        digest::from_bytes(&compressed)
+    );
+}
+
+#[test]
+fn import_docker_save_archives_verifies_diff_ids() {
+    let plain = layer("etc/os-release", b"ID=test");
+    let gzipped = gzip(&layer("bin/app", b"#!/bin/sh"));
+    // the diff ID of a compressed layer is the digest of its uncompressed tar
+    let diff_ids = [digest::from_bytes(&plain), digest::from_bytes(&gzipped)];
+    let tarball = docker_save(&[(Some(&["app:v1"]), &diff_ids)], &[plain.clone(), gzipped]);
+
+    let fixture = ImageStores::new();
+    let err = archive::import_index(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        &mut &tarball[..],
+        &ImportOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert!(err.to_string().contains("but the image config expects"), "{}", err);
+    assert!(fixture.images.get(&default_ctx(), "docker.io/library/app:v1").is_err());
+
+    // so is a config which does not list every layer
+    let tarball = docker_save(&[(Some(&["app:v1"]), &diff_ids[..1])], &[plain.clone(), plain]);
+    let err = archive::import_index(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        &mut &tarball[..],
+        &ImportOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+}
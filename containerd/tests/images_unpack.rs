@@ -0,0 +1,293 @@
+mod common;
+
+use common::snapshotter::MemorySnapshotter;
+use common::{default_ctx, TempDir};
+use containerd::api::types::{Descriptor, Platform};
+use containerd::content::{self, local, Store as _};
+use containerd::context::Context;
+use containerd::diff::Applier;
+use containerd::digest;
+use containerd::errdefs::Error;
+use containerd::images::unpack::{self, Unpacker};
+use containerd::images::{oci, Image};
+use containerd::labels;
+use containerd::mount::Mount;
+use containerd::platforms;
+use containerd::snapshots::Snapshotter;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// RecordingApplier reads the layers, which are uncompressed, and records
+/// the ones applied.
+struct RecordingApplier {
+    content: Arc<local::Store>,
+    applied: Mutex<Vec<String>>,
+}
+
+impl Applier for RecordingApplier {
+    fn apply(&self, ctx: &Context, desc: &Descriptor, mounts: &[Mount]) -> Result<Descriptor, Error> {
+        assert_eq!(mounts.len(), 1);
+        let p = content::read_blob(self.content.as_ref(), ctx, desc)?;
+        // give concurrent unpacks the chance to race on the same layer
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        self.applied.lock().unwrap().push(desc.digest.clone());
+        Ok(common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, &p))
+    }
+}
+
+struct Fixture {
+    _root: TempDir,
+    content: Arc<local::Store>,
+    snapshotter: Arc<MemorySnapshotter>,
+    applier: Arc<RecordingApplier>,
+    unpacker: Unpacker,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        let root = TempDir::new();
+        let content = Arc::new(local::Store::new(root.path().join("content")).unwrap());
+        let snapshotter = Arc::new(MemorySnapshotter::default());
+        let applier = Arc::new(RecordingApplier {
+            content: content.clone(),
+            applied: Mutex::new(Vec::new()),
+        });
+        let unpacker = Unpacker::new(content.clone(), "overlayfs", snapshotter.clone(), applier.clone());
+        Fixture {
+            _root: root,
+            content,
+            snapshotter,
+            applier,
+            unpacker,
+        }
+    }
+
+    fn put(&self, media_type: &str, data: &[u8]) -> Descriptor {
+        let desc = common::descriptor(media_type, data);
+        content::write_blob(
+            self.content.as_ref(),
+            &default_ctx(),
+            &desc.digest,
+            &mut &data[..],
+            &desc,
+            HashMap::new(),
+        )
+        .unwrap();
+        desc
+    }
+
+    /// add_manifest adds a manifest of the layers for the platform, the diff
+    /// IDs of the config are taken as given.
+    fn add_manifest(&self, platform: &Platform, layers: &[&str], diff_ids: &[String]) -> Descriptor {
+        let config = serde_json::json!({
+            "architecture": platform.architecture,
+            "os": platform.os,
+            "rootfs": {"type": "layers", "diff_ids": diff_ids},
+        });
+        let config = self.put(oci::MEDIA_TYPE_IMAGE_CONFIG, &serde_json::to_vec(&config).unwrap());
+        let manifest = oci::Manifest {
+            schema_version: 2,
+            media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+            config: config.into(),
+            layers: layers
+                .iter()
+                .map(|l| self.put(oci::MEDIA_TYPE_IMAGE_LAYER, l.as_bytes()).into())
+                .collect(),
+            ..Default::default()
+        };
+        self.put(oci::MEDIA_TYPE_IMAGE_MANIFEST, &serde_json::to_vec(&manifest).unwrap())
+    }
+
+    fn config_labels(&self, manifest: &Descriptor) -> HashMap<String, String> {
+        let manifest: oci::Manifest =
+            serde_json::from_slice(&content::read_blob(self.content.as_ref(), &default_ctx(), manifest).unwrap())
+                .unwrap();
+        self.content
+            .info(&default_ctx(), &manifest.config.descriptor.digest)
+            .unwrap()
+            .labels
+    }
+
+    fn applied(&self) -> usize {
+        self.applier.applied.lock().unwrap().len()
+    }
+}
+
+fn diff_ids(layers: &[&str]) -> Vec<String> {
+    layers.iter().map(|l| digest::from_bytes(l.as_bytes())).collect()
+}
+
+fn linux(architecture: &str) -> Platform {
+    Platform {
+        os: "linux".to_string(),
+        architecture: architecture.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn chain_ids() {
+    let ids = diff_ids(&["a", "b", "c"]);
+    let chain = unpack::chain_ids(&ids);
+
+    assert_eq!(chain.len(), 3);
+    assert_eq!(chain[0], ids[0]);
+    assert_eq!(
+        chain[1],
+        digest::from_bytes(format!("{} {}", ids[0], ids[1]).as_bytes())
+    );
+    assert_eq!(
+        chain[2],
+        digest::from_bytes(format!("{} {}", chain[1], ids[2]).as_bytes())
+    );
+    assert_eq!(unpack::chain_id(&ids), chain[2]);
+    assert_eq!(unpack::chain_id(&[]), "");
+}
+
+#[test]
+fn unpack_commits_layers_under_chain_ids() {
+    let fixture = Fixture::new();
+    let layers = ["base", "app"];
+    let manifest = fixture.add_manifest(&linux("amd64"), &layers, &diff_ids(&layers));
+    let image = Image::new("docker.io/library/app:v1", manifest.clone());
+
+    let top = fixture
+        .unpacker
+        .unpack(&default_ctx(), &image, &platforms::only(linux("amd64")))
+        .unwrap();
+
+    let chain = unpack::chain_ids(&diff_ids(&layers));
+    assert_eq!(top, chain[1]);
+    assert_eq!(fixture.snapshotter.stat(&default_ctx(), &chain[0]).unwrap().parent, "");
+    assert_eq!(
+        fixture.snapshotter.stat(&default_ctx(), &chain[1]).unwrap().parent,
+        chain[0]
+    );
+    assert_eq!(fixture.snapshotter.committed().len(), 2);
+    assert_eq!(fixture.snapshotter.active(), 0);
+
+    let key = format!("{}.overlayfs", labels::GC_REF_SNAPSHOT_PREFIX);
+    assert_eq!(fixture.config_labels(&manifest).get(&key), Some(&top));
+
+    // a second unpack finds the snapshots
+    assert_eq!(
+        fixture
+            .unpacker
+            .unpack(&default_ctx(), &image, &platforms::only(linux("amd64")))
+            .unwrap(),
+        top
+    );
+    assert_eq!(fixture.applied(), 2);
+}
+
+#[test]
+fn unpack_skips_shared_layers() {
+    let fixture = Fixture::new();
+    let base = fixture.add_manifest(&linux("amd64"), &["base"], &diff_ids(&["base"]));
+    let app = fixture.add_manifest(&linux("amd64"), &["base", "app"], &diff_ids(&["base", "app"]));
+    let matcher = platforms::only(linux("amd64"));
+
+    fixture
+        .unpacker
+        .unpack(&default_ctx(), &Image::new("base", base), &matcher)
+        .unwrap();
+    fixture
+        .unpacker
+        .unpack(&default_ctx(), &Image::new("app", app), &matcher)
+        .unwrap();
+
+    assert_eq!(fixture.applied(), 2);
+    assert_eq!(fixture.snapshotter.committed().len(), 2);
+}
+
+#[test]
+fn unpack_resolves_index_for_platform() {
+    let fixture = Fixture::new();
+    let amd64 = fixture.add_manifest(&linux("amd64"), &["amd64"], &diff_ids(&["amd64"]));
+    let arm = fixture.add_manifest(&linux("arm"), &["arm"], &diff_ids(&["arm"]));
+    let arm = oci::Descriptor {
+        descriptor: arm,
+        platform: Some(Platform {
+            variant: "v6".to_string(),
+            ..linux("arm")
+        }),
+    };
+    let index = oci::Index {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_INDEX.to_string(),
+        // the amd64 manifest is only matched by its config
+        manifests: vec![amd64.into(), arm],
+        ..Default::default()
+    };
+    let index = fixture.put(oci::MEDIA_TYPE_IMAGE_INDEX, &serde_json::to_vec(&index).unwrap());
+    let image = Image::new("docker.io/library/multi:v1", index);
+
+    let top = fixture
+        .unpacker
+        .unpack(&default_ctx(), &image, &platforms::only(linux("arm64")))
+        .unwrap();
+    assert_eq!(top, digest::from_bytes(b"arm"));
+
+    let top = fixture
+        .unpacker
+        .unpack(&default_ctx(), &image, &platforms::only(linux("amd64")))
+        .unwrap();
+    assert_eq!(top, digest::from_bytes(b"amd64"));
+
+    let err = fixture
+        .unpacker
+        .unpack(&default_ctx(), &image, &platforms::only(linux("s390x")))
+        .unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+}
+
+#[test]
+fn unpack_rejects_wrong_diff_id() {
+    let fixture = Fixture::new();
+    let manifest = fixture.add_manifest(&linux("amd64"), &["base"], &diff_ids(&["other"]));
+    let image = Image::new("docker.io/library/app:v1", manifest);
+
+    let err = fixture
+        .unpacker
+        .unpack(&default_ctx(), &image, &platforms::only(linux("amd64")))
+        .unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert!(fixture.snapshotter.committed().is_empty());
+    assert_eq!(fixture.snapshotter.active(), 0);
+
+    let manifest = fixture.add_manifest(&linux("amd64"), &["base", "app"], &diff_ids(&["base"]));
+    let image = Image::new("docker.io/library/app:v2", manifest);
+    let err = fixture
+        .unpacker
+        .unpack(&default_ctx(), &image, &platforms::only(linux("amd64")))
+        .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+}
+
+#[test]
+fn concurrent_unpacks_apply_layers_once() {
+    let fixture = Arc::new(Fixture::new());
+    let layers = ["base", "lib", "app"];
+    let manifest = fixture.add_manifest(&linux("amd64"), &layers, &diff_ids(&layers));
+    let image = Image::new("docker.io/library/app:v1", manifest);
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let fixture = fixture.clone();
+            let image = image.clone();
+            std::thread::spawn(move || {
+                fixture
+                    .unpacker
+                    .unpack(&default_ctx(), &image, &platforms::only(linux("amd64")))
+                    .unwrap()
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), unpack::chain_id(&diff_ids(&layers)));
+    }
+
+    assert_eq!(fixture.applied(), 3);
+    assert_eq!(fixture.snapshotter.committed().len(), 3);
+    assert_eq!(fixture.snapshotter.active(), 0);
+}
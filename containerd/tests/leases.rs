@@ -0,0 +1,322 @@
+mod common;
+
+use common::snapshotter::MemorySnapshotter;
+use common::{default_ctx, TempDir};
+use containerd::content::{self, local, Store as _, WriterOpts};
+use containerd::context::Context;
+use containerd::images::oci;
+use containerd::labels;
+use containerd::leases::{self, Lease, LeasedContentStore, LeasedSnapshotter, Manager as _, Resource};
+use containerd::metadata::db::DB;
+use containerd::metadata::leases::LeaseManager;
+use containerd::snapshots::Snapshotter as _;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+fn open(root: &Path) -> LeaseManager {
+    LeaseManager::new(Arc::new(DB::open(root.join("meta.db")).unwrap()))
+}
+
+fn lease(id: &str, labels: &[(&str, &str)]) -> Lease {
+    let mut lease = Lease::new(id);
+    lease.labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    lease
+}
+
+#[test]
+fn create_list_delete() {
+    let root = TempDir::new();
+    let manager = open(root.path());
+
+    let created = manager
+        .create(&default_ctx(), lease("pull-1", &[("purpose", "pull")]))
+        .unwrap();
+    assert!(created.created_at > OffsetDateTime::UNIX_EPOCH);
+    manager
+        .create(&default_ctx(), lease("unpack-1", &[("purpose", "unpack")]))
+        .unwrap();
+    manager.create(&common::ctx("other"), lease("pull-1", &[])).unwrap();
+
+    let err = manager.create(&default_ctx(), lease("pull-1", &[])).unwrap_err();
+    assert!(err.is_already_exists(), "{}", err);
+    for invalid in ["", "-lease", "lease..1", "lease/1"] {
+        let err = manager.create(&default_ctx(), lease(invalid, &[])).unwrap_err();
+        assert!(err.is_invalid_argument(), "{:?}: {}", invalid, err);
+    }
+    let err = manager.create(&Context::new(), lease("pull-2", &[])).unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+
+    let ids = |filters: &[&str]| -> Vec<String> {
+        manager
+            .list(&default_ctx(), filters)
+            .unwrap()
+            .into_iter()
+            .map(|l| l.id)
+            .collect()
+    };
+    assert_eq!(ids(&[]), ["pull-1", "unpack-1"]);
+    assert_eq!(ids(&["labels.purpose==unpack"]), ["unpack-1"]);
+    assert_eq!(ids(&["id==pull-1", "id==missing"]), ["pull-1"]);
+
+    manager.delete(&default_ctx(), "pull-1").unwrap();
+    assert_eq!(ids(&[]), ["unpack-1"]);
+    assert!(manager.delete(&default_ctx(), "pull-1").unwrap_err().is_not_found());
+    assert_eq!(manager.list(&common::ctx("other"), &[]).unwrap().len(), 1);
+}
+
+#[test]
+fn resources() {
+    let root = TempDir::new();
+    let manager = open(root.path());
+    manager.create(&default_ctx(), Lease::new("lease")).unwrap();
+    let dgst = containerd::digest::from_bytes(b"blob");
+
+    manager
+        .add_resource(&default_ctx(), "lease", Resource::new(leases::RESOURCE_CONTENT, &dgst))
+        .unwrap();
+    manager
+        .add_resource(
+            &default_ctx(),
+            "lease",
+            Resource::new(leases::RESOURCE_INGESTS, "layer-1"),
+        )
+        .unwrap();
+    manager
+        .add_resource(&default_ctx(), "lease", Resource::snapshot("overlayfs", "extract-1"))
+        .unwrap();
+    // adding a resource twice references it once
+    manager
+        .add_resource(&default_ctx(), "lease", Resource::new(leases::RESOURCE_CONTENT, &dgst))
+        .unwrap();
+
+    let resources = manager.list_resources(&default_ctx(), "lease").unwrap();
+    assert_eq!(resources.len(), 3);
+    assert!(resources.contains(&Resource::new("snapshots/overlayfs", "extract-1")));
+
+    manager
+        .delete_resource(
+            &default_ctx(),
+            "lease",
+            Resource::new(leases::RESOURCE_INGESTS, "layer-1"),
+        )
+        .unwrap();
+    assert_eq!(manager.list_resources(&default_ctx(), "lease").unwrap().len(), 2);
+
+    let err = manager
+        .add_resource(&default_ctx(), "lease", Resource::new("containers", "c1"))
+        .unwrap_err();
+    assert!(matches!(err, containerd::errdefs::Error::NotImplemented(_)), "{}", err);
+    for invalid in [
+        Resource::new("content", "sha256:abc"),
+        Resource::new("snapshots/", "key"),
+        Resource::new("content", ""),
+    ] {
+        assert!(manager.add_resource(&default_ctx(), "lease", invalid).is_err());
+    }
+    let err = manager
+        .add_resource(
+            &default_ctx(),
+            "missing",
+            Resource::new(leases::RESOURCE_INGESTS, "ref"),
+        )
+        .unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+    assert!(manager
+        .list_resources(&default_ctx(), "missing")
+        .unwrap_err()
+        .is_not_found());
+
+    // the resources go with the lease
+    manager.delete(&default_ctx(), "lease").unwrap();
+    manager.create(&default_ctx(), Lease::new("lease")).unwrap();
+    assert!(manager.list_resources(&default_ctx(), "lease").unwrap().is_empty());
+}
+
+#[test]
+fn leases_are_kept_across_restarts() {
+    let root = TempDir::new();
+    let manager = open(root.path());
+    let created = manager
+        .create(&default_ctx(), lease("pull", &[("purpose", "pull")]))
+        .unwrap();
+    let dgst = containerd::digest::from_bytes(b"blob");
+    let resources = [
+        Resource::new(leases::RESOURCE_CONTENT, &dgst),
+        Resource::new(leases::RESOURCE_INGESTS, "layer-1"),
+        Resource::snapshot("overlayfs", "extract-1"),
+        Resource::snapshot("native", "extract-2"),
+    ];
+    for resource in &resources {
+        manager.add_resource(&default_ctx(), "pull", resource.clone()).unwrap();
+    }
+    manager.create(&common::ctx("other"), Lease::new("deleted")).unwrap();
+    manager.delete(&common::ctx("other"), "deleted").unwrap();
+    drop(manager);
+
+    let manager = open(root.path());
+    assert_eq!(manager.list(&default_ctx(), &[]).unwrap(), [created]);
+    let mut listed = manager.list_resources(&default_ctx(), "pull").unwrap();
+    listed.sort();
+    let mut expected = resources.to_vec();
+    expected.sort();
+    assert_eq!(listed, expected);
+    assert!(manager.list(&common::ctx("other"), &[]).unwrap().is_empty());
+}
+
+#[test]
+fn expiration() {
+    let now = OffsetDateTime::now_utc();
+
+    let lease = Lease::new("lease").with_expiration(Duration::hours(1));
+    assert!(lease.labels.contains_key(labels::GC_EXPIRE));
+    assert!(!lease.is_expired(now));
+    assert!(lease.is_expired(now + Duration::hours(2)));
+
+    let expired = Lease::new("lease").with_expiration(Duration::seconds(-1));
+    assert!(expired.is_expired(now));
+
+    let invalid = self::lease("lease", &[(labels::GC_EXPIRE, "tomorrow")]);
+    assert_eq!(invalid.expires_at(), None);
+    assert!(!invalid.is_expired(now + Duration::days(365)));
+    assert!(!Lease::new("lease").is_expired(now));
+
+    let root = TempDir::new();
+    let manager = open(root.path());
+    let created = manager
+        .create(
+            &default_ctx(),
+            self::lease("lease", &[(labels::GC_EXPIRE, "2020-01-01T00:00:00Z")]),
+        )
+        .unwrap();
+    assert!(created.is_expired(now));
+
+    assert_ne!(leases::generate_id(), leases::generate_id());
+    leases::validate(&Lease::new(&leases::generate_id())).unwrap();
+}
+
+#[test]
+fn leased_content_store_records_ingests_and_content() {
+    let root = TempDir::new();
+    let local = Arc::new(local::Store::new(root.path().join("content")).unwrap());
+    let manager = Arc::new(open(root.path()));
+    manager.create(&default_ctx(), Lease::new("pull")).unwrap();
+    let store = LeasedContentStore::new(local.clone(), manager.clone());
+    let leased = default_ctx().with_lease("pull");
+
+    let data = b"layer data";
+    let desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, data);
+    content::write_blob(&store, &leased, "layer-ref", &mut &data[..], &desc, HashMap::new()).unwrap();
+    assert!(local.info(&default_ctx(), &desc.digest).is_ok());
+    let mut resources = manager.list_resources(&default_ctx(), "pull").unwrap();
+    resources.sort();
+    assert_eq!(
+        resources,
+        [
+            Resource::new(leases::RESOURCE_INGESTS, "layer-ref"),
+            Resource::new(leases::RESOURCE_CONTENT, &desc.digest),
+        ]
+    );
+
+    // content written before the lease is protected once written again
+    let existing = common::descriptor(oci::MEDIA_TYPE_IMAGE_CONFIG, b"{}");
+    content::write_blob(
+        local.as_ref(),
+        &default_ctx(),
+        "config-ref",
+        &mut &b"{}"[..],
+        &existing,
+        HashMap::new(),
+    )
+    .unwrap();
+    content::write_blob(
+        &store,
+        &leased,
+        "config-ref",
+        &mut &b"{}"[..],
+        &existing,
+        HashMap::new(),
+    )
+    .unwrap();
+    let resources = manager.list_resources(&default_ctx(), "pull").unwrap();
+    assert!(resources.contains(&Resource::new(leases::RESOURCE_CONTENT, &existing.digest)));
+
+    // a failed commit does not reference the content
+    let mut w = store
+        .writer(
+            &leased,
+            WriterOpts {
+                reference: "broken-ref".to_string(),
+                desc: Default::default(),
+            },
+        )
+        .unwrap();
+    w.write_all(b"partial").unwrap();
+    let wrong = containerd::digest::from_bytes(b"other");
+    assert!(w.commit(0, &wrong, HashMap::new()).is_err());
+    let resources = manager.list_resources(&default_ctx(), "pull").unwrap();
+    assert!(!resources.iter().any(|r| r.id == wrong));
+    assert!(resources.contains(&Resource::new(leases::RESOURCE_INGESTS, "broken-ref")));
+
+    // writes without a lease are not recorded
+    let desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"unleased");
+    content::write_blob(
+        &store,
+        &default_ctx(),
+        "unleased-ref",
+        &mut &b"unleased"[..],
+        &desc,
+        HashMap::new(),
+    )
+    .unwrap();
+    let resources = manager.list_resources(&default_ctx(), "pull").unwrap();
+    assert!(!resources.iter().any(|r| r.id == desc.digest || r.id == "unleased-ref"));
+
+    // writes fail once the lease is deleted
+    manager.delete(&default_ctx(), "pull").unwrap();
+    let desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"more");
+    let err = content::write_blob(&store, &leased, "more-ref", &mut &b"more"[..], &desc, HashMap::new()).unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+}
+
+#[test]
+fn leased_snapshotter_records_snapshots() {
+    let memory = Arc::new(MemorySnapshotter::default());
+    let root = TempDir::new();
+    let manager = Arc::new(open(root.path()));
+    manager.create(&default_ctx(), Lease::new("unpack")).unwrap();
+    let snapshotter = LeasedSnapshotter::new(memory.clone(), "overlayfs", manager.clone());
+    let leased = default_ctx().with_lease("unpack");
+
+    snapshotter.prepare(&leased, "extract-1", "", HashMap::new()).unwrap();
+    snapshotter
+        .commit(&leased, "layer-1", "extract-1", HashMap::new())
+        .unwrap();
+    snapshotter.view(&leased, "view-1", "layer-1", HashMap::new()).unwrap();
+
+    // committing a snapshot that exists references the existing one
+    memory.prepare(&default_ctx(), "extract-2", "", HashMap::new()).unwrap();
+    memory
+        .commit(&default_ctx(), "layer-2", "extract-2", HashMap::new())
+        .unwrap();
+    snapshotter.prepare(&leased, "extract-3", "", HashMap::new()).unwrap();
+    assert!(snapshotter
+        .commit(&leased, "layer-2", "extract-3", HashMap::new())
+        .unwrap_err()
+        .is_already_exists());
+
+    let ids: Vec<String> = manager
+        .list_resources(&default_ctx(), "unpack")
+        .unwrap()
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+    assert_eq!(ids, ["extract-1", "extract-3", "layer-1", "layer-2", "view-1"]);
+    assert!(manager
+        .list_resources(&default_ctx(), "unpack")
+        .unwrap()
+        .iter()
+        .all(|r| r.kind == "snapshots/overlayfs"));
+}
@@ -0,0 +1,247 @@
+mod common;
+
+use common::{default_ctx, TempDir};
+use containerd::containers::{Container, Store};
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use containerd::metadata::containers::ContainerStore;
+use containerd::metadata::db::DB;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+fn open(root: &Path) -> ContainerStore {
+    ContainerStore::new(Arc::new(DB::open(root.join("meta.db")).unwrap()))
+}
+
+fn container(id: &str) -> Container {
+    let mut container = Container::new(id, "io.containerd.runc.v2");
+    container.image = "docker.io/library/alpine:3".to_string();
+    container.snapshotter = "overlayfs".to_string();
+    container.snapshot_key = format!("{}-rootfs", id);
+    container.spec = prost_types::Any {
+        type_url: "types.containerd.io/opencontainers/runtime-spec/1/Spec".to_string(),
+        value: b"{}".to_vec(),
+    };
+    container.labels = HashMap::from([("app".to_string(), id.to_string())]);
+    container
+}
+
+#[test]
+fn create_persists_across_restarts() {
+    let root = TempDir::new();
+    let store = open(root.path());
+
+    let mut c1 = container("c1");
+    c1.extensions.insert(
+        "io.example/state".to_string(),
+        prost_types::Any {
+            type_url: "example.State".to_string(),
+            value: vec![1, 2, 3],
+        },
+    );
+    let created = store.create(&default_ctx(), c1.clone()).unwrap();
+    assert!(created.created_at > OffsetDateTime::UNIX_EPOCH);
+    assert_eq!(created.created_at, created.updated_at);
+    store.create(&default_ctx(), container("c2")).unwrap();
+    store.create(&common::ctx("other"), container("c1")).unwrap();
+
+    let err = store.create(&default_ctx(), container("c1")).unwrap_err();
+    assert!(err.is_already_exists(), "{}", err);
+    for invalid in [
+        Container::new("", "runc"),
+        Container::new("c/3", "runc"),
+        Container::new("c3", ""),
+    ] {
+        let err = store.create(&default_ctx(), invalid).unwrap_err();
+        assert!(err.is_invalid_argument(), "{}", err);
+    }
+    let err = store.create(&Context::new(), container("c3")).unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    drop(store);
+
+    let store = open(root.path());
+    let got = store.get(&default_ctx(), "c1").unwrap();
+    assert_eq!(got, created);
+    assert_eq!(got.extensions, c1.extensions);
+    assert!(store.get(&default_ctx(), "c3").unwrap_err().is_not_found());
+
+    let ids = |ctx: &Context, filters: &[&str]| -> Vec<String> {
+        store.list(ctx, filters).unwrap().into_iter().map(|c| c.id).collect()
+    };
+    assert_eq!(ids(&default_ctx(), &[]), ["c1", "c2"]);
+    assert_eq!(ids(&default_ctx(), &["labels.app==c2"]), ["c2"]);
+    assert_eq!(
+        ids(&default_ctx(), &["id==c1", "snapshot_key==c2-rootfs"]),
+        ["c1", "c2"]
+    );
+    assert_eq!(ids(&common::ctx("other"), &[]), ["c1"]);
+    assert!(ids(&common::ctx("empty"), &[]).is_empty());
+}
+
+#[test]
+fn update_replaces_mutable_fields() {
+    let root = TempDir::new();
+    let store = open(root.path());
+    let created = store.create(&default_ctx(), container("c1")).unwrap();
+
+    let mut requested = created.clone();
+    requested.image = "docker.io/library/alpine:4".to_string();
+    requested.labels.clear();
+    requested.snapshot_key = "c1-rootfs-2".to_string();
+    let updated = store.update(&default_ctx(), requested, &[]).unwrap();
+    assert_eq!(updated.image, "docker.io/library/alpine:4");
+    assert!(updated.labels.is_empty());
+    assert_eq!(updated.created_at, created.created_at);
+    assert!(updated.updated_at > created.updated_at);
+
+    let mut immutable = updated.clone();
+    immutable.runtime.name = "io.containerd.kata.v2".to_string();
+    let err = store.update(&default_ctx(), immutable, &[]).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    let mut immutable = updated.clone();
+    immutable.snapshotter = "native".to_string();
+    assert!(store
+        .update(&default_ctx(), immutable, &[])
+        .unwrap_err()
+        .is_invalid_argument());
+
+    assert!(store
+        .update(&default_ctx(), container("missing"), &[])
+        .unwrap_err()
+        .is_not_found());
+    drop(store);
+
+    // failed updates are not persisted
+    let store = open(root.path());
+    assert_eq!(store.get(&default_ctx(), "c1").unwrap(), updated);
+}
+
+#[test]
+fn update_field_paths() {
+    let root = TempDir::new();
+    let store = open(root.path());
+    let mut c1 = container("c1");
+    c1.labels.insert("tier".to_string(), "gold".to_string());
+    c1.extensions.insert("io.example/a".to_string(), Default::default());
+    let created = store.create(&default_ctx(), c1).unwrap();
+
+    let mut requested = Container::new("c1", "io.containerd.kata.v2");
+    requested.labels.insert("tier".to_string(), "silver".to_string());
+    requested.image = "docker.io/library/alpine:4".to_string();
+    requested.extensions.insert(
+        "io.example/b".to_string(),
+        prost_types::Any {
+            type_url: "example.B".to_string(),
+            value: vec![1],
+        },
+    );
+
+    // only the listed fields change, the other fields of the request are ignored
+    let updated = store
+        .update(
+            &default_ctx(),
+            requested.clone(),
+            &["labels.tier", "labels.app", "image", "extensions.io.example/b"],
+        )
+        .unwrap();
+    assert_eq!(
+        updated.labels,
+        HashMap::from([("tier".to_string(), "silver".to_string())])
+    );
+    assert_eq!(updated.image, "docker.io/library/alpine:4");
+    assert_eq!(updated.spec, created.spec);
+    assert_eq!(updated.snapshot_key, created.snapshot_key);
+    assert_eq!(updated.runtime, created.runtime);
+    let mut keys: Vec<&String> = updated.extensions.keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["io.example/a", "io.example/b"]);
+
+    let updated = store
+        .update(
+            &default_ctx(),
+            requested.clone(),
+            &["labels", "spec", "snapshot_key", "extensions"],
+        )
+        .unwrap();
+    assert_eq!(updated.labels, requested.labels);
+    assert_eq!(updated.spec, Default::default());
+    assert_eq!(updated.snapshot_key, "");
+    assert_eq!(updated.extensions, requested.extensions);
+    assert_eq!(store.get(&default_ctx(), "c1").unwrap(), updated);
+
+    for path in ["runtime", "snapshotter", "sandbox_id"] {
+        let err = store.update(&default_ctx(), requested.clone(), &[path]).unwrap_err();
+        assert!(err.is_invalid_argument(), "{}: {}", path, err);
+        assert!(err.to_string().contains("immutable"), "{}", err);
+    }
+    for path in ["id", "created_at", "labels.", "unknown"] {
+        let err = store
+            .update(&default_ctx(), requested.clone(), &["image", path])
+            .unwrap_err();
+        assert!(err.is_invalid_argument(), "{}: {}", path, err);
+    }
+    // a rejected update changes nothing
+    assert_eq!(store.get(&default_ctx(), "c1").unwrap(), updated);
+}
+
+#[test]
+fn delete() {
+    let root = TempDir::new();
+    let store = open(root.path());
+    store.create(&default_ctx(), container("c1")).unwrap();
+
+    store.delete(&default_ctx(), "c1").unwrap();
+    assert!(store.delete(&default_ctx(), "c1").unwrap_err().is_not_found());
+    assert!(store.delete(&common::ctx("other"), "c1").unwrap_err().is_not_found());
+    drop(store);
+
+    let store = open(root.path());
+    assert!(store.get(&default_ctx(), "c1").unwrap_err().is_not_found());
+    // the id can be reused
+    store.create(&default_ctx(), container("c1")).unwrap();
+}
+
+#[test]
+fn database_is_exclusive_and_checked() {
+    let root = TempDir::new();
+    let path = root.path().join("meta.db");
+    let db = DB::open(&path).unwrap();
+    let keys: [&[u8]; 2] = [b"v1", b"default"];
+    db.update(|tx| tx.create_path(&keys).map(|_| ())).unwrap();
+
+    let err = DB::open(&path).err().unwrap();
+    assert!(matches!(err, Error::Unavailable(_)), "{}", err);
+    drop(db);
+
+    let db = DB::open(&path).unwrap();
+    assert!(db.view(|tx| Ok(tx.path(&keys).is_some())).unwrap());
+    drop(db);
+
+    let mut p = std::fs::read(&path).unwrap();
+    let last = p.len() - 1;
+    p[last] ^= 0xff;
+    std::fs::write(&path, p).unwrap();
+    let err = DB::open(&path).err().unwrap();
+    assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+}
+
+#[test]
+fn mutation_callbacks_follow_commits() {
+    let root = TempDir::new();
+    let db = Arc::new(DB::open(root.path().join("meta.db")).unwrap());
+    let mutations = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = mutations.clone();
+    db.register_mutation_callback(Arc::new(move |dirty| recorded.lock().unwrap().push(dirty)));
+    let store = ContainerStore::new(db);
+
+    store.create(&default_ctx(), container("c1")).unwrap();
+    store.update(&default_ctx(), container("c1"), &["labels"]).unwrap();
+    // failed updates are not mutations
+    assert!(store.create(&default_ctx(), container("c1")).is_err());
+    assert!(store.delete(&default_ctx(), "missing").is_err());
+    store.delete(&default_ctx(), "c1").unwrap();
+
+    assert_eq!(*mutations.lock().unwrap(), [false, false, true]);
+}
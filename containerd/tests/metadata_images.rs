@@ -0,0 +1,225 @@
+use containerd::api::events::{ImageCreate, ImageDelete, ImageUpdate};
+use containerd::api::types::Descriptor;
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use containerd::events::Publisher;
+use containerd::images::{Image, Store};
+use containerd::metadata::images::ImageStore;
+use prost::Message;
+use std::sync::{Arc, Mutex};
+
+fn ctx() -> Context {
+    Context::new().with_namespace("default")
+}
+
+const MEDIA_TYPE_IMAGE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Recorder keeps the events published to it.
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<(String, String, prost_types::Any)>>,
+}
+
+impl Publisher for Recorder {
+    fn publish(&self, namespace: &str, topic: &str, event: prost_types::Any) -> Result<(), Error> {
+        self.events
+            .lock()
+            .unwrap()
+            .push((namespace.to_string(), topic.to_string(), event));
+        Ok(())
+    }
+}
+
+impl Recorder {
+    /// next returns the namespace and topic of the oldest event not returned
+    /// yet, with the event.
+    fn next(&self) -> (String, String, prost_types::Any) {
+        let mut events = self.events.lock().unwrap();
+        assert!(!events.is_empty(), "no event published");
+        events.remove(0)
+    }
+}
+
+/// digest returns a valid digest made from the content.
+fn digest(content: &str) -> String {
+    let encoded: String = content.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{:0<64}", encoded)
+}
+
+fn image(name: &str, content: &str) -> Image {
+    let target = Descriptor {
+        media_type: MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        digest: digest(content),
+        size: content.len() as i64,
+        ..Default::default()
+    };
+    Image::new(name, target)
+}
+
+fn open() -> (ImageStore, Arc<Recorder>) {
+    let recorder = Arc::new(Recorder::default());
+    (ImageStore::new(recorder.clone()), recorder)
+}
+
+fn names(store: &ImageStore, filters: &[&str]) -> Vec<String> {
+    store
+        .list(&ctx(), filters)
+        .unwrap()
+        .into_iter()
+        .map(|i| i.name)
+        .collect()
+}
+
+#[test]
+fn images_are_created_listed_and_deleted() {
+    let (store, _events) = open();
+
+    let created = store
+        .create(&ctx(), image("docker.io/library/nginx:latest", "nginx"))
+        .unwrap();
+    assert_eq!(created.created_at, created.updated_at);
+    assert!(created.created_at > time::OffsetDateTime::UNIX_EPOCH);
+    assert_eq!(store.get(&ctx(), "docker.io/library/nginx:latest").unwrap(), created);
+    store
+        .create(&ctx(), image("docker.io/library/alpine:3", "alpine"))
+        .unwrap();
+    store
+        .create(
+            &Context::new().with_namespace("other"),
+            image("docker.io/library/redis:7", "redis"),
+        )
+        .unwrap();
+
+    let err = store
+        .create(&ctx(), image("docker.io/library/nginx:latest", "other"))
+        .unwrap_err();
+    assert!(err.is_already_exists(), "{}", err);
+    let err = store.create(&ctx(), image("", "nginx")).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    let mut invalid = image("docker.io/library/busybox:1", "busybox");
+    invalid.target.digest = "sha256:short".to_string();
+    assert!(store.create(&ctx(), invalid).unwrap_err().is_invalid_argument());
+
+    assert_eq!(
+        names(&store, &[]),
+        ["docker.io/library/alpine:3", "docker.io/library/nginx:latest"]
+    );
+    let nginx = format!("target.digest=={}", digest("nginx"));
+    assert_eq!(names(&store, &[&nginx]), ["docker.io/library/nginx:latest"]);
+    let any = ["name==docker.io/library/alpine:3", "name==docker.io/library/redis:7"];
+    assert_eq!(names(&store, &any), ["docker.io/library/alpine:3"]);
+
+    store.delete(&ctx(), "docker.io/library/nginx:latest").unwrap();
+    let err = store.get(&ctx(), "docker.io/library/nginx:latest").unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+    assert!(store
+        .delete(&ctx(), "docker.io/library/nginx:latest")
+        .unwrap_err()
+        .is_not_found());
+    store
+        .get(&Context::new().with_namespace("other"), "docker.io/library/redis:7")
+        .unwrap();
+
+    let err = store.get(&Context::new(), "docker.io/library/alpine:3").unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+}
+
+#[test]
+fn update_only_mutates_field_paths() {
+    let (store, _events) = open();
+    let mut created = image("docker.io/library/nginx:latest", "nginx");
+    created.labels.insert("a".to_string(), "1".to_string());
+    created.labels.insert("b".to_string(), "2".to_string());
+    created
+        .target
+        .annotations
+        .insert("org.opencontainers.image.ref.name".to_string(), "latest".to_string());
+    let created = store.create(&ctx(), created).unwrap();
+
+    let mut update = image("docker.io/library/nginx:latest", "other");
+    update.labels.insert("a".to_string(), "10".to_string());
+    update.labels.insert("c".to_string(), "3".to_string());
+    let updated = store.update(&ctx(), update.clone(), &["labels.a", "labels.b"]).unwrap();
+    assert_eq!(updated.labels.len(), 1);
+    assert_eq!(updated.labels["a"], "10");
+    assert_eq!(updated.target, created.target);
+    assert_eq!(updated.created_at, created.created_at);
+    assert!(updated.updated_at >= created.updated_at);
+    assert_eq!(store.get(&ctx(), "docker.io/library/nginx:latest").unwrap(), updated);
+
+    let updated = store
+        .update(
+            &ctx(),
+            update.clone(),
+            &["annotations.org.opencontainers.image.ref.name"],
+        )
+        .unwrap();
+    assert!(updated.target.annotations.is_empty());
+    assert_eq!(updated.target.digest, created.target.digest);
+
+    let updated = store.update(&ctx(), update.clone(), &["target"]).unwrap();
+    assert_eq!(updated.target, update.target);
+    assert_eq!(updated.labels.len(), 1);
+
+    // no field paths replace the labels and the target
+    let updated = store.update(&ctx(), update.clone(), &[]).unwrap();
+    assert_eq!(
+        (updated.labels, updated.target),
+        (update.labels.clone(), update.target.clone())
+    );
+
+    for path in ["name", "created_at", "unknown"] {
+        let err = store.update(&ctx(), update.clone(), &[path]).unwrap_err();
+        assert!(err.is_invalid_argument(), "{}: {}", path, err);
+    }
+    let mut invalid = update.clone();
+    invalid.target.media_type.clear();
+    assert!(store
+        .update(&ctx(), invalid, &["target"])
+        .unwrap_err()
+        .is_invalid_argument());
+    assert_eq!(
+        store.get(&ctx(), "docker.io/library/nginx:latest").unwrap().target,
+        update.target
+    );
+
+    let err = store
+        .update(&ctx(), image("docker.io/library/missing:1", "missing"), &[])
+        .unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+    assert!(store
+        .update(&ctx(), image("", "nginx"), &[])
+        .unwrap_err()
+        .is_invalid_argument());
+}
+
+#[test]
+fn changes_publish_image_events() {
+    let (store, events) = open();
+
+    let mut nginx = image("docker.io/library/nginx:latest", "nginx");
+    nginx.labels.insert("env".to_string(), "prod".to_string());
+    store.create(&ctx(), nginx.clone()).unwrap();
+    let (namespace, topic, event) = events.next();
+    assert_eq!((namespace.as_str(), topic.as_str()), ("default", "/images/create"));
+    assert_eq!(event.type_url, "containerd.api.events.ImageCreate");
+    let create = ImageCreate::decode(&event.value[..]).unwrap();
+    assert_eq!((create.name, create.labels), (nginx.name.clone(), nginx.labels.clone()));
+
+    nginx.labels.insert("env".to_string(), "dev".to_string());
+    store.update(&ctx(), nginx.clone(), &["labels.env"]).unwrap();
+    let (_, topic, event) = events.next();
+    assert_eq!(topic, "/images/update");
+    let update = ImageUpdate::decode(&event.value[..]).unwrap();
+    assert_eq!(update.labels["env"], "dev");
+
+    store.delete(&ctx(), &nginx.name).unwrap();
+    let (_, topic, event) = events.next();
+    assert_eq!(topic, "/images/delete");
+    assert_eq!(ImageDelete::decode(&event.value[..]).unwrap().name, nginx.name);
+
+    // failed changes publish nothing
+    assert!(store.delete(&ctx(), &nginx.name).is_err());
+    assert!(store.update(&ctx(), nginx, &[]).is_err());
+    assert!(events.events.lock().unwrap().is_empty());
+}
@@ -0,0 +1,258 @@
+mod common;
+
+use common::snapshotter::MemorySnapshotter;
+use common::TempDir;
+use containerd::api::events::{NamespaceCreate, NamespaceDelete, NamespaceUpdate};
+use containerd::containers::{Container, Store as _};
+use containerd::content::{self, local, Store as _};
+use containerd::errdefs::Error;
+use containerd::events::Publisher;
+use containerd::images::{oci, Image, Store as _};
+use containerd::metadata::containers::ContainerStore;
+use containerd::metadata::db::DB;
+use containerd::metadata::images::ImageStore;
+use containerd::metadata::namespaces::NamespaceStore;
+use containerd::namespaces::{Namespace, Store};
+use containerd::snapshots::Snapshotter as _;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Recorder keeps the events published to it.
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<(String, String, prost_types::Any)>>,
+}
+
+impl Publisher for Recorder {
+    fn publish(&self, namespace: &str, topic: &str, event: prost_types::Any) -> Result<(), Error> {
+        self.events
+            .lock()
+            .unwrap()
+            .push((namespace.to_string(), topic.to_string(), event));
+        Ok(())
+    }
+}
+
+struct Stores {
+    _root: TempDir,
+    recorder: Arc<Recorder>,
+    images: Arc<ImageStore>,
+    containers: ContainerStore,
+    content: Arc<local::Store>,
+    snapshotter: Arc<MemorySnapshotter>,
+    namespaces: NamespaceStore,
+}
+
+impl Stores {
+    fn new() -> Stores {
+        let root = TempDir::new();
+        let recorder = Arc::new(Recorder::default());
+        let images = Arc::new(ImageStore::new(recorder.clone()));
+        let db = Arc::new(DB::open(root.path().join("meta.db")).unwrap());
+        let containers = ContainerStore::new(db.clone());
+        let content = Arc::new(local::Store::new(root.path().join("content")).unwrap());
+        let snapshotter = Arc::new(MemorySnapshotter::default());
+        let namespaces = NamespaceStore::new(db.clone(), recorder.clone(), images.clone(), content.clone())
+            .with_snapshotter("overlayfs", snapshotter.clone());
+        Stores {
+            _root: root,
+            recorder,
+            images,
+            containers,
+            content,
+            snapshotter,
+            namespaces,
+        }
+    }
+
+    /// topics returns the namespaces and topics of the events published so
+    /// far, and forgets them.
+    fn topics(&self) -> Vec<(String, String)> {
+        self.recorder
+            .events
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|(ns, topic, _)| (ns, topic))
+            .collect()
+    }
+}
+
+fn namespace(name: &str, labels: &[(&str, &str)]) -> Namespace {
+    let mut namespace = Namespace::new(name);
+    namespace.labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    namespace
+}
+
+#[test]
+fn create_get_list() {
+    let stores = Stores::new();
+    let store = &stores.namespaces;
+
+    store.create(namespace("tenant-b", &[("team", "b")])).unwrap();
+    store.create(namespace("tenant-a", &[("team", "a")])).unwrap();
+    assert!(store
+        .create(Namespace::new("tenant-a"))
+        .unwrap_err()
+        .is_already_exists());
+    for invalid in ["", "-tenant", "tenant/a", "tenant..a"] {
+        let err = store.create(Namespace::new(invalid)).unwrap_err();
+        assert!(err.is_invalid_argument(), "{:?}: {}", invalid, err);
+    }
+    let too_large = "x".repeat(4096);
+    let err = store
+        .create(namespace("tenant-c", &[("large", too_large.as_str())]))
+        .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+
+    assert_eq!(store.get("tenant-a").unwrap().labels["team"], "a");
+    assert!(store.get("tenant-c").unwrap_err().is_not_found());
+
+    let names =
+        |filters: &[&str]| -> Vec<String> { store.list(filters).unwrap().into_iter().map(|ns| ns.name).collect() };
+    assert_eq!(names(&[]), ["tenant-a", "tenant-b"]);
+    assert_eq!(names(&["labels.team==b"]), ["tenant-b"]);
+
+    let (ns, topic, event) = stores.recorder.events.lock().unwrap()[0].clone();
+    assert_eq!((ns.as_str(), topic.as_str()), ("tenant-b", "/namespaces/create"));
+    assert_eq!(event.type_url, "containerd.api.events.NamespaceCreate");
+    let event = NamespaceCreate::decode(event.value.as_slice()).unwrap();
+    assert_eq!((event.name.as_str(), event.labels["team"].as_str()), ("tenant-b", "b"));
+    assert_eq!(stores.topics().len(), 2);
+}
+
+#[test]
+fn update_labels() {
+    let stores = Stores::new();
+    let store = &stores.namespaces;
+    store
+        .create(namespace("tenant", &[("team", "a"), ("tier", "gold")]))
+        .unwrap();
+    stores.topics();
+
+    let updated = store
+        .update(
+            namespace("tenant", &[("team", "b"), ("tier", "silver")]),
+            &["labels.team"],
+        )
+        .unwrap();
+    assert_eq!(
+        updated.labels,
+        HashMap::from([
+            ("team".to_string(), "b".to_string()),
+            ("tier".to_string(), "gold".to_string())
+        ])
+    );
+
+    // a label missing from the update is removed
+    let updated = store.update(namespace("tenant", &[]), &["labels.tier"]).unwrap();
+    assert_eq!(updated.labels.len(), 1);
+
+    let updated = store.update(namespace("tenant", &[("owner", "ops")]), &[]).unwrap();
+    assert_eq!(updated, store.get("tenant").unwrap());
+    assert_eq!(updated.labels.keys().collect::<Vec<_>>(), ["owner"]);
+
+    let err = store.update(namespace("tenant", &[]), &["name"]).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert!(store.update(namespace("missing", &[]), &[]).unwrap_err().is_not_found());
+
+    let events = stores.recorder.events.lock().unwrap().clone();
+    assert_eq!(events.len(), 3);
+    let event = NamespaceUpdate::decode(events[2].2.value.as_slice()).unwrap();
+    assert_eq!(event.labels["owner"], "ops");
+}
+
+#[test]
+fn delete_refuses_non_empty_namespaces() {
+    let stores = Stores::new();
+    let store = &stores.namespaces;
+    store.create(Namespace::new("tenant")).unwrap();
+
+    let data = b"{}";
+    let desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_MANIFEST, data);
+    content::write_blob(
+        stores.content.as_ref(),
+        &common::ctx("tenant"),
+        "ref",
+        &mut &data[..],
+        &desc,
+        HashMap::new(),
+    )
+    .unwrap();
+    stores
+        .images
+        .create(&common::ctx("tenant"), Image::new("app:v1", desc.clone()))
+        .unwrap();
+    stores
+        .containers
+        .create(&common::ctx("tenant"), Container::new("c1", "io.containerd.runc.v2"))
+        .unwrap();
+    stores
+        .snapshotter
+        .prepare(&common::ctx("tenant"), "rootfs", "", HashMap::new())
+        .unwrap();
+
+    let err = store.delete("tenant").unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert!(
+        err.to_string()
+            .contains("still has containers, images, content, snapshots"),
+        "{}",
+        err
+    );
+
+    stores.containers.delete(&common::ctx("tenant"), "c1").unwrap();
+    stores.images.delete(&common::ctx("tenant"), "app:v1").unwrap();
+    let err = store.delete("tenant").unwrap_err();
+    assert!(err.to_string().contains("still has content, snapshots"), "{}", err);
+
+    stores.content.delete(&common::ctx("tenant"), &desc.digest).unwrap();
+    stores.snapshotter.remove(&common::ctx("tenant"), "rootfs").unwrap();
+    stores.topics();
+
+    store.delete("tenant").unwrap();
+    assert!(store.get("tenant").unwrap_err().is_not_found());
+    assert!(store.delete("tenant").unwrap_err().is_not_found());
+
+    let events = stores.recorder.events.lock().unwrap().clone();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].1, "/namespaces/delete");
+    let event = NamespaceDelete::decode(events[0].2.value.as_slice()).unwrap();
+    assert_eq!(event.name, "tenant");
+}
+
+#[test]
+fn namespaces_are_kept_across_restarts() {
+    let root = TempDir::new();
+    let path = root.path().join("meta.db");
+    let open = |db: Arc<DB>| {
+        let content = Arc::new(local::Store::new(root.path().join("content")).unwrap());
+        NamespaceStore::new(
+            db,
+            Arc::new(Recorder::default()),
+            Arc::new(ImageStore::new(Arc::new(Recorder::default()))),
+            content,
+        )
+    };
+
+    let db = Arc::new(DB::open(&path).unwrap());
+    let store = open(db.clone());
+    store.create(namespace("tenant-a", &[("team", "a")])).unwrap();
+    store.create(namespace("tenant-b", &[])).unwrap();
+    store.delete("tenant-b").unwrap();
+    drop((store, db));
+
+    let db = Arc::new(DB::open(&path).unwrap());
+    let store = open(db.clone());
+    assert_eq!(store.list(&[]).unwrap(), [namespace("tenant-a", &[("team", "a")])]);
+
+    // the containers of the database keep the namespace, the check and the
+    // removal being one update
+    ContainerStore::new(db)
+        .create(&common::ctx("tenant-a"), Container::new("c1", "io.containerd.runc.v2"))
+        .unwrap();
+    let err = store.delete("tenant-a").unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert!(store.get("tenant-a").is_ok());
+}
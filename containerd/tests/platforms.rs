@@ -0,0 +1,112 @@
+use containerd::api::types::Platform;
+use containerd::platforms::{self, MatchComparer, Matcher};
+
+fn platform(os: &str, architecture: &str, variant: &str) -> Platform {
+    Platform {
+        os: os.to_string(),
+        architecture: architecture.to_string(),
+        variant: variant.to_string(),
+    }
+}
+
+#[test]
+fn parse_and_format() {
+    for (specifier, expected, formatted) in [
+        ("linux/amd64", platform("linux", "amd64", ""), "linux/amd64"),
+        ("linux/x86_64", platform("linux", "amd64", ""), "linux/amd64"),
+        ("Linux/ARM64/v8", platform("linux", "arm64", ""), "linux/arm64"),
+        ("linux/aarch64", platform("linux", "arm64", ""), "linux/arm64"),
+        ("linux/armhf", platform("linux", "arm", "v7"), "linux/arm/v7"),
+        ("linux/armel", platform("linux", "arm", "v6"), "linux/arm/v6"),
+        ("linux/arm", platform("linux", "arm", "v7"), "linux/arm/v7"),
+        ("linux/arm/6", platform("linux", "arm", "v6"), "linux/arm/v6"),
+        ("linux/i386", platform("linux", "386", ""), "linux/386"),
+        ("macos/arm64", platform("darwin", "arm64", ""), "darwin/arm64"),
+        ("windows/amd64", platform("windows", "amd64", ""), "windows/amd64"),
+    ] {
+        let parsed = platforms::parse(specifier).unwrap();
+        assert_eq!(parsed, expected, "{}", specifier);
+        assert_eq!(platforms::format(&parsed), formatted);
+    }
+}
+
+#[test]
+fn parse_single_component() {
+    let host = platforms::default_spec();
+
+    let parsed = platforms::parse("linux").unwrap();
+    assert_eq!(parsed.os, "linux");
+    assert_eq!(parsed.architecture, host.architecture);
+
+    let parsed = platforms::parse("aarch64").unwrap();
+    assert_eq!(parsed, platform(&host.os, "arm64", ""));
+
+    assert!(platforms::parse("toaster").unwrap_err().is_invalid_argument());
+}
+
+#[test]
+fn parse_invalid() {
+    for specifier in ["", "linux/", "/amd64", "linux/amd64/v8/extra", "linux/amd 64"] {
+        assert!(platforms::parse(specifier).unwrap_err().is_invalid_argument(), "{:?}", specifier);
+    }
+}
+
+#[test]
+fn format_unknown() {
+    assert_eq!(platforms::format(&Platform::default()), "unknown");
+}
+
+#[test]
+fn default_spec_is_normalized() {
+    let host = platforms::default_spec();
+    assert_eq!(platforms::normalize(host.clone()), host);
+    assert_eq!(platforms::parse(&platforms::default_string()).unwrap(), host);
+    assert!(platforms::default_matcher().matches(&host));
+}
+
+#[test]
+fn only_falls_back_to_older_variants() {
+    let matcher = platforms::only(platform("linux", "arm", "v8"));
+    for variant in ["v8", "v7", "v6", "v5"] {
+        assert!(matcher.matches(&platform("linux", "arm", variant)), "{}", variant);
+    }
+    assert!(!matcher.matches(&platform("linux", "arm64", "")));
+    assert!(!matcher.matches(&platform("windows", "arm", "v7")));
+
+    assert!(matcher.less(&platform("linux", "arm", "v7"), &platform("linux", "arm", "v6")));
+    assert!(!matcher.less(&platform("linux", "arm", "v6"), &platform("linux", "arm", "v7")));
+
+    let matcher = platforms::only(platform("linux", "arm", "v6"));
+    assert!(!matcher.matches(&platform("linux", "arm", "v7")));
+}
+
+#[test]
+fn only_arm64_runs_arm() {
+    let matcher = platforms::only(platform("linux", "aarch64", ""));
+    assert!(matcher.matches(&platform("linux", "arm64", "v8")));
+    assert!(matcher.matches(&platform("linux", "arm", "v7")));
+    assert!(matcher.less(&platform("linux", "arm64", ""), &platform("linux", "arm", "v8")));
+
+    let matcher = platforms::only_strict(platform("linux", "aarch64", ""));
+    assert!(matcher.matches(&platform("linux", "arm64", "v8")));
+    assert!(!matcher.matches(&platform("linux", "arm", "v7")));
+}
+
+#[test]
+fn ordered_ranks_candidates() {
+    let matcher = platforms::ordered([platform("linux", "arm64", ""), platform("linux", "amd64", "")]);
+
+    let mut candidates = vec![
+        platform("windows", "amd64", ""),
+        platform("linux", "x86_64", ""),
+        platform("linux", "arm", "v7"),
+        platform("linux", "aarch64", ""),
+    ];
+    matcher.sort(&mut candidates, |p| p);
+
+    assert_eq!(candidates[0], platform("linux", "aarch64", ""));
+    assert_eq!(candidates[1], platform("linux", "x86_64", ""));
+    assert!(!matcher.matches(&candidates[2]));
+    assert!(!matcher.matches(&candidates[3]));
+    assert!(!matcher.less(&candidates[2], &candidates[0]));
+}
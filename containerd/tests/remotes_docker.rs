@@ -0,0 +1,647 @@
+mod common;
+
+use common::registry::{Auth, Registry};
+use common::{default_ctx, ImageStores};
+use containerd::api::types::{Descriptor, Platform};
+use containerd::content::{self, Store as _, WriterOpts};
+use containerd::digest;
+use containerd::images::{oci, Store as _};
+use containerd::labels;
+use containerd::platforms;
+use containerd::reference::Spec;
+use containerd::remotes::docker::auth::{self, DockerAuthorizer};
+use containerd::remotes::docker::{self, DockerResolver, RegistryOptions, ResolverOptions};
+use containerd::remotes::{self, Resolver};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+const REPOSITORY: &str = "team/app";
+
+/// push_image stores a manifest of a config and a layer in the registry,
+/// tagged v1. The manifest, config and layer are returned.
+fn push_image(registry: &Registry, platform: &str, tag: Option<&str>) -> [Descriptor; 3] {
+    let config =
+        serde_json::json!({"architecture": platform, "os": "linux", "rootfs": {"type": "layers", "diff_ids": []}});
+    let config = serde_json::to_vec(&config).unwrap();
+    let layer = format!("layer of {}", platform).repeat(100);
+
+    registry.put_blob(REPOSITORY, &config, None);
+    registry.put_blob(REPOSITORY, layer.as_bytes(), None);
+    let config = common::descriptor(oci::MEDIA_TYPE_IMAGE_CONFIG, &config);
+    let layer = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, layer.as_bytes());
+
+    let manifest = oci::Manifest {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        config: config.clone().into(),
+        layers: vec![layer.clone().into()],
+        ..Default::default()
+    };
+    let manifest = serde_json::to_vec(&manifest).unwrap();
+    registry.put_manifest(REPOSITORY, tag, oci::MEDIA_TYPE_IMAGE_MANIFEST, &manifest);
+    [
+        common::descriptor(oci::MEDIA_TYPE_IMAGE_MANIFEST, &manifest),
+        config,
+        layer,
+    ]
+}
+
+fn resolver(opts: RegistryOptions) -> DockerResolver {
+    chunked_resolver(opts, 0)
+}
+
+fn chunked_resolver(opts: RegistryOptions, chunk_size: u64) -> DockerResolver {
+    DockerResolver::new(ResolverOptions {
+        hosts: docker::configure_default_registries(RegistryOptions {
+            plain_http: true,
+            ..opts
+        }),
+        headers: HashMap::new(),
+        chunk_size,
+    })
+}
+
+/// write_image writes a manifest of a config and a layer into the content
+/// store. The manifest, config and layer are returned.
+fn write_image(fixture: &ImageStores, layer: &[u8]) -> [Descriptor; 3] {
+    let config =
+        serde_json::json!({"architecture": "amd64", "os": "linux", "rootfs": {"type": "layers", "diff_ids": []}});
+    let config = serde_json::to_vec(&config).unwrap();
+    let config_desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_CONFIG, &config);
+    let layer_desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, layer);
+    let manifest = oci::Manifest {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        config: config_desc.clone().into(),
+        layers: vec![layer_desc.clone().into()],
+        ..Default::default()
+    };
+    let manifest = serde_json::to_vec(&manifest).unwrap();
+    let manifest_desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_MANIFEST, &manifest);
+
+    for (desc, data) in [
+        (&config_desc, &config[..]),
+        (&layer_desc, layer),
+        (&manifest_desc, &manifest[..]),
+    ] {
+        assert_eq!(&fixture.put(&desc.media_type, data), desc);
+    }
+    [manifest_desc, config_desc, layer_desc]
+}
+
+fn authorizer(username: &str, password: &str) -> Arc<DockerAuthorizer> {
+    let (username, password) = (username.to_string(), password.to_string());
+    let credentials: auth::Credentials = Arc::new(move |_: &str| Some((username.clone(), password.clone())));
+    Arc::new(DockerAuthorizer::new(
+        ureq::AgentBuilder::new().build(),
+        Some(credentials),
+    ))
+}
+
+fn reference(registry: &Registry, object: &str) -> String {
+    format!("{}/{}{}", registry.host(), REPOSITORY, object)
+}
+
+#[test]
+fn parse_spec() {
+    let spec = Spec::parse("registry.internal:5000/team/app:v1").unwrap();
+    assert_eq!(spec.locator, "registry.internal:5000/team/app");
+    assert_eq!(spec.object, "v1");
+    assert_eq!(
+        (spec.hostname(), spec.repository()),
+        ("registry.internal:5000", "team/app")
+    );
+    assert_eq!((spec.tag(), spec.digest()), (Some("v1"), None));
+    assert_eq!(spec.to_string(), "registry.internal:5000/team/app:v1");
+
+    let dgst = digest::from_bytes(b"manifest");
+    let spec = Spec::parse(&format!("docker.io/library/alpine@{}", dgst)).unwrap();
+    assert_eq!((spec.tag(), spec.digest()), (None, Some(dgst.as_str())));
+    assert_eq!(spec.to_string(), format!("docker.io/library/alpine@{}", dgst));
+
+    for invalid in [
+        "alpine",
+        "/alpine:latest",
+        "docker.io/alpine@sha256:abc",
+        "docker.io/alpine:in valid",
+    ] {
+        assert!(Spec::parse(invalid).unwrap_err().is_invalid_argument(), "{}", invalid);
+    }
+}
+
+#[test]
+fn parse_challenges() {
+    let challenge = auth::parse_challenge(
+        r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#,
+    )
+    .unwrap();
+    assert_eq!(challenge.scheme, "bearer");
+    assert_eq!(challenge.parameters["realm"], "https://auth.docker.io/token");
+    assert_eq!(challenge.parameters["service"], "registry.docker.io");
+    assert_eq!(challenge.parameters["scope"], "repository:library/alpine:pull,push");
+
+    let challenge = auth::parse_challenge("Basic realm=test, charset=\"UTF-8\"").unwrap();
+    assert_eq!(challenge.scheme, "basic");
+    assert_eq!(
+        (
+            challenge.parameters["realm"].as_str(),
+            challenge.parameters["charset"].as_str()
+        ),
+        ("test", "UTF-8")
+    );
+
+    assert!(auth::parse_challenge("Bearer realm=\"unterminated").is_err());
+}
+
+#[test]
+fn resolve_by_tag_and_digest() {
+    let registry = Registry::start(Auth::None);
+    let [manifest, _, layer] = push_image(&registry, "amd64", Some("v1"));
+    let resolver = resolver(RegistryOptions::default());
+
+    let (name, desc) = resolver.resolve(&reference(&registry, ":v1")).unwrap();
+    assert_eq!(name, reference(&registry, ":v1"));
+    assert_eq!(desc, manifest);
+    let head = registry.requests().into_iter().find(|r| r.method == "HEAD").unwrap();
+    assert!(head.headers["accept"].contains(oci::MEDIA_TYPE_IMAGE_INDEX));
+
+    let (_, desc) = resolver
+        .resolve(&reference(&registry, &format!("@{}", manifest.digest)))
+        .unwrap();
+    assert_eq!(desc, manifest);
+
+    // a digest which is not a manifest is resolved as a blob
+    let (_, desc) = resolver
+        .resolve(&reference(&registry, &format!("@{}", layer.digest)))
+        .unwrap();
+    assert_eq!((desc.digest, desc.size), (layer.digest, layer.size));
+
+    let err = resolver.resolve(&reference(&registry, ":missing")).unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+}
+
+#[test]
+fn resolve_digests_manifests_served_without_digest() {
+    let registry = Registry::start(Auth::None);
+    let [manifest, _, _] = push_image(&registry, "amd64", Some("v1"));
+    registry.omit_digests();
+    let resolver = resolver(RegistryOptions::default());
+
+    let (_, desc) = resolver.resolve(&reference(&registry, ":v1")).unwrap();
+    assert_eq!(desc, manifest);
+
+    // the content of a manifest is only read up to its size limit
+    let large = vec![b' '; (4 << 20) + 1];
+    registry.put_manifest(REPOSITORY, Some("large"), oci::MEDIA_TYPE_IMAGE_MANIFEST, &large);
+    let err = resolver.resolve(&reference(&registry, ":large")).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert!(err.to_string().contains("manifest too large"), "{}", err);
+}
+
+#[test]
+fn pull_writes_content_and_image() {
+    let registry = Registry::start(Auth::None);
+    let [manifest, config, layer] = push_image(&registry, "amd64", Some("v1"));
+    let fixture = ImageStores::new();
+    let name = reference(&registry, ":v1");
+
+    let image = remotes::pull(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        &resolver(RegistryOptions::default()),
+        &name,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    assert_eq!((image.name.as_str(), &image.target), (name.as_str(), &manifest));
+    assert_eq!(fixture.images.get(&default_ctx(), &name).unwrap().target, manifest);
+    assert_eq!(
+        content::read_blob(&fixture.content, &default_ctx(), &layer).unwrap(),
+        registry.blob(REPOSITORY, &layer.digest).unwrap()
+    );
+    let info = fixture.content.info(&default_ctx(), &manifest.digest).unwrap();
+    assert_eq!(
+        info.labels[&format!("{}.0", labels::GC_REF_CONTENT_PREFIX)],
+        config.digest
+    );
+    assert_eq!(
+        info.labels[&format!("{}.1", labels::GC_REF_CONTENT_PREFIX)],
+        layer.digest
+    );
+}
+
+#[test]
+fn pull_selects_platform_of_index() {
+    let registry = Registry::start(Auth::None);
+    let [amd64, ..] = push_image(&registry, "amd64", None);
+    let [arm64, arm64_config, _] = push_image(&registry, "arm64", None);
+    let platform = |architecture: &str| Platform {
+        os: "linux".to_string(),
+        architecture: architecture.to_string(),
+        ..Default::default()
+    };
+    let index = oci::Index {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_INDEX.to_string(),
+        manifests: vec![
+            oci::Descriptor {
+                descriptor: amd64.clone(),
+                platform: Some(platform("amd64")),
+            },
+            oci::Descriptor {
+                descriptor: arm64.clone(),
+                platform: Some(platform("arm64")),
+            },
+        ],
+        ..Default::default()
+    };
+    let index = serde_json::to_vec(&index).unwrap();
+    registry.put_manifest(REPOSITORY, Some("v1"), oci::MEDIA_TYPE_IMAGE_INDEX, &index);
+    let fixture = ImageStores::new();
+
+    let image = remotes::pull(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        &resolver(RegistryOptions::default()),
+        &reference(&registry, ":v1"),
+        &platforms::only(platform("aarch64")),
+    )
+    .unwrap();
+
+    assert_eq!(image.target.media_type, oci::MEDIA_TYPE_IMAGE_INDEX);
+    assert!(fixture.has(&arm64.digest));
+    assert!(fixture.has(&arm64_config.digest));
+    assert!(!fixture.has(&amd64.digest));
+}
+
+#[test]
+fn bearer_token_auth() {
+    let registry = Registry::start(Auth::Bearer {
+        username: "puller".to_string(),
+        password: "secret".to_string(),
+    });
+    let [manifest, ..] = push_image(&registry, "amd64", Some("v1"));
+    let name = reference(&registry, ":v1");
+
+    let resolver = resolver(RegistryOptions {
+        authorizer: Some(authorizer("puller", "secret")),
+        ..Default::default()
+    });
+    let fixture = ImageStores::new();
+    let fetcher = resolver.fetcher(&name).unwrap();
+    let (_, desc) = resolver.resolve(&name).unwrap();
+    remotes::fetch_all(
+        &fixture.content,
+        &default_ctx(),
+        fetcher.as_ref(),
+        &desc,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+    assert!(fixture.has(&manifest.digest));
+
+    let token = registry.requests().into_iter().find(|r| r.path == "/token").unwrap();
+    assert_eq!(token.query["scope"], format!("repository:{}:pull", REPOSITORY));
+    assert_eq!(token.query["service"], "test-registry");
+    let tokens = registry.requests().iter().filter(|r| r.path == "/token").count();
+    assert_eq!(tokens, 1, "the token is reused by the following requests");
+
+    // without the right credentials no token is issued
+    let resolver = self::resolver(RegistryOptions {
+        authorizer: Some(authorizer("puller", "wrong")),
+        ..Default::default()
+    });
+    assert!(resolver.resolve(&name).unwrap_err().is_failed_precondition());
+    let resolver = self::resolver(RegistryOptions::default());
+    assert!(resolver.resolve(&name).unwrap_err().is_failed_precondition());
+}
+
+#[test]
+fn basic_auth() {
+    let registry = Registry::start(Auth::Basic {
+        username: "puller".to_string(),
+        password: "secret".to_string(),
+    });
+    let [manifest, ..] = push_image(&registry, "amd64", Some("v1"));
+    let name = reference(&registry, ":v1");
+
+    let resolver = resolver(RegistryOptions {
+        authorizer: Some(authorizer("puller", "secret")),
+        ..Default::default()
+    });
+    assert_eq!(resolver.resolve(&name).unwrap().1, manifest);
+
+    let resolver = self::resolver(RegistryOptions {
+        authorizer: Some(authorizer("puller", "wrong")),
+        ..Default::default()
+    });
+    assert!(resolver.resolve(&name).unwrap_err().is_failed_precondition());
+}
+
+#[test]
+fn fetch_resumes_with_range() {
+    let registry = Registry::start(Auth::None);
+    let [_, _, layer] = push_image(&registry, "amd64", Some("v1"));
+    let data = registry.blob(REPOSITORY, &layer.digest).unwrap();
+    let fixture = ImageStores::new();
+
+    // an interrupted fetch left half of the layer in the ingest
+    let mut w = fixture
+        .content
+        .writer(
+            &default_ctx(),
+            WriterOpts {
+                reference: remotes::make_ref_key(&layer),
+                desc: layer.clone(),
+            },
+        )
+        .unwrap();
+    w.write_all(&data[..data.len() / 2]).unwrap();
+    drop(w);
+
+    let fetcher = resolver(RegistryOptions::default())
+        .fetcher(&reference(&registry, ":v1"))
+        .unwrap();
+    remotes::fetch(&fixture.content, &default_ctx(), fetcher.as_ref(), &layer).unwrap();
+
+    assert_eq!(
+        content::read_blob(&fixture.content, &default_ctx(), &layer).unwrap(),
+        data
+    );
+    let get = registry
+        .requests()
+        .into_iter()
+        .find(|r| r.path.ends_with(&layer.digest))
+        .unwrap();
+    assert_eq!(get.headers["range"], format!("bytes={}-", data.len() / 2));
+}
+
+#[test]
+fn fetch_verifies_digest() {
+    let registry = Registry::start(Auth::None);
+    let layer = common::descriptor(oci::MEDIA_TYPE_IMAGE_LAYER, b"expected layer");
+    registry.put_blob(REPOSITORY, b"tampered layer", Some(&layer.digest));
+    let fixture = ImageStores::new();
+
+    let fetcher = resolver(RegistryOptions::default())
+        .fetcher(&reference(&registry, ":v1"))
+        .unwrap();
+    let err = remotes::fetch(&fixture.content, &default_ctx(), fetcher.as_ref(), &layer).unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert!(!fixture.has(&layer.digest));
+    assert!(
+        fixture.content.list_statuses(&default_ctx(), &[]).unwrap().is_empty(),
+        "the ingest is aborted"
+    );
+}
+
+#[test]
+fn pull_from_mirror() {
+    let upstream = Registry::start(Auth::None);
+    let mirror = Registry::start(Auth::None);
+    // the image is only on the mirror
+    let [manifest, _, layer] = push_image(&mirror, "amd64", Some("v1"));
+    let name = reference(&upstream, ":v1");
+
+    let mut mirrors = HashMap::new();
+    mirrors.insert(
+        upstream.host().to_string(),
+        vec![format!("http://{}/v2", mirror.host())],
+    );
+    let resolver = resolver(RegistryOptions {
+        mirrors,
+        ..Default::default()
+    });
+    let fixture = ImageStores::new();
+
+    let image = remotes::pull(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        &resolver,
+        &name,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+    assert_eq!(image.target, manifest);
+    assert!(fixture.has(&layer.digest));
+    assert!(upstream.requests().is_empty());
+    assert!(mirror.requests().iter().all(|r| r.query["ns"] == upstream.host()));
+}
+
+#[test]
+fn push_uploads_blobs_before_manifest() {
+    let registry = Registry::start(Auth::Bearer {
+        username: "user".to_string(),
+        password: "secret".to_string(),
+    });
+    let fixture = ImageStores::new();
+    let [manifest, config, layer] = write_image(&fixture, &b"layer".repeat(100));
+    let resolver = resolver(RegistryOptions {
+        authorizer: Some(authorizer("user", "secret")),
+        ..Default::default()
+    });
+
+    let name = reference(&registry, ":v1");
+    remotes::push(
+        &fixture.content,
+        &default_ctx(),
+        &resolver,
+        &name,
+        &manifest,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    for desc in [&config, &layer] {
+        assert_eq!(
+            registry.blob(REPOSITORY, &desc.digest).unwrap(),
+            content::read_blob(&fixture.content, &default_ctx(), desc).unwrap()
+        );
+    }
+    let (media_type, data) = registry.manifest(REPOSITORY, "v1").unwrap();
+    assert_eq!(media_type, oci::MEDIA_TYPE_IMAGE_MANIFEST);
+    assert_eq!(
+        data,
+        content::read_blob(&fixture.content, &default_ctx(), &manifest).unwrap()
+    );
+
+    let writes: Vec<_> = registry.requests().into_iter().filter(|r| r.method == "PUT").collect();
+    assert_eq!(writes.len(), 3);
+    assert_eq!(writes[2].path, format!("/v2/{}/manifests/v1", REPOSITORY));
+    assert_eq!(writes[2].headers["content-type"], oci::MEDIA_TYPE_IMAGE_MANIFEST);
+
+    let (_, desc) = resolver.resolve(&name).unwrap();
+    assert_eq!(desc, manifest);
+}
+
+#[test]
+fn push_rejects_manifests_over_the_size_limit() {
+    let registry = Registry::start(Auth::None);
+    let resolver = resolver(RegistryOptions::default());
+    let pusher = resolver.pusher(&reference(&registry, ":v1")).unwrap();
+
+    let large = vec![b' '; (4 << 20) + 1];
+    let desc = common::descriptor(oci::MEDIA_TYPE_IMAGE_MANIFEST, &large);
+    let err = pusher.push(&desc, &mut std::io::Cursor::new(large)).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+    assert!(err.to_string().contains("too large"), "{}", err);
+    assert!(registry.requests().is_empty());
+    assert!(registry.manifest(REPOSITORY, "v1").is_none());
+}
+
+#[test]
+fn push_skips_existing_content() {
+    let registry = Registry::start(Auth::None);
+    let fixture = ImageStores::new();
+    let [manifest, ..] = write_image(&fixture, &b"layer".repeat(100));
+    let resolver = resolver(RegistryOptions::default());
+    let name = reference(&registry, ":v1");
+
+    remotes::push(
+        &fixture.content,
+        &default_ctx(),
+        &resolver,
+        &name,
+        &manifest,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+    let pushed = registry.requests().len();
+    remotes::push(
+        &fixture.content,
+        &default_ctx(),
+        &resolver,
+        &name,
+        &manifest,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    let requests = registry.requests();
+    assert!(
+        requests[pushed..].iter().all(|r| r.method == "HEAD"),
+        "{:?}",
+        &requests[pushed..]
+    );
+}
+
+#[test]
+fn push_mounts_blobs_from_source_repository() {
+    let registry = Registry::start(Auth::None);
+    let [manifest, config, layer] = push_image(&registry, "amd64", Some("v1"));
+    let fixture = ImageStores::new();
+    let resolver = resolver(RegistryOptions::default());
+    remotes::pull(
+        &fixture.content,
+        &fixture.images,
+        &default_ctx(),
+        &resolver,
+        &reference(&registry, ":v1"),
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    let key = format!("{}.{}", labels::LABEL_DISTRIBUTION_SOURCE_PREFIX, registry.host());
+    assert_eq!(
+        fixture.content.info(&default_ctx(), &layer.digest).unwrap().labels[&key],
+        REPOSITORY
+    );
+
+    let pushed = registry.requests().len();
+    let name = format!("{}/team/other:v1", registry.host());
+    remotes::push(
+        &fixture.content,
+        &default_ctx(),
+        &resolver,
+        &name,
+        &manifest,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    for desc in [&config, &layer] {
+        assert!(registry.blob("team/other", &desc.digest).is_some());
+    }
+    assert!(registry.manifest("team/other", "v1").is_some());
+    let requests = registry.requests();
+    let mounts: Vec<_> = requests[pushed..].iter().filter(|r| r.method == "POST").collect();
+    assert_eq!(mounts.len(), 2);
+    assert!(mounts.iter().all(|r| r.query["from"] == REPOSITORY));
+    assert!(!requests[pushed..]
+        .iter()
+        .any(|r| r.path.contains("/blobs/uploads/upload-")));
+}
+
+#[test]
+fn push_resumes_chunked_upload() {
+    let registry = Registry::start(Auth::None);
+    let fixture = ImageStores::new();
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    let [manifest, config, layer] = write_image(&fixture, &data);
+    let resolver = chunked_resolver(RegistryOptions::default(), 128);
+
+    // only the layer is uploaded
+    registry.put_blob(
+        REPOSITORY,
+        &content::read_blob(&fixture.content, &default_ctx(), &config).unwrap(),
+        None,
+    );
+    registry.fail_next_chunk();
+    let name = reference(&registry, ":v1");
+    remotes::push(
+        &fixture.content,
+        &default_ctx(),
+        &resolver,
+        &name,
+        &manifest,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    assert_eq!(registry.blob(REPOSITORY, &layer.digest).unwrap(), data);
+    let requests = registry.requests();
+    let patches: Vec<_> = requests.iter().filter(|r| r.method == "PATCH").collect();
+    assert_eq!(patches[0].headers["content-range"], "0-127");
+    assert!(
+        patches.iter().any(|r| r.headers["content-range"] == "64-191"),
+        "{:?}",
+        patches
+    );
+    assert!(requests
+        .iter()
+        .any(|r| r.method == "GET" && r.path.contains("/blobs/uploads/")));
+}
+
+#[test]
+fn push_replaces_tag_of_a_different_manifest() {
+    let registry = Registry::start(Auth::None);
+    registry.omit_digests();
+    let [previous, ..] = push_image(&registry, "arm64", Some("v1"));
+    let fixture = ImageStores::new();
+    let [manifest, ..] = write_image(&fixture, &b"layer".repeat(100));
+    let resolver = resolver(RegistryOptions::default());
+
+    remotes::push(
+        &fixture.content,
+        &default_ctx(),
+        &resolver,
+        &reference(&registry, ":v1"),
+        &manifest,
+        &platforms::default_matcher(),
+    )
+    .unwrap();
+
+    let (_, data) = registry.manifest(REPOSITORY, "v1").unwrap();
+    assert_ne!(digest::from_bytes(&data), previous.digest);
+    assert_eq!(
+        data,
+        content::read_blob(&fixture.content, &default_ctx(), &manifest).unwrap()
+    );
+    let writes: Vec<_> = registry.requests().into_iter().filter(|r| r.method == "PUT").collect();
+    assert_eq!(writes.last().unwrap().path, format!("/v2/{}/manifests/v1", REPOSITORY));
+}
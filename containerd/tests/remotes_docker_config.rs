@@ -0,0 +1,351 @@
+mod common;
+
+use common::registry::{Auth, Registry};
+use common::TempDir;
+use containerd::images::oci;
+use containerd::remotes::docker::auth::Credentials;
+use containerd::remotes::docker::config::{self, HostOptions};
+use containerd::remotes::docker::{DockerResolver, HostCapabilities, RegistryHost, ResolverOptions};
+use containerd::remotes::Resolver;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Certs is a private CA with a server and a client certificate it signed.
+struct Certs {
+    ca: rcgen::Certificate,
+    server: rcgen::Certificate,
+    server_key: KeyPair,
+    client: rcgen::Certificate,
+    client_key: KeyPair,
+}
+
+impl Certs {
+    /// generate generates the certificates of a CA named ca_name.
+    fn generate(ca_name: &str) -> Certs {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, ca_name);
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(vec!["127.0.0.1".to_string(), "localhost".to_string()]).unwrap();
+        let server = params.signed_by(&server_key, &ca, &ca_key).unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "test client");
+        let client = params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        Certs {
+            ca,
+            server,
+            server_key,
+            client,
+            client_key,
+        }
+    }
+
+    /// server_config serves the server certificate, requiring a client
+    /// certificate signed by the CA if client_auth is set.
+    fn server_config(&self, client_auth: bool) -> Arc<rustls::ServerConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if client_auth {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(self.ca.der().clone()).unwrap();
+            let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.server_key.serialize_der()));
+        let chain: Vec<CertificateDer<'static>> = vec![self.server.der().clone()];
+        Arc::new(builder.with_single_cert(chain, key).unwrap())
+    }
+}
+
+fn write(dir: &Path, name: &str, data: &str) {
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join(name), data).unwrap();
+}
+
+fn hosts(root: &Path, host: &str) -> Vec<RegistryHost> {
+    let hosts = config::configure_hosts(HostOptions {
+        host_dir: Some(config::host_dir_from_root(root)),
+        ..Default::default()
+    });
+    hosts(host).unwrap()
+}
+
+fn resolver(root: &Path) -> DockerResolver {
+    DockerResolver::new(ResolverOptions {
+        hosts: config::configure_hosts(HostOptions {
+            host_dir: Some(config::host_dir_from_root(root)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// tagged_manifest stores an empty manifest in the registry tagged v1 and
+/// returns the reference to it.
+fn tagged_manifest(registry: &Registry) -> String {
+    let manifest = serde_json::to_vec(&oci::Manifest {
+        schema_version: 2,
+        media_type: oci::MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    registry.put_manifest("team/app", Some("v1"), oci::MEDIA_TYPE_IMAGE_MANIFEST, &manifest);
+    format!("{}/team/app:v1", registry.host())
+}
+
+#[test]
+fn hosts_file_lists_mirrors_before_server() {
+    let root = TempDir::new();
+    write(
+        &root.path().join("registry.internal:5000"),
+        "hosts.toml",
+        r#"
+server = "https://registry.internal:5000"
+capabilities = ["pull", "resolve", "push"]
+
+[header]
+x-trace = ["a", "b"]
+
+[host."https://mirror-b.internal"]
+capabilities = ["pull", "resolve"]
+[host."https://mirror-b.internal".header]
+x-mirror = "b"
+
+[host."mirror-a.internal:8443/cache"]
+capabilities = ["pull"]
+
+[host."http://mirror-c.internal/custom/api"]
+override_path = true
+"#,
+    );
+
+    let hosts = hosts(root.path(), "registry.internal:5000");
+    let endpoints: Vec<_> = hosts
+        .iter()
+        .map(|h| (h.scheme.as_str(), h.host.as_str(), h.path.as_str()))
+        .collect();
+    assert_eq!(
+        endpoints,
+        [
+            ("https", "mirror-b.internal", "/v2"),
+            ("https", "mirror-a.internal:8443", "/cache/v2"),
+            ("http", "mirror-c.internal", "/custom/api"),
+            ("https", "registry.internal:5000", "/v2"),
+        ]
+    );
+    assert_eq!(
+        hosts[0].capabilities,
+        HostCapabilities::PULL | HostCapabilities::RESOLVE
+    );
+    assert_eq!(hosts[1].capabilities, HostCapabilities::PULL);
+    assert_eq!(hosts[2].capabilities, HostCapabilities::ALL);
+    assert_eq!(hosts[0].header["x-mirror"], "b");
+    assert_eq!(hosts[3].header["x-trace"], "a, b");
+    assert!(hosts.iter().all(|h| h.authorizer.is_some()));
+}
+
+#[test]
+fn hosts_default_to_upstream() {
+    let root = TempDir::new();
+    let hosts = hosts(root.path(), "docker.io");
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(
+        (hosts[0].scheme.as_str(), hosts[0].host.as_str()),
+        ("https", "registry-1.docker.io")
+    );
+    assert_eq!(hosts[0].capabilities, HostCapabilities::ALL);
+
+    // the _default directory applies to every host without its own
+    write(
+        &root.path().join("_default"),
+        "hosts.toml",
+        "[host.\"https://mirror.internal\"]\ncapabilities = [\"pull\", \"resolve\"]\n",
+    );
+    let hosts = self::hosts(root.path(), "quay.io");
+    let names: Vec<_> = hosts.iter().map(|h| h.host.as_str()).collect();
+    assert_eq!(names, ["mirror.internal", "quay.io"]);
+}
+
+#[test]
+fn hosts_file_rejects_invalid_config() {
+    let root = TempDir::new();
+    let dir = root.path().join("registry.internal");
+    let options = HostOptions {
+        host_dir: Some(config::host_dir_from_root(root.path())),
+        ..Default::default()
+    };
+
+    for invalid in [
+        "capabilities = [\"delete\"]",
+        "server = 5000",
+        "[host.\"ftp://mirror.internal\"]",
+        "[host.\"https://mirror.internal\"]\noverride_path = true",
+        "[host",
+    ] {
+        write(&dir, "hosts.toml", invalid);
+        let err = config::configure_hosts(options.clone())("registry.internal")
+            .err()
+            .unwrap();
+        assert!(err.is_invalid_argument(), "{}: {}", invalid, err);
+    }
+}
+
+#[test]
+fn tls_with_private_ca() {
+    let certs = Certs::generate("test CA");
+    let registry = Registry::start_tls(Auth::None, certs.server_config(false));
+    let reference = tagged_manifest(&registry);
+    let root = TempDir::new();
+
+    let err = resolver(root.path()).resolve(&reference).unwrap_err();
+    assert!(err.to_string().contains("https://"), "{}", err);
+
+    let dir = root.path().join(registry.host());
+    write(&dir, "ca.crt", &certs.ca.pem());
+    write(
+        &dir,
+        "hosts.toml",
+        &format!("server = \"https://{}\"\nca = \"ca.crt\"\n", registry.host()),
+    );
+    let (_, desc) = resolver(root.path()).resolve(&reference).unwrap();
+    assert_eq!(desc.media_type, oci::MEDIA_TYPE_IMAGE_MANIFEST);
+
+    write(
+        &dir,
+        "hosts.toml",
+        &format!("server = \"https://{}\"\nskip_verify = true\n", registry.host()),
+    );
+    std::fs::remove_file(dir.join("ca.crt")).unwrap();
+    resolver(root.path()).resolve(&reference).unwrap();
+}
+
+#[test]
+fn tls_client_certificates() {
+    let certs = Certs::generate("test CA");
+    let registry = Registry::start_tls(Auth::None, certs.server_config(true));
+    let reference = tagged_manifest(&registry);
+    let root = TempDir::new();
+    let dir = root.path().join(registry.host());
+    write(&dir, "ca.crt", &certs.ca.pem());
+    write(&dir, "client.crt", &certs.client.pem());
+    write(&dir, "client.key", &certs.client_key.serialize_pem());
+
+    write(
+        &dir,
+        "hosts.toml",
+        &format!("server = \"https://{}\"\nca = \"ca.crt\"\n", registry.host()),
+    );
+    assert!(resolver(root.path()).resolve(&reference).is_err());
+
+    write(
+        &dir,
+        "hosts.toml",
+        &format!(
+            "server = \"https://{}\"\nca = \"ca.crt\"\nclient = [[\"client.crt\", \"client.key\"]]\n",
+            registry.host()
+        ),
+    );
+    resolver(root.path()).resolve(&reference).unwrap();
+
+    // without hosts.toml the certificates are found by their extension
+    let legacy = TempDir::new();
+    let dir = legacy.path().join(registry.host());
+    write(&dir, "ca.crt", &certs.ca.pem());
+    write(&dir, "client.cert", &certs.client.pem());
+    write(&dir, "client.key", &certs.client_key.serialize_pem());
+    let upstream = hosts(legacy.path(), registry.host());
+    assert_eq!((upstream.len(), upstream[0].scheme.as_str()), (1, "https"));
+    resolver(legacy.path()).resolve(&reference).unwrap();
+}
+
+#[test]
+fn tls_client_certificate_issued_by_accepted_ca() {
+    let (other, certs) = (Certs::generate("other CA"), Certs::generate("test CA"));
+    let registry = Registry::start_tls(Auth::None, certs.server_config(true));
+    let reference = tagged_manifest(&registry);
+    let root = TempDir::new();
+    let dir = root.path().join(registry.host());
+    write(&dir, "ca.crt", &certs.ca.pem());
+    for (name, certs) in [("other", &other), ("client", &certs)] {
+        write(&dir, &format!("{}.cert", name), &certs.client.pem());
+        write(&dir, &format!("{}.key", name), &certs.client_key.serialize_pem());
+    }
+
+    // the certificate of the other CA comes first but is not presented
+    write(
+        &dir,
+        "hosts.toml",
+        &format!(
+            "server = \"https://{}\"\nca = \"ca.crt\"\nclient = [{}, {}]\n",
+            registry.host(),
+            "[\"other.cert\", \"other.key\"]",
+            "[\"client.cert\", \"client.key\"]"
+        ),
+    );
+    resolver(root.path()).resolve(&reference).unwrap();
+
+    std::fs::remove_file(dir.join("hosts.toml")).unwrap();
+    resolver(root.path()).resolve(&reference).unwrap();
+}
+
+#[test]
+fn host_dir_with_underscored_port() {
+    let root = TempDir::new();
+    let mirror = "[host.\"https://mirror.internal\"]\n";
+    write(&root.path().join("registry.internal_5000"), "hosts.toml", mirror);
+
+    let hosts = hosts(root.path(), "registry.internal:5000");
+    let names: Vec<_> = hosts.iter().map(|h| h.host.as_str()).collect();
+    assert_eq!(names, ["mirror.internal", "registry.internal:5000"]);
+
+    // the directory named after the host comes first
+    write(&root.path().join("registry.internal:5000"), "hosts.toml", "");
+    assert_eq!(self::hosts(root.path(), "registry.internal:5000").len(), 1);
+}
+
+#[test]
+fn authorizers_are_shared_by_the_resolves() {
+    let registry = Registry::start(Auth::Bearer {
+        username: "puller".to_string(),
+        password: "secret".to_string(),
+    });
+    let reference = tagged_manifest(&registry);
+    let credentials: Credentials = Arc::new(|_: &str| Some(("puller".to_string(), "secret".to_string())));
+    let hosts = config::configure_hosts(HostOptions {
+        credentials: Some(credentials),
+        plain_http: true,
+        ..Default::default()
+    });
+
+    let first = hosts(registry.host()).unwrap();
+    let second = hosts(registry.host()).unwrap();
+    assert!(Arc::ptr_eq(
+        first[0].authorizer.as_ref().unwrap(),
+        second[0].authorizer.as_ref().unwrap()
+    ));
+
+    let resolver = DockerResolver::new(ResolverOptions {
+        hosts,
+        ..Default::default()
+    });
+    for _ in 0..3 {
+        resolver.resolve(&reference).unwrap();
+    }
+    let tokens = registry.requests().iter().filter(|r| r.path == "/token").count();
+    assert_eq!(tokens, 1, "the token is reused by the following resolves");
+}
@@ -0,0 +1,386 @@
+mod common;
+
+use common::TempDir;
+use containerd::errdefs::Error;
+use containerd::mount::Mount;
+use containerd::runtime::runc::{RuncConfig, RuncRuntime};
+use containerd::runtime::task::{ExecOpts, Status};
+use containerd::runtime::{CreateOpts, PlatformRuntime, IO};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// RUNC is a fake runtime binary with the command line of runc. The
+/// processes of its containers are shell processes, which print a greeting
+/// once the container is started, and its exec processes print their spec
+/// and exit with 3. Its creates list the rootfs of the bundle in id.rootfs.
+const RUNC: &str = r#"#!/bin/sh
+echo "$*" >> "$(dirname "$0")/runc.log"
+while [ $# -gt 0 ]; do
+    case "$1" in
+    --root) root=$2; shift 2 ;;
+    --log) log=$2; shift 2 ;;
+    --log-format) shift 2 ;;
+    *) break ;;
+    esac
+done
+cmd=$1; shift
+while [ $# -gt 1 ]; do
+    case "$1" in
+    --bundle) bundle=$2; shift 2 ;;
+    --pid-file) pid_file=$2; shift 2 ;;
+    --process) process=$2; shift 2 ;;
+    --format) shift 2 ;;
+    --all|--force|--detach) shift ;;
+    *) break ;;
+    esac
+done
+id=$1; signal=$2; dir=$root/$id
+if [ "$cmd" != create ] && [ ! -d "$dir" ]; then
+    echo "container $id does not exist" >&2
+    exit 1
+fi
+pid=$(cat "$dir/pid" 2>/dev/null)
+case "$cmd" in
+create)
+    ls "$bundle/rootfs" > "$(dirname "$0")/$id.rootfs"
+    if grep -q fail "$bundle/config.json"; then
+        echo '{"level":"warning","msg":"cgroup v1"}' >> "$log"
+        echo '{"level":"error","msg":"no such cgroup"}' >> "$log"
+        exit 1
+    fi
+    mkdir -p "$dir"
+    (while [ ! -e "$dir/started" ]; do sleep 0.01; done; echo "hello from $id"; exec sleep 1000) &
+    echo $! > "$dir/pid"
+    echo $! > "$pid_file" ;;
+start) touch "$dir/started" ;;
+state)
+    if ! kill -0 "$pid" 2>/dev/null; then status=stopped
+    elif [ -e "$dir/paused" ]; then status=paused
+    elif [ -e "$dir/started" ]; then status=running
+    else status=created; fi
+    echo "{\"ociVersion\":\"1.0.2\",\"id\":\"$id\",\"pid\":$pid,\"status\":\"$status\",\"bundle\":\"/\"}" ;;
+kill) kill -"$signal" "$pid" ;;
+delete) kill -9 "$pid" 2>/dev/null; rm -rf "$dir" ;;
+ps) echo "[$pid]" ;;
+pause) touch "$dir/paused" ;;
+resume) rm "$dir/paused" ;;
+exec)
+    (sleep 0.1; echo "exec $(cat "$process")"; exit 3) &
+    echo $! > "$pid_file" ;;
+esac
+"#;
+
+struct Fixture {
+    root: TempDir,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        let root = TempDir::new();
+        fs::create_dir(root.path().join("bin")).unwrap();
+        let binary = root.path().join("bin/runc");
+        fs::write(&binary, RUNC).unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        Fixture { root }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.path().join(path)
+    }
+
+    fn runtime(&self) -> RuncRuntime {
+        RuncRuntime::new(RuncConfig {
+            binary: self.path("bin/runc"),
+            runtime_root: self.path("runc"),
+            root: self.path("work"),
+            state: self.path("state"),
+        })
+        .unwrap()
+    }
+
+    /// commands returns the commands the runtime binary ran, without its
+    /// global options.
+    fn commands(&self) -> Vec<String> {
+        let root = format!("--root {} ", self.path("runc/tenant").display());
+        fs::read_to_string(self.path("bin/runc.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.replacen(&root, "", 1))
+            .collect()
+    }
+
+    fn opts(&self, spec: &str) -> CreateOpts {
+        CreateOpts {
+            spec: prost_types::Any {
+                type_url: "types.containerd.io/opencontainers/runtime-spec/1/Spec".to_string(),
+                value: spec.as_bytes().to_vec(),
+            },
+            io: IO {
+                stdout: self.path("stdout").to_string_lossy().into_owned(),
+                ..Default::default()
+            },
+            runtime: "io.containerd.runc.v2".to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// eventually_contains waits for the file to contain the text.
+async fn eventually_contains(path: &Path, text: &str) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        if contents.contains(text) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "{:?} does not contain {:?}", contents, text);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tasks_run_in_containers_of_the_runtime_binary() {
+    let fixture = Fixture::new();
+    let runtime = fixture.runtime();
+    let ctx = common::ctx("tenant");
+    assert_eq!(runtime.id(), "io.containerd.runtime.runc.task");
+
+    let task = runtime.create(&ctx, "app", fixture.opts("{}")).await.unwrap();
+    let bundle = fixture.path("state/tenant/app");
+    assert_eq!(fs::read_to_string(bundle.join("config.json")).unwrap(), "{}");
+    let pid = task.pid().await.unwrap();
+    assert_eq!(
+        fs::read_to_string(bundle.join("init.pid")).unwrap().trim(),
+        pid.to_string()
+    );
+    let state = task.state().await.unwrap();
+    assert_eq!((state.status, state.pid), (Status::CreatedStatus, pid));
+    assert_eq!(state.stdout, fixture.path("stdout").to_string_lossy());
+
+    task.start().await.unwrap();
+    eventually_contains(&fixture.path("stdout"), "hello from app").await;
+    assert_eq!(task.state().await.unwrap().status, Status::RunningStatus);
+    assert_eq!(
+        task.pids().await.unwrap().iter().map(|p| p.pid).collect::<Vec<_>>(),
+        [pid]
+    );
+    task.pause().await.unwrap();
+    assert_eq!(task.state().await.unwrap().status, Status::PausedStatus);
+    task.resume().await.unwrap();
+    assert_eq!(task.state().await.unwrap().status, Status::RunningStatus);
+    let err = task.stats().await.unwrap_err();
+    assert!(matches!(err, Error::NotImplemented(_)), "{}", err);
+
+    assert_eq!(runtime.get(&ctx, "app").await.unwrap().pid().await.unwrap(), pid);
+    assert_eq!(runtime.tasks(&ctx, false).await.unwrap().len(), 1);
+    assert!(runtime.tasks(&common::ctx("other"), false).await.unwrap().is_empty());
+
+    // the exit of the init process is collected once it is killed
+    task.kill(9, false).await.unwrap();
+    let exit = task.wait().await.unwrap();
+    assert_eq!((exit.pid, exit.status), (pid, 137));
+    let state = task.state().await.unwrap();
+    assert_eq!((state.status, state.exit_status), (Status::StoppedStatus, 137));
+
+    let exit = runtime.delete(&ctx, "app").await.unwrap();
+    assert_eq!((exit.pid, exit.status), (pid, 137));
+    assert!(!bundle.exists());
+    assert!(!fixture.path("work/tenant/app").exists());
+    assert!(!fixture.path("runc/tenant/app").exists());
+    assert!(runtime.get(&ctx, "app").await.is_err());
+
+    let bundle = bundle.display();
+    assert_eq!(
+        fixture.commands(),
+        [
+            format!(
+                "--log {bundle}/log.json --log-format json create --bundle {bundle} --pid-file {bundle}/init.pid app"
+            ),
+            "state app".to_string(),
+            "start app".to_string(),
+            "state app".to_string(),
+            "ps --format json app".to_string(),
+            "pause app".to_string(),
+            "state app".to_string(),
+            "resume app".to_string(),
+            "state app".to_string(),
+            "kill app 9".to_string(),
+            "delete --force app".to_string(),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exec_processes_run_in_the_container() {
+    let fixture = Fixture::new();
+    let runtime = fixture.runtime();
+    let ctx = common::ctx("tenant");
+    let task = runtime.create(&ctx, "app", fixture.opts("{}")).await.unwrap();
+    task.start().await.unwrap();
+
+    let opts = ExecOpts {
+        spec: prost_types::Any {
+            type_url: "types.containerd.io/opencontainers/runtime-spec/1/Process".to_string(),
+            value: br#"{"args":["probe"]}"#.to_vec(),
+        },
+        io: IO {
+            stdout: fixture.path("probe").to_string_lossy().into_owned(),
+            ..Default::default()
+        },
+    };
+    let process = task.exec("probe", opts.clone()).await.unwrap();
+    let err = task.exec("probe", opts).await.err().unwrap();
+    assert!(err.is_already_exists(), "{}", err);
+    assert_eq!(process.id(), "probe");
+    assert_eq!(process.state().await.unwrap().status, Status::CreatedStatus);
+    assert!(process.wait().await.unwrap_err().is_failed_precondition());
+
+    process.start().await.unwrap();
+    let exit = process.wait().await.unwrap();
+    assert_eq!(exit.status, 3);
+    assert_ne!(exit.pid, task.pid().await.unwrap());
+    assert_eq!(
+        fs::read_to_string(fixture.path("probe")).unwrap(),
+        "exec {\"args\":[\"probe\"]}\n"
+    );
+    let state = task.process("probe").await.unwrap().state().await.unwrap();
+    assert_eq!((state.status, state.exit_status), (Status::StoppedStatus, 3));
+    let err = process.kill(9, false).await.unwrap_err();
+    assert!(
+        err.is_not_found() && err.to_string().contains("already finished"),
+        "{}",
+        err
+    );
+
+    let bundle = fixture.path("state/tenant/app");
+    assert_eq!(
+        fixture.commands().last().unwrap(),
+        &format!(
+            "--log {bundle}/exec-probe.log --log-format json exec --detach --process {bundle}/exec-probe.json \
+             --pid-file {bundle}/exec-probe.pid app",
+            bundle = bundle.display()
+        )
+    );
+    assert_eq!(process.delete().await.unwrap().status, 3);
+    assert!(task.process("probe").await.is_err());
+    assert!(!bundle.join("exec-probe.json").exists());
+
+    task.kill(15, false).await.unwrap();
+    assert_eq!(task.wait().await.unwrap().status, 143);
+    runtime.delete(&ctx, "app").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_stopped_or_created_tasks_are_deleted() {
+    let fixture = Fixture::new();
+    let runtime = fixture.runtime();
+    let ctx = common::ctx("tenant");
+
+    let task = runtime.create(&ctx, "app", fixture.opts("{}")).await.unwrap();
+    task.start().await.unwrap();
+    let err = runtime.delete(&ctx, "app").await.unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert!(fixture.path("state/tenant/app").exists());
+    task.kill(9, false).await.unwrap();
+    task.wait().await.unwrap();
+    runtime.delete(&ctx, "app").await.unwrap();
+
+    // a task which was not started is killed
+    let task = runtime.create(&ctx, "app", fixture.opts("{}")).await.unwrap();
+    let pid = task.pid().await.unwrap();
+    let exit = runtime.delete(&ctx, "app").await.unwrap();
+    assert_eq!((exit.pid, exit.status), (pid, 137));
+    assert!(!fixture.path("state/tenant/app").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_creates_remove_the_bundle() {
+    let fixture = Fixture::new();
+    let runtime = fixture.runtime();
+    let ctx = common::ctx("tenant");
+
+    let err = match runtime.create(&ctx, "app", fixture.opts(r#"{"fail":true}"#)).await {
+        Ok(_) => panic!("task created"),
+        Err(e) => e.to_string(),
+    };
+    assert!(
+        err.contains(": no such cgroup") && !err.contains("cgroup v1"),
+        "{}",
+        err
+    );
+    assert!(!fixture.path("state/tenant/app").exists());
+    assert!(!fixture.path("work/tenant/app").exists());
+    assert!(fixture.commands().last().unwrap().starts_with("delete --force app"));
+    assert!(runtime.tasks(&ctx, false).await.unwrap().is_empty());
+
+    let mut opts = fixture.opts("{}");
+    opts.io.terminal = true;
+    let err = match runtime.create(&ctx, "app", opts).await {
+        Ok(_) => panic!("task created"),
+        Err(e) => e.to_string(),
+    };
+    assert!(err.contains("terminals"), "{}", err);
+
+    runtime.create(&ctx, "app", fixture.opts("{}")).await.unwrap();
+    runtime.delete(&ctx, "app").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tasks_run_on_their_rootfs() {
+    if !common::is_root() {
+        eprintln!("skipping: mounting needs root");
+        return;
+    }
+    let fixture = Fixture::new();
+    let runtime = fixture.runtime();
+    let ctx = common::ctx("tenant");
+    let dir = |path: &str| {
+        let path = fixture.path(path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    };
+    let (lower, upper, work) = (dir("snapshots/1/fs"), dir("snapshots/2/fs"), dir("snapshots/2/work"));
+    fs::write(lower.join("data"), "lower").unwrap();
+    let opts = |spec: &str| {
+        let mut opts = fixture.opts(spec);
+        opts.root_fs = vec![Mount::new(
+            "overlay",
+            "overlay",
+            vec![
+                format!("workdir={}", work.display()),
+                format!("upperdir={}", upper.display()),
+                format!("lowerdir={}", lower.display()),
+            ],
+        )];
+        opts
+    };
+
+    // the rootfs is mounted before the container is created
+    let task = runtime.create(&ctx, "app", opts("{}")).await.unwrap();
+    let rootfs = fixture.path("state/tenant/app/rootfs");
+    assert_eq!(fs::read_to_string(fixture.path("bin/app.rootfs")).unwrap(), "data\n");
+    fs::write(rootfs.join("written"), "upper").unwrap();
+    assert_eq!(fs::read_to_string(upper.join("written")).unwrap(), "upper");
+
+    task.kill(9, false).await.unwrap();
+    task.wait().await.unwrap();
+    runtime.delete(&ctx, "app").await.unwrap();
+    assert!(!fixture.path("state/tenant/app").exists());
+    assert_eq!(fs::read_to_string(lower.join("data")).unwrap(), "lower");
+    assert_eq!(fs::read_to_string(upper.join("written")).unwrap(), "upper");
+
+    // the rootfs of a failed create is unmounted
+    runtime
+        .create(&ctx, "app", opts(r#"{"fail":true}"#))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(fixture.path("bin/app.rootfs")).unwrap(),
+        "data\nwritten\n"
+    );
+    assert!(!fixture.path("state/tenant/app").exists());
+    assert_eq!(fs::read_to_string(upper.join("written")).unwrap(), "upper");
+}
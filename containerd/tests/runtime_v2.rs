@@ -0,0 +1,526 @@
+mod common;
+
+use common::TempDir;
+use containerd::api::task::v2::{
+    register_task_service, CreateTaskRequest, CreateTaskResponse, DeleteRequest, DeleteResponse, KillRequest,
+    ShutdownRequest, StartRequest, StartResponse, StateRequest, StateResponse, Task,
+};
+use containerd::api::v1::types::Status as ApiStatus;
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use containerd::mount::{self, Mount};
+use containerd::runtime::task::Status;
+use containerd::runtime::v2::{self, Bundle, ManagerConfig, ShimManager};
+use containerd::runtime::{CreateOpts, PlatformRuntime};
+use containerd::ttrpc::Server;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Shim records the requests of the manager and serves a single running
+/// task per id.
+#[derive(Default)]
+struct Shim {
+    created: Mutex<Vec<(Option<String>, CreateTaskRequest)>>,
+    killed: Mutex<Vec<u32>>,
+    shutdowns: Mutex<u32>,
+}
+
+#[async_trait::async_trait]
+impl Task for Shim {
+    async fn create(&self, ctx: &Context, req: CreateTaskRequest) -> Result<CreateTaskResponse, Error> {
+        if req.id == "broken" {
+            return Err(Error::InvalidArgument("broken spec".to_string()));
+        }
+        self.created
+            .lock()
+            .unwrap()
+            .push((ctx.namespace().map(String::from), req));
+        Ok(CreateTaskResponse { pid: 42 })
+    }
+
+    async fn start(&self, _: &Context, _: StartRequest) -> Result<StartResponse, Error> {
+        Ok(StartResponse { pid: 42 })
+    }
+
+    async fn state(&self, _: &Context, req: StateRequest) -> Result<StateResponse, Error> {
+        Ok(StateResponse {
+            id: req.id,
+            pid: 42,
+            status: ApiStatus::Running as i32,
+            ..Default::default()
+        })
+    }
+
+    async fn kill(&self, _: &Context, req: KillRequest) -> Result<(), Error> {
+        self.killed.lock().unwrap().push(req.signal);
+        Ok(())
+    }
+
+    async fn delete(&self, _: &Context, _: DeleteRequest) -> Result<DeleteResponse, Error> {
+        Ok(DeleteResponse {
+            pid: 42,
+            exit_status: 0,
+            ..Default::default()
+        })
+    }
+
+    async fn shutdown(&self, _: &Context, _: ShutdownRequest) -> Result<(), Error> {
+        *self.shutdowns.lock().unwrap() += 1;
+        Ok(())
+    }
+}
+
+/// Fixture runs the ttrpc server of a fake shim in the test process, and
+/// installs a shim binary answering `start` with its address.
+struct Fixture {
+    root: TempDir,
+    shim: Arc<Shim>,
+    server: Option<tokio::task::JoinHandle<Result<(), Error>>>,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        let root = TempDir::new();
+        let shim = Arc::new(Shim::default());
+        let listener = tokio::net::UnixListener::bind(root.path().join("shim.sock")).unwrap();
+        let mut ttrpc = Server::new();
+        register_task_service(&mut ttrpc, shim.clone());
+        let server = tokio::spawn(ttrpc.serve(listener, std::future::pending()));
+        fs::create_dir(root.path().join("bin")).unwrap();
+        Fixture {
+            root,
+            shim,
+            server: Some(server),
+        }
+    }
+
+    /// install writes a shim binary running the script for its last
+    /// argument, after logging how it was run.
+    fn install(&self, name: &str, script: &str) -> PathBuf {
+        let path = self.root.path().join("bin").join(name);
+        let body = format!(
+            "#!/bin/sh\necho \"$PWD $TTRPC_ADDRESS $NAMESPACE $*\" >> {}\nfor last; do :; done\n{}\n",
+            self.log_path().display(),
+            script
+        );
+        fs::write(&path, body).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// install_shim installs a shim answering `start` with the address of
+    /// the fake shim, and `delete` with the exit of a killed task.
+    fn install_shim(&self, name: &str) -> PathBuf {
+        let script = format!(
+            "case \"$last\" in\nstart) echo unix://{} ;;\ndelete) printf '\\010\\052\\020\\211\\001' ;;\nesac",
+            self.root.path().join("shim.sock").display()
+        );
+        self.install(name, &script)
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.root.path().join("shim.log")
+    }
+
+    fn log(&self) -> Vec<String> {
+        fs::read_to_string(self.log_path())
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    fn state(&self) -> PathBuf {
+        self.root.path().join("state")
+    }
+
+    fn work(&self) -> PathBuf {
+        self.root.path().join("work")
+    }
+
+    fn manager(&self) -> ShimManager {
+        ShimManager::new(ManagerConfig {
+            root: self.work(),
+            state: self.state(),
+            address: "/run/test/containerd.sock".to_string(),
+            ttrpc_address: "/run/test/containerd.sock.ttrpc".to_string(),
+            publish_binary: "/usr/bin/containerd".to_string(),
+            path: vec![PathBuf::from("/nonexistent"), self.root.path().join("bin")],
+        })
+    }
+
+    /// stop stops the fake shim, as if it crashed.
+    async fn stop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+    }
+}
+
+fn opts(runtime: &str) -> CreateOpts {
+    CreateOpts {
+        spec: prost_types::Any {
+            type_url: "types.containerd.io/opencontainers/runtime-spec/1/Spec".to_string(),
+            value: br#"{"ociVersion":"1.1.0"}"#.to_vec(),
+        },
+        runtime: runtime.to_string(),
+        ..Default::default()
+    }
+}
+
+/// create_err returns the error creating the task.
+async fn create_err(manager: &ShimManager, ctx: &Context, id: &str, runtime: &str) -> String {
+    match manager.create(ctx, id, opts(runtime)).await {
+        Ok(_) => panic!("task {} created", id),
+        Err(e) => e.to_string(),
+    }
+}
+
+fn bundle(fixture: &Fixture, namespace: &str, id: &str) -> PathBuf {
+    fixture.state().join(namespace).join(id)
+}
+
+#[test]
+fn binary_names_follow_the_runtime_name() {
+    assert_eq!(
+        v2::binary_name("io.containerd.runc.v2").as_deref(),
+        Some("containerd-shim-runc-v2")
+    );
+    assert_eq!(
+        v2::binary_name("io.containerd.kata.v2").as_deref(),
+        Some("containerd-shim-kata-v2")
+    );
+    assert_eq!(v2::binary_name("runc"), None);
+    assert_eq!(v2::binary_name("io.containerd..v2"), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tasks_are_created_in_shims() {
+    let fixture = Fixture::new();
+    fixture.install_shim("containerd-shim-fake-v2");
+    let manager = fixture.manager();
+    let ctx = common::ctx("tenant");
+    assert_eq!(manager.id(), "io.containerd.runtime.v2.task");
+
+    let task = manager
+        .create(&ctx, "app", opts("io.containerd.fake.v2"))
+        .await
+        .unwrap();
+    let bundle = bundle(&fixture, "tenant", "app");
+    let socket = fixture.root.path().join("shim.sock");
+
+    // the shim is started in the bundle, which holds the spec and address
+    assert_eq!(
+        fixture.log(),
+        [format!(
+            "{} /run/test/containerd.sock.ttrpc tenant -namespace tenant -address /run/test/containerd.sock \
+             -publish-binary /usr/bin/containerd -id app start",
+            bundle.display()
+        )]
+    );
+    assert_eq!(
+        fs::read_to_string(bundle.join("config.json")).unwrap(),
+        r#"{"ociVersion":"1.1.0"}"#
+    );
+    assert!(bundle.join("rootfs").is_dir());
+    let work = fixture.work().join("tenant").join("app");
+    assert_eq!(fs::read_link(bundle.join("work")).unwrap(), work);
+    assert_eq!(
+        fs::read_to_string(bundle.join("address")).unwrap(),
+        format!("unix://{}", socket.display())
+    );
+
+    let created = fixture.shim.created.lock().unwrap().clone();
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].0.as_deref(), Some("tenant"));
+    assert_eq!(created[0].1.id, "app");
+    assert_eq!(created[0].1.bundle, bundle.to_string_lossy());
+
+    // the task is driven over ttrpc
+    assert_eq!(task.id(), "app");
+    assert_eq!(task.namespace(), "tenant");
+    assert_eq!(task.pid().await.unwrap(), 42);
+    task.start().await.unwrap();
+    let state = task.state().await.unwrap();
+    assert_eq!(state.status, Status::RunningStatus);
+    assert_eq!(state.pid, 42);
+    task.kill(9, false).await.unwrap();
+    assert_eq!(*fixture.shim.killed.lock().unwrap(), [9]);
+    let err = task.pause().await.unwrap_err().to_string();
+    assert!(err.contains("not implemented"), "{}", err);
+
+    assert_eq!(manager.get(&ctx, "app").await.unwrap().id(), "app");
+    assert!(manager.get(&common::ctx("other"), "app").await.is_err());
+    assert_eq!(manager.tasks(&ctx, false).await.unwrap().len(), 1);
+    assert!(manager.tasks(&common::ctx("other"), false).await.unwrap().is_empty());
+    assert_eq!(manager.tasks(&Context::new(), true).await.unwrap().len(), 1);
+    let err = create_err(&manager, &ctx, "app", "io.containerd.fake.v2").await;
+    assert!(err.contains("already exists"), "{}", err);
+
+    let exit = manager.delete(&ctx, "app").await.unwrap();
+    assert_eq!((exit.pid, exit.status), (42, 0));
+    assert_eq!(*fixture.shim.shutdowns.lock().unwrap(), 1);
+    assert!(!bundle.exists());
+    assert!(!work.exists());
+    assert!(manager.get(&ctx, "app").await.is_err());
+    assert!(manager.delete(&ctx, "app").await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runtimes_resolve_to_shim_binaries() {
+    let fixture = Fixture::new();
+    let binary = fixture.install_shim("shim");
+    let manager = fixture.manager();
+    let ctx = common::ctx("tenant");
+
+    let err = create_err(&manager, &ctx, "app", "io.containerd.missing.v2").await;
+    assert!(err.contains("containerd-shim-missing-v2"), "{}", err);
+    let err = create_err(&manager, &ctx, "app", "runc").await;
+    assert!(err.contains("invalid runtime name"), "{}", err);
+    let err = create_err(&manager, &Context::new(), "app", binary.to_str().unwrap()).await;
+    assert!(err.contains("namespace"), "{}", err);
+    assert!(fixture.log().is_empty());
+
+    // an absolute path is run as is
+    let task = manager
+        .create(&ctx, "app", opts(binary.to_str().unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(task.pid().await.unwrap(), 42);
+    assert_eq!(fixture.log().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gone_shims_are_cleaned_up_by_their_binary() {
+    let mut fixture = Fixture::new();
+    fixture.install_shim("containerd-shim-fake-v2");
+    let manager = fixture.manager();
+    let ctx = common::ctx("tenant");
+    manager
+        .create(&ctx, "app", opts("io.containerd.fake.v2"))
+        .await
+        .unwrap();
+    let bundle = bundle(&fixture, "tenant", "app");
+
+    fixture.stop().await;
+    let exit = manager.delete(&ctx, "app").await.unwrap();
+    assert_eq!((exit.pid, exit.status), (42, 137));
+    let log = fixture.log();
+    assert_eq!(log.len(), 2);
+    assert!(
+        log[1].ends_with(&format!("-id app -bundle {} delete", bundle.display())),
+        "{}",
+        log[1]
+    );
+    assert!(!bundle.exists());
+    assert!(manager.tasks(&ctx, false).await.unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_creates_remove_the_bundle() {
+    let fixture = Fixture::new();
+    fixture.install("containerd-shim-failing-v2", "echo \"no cgroups\" >&2\nexit 1");
+    fixture.install_shim("containerd-shim-fake-v2");
+    let manager = fixture.manager();
+    let ctx = common::ctx("tenant");
+
+    let err = create_err(&manager, &ctx, "app", "io.containerd.failing.v2").await;
+    assert!(err.contains("no cgroups"), "{}", err);
+    assert!(!bundle(&fixture, "tenant", "app").exists());
+
+    // a shim failing to create the task is shut down and cleaned up
+    let err = create_err(&manager, &ctx, "broken", "io.containerd.fake.v2").await;
+    assert!(err.contains("broken spec"), "{}", err);
+    assert_eq!(*fixture.shim.shutdowns.lock().unwrap(), 1);
+    let log = fixture.log();
+    assert!(log.last().unwrap().ends_with(" delete"), "{:?}", log);
+    assert!(!bundle(&fixture, "tenant", "broken").exists());
+    assert!(manager.tasks(&ctx, false).await.unwrap().is_empty());
+
+    // the id can be used again
+    manager
+        .create(&ctx, "app", opts("io.containerd.fake.v2"))
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tasks_are_shared_across_tokio_tasks() {
+    let fixture = Fixture::new();
+    fixture.install_shim("containerd-shim-fake-v2");
+    let manager: Arc<dyn PlatformRuntime> = Arc::new(fixture.manager());
+    let runtimes = Arc::new(HashMap::from([(manager.id(), manager)]));
+    let ctx = common::ctx("tenant");
+    let runtime = &runtimes["io.containerd.runtime.v2.task"];
+    let task = runtime
+        .create(&ctx, "app", opts("io.containerd.fake.v2"))
+        .await
+        .unwrap();
+
+    let handlers: Vec<_> = (0..4)
+        .map(|signal| {
+            let (runtimes, task) = (runtimes.clone(), task.clone());
+            tokio::spawn(async move {
+                let ctx = common::ctx("tenant");
+                let runtime = &runtimes["io.containerd.runtime.v2.task"];
+                let state = runtime.get(&ctx, "app").await?.state().await?;
+                task.kill(signal, false).await?;
+                Ok::<_, Error>(state.pid)
+            })
+        })
+        .collect();
+    for handler in handlers {
+        assert_eq!(handler.await.unwrap().unwrap(), 42);
+    }
+    let mut killed = fixture.shim.killed.lock().unwrap().clone();
+    killed.sort();
+    assert_eq!(killed, [0, 1, 2, 3]);
+
+    runtime.delete(&ctx, "app").await.unwrap();
+}
+
+fn spec() -> prost_types::Any {
+    opts("").spec
+}
+
+#[test]
+fn bundles_hold_the_spec_rootfs_and_work_dir() {
+    let root = TempDir::new();
+    let (work, state) = (root.path().join("work"), root.path().join("state"));
+    // the working directory of a previous task is replaced
+    fs::create_dir_all(work.join("tenant/app")).unwrap();
+    fs::write(work.join("tenant/app/stale"), "").unwrap();
+
+    let bundle = Bundle::new(&work, &state, "tenant", "app", &spec()).unwrap();
+    assert_eq!(bundle.path, state.join("tenant/app"));
+    assert_eq!(bundle.rootfs(), state.join("tenant/app/rootfs"));
+    assert_eq!(fs::metadata(&bundle.path).unwrap().permissions().mode() & 0o777, 0o700);
+    assert_eq!(
+        fs::read_to_string(bundle.path.join(v2::CONFIG_FILENAME)).unwrap(),
+        r#"{"ociVersion":"1.1.0"}"#
+    );
+    assert!(fs::read_dir(bundle.rootfs()).unwrap().next().is_none());
+    assert_eq!(
+        fs::read_link(bundle.path.join("work")).unwrap(),
+        work.join("tenant/app")
+    );
+    assert!(!work.join("tenant/app/stale").exists());
+
+    let err = Bundle::new(&work, &state, "tenant", "app", &spec()).unwrap_err();
+    assert!(err.is_already_exists(), "{}", err);
+    assert!(bundle.path.join(v2::CONFIG_FILENAME).exists());
+    let err = Bundle::new(&work, &state, "tenant", "../app", &spec()).unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+
+    // a rootfs which is not mounted is left as is
+    mount::unmount_recursive(&bundle.rootfs(), 0).unwrap();
+    mount::unmount_recursive(&root.path().join("missing"), 0).unwrap();
+
+    bundle.delete().unwrap();
+    assert!(!bundle.path.exists());
+    assert!(!work.join("tenant/app").exists());
+    assert!(fs::read_dir(state.join("tenant")).unwrap().next().is_none());
+    assert!(fs::read_dir(work.join("tenant")).unwrap().next().is_none());
+    bundle.delete().unwrap();
+}
+
+#[test]
+fn bundles_are_removed_when_creation_fails() {
+    let root = TempDir::new();
+    let state = root.path().join("state");
+    // the working directories cannot be created below a file
+    let work = root.path().join("work");
+    fs::write(&work, "").unwrap();
+
+    Bundle::new(&work, &state, "tenant", "app", &spec()).unwrap_err();
+    assert!(!state.join("tenant/app").exists());
+
+    // mounts made before a failing one are undone
+    let work = root.path().join("work-dir");
+    let bundle = Bundle::new(&work, &state, "tenant", "app", &spec()).unwrap();
+    let missing = Mount::new("bind", root.path().join("missing"), vec!["rbind".to_string()]);
+    let err = bundle.mount(&[missing]).unwrap_err();
+    assert!(err.to_string().contains("mount rootfs of task app"), "{}", err);
+    assert!(fs::read_dir(bundle.rootfs()).unwrap().next().is_none());
+    bundle.delete().unwrap();
+}
+
+#[test]
+fn bundles_keep_a_rootfs_which_is_still_in_use() {
+    let root = TempDir::new();
+    let bundle = Bundle::new(
+        &root.path().join("work"),
+        &root.path().join("state"),
+        "tenant",
+        "app",
+        &spec(),
+    )
+    .unwrap();
+    fs::write(bundle.rootfs().join("data"), "precious").unwrap();
+
+    let err = bundle.delete().unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+    assert_eq!(fs::read_to_string(bundle.rootfs().join("data")).unwrap(), "precious");
+}
+
+#[test]
+fn bundles_mount_the_rootfs_and_unmount_it_on_delete() {
+    if !common::is_root() {
+        eprintln!("skipping: mounting needs root");
+        return;
+    }
+    let root = TempDir::new();
+    let (work, state) = (root.path().join("work"), root.path().join("state"));
+    let dir = |path: PathBuf| {
+        fs::create_dir_all(&path).unwrap();
+        path
+    };
+
+    // a read only bind mount
+    let source = dir(root.path().join("source"));
+    fs::write(source.join("data"), "bound").unwrap();
+    let bundle = Bundle::new(&work, &state, "tenant", "bind", &spec()).unwrap();
+    let bind = Mount::new("bind", &source, vec!["rbind".to_string(), "ro".to_string()]);
+    bundle.mount(&[bind]).unwrap();
+    assert_eq!(fs::read_to_string(bundle.rootfs().join("data")).unwrap(), "bound");
+    fs::write(bundle.rootfs().join("other"), "").unwrap_err();
+    bundle.delete().unwrap();
+    assert!(!bundle.path.exists());
+    assert_eq!(fs::read_to_string(source.join("data")).unwrap(), "bound");
+
+    // an overlay of snapshots, with more lower directories than fit in a page
+    // unless they are given relative to their parent
+    let snapshots = dir(root.path().join("io.containerd.snapshotter.v1.overlayfs/snapshots"));
+    let lower: Vec<_> = (0..64).map(|i| dir(snapshots.join(format!("{}/fs", i)))).collect();
+    fs::write(lower[0].join("data"), "top").unwrap();
+    fs::write(lower[63].join("data"), "bottom").unwrap();
+    fs::write(lower[63].join("base"), "base").unwrap();
+    let (upper, overlay_work) = (dir(snapshots.join("64/fs")), dir(snapshots.join("64/work")));
+    let lowerdir: Vec<_> = lower.iter().map(|path| path.display().to_string()).collect();
+    assert!(lowerdir.join(":").len() > 4096);
+    let overlay = Mount::new(
+        "overlay",
+        "overlay",
+        vec![
+            format!("workdir={}", overlay_work.display()),
+            format!("upperdir={}", upper.display()),
+            format!("lowerdir={}", lowerdir.join(":")),
+        ],
+    );
+    let bundle = Bundle::new(&work, &state, "tenant", "overlay", &spec()).unwrap();
+    bundle.mount(&[overlay]).unwrap();
+    assert_eq!(fs::read_to_string(bundle.rootfs().join("data")).unwrap(), "top");
+    assert_eq!(fs::read_to_string(bundle.rootfs().join("base")).unwrap(), "base");
+    fs::write(bundle.rootfs().join("written"), "upper").unwrap();
+    assert_eq!(fs::read_to_string(upper.join("written")).unwrap(), "upper");
+
+    bundle.delete().unwrap();
+    assert!(!bundle.path.exists());
+    assert!(fs::read_dir(state.join("tenant")).unwrap().next().is_none());
+    assert_eq!(fs::read_to_string(lower[63].join("base")).unwrap(), "base");
+    assert_eq!(fs::read_to_string(upper.join("written")).unwrap(), "upper");
+}
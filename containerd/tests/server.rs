@@ -0,0 +1,139 @@
+mod common;
+
+use common::TempDir;
+use containerd::api::services::events::v1::events_client::EventsClient;
+use containerd::api::services::events::v1::events_server::EventsServer;
+use containerd::api::services::events::v1::SubscribeRequest;
+use containerd::api::services::namespaces::v1::namespaces_client::NamespacesClient;
+use containerd::api::services::namespaces::v1::ListNamespacesRequest;
+use containerd::api::services::version::v1::version_client::VersionClient;
+use containerd::api::services::version::v1::version_server::VersionServer;
+use containerd::dialer;
+use containerd::events::exchange::Exchange;
+use containerd::server::{self, Builder, Config};
+use containerd::services::{events, version};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tonic::Code;
+
+fn config(root: &TempDir) -> Config {
+    Config {
+        address: root.path().join("run").join("containerd.sock"),
+        ..Default::default()
+    }
+}
+
+/// serve serves the version and events services until the returned sender
+/// is dropped.
+async fn serve(config: Config) -> (oneshot::Sender<()>, JoinHandle<Result<(), containerd::errdefs::Error>>) {
+    let (tx, rx) = oneshot::channel::<()>();
+    let address = config.address.clone();
+    let server = Builder::new(config)
+        .add_service(VersionServer::new(version::Service))
+        .add_service(EventsServer::new(events::Service::new(Arc::new(Exchange::new()))));
+    let handle = tokio::spawn(server.serve(async {
+        let _ = rx.await;
+    }));
+    wait_for_socket(&address).await;
+    (tx, handle)
+}
+
+/// wait_for_socket waits until the server accepts connections on path.
+async fn wait_for_socket(path: &Path) {
+    for _ in 0..50 {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("no server listens on {}", path.display());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_the_added_services() {
+    let root = TempDir::new();
+    let config = config(&root);
+    let (stop, handle) = serve(config.clone()).await;
+
+    let channel = dialer::connect(&config.address).await.unwrap();
+    let version = VersionClient::new(channel.clone())
+        .version(())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(version.version, containerd::VERSION);
+    assert_eq!(version.revision, containerd::REVISION);
+
+    EventsClient::new(channel.clone())
+        .subscribe(SubscribeRequest::default())
+        .await
+        .unwrap();
+
+    // the namespaces service was not added
+    let status = NamespacesClient::new(channel)
+        .list(ListNamespacesRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unimplemented, "{}", status);
+
+    drop(stop);
+    handle.await.unwrap().unwrap();
+    assert!(!config.address.exists());
+    let err = dialer::connect(&config.address).await.unwrap_err();
+    assert!(matches!(err, containerd::errdefs::Error::Unavailable(_)), "{}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn socket_has_the_configured_permissions() {
+    let root = TempDir::new();
+    let config = Config {
+        mode: 0o600,
+        uid: Some(nix::unistd::getuid().as_raw()),
+        gid: Some(nix::unistd::getgid().as_raw()),
+        ..config(&root)
+    };
+    let (_stop, _handle) = serve(config.clone()).await;
+
+    let metadata = std::fs::metadata(&config.address).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    let dir = std::fs::metadata(config.address.parent().unwrap()).unwrap();
+    assert_eq!(dir.permissions().mode() & 0o777, 0o711);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stale_sockets_are_replaced() {
+    let root = TempDir::new();
+    let config = config(&root);
+
+    // a socket left behind by a server that is gone
+    drop(server::listen(&config).unwrap());
+    assert!(config.address.exists());
+    let (_stop, _handle) = serve(config.clone()).await;
+    let channel = dialer::connect(&config.address).await.unwrap();
+    VersionClient::new(channel).version(()).await.unwrap();
+
+    // while a server listens, the socket is not taken over
+    let err = server::listen(&config).unwrap_err();
+    assert!(err.is_already_exists(), "{}", err);
+
+    let file = root.path().join("file");
+    std::fs::write(&file, "").unwrap();
+    let err = server::listen(&Config {
+        address: file,
+        ..Default::default()
+    })
+    .unwrap_err();
+    assert!(err.is_already_exists(), "{}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serving_requires_a_service() {
+    let root = TempDir::new();
+    let err = Builder::new(config(&root)).serve(async {}).await.unwrap_err();
+    assert!(err.is_failed_precondition(), "{}", err);
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use common::TempDir;
+use containerd::api::events::{ContainerCreate, TaskExit, TaskStart};
+use containerd::api::services::events::v1::events_client::EventsClient;
+use containerd::api::services::events::v1::events_server::EventsServer;
+use containerd::api::services::events::v1::{Envelope, ForwardRequest, PublishRequest, SubscribeRequest};
+use containerd::dialer;
+use containerd::events::exchange::Exchange;
+use containerd::events::{self, Event};
+use containerd::namespaces;
+use containerd::protobuf;
+use containerd::services::events::Service;
+use prost::Message;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tokio_stream::StreamExt;
+use tonic::transport::{Channel, Server};
+use tonic::{Code, Request, Streaming};
+
+/// serve serves the events service on a socket in root.
+fn serve(root: &TempDir, service: Service) -> PathBuf {
+    let path = root.path().join("containerd.sock");
+    let incoming = UnixListenerStream::new(UnixListener::bind(&path).unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(EventsServer::new(service))
+            .serve_with_incoming(incoming),
+    );
+    path
+}
+
+async fn connect(path: PathBuf) -> EventsClient<Channel> {
+    EventsClient::new(dialer::connect(path).await.unwrap())
+}
+
+fn publish_request<E: Event>(namespace: Option<&str>, topic: &str, event: &E) -> Request<PublishRequest> {
+    let mut request = Request::new(PublishRequest {
+        topic: topic.to_string(),
+        event: Some(events::marshal(event)),
+    });
+    if let Some(namespace) = namespace {
+        request
+            .metadata_mut()
+            .insert(namespaces::GRPC_HEADER, namespace.parse().unwrap());
+    }
+    request
+}
+
+async fn next(stream: &mut Streaming<Envelope>) -> Envelope {
+    tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("no event received")
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publish_forward_and_subscribe() {
+    let root = TempDir::new();
+    let exchange = Arc::new(Exchange::new());
+    let mut client = connect(serve(&root, Service::new(exchange.clone()))).await;
+
+    let mut tasks = client
+        .subscribe(SubscribeRequest {
+            filters: vec![r#"topic~="^/tasks/""#.to_string()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut all = client
+        .subscribe(SubscribeRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let start = TaskStart {
+        container_id: "app".to_string(),
+        pid: 42,
+    };
+    client
+        .publish(publish_request(Some("default"), TaskStart::TOPIC, &start))
+        .await
+        .unwrap();
+    let create = ContainerCreate {
+        id: "app".to_string(),
+        ..Default::default()
+    };
+    client
+        .publish(publish_request(Some("default"), ContainerCreate::TOPIC, &create))
+        .await
+        .unwrap();
+
+    // a shim forwards the exit it observed earlier
+    let exited_at = OffsetDateTime::now_utc() - Duration::from_secs(1);
+    let exit = TaskExit {
+        container_id: "app".to_string(),
+        exit_status: 1,
+        ..Default::default()
+    };
+    client
+        .forward(ForwardRequest {
+            envelope: Some(Envelope {
+                timestamp: Some(protobuf::to_timestamp(exited_at)),
+                namespace: "other".to_string(),
+                topic: TaskExit::TOPIC.to_string(),
+                event: Some(events::marshal(&exit)),
+            }),
+        })
+        .await
+        .unwrap();
+
+    let envelope = next(&mut tasks).await;
+    assert_eq!(envelope.namespace, "default");
+    assert_eq!(envelope.topic, "/tasks/start");
+    let event = envelope.event.unwrap();
+    assert_eq!(event.type_url, TaskStart::TYPE_URL);
+    assert_eq!(TaskStart::decode(event.value.as_slice()).unwrap(), start);
+
+    let envelope = next(&mut tasks).await;
+    assert_eq!(envelope.namespace, "other");
+    assert_eq!(protobuf::from_timestamp(envelope.timestamp.as_ref()), exited_at);
+    assert_eq!(
+        TaskExit::decode(envelope.event.unwrap().value.as_slice()).unwrap(),
+        exit
+    );
+
+    let topics = [
+        next(&mut all).await.topic,
+        next(&mut all).await.topic,
+        next(&mut all).await.topic,
+    ];
+    assert_eq!(topics, ["/tasks/start", "/containers/create", "/tasks/exit"]);
+
+    // closing the streams unsubscribes
+    assert_eq!(exchange.subscribers(), 2);
+    drop(tasks);
+    drop(all);
+    for _ in 0..50 {
+        if exchange.subscribers() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(exchange.subscribers(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_requests_are_rejected() {
+    let root = TempDir::new();
+    let exchange = Arc::new(Exchange::new());
+    let service = Service::new(exchange.clone()).with_default_namespace("");
+    let mut client = connect(serve(&root, service)).await;
+    let exit = TaskExit::default();
+
+    let status = client
+        .publish(publish_request(None, TaskExit::TOPIC, &exit))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition, "{}", status);
+
+    for (namespace, topic) in [
+        ("default", "tasks/exit"),
+        ("default", "/tasks/exit!"),
+        ("-", "/tasks/exit"),
+    ] {
+        let status = client
+            .publish(publish_request(Some(namespace), topic, &exit))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(status.code(), Code::InvalidArgument | Code::FailedPrecondition),
+            "{:?} {:?}: {}",
+            namespace,
+            topic,
+            status
+        );
+    }
+
+    let mut request = Request::new(PublishRequest {
+        topic: TaskExit::TOPIC.to_string(),
+        event: None,
+    });
+    request
+        .metadata_mut()
+        .insert(namespaces::GRPC_HEADER, "default".parse().unwrap());
+    let status = client.publish(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument, "{}", status);
+
+    let status = client.forward(ForwardRequest::default()).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument, "{}", status);
+    let status = client
+        .forward(ForwardRequest {
+            envelope: Some(Envelope {
+                namespace: "default".to_string(),
+                topic: "/tasks".to_string(),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument, "{}", status);
+
+    let status = client
+        .subscribe(SubscribeRequest {
+            filters: vec!["topic==".to_string()],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument, "{}", status);
+    assert!(status.message().contains("parse error"), "{}", status);
+    assert_eq!(exchange.subscribers(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn events_without_namespace_are_published_in_the_default_one() {
+    let root = TempDir::new();
+    let exchange = Arc::new(Exchange::new());
+    let mut client = connect(serve(&root, Service::new(exchange.clone()))).await;
+    let mut stream = client
+        .subscribe(SubscribeRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let exit = TaskExit::default();
+    client
+        .publish(publish_request(None, TaskExit::TOPIC, &exit))
+        .await
+        .unwrap();
+    assert_eq!(next(&mut stream).await.namespace, namespaces::default_namespace());
+
+    let root = TempDir::new();
+    let service = Service::new(exchange.clone()).with_default_namespace("tenant");
+    let mut client = connect(serve(&root, service)).await;
+    client
+        .publish(publish_request(None, TaskExit::TOPIC, &exit))
+        .await
+        .unwrap();
+    assert_eq!(next(&mut stream).await.namespace, "tenant");
+}
@@ -0,0 +1,303 @@
+mod common;
+
+use common::TempDir;
+use containerd::api::events::TaskExit;
+use containerd::api::services::ttrpc::events::v1::{self as ttrpc_events, EventsClient, ForwardRequest};
+use containerd::api::task::v2::{
+    register_task_service, KillRequest, PidsRequest, StateRequest, StateResponse, Task, TaskClient, WaitRequest,
+    WaitResponse,
+};
+use containerd::context::Context;
+use containerd::errdefs::Error;
+use containerd::events::exchange::{Exchange, SubscribeOpts};
+use containerd::events::{self, Event};
+use containerd::protobuf;
+use containerd::services;
+use containerd::ttrpc::{Client, Response, Server, ServerStream, ServiceDesc};
+use prost::Message;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Shim records the requests it receives and answers some of the methods of
+/// the task service.
+#[derive(Default)]
+struct Shim {
+    killed: Mutex<Vec<(Option<String>, u32)>>,
+}
+
+#[async_trait::async_trait]
+impl Task for Shim {
+    async fn state(&self, ctx: &Context, req: StateRequest) -> Result<StateResponse, Error> {
+        if req.id != "app" {
+            return Err(Error::NotFound(format!("task {}", req.id)));
+        }
+        Ok(StateResponse {
+            id: req.id,
+            pid: 42,
+            bundle: ctx.namespace().unwrap_or_default().to_string(),
+            ..Default::default()
+        })
+    }
+
+    async fn kill(&self, ctx: &Context, req: KillRequest) -> Result<(), Error> {
+        self.killed
+            .lock()
+            .unwrap()
+            .push((ctx.namespace().map(String::from), req.signal));
+        Ok(())
+    }
+
+    async fn wait(&self, ctx: &Context, _: WaitRequest) -> Result<WaitResponse, Error> {
+        assert!(ctx.deadline().is_some());
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        Ok(WaitResponse::default())
+    }
+}
+
+/// count streams the numbers up to the request of the client.
+async fn count(_: Context, mut stream: ServerStream) -> Result<(), Error> {
+    let n: u32 = stream.recv().await?.unwrap_or_default();
+    for i in 1..=n {
+        stream.send(&i)?;
+    }
+    Ok(())
+}
+
+/// sum answers the sum of the numbers the client streams.
+async fn sum(_: Context, mut stream: ServerStream) -> Result<(), Error> {
+    let mut total = 0u32;
+    while let Some(i) = stream.recv::<u32>().await? {
+        if i == 0 {
+            return Err(Error::InvalidArgument("zero".to_string()));
+        }
+        total += i;
+    }
+    stream.send(&total)
+}
+
+fn serve(root: &TempDir, server: Server) -> PathBuf {
+    let path = root.path().join("shim.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    tokio::spawn(server.serve(listener, std::future::pending()));
+    path
+}
+
+fn shim_server(shim: Arc<Shim>) -> Server {
+    let mut server = Server::new();
+    register_task_service(&mut server, shim);
+    server.register(
+        "test.Numbers",
+        ServiceDesc::new().stream("Count", count).stream("Sum", sum),
+    );
+    server
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_are_answered() {
+    let root = TempDir::new();
+    let shim = Arc::new(Shim::default());
+    let client = Client::connect(serve(&root, shim_server(shim.clone()))).await.unwrap();
+    let task = TaskClient::new(client.clone());
+    let ctx = Context::new().with_namespace("tenant");
+
+    let state = task
+        .state(
+            &ctx,
+            &StateRequest {
+                id: "app".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(state.pid, 42);
+    assert_eq!(state.bundle, "tenant");
+
+    // the requests of concurrent callers share the connection
+    let mut kills = Vec::new();
+    for signal in 1..=8 {
+        let task = task.clone();
+        let ctx = ctx.clone();
+        kills.push(tokio::spawn(async move {
+            task.kill(
+                &ctx,
+                &KillRequest {
+                    id: "app".to_string(),
+                    signal,
+                    ..Default::default()
+                },
+            )
+            .await
+        }));
+    }
+    for kill in kills {
+        kill.await.unwrap().unwrap();
+    }
+    let mut killed = shim.killed.lock().unwrap().clone();
+    killed.sort();
+    assert_eq!(
+        killed,
+        (1..=8).map(|s| (Some("tenant".to_string()), s)).collect::<Vec<_>>()
+    );
+
+    let err = task
+        .state(
+            &ctx,
+            &StateRequest {
+                id: "other".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert!(err.is_not_found(), "{}", err);
+
+    let err = task.pids(&ctx, &PidsRequest::default()).await.unwrap_err();
+    assert!(matches!(err, Error::NotImplemented(_)), "{}", err);
+    let err = client
+        .request(&ctx, "containerd.task.v2.Other", "State", Vec::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::NotImplemented(_)), "{}", err);
+
+    // the handler is cancelled at the deadline of the client
+    let err = task
+        .wait(&ctx.with_timeout(Duration::from_millis(100)), &WaitRequest::default())
+        .await
+        .unwrap_err();
+    assert!(err.is_deadline_exceeded(), "{}", err);
+    assert!(!client.is_closed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_exchange_messages() {
+    let root = TempDir::new();
+    let client = Client::connect(serve(&root, shim_server(Arc::default())))
+        .await
+        .unwrap();
+    let ctx = Context::new();
+
+    let mut stream = client.server_stream(&ctx, "test.Numbers", "Count", &3u32).unwrap();
+    let mut received = Vec::new();
+    while let Some(i) = stream.recv::<u32>().await.unwrap() {
+        received.push(i);
+    }
+    assert_eq!(received, [1, 2, 3]);
+
+    let mut stream = client.stream(&ctx, "test.Numbers", "Sum").unwrap();
+    for i in [1u32, 2, 3, 4] {
+        stream.send(&i).unwrap();
+    }
+    stream.close_send().unwrap();
+    assert_eq!(stream.recv::<u32>().await.unwrap(), Some(10));
+    assert_eq!(stream.recv::<u32>().await.unwrap(), None);
+
+    // the status of the handler ends the stream
+    let mut stream = client.stream(&ctx, "test.Numbers", "Sum").unwrap();
+    stream.send(&0u32).unwrap();
+    let err = stream.recv::<u32>().await.unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+}
+
+/// raw_request writes a request message with the stream id and reads the
+/// response of the server.
+async fn raw_request(conn: &mut UnixStream, id: u32, payload: &[u8]) -> Response {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    header.extend_from_slice(&id.to_be_bytes());
+    header.extend_from_slice(&[1, 0]);
+    conn.write_all(&header).await.unwrap();
+    conn.write_all(payload).await.unwrap();
+
+    let mut header = [0u8; 10];
+    conn.read_exact(&mut header).await.unwrap();
+    assert_eq!(u32::from_be_bytes(header[4..8].try_into().unwrap()), id);
+    assert_eq!(header[8], 2, "not a response");
+    let mut payload = vec![0; u32::from_be_bytes(header[..4].try_into().unwrap()) as usize];
+    conn.read_exact(&mut payload).await.unwrap();
+    Response::decode(payload.as_slice()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn protocol_errors_are_reported_on_their_stream() {
+    let root = TempDir::new();
+    let mut conn = UnixStream::connect(serve(&root, shim_server(Arc::default())))
+        .await
+        .unwrap();
+    let request = containerd::ttrpc::Request {
+        service: "containerd.task.v2.Task".to_string(),
+        method: "State".to_string(),
+        payload: StateRequest {
+            id: "app".to_string(),
+            ..Default::default()
+        }
+        .encode_to_vec(),
+        ..Default::default()
+    }
+    .encode_to_vec();
+
+    let code = |response: &Response| response.status.as_ref().map_or(0, |s| s.code);
+    let response = raw_request(&mut conn, 2, &request).await;
+    assert_eq!(code(&response), tonic::Code::InvalidArgument as i32, "{:?}", response);
+
+    let response = raw_request(&mut conn, 3, &request).await;
+    assert_eq!(code(&response), 0, "{:?}", response);
+    assert_eq!(StateResponse::decode(response.payload.as_slice()).unwrap().pid, 42);
+
+    let response = raw_request(&mut conn, 3, &request).await;
+    assert_eq!(code(&response), tonic::Code::InvalidArgument as i32, "{:?}", response);
+
+    // an oversized message is discarded, the connection stays usable
+    let large = vec![0u8; containerd::ttrpc::MESSAGE_LENGTH_MAX + 1];
+    let response = raw_request(&mut conn, 5, &large).await;
+    assert_eq!(code(&response), tonic::Code::InvalidArgument as i32, "{:?}", response);
+    assert!(response
+        .status
+        .unwrap()
+        .message
+        .contains("exceeds maximum message size"));
+
+    let response = raw_request(&mut conn, 7, &request).await;
+    assert_eq!(code(&response), 0, "{:?}", response);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shims_forward_events_to_the_exchange() {
+    let root = TempDir::new();
+    let exchange = Arc::new(Exchange::new());
+    let sub = exchange.subscribe(&[], SubscribeOpts::default()).unwrap();
+    let mut server = Server::new();
+    ttrpc_events::register_events_service(&mut server, Arc::new(services::events::Service::new(exchange)));
+    let client = EventsClient::new(Client::connect(serve(&root, server)).await.unwrap());
+
+    let exited_at = OffsetDateTime::now_utc() - Duration::from_secs(1);
+    let exit = TaskExit {
+        container_id: "app".to_string(),
+        exit_status: 137,
+        ..Default::default()
+    };
+    let forward = ForwardRequest {
+        envelope: Some(ttrpc_events::Envelope {
+            timestamp: Some(protobuf::to_timestamp(exited_at)),
+            namespace: "tenant".to_string(),
+            topic: TaskExit::TOPIC.to_string(),
+            event: Some(events::marshal(&exit)),
+        }),
+    };
+    client.forward(&Context::new(), &forward).await.unwrap();
+
+    let envelope = sub.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    assert_eq!(envelope.namespace, "tenant");
+    assert_eq!(envelope.timestamp, exited_at);
+    assert_eq!(envelope.decode::<TaskExit>().unwrap(), exit);
+
+    let err = client
+        .forward(&Context::new(), &ForwardRequest::default())
+        .await
+        .unwrap_err();
+    assert!(err.is_invalid_argument(), "{}", err);
+}